@@ -0,0 +1,131 @@
+//! Lists the libraries of a package, their targets and the functions they export.
+
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+use toml::Value;
+
+use crate::package::read_manifest;
+use crate::{exit_failure, MANIFEST};
+
+/// Print the contents of the package in `dir`
+pub(crate) fn inspect(dir: &Path) {
+    if !dir.is_dir() {
+        exit_failure!("package directory {} doesn't exist", dir.display());
+    }
+    let manifest = read_manifest(&dir.join(MANIFEST)).unwrap_or_default();
+    let mut libs: Vec<String> = manifest.keys().cloned().collect();
+    // Packages made by hand have no manifest, but each library has its bindings, which
+    // may be next to other Rust files when the package is in the source directory
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|e| e != "rs") {
+            continue;
+        }
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let is_bindings =
+            fs::read_to_string(&path).is_ok_and(|s| s.contains(&format!("pub mod {name} {{")));
+        if is_bindings && !libs.contains(&name) {
+            libs.push(name);
+        }
+    }
+    if libs.is_empty() {
+        exit_failure!("no ISPC libraries found in {}", dir.display());
+    }
+    libs.sort();
+
+    for lib in &libs {
+        println!("{lib}");
+        let info = manifest.get(lib);
+        let field = |name: &str| info.and_then(|i| i.get(name));
+        if let Some(version) = field("ispc_version").and_then(Value::as_str) {
+            println!("  ispc version: {version}");
+        }
+        let isas = strings(field("isas"));
+        if !isas.is_empty() {
+            println!("  isas: {}", isas.join(", "));
+        }
+        println!("  targets:");
+        let mut targets = strings(field("targets"));
+        for t in library_targets(dir, lib) {
+            if !targets.contains(&t) {
+                targets.push(t);
+            }
+        }
+        targets.sort();
+        for t in &targets {
+            let file = library_file(lib, t);
+            match fs::metadata(dir.join(&file)) {
+                Ok(m) => println!("    {t}: {file} ({} KiB)", m.len().div_ceil(1024)),
+                Err(_) => println!("    {t}: missing {file}"),
+            }
+        }
+        match fs::read_to_string(dir.join(lib).with_extension("rs")) {
+            Ok(bindings) => {
+                println!("  functions:");
+                for f in exported_functions(&bindings) {
+                    println!("    {f}");
+                }
+            }
+            Err(_) => println!("  missing bindings {lib}.rs"),
+        }
+    }
+}
+
+fn strings(value: Option<&Value>) -> Vec<String> {
+    value
+        .and_then(Value::as_array)
+        .map(|a| {
+            a.iter()
+                .filter_map(Value::as_str)
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Get the name of the library file `PackagedModule` links for `lib` on `target`
+fn library_file(lib: &str, target: &str) -> String {
+    if target.contains("msvc") {
+        format!("{lib}{target}.lib")
+    } else {
+        format!("lib{lib}{target}.a")
+    }
+}
+
+/// Find the targets of the libraries of `lib` in `dir`, which are named after them
+fn library_targets(dir: &Path, lib: &str) -> Vec<String> {
+    let unix_prefix = format!("lib{lib}");
+    let mut targets = Vec::new();
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let target = if let Some(t) = name.strip_suffix(".a") {
+            t.strip_prefix(&unix_prefix)
+        } else if let Some(t) = name.strip_suffix(".lib") {
+            t.strip_prefix(lib).filter(|t| t.contains("msvc"))
+        } else {
+            None
+        };
+        // Triples have at least an architecture, vendor or OS and a separating dash
+        if let Some(t) = target.filter(|t| t.contains('-')) {
+            targets.push(t.to_owned());
+        }
+    }
+    targets
+}
+
+/// Get the signatures of the functions declared in the `extern` blocks of the bindings
+fn exported_functions(bindings: &str) -> Vec<String> {
+    let decl = Regex::new(r"pub fn (\w+)\s*\(([^{};]*)\)\s*(->\s*[^{};]+)?;").unwrap();
+    let space = Regex::new(r"\s+").unwrap();
+    decl.captures_iter(bindings)
+        .map(|c| {
+            let params = space.replace_all(c[2].trim().trim_end_matches(','), " ");
+            let ret = c.get(3).map_or(String::new(), |r| {
+                format!(" {}", space.replace_all(r.as_str().trim(), " "))
+            });
+            format!("{}({params}){ret}", &c[1])
+        })
+        .collect()
+}
@@ -0,0 +1,153 @@
+//! `cargo ispc` precompiles ISPC kernels and their Rust bindings for a set of target
+//! triples into the layout `ispc_rt::PackagedModule` links against, so crates using ISPC
+//! can be published without requiring their users to install the ISPC compiler.
+//!
+//! ```text
+//! cargo ispc build --lib simple --out src/ --target x86_64-unknown-linux-gnu \
+//!     --target aarch64-apple-darwin --isa avx2-i32x8 --isa neon-i32x4 src/simple.ispc
+//! cargo ispc inspect src/
+//! ```
+//!
+//! `build` compiles the library for each target with `ispc_compile`, writing the
+//! `lib<lib><target>.a` (or `<lib><target>.lib` for MSVC) libraries and the `<lib>.rs`
+//! bindings to the output directory, and records the ISPC version, targets and ISAs
+//! in `ispc-package.toml` there. `inspect` lists the libraries of a package, which of
+//! their targets are present, the ISPC version they were built with and the functions
+//! exported by their bindings.
+
+mod inspect;
+mod package;
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+use ispc_compile::TargetISA;
+
+/// Print the message and exit with a failure exit code
+macro_rules! exit_failure {
+    ($($arg:tt)*) => {{
+        eprintln!("error: {}", format!($($arg)*));
+        std::process::exit(1);
+    }};
+}
+pub(crate) use exit_failure;
+
+/// The name of the manifest recording the libraries of a package
+const MANIFEST: &str = "ispc-package.toml";
+
+const USAGE: &str = "\
+Precompile ISPC kernels and bindings for use with ispc_rt::PackagedModule
+
+Usage:
+    cargo ispc build --lib <name> [options] <files>...
+    cargo ispc inspect [<dir>]
+
+Build options:
+    --lib <name>          Name of the library to build
+    --out <dir>           Directory to write the package to [default: ispc]
+    --target <triple>     Target to build for, may be repeated [default: host]
+    --isa <isa>           ISPC target ISA, e.g. avx2-i32x8, may be repeated
+    -I <dir>              Add an include path
+    -D <name[=value]>     Define a preprocessor symbol
+    --opt-level <level>   Optimization level [default: 3]
+    --debug               Emit debug information
+";
+
+/// The options of `cargo ispc build`
+#[derive(Default)]
+pub(crate) struct BuildOptions {
+    pub lib: String,
+    pub out: PathBuf,
+    pub targets: Vec<String>,
+    pub isas: Vec<TargetISA>,
+    pub includes: Vec<PathBuf>,
+    pub defines: Vec<(String, Option<String>)>,
+    pub opt_level: u32,
+    pub debug: bool,
+    pub files: Vec<PathBuf>,
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // Cargo passes the name of the subcommand as the first argument
+    if args.first().map(String::as_str) == Some("ispc") {
+        args.remove(0);
+    }
+    let mut args = args.into_iter();
+    match args.next().as_deref() {
+        Some("build") => package::build(&parse_build_options(args)),
+        Some("inspect") => {
+            let dir = args
+                .next()
+                .map_or_else(|| PathBuf::from("ispc"), PathBuf::from);
+            inspect::inspect(&dir);
+        }
+        Some("-h" | "--help" | "help") => print!("{USAGE}"),
+        Some(cmd) => exit_failure!("unknown command {cmd}\n\n{USAGE}"),
+        None => exit_failure!("missing command\n\n{USAGE}"),
+    }
+}
+
+fn parse_build_options(mut args: impl Iterator<Item = String>) -> BuildOptions {
+    let mut opts = BuildOptions {
+        out: PathBuf::from("ispc"),
+        opt_level: 3,
+        ..BuildOptions::default()
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .unwrap_or_else(|| exit_failure!("missing value for {name}"))
+        };
+        match arg.as_str() {
+            "--lib" => opts.lib = value("--lib"),
+            "--out" => opts.out = PathBuf::from(value("--out")),
+            "--target" => opts.targets.push(value("--target")),
+            "--isa" => match value("--isa").parse() {
+                Ok(isa) => opts.isas.push(isa),
+                Err(e) => exit_failure!("{e}"),
+            },
+            "-I" => opts.includes.push(PathBuf::from(value("-I"))),
+            "-D" => {
+                let define = value("-D");
+                opts.defines.push(match define.split_once('=') {
+                    Some((name, v)) => (name.to_owned(), Some(v.to_owned())),
+                    None => (define, None),
+                });
+            }
+            "--opt-level" => {
+                opts.opt_level = value("--opt-level")
+                    .parse()
+                    .unwrap_or_else(|e| exit_failure!("invalid optimization level: {e}"))
+            }
+            "--debug" => opts.debug = true,
+            _ if arg.starts_with('-') => exit_failure!("unknown option {arg}\n\n{USAGE}"),
+            _ => opts.files.push(PathBuf::from(arg)),
+        }
+    }
+    if opts.lib.is_empty() {
+        exit_failure!("missing the name of the library to build, set it with --lib");
+    }
+    if opts.files.is_empty() {
+        exit_failure!("no ISPC source files to build");
+    }
+    if opts.targets.is_empty() {
+        opts.targets.push(host_target());
+    }
+    opts
+}
+
+/// Get the target triple of the host from `rustc`
+fn host_target() -> String {
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = Command::new(rustc)
+        .arg("-vV")
+        .output()
+        .unwrap_or_else(|e| exit_failure!("failed to run rustc to get the host target: {e}"));
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|l| l.strip_prefix("host: "))
+        .map(str::to_owned)
+        .unwrap_or_else(|| exit_failure!("failed to get the host target from rustc"))
+}
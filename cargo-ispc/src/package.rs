@@ -0,0 +1,108 @@
+//! Builds the libraries and bindings of a package for each target and records them in
+//! the package manifest.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use toml::{Table, Value};
+
+use crate::{exit_failure, BuildOptions, MANIFEST};
+
+/// Build the library for each target into the output directory
+pub(crate) fn build(opts: &BuildOptions) {
+    if let Err(e) = fs::create_dir_all(&opts.out) {
+        exit_failure!(
+            "failed to create output directory {}: {e}",
+            opts.out.display()
+        );
+    }
+    let mut ispc_version = None;
+    for target in &opts.targets {
+        eprintln!("Building {} for {target}", opts.lib);
+        // ispc_compile and bindgen read these from the environment of build scripts
+        let build_dir = opts.out.join("build").join(target);
+        if let Err(e) = fs::create_dir_all(&build_dir) {
+            exit_failure!(
+                "failed to create build directory {}: {e}",
+                build_dir.display()
+            );
+        }
+        env::set_var("OUT_DIR", &build_dir);
+        env::set_var("TARGET", target);
+
+        let mut cfg = ispc_compile::Config::new();
+        cfg.target(target)
+            .out_dir(&opts.out)
+            .debug(opts.debug)
+            .opt_level(opts.opt_level)
+            .cargo_metadata(false);
+        for f in &opts.files {
+            cfg.file(f);
+        }
+        for i in &opts.includes {
+            cfg.include_path(i);
+        }
+        for (name, value) in &opts.defines {
+            cfg.add_define(name, value.as_deref());
+        }
+        if !opts.isas.is_empty() {
+            cfg.target_isas(opts.isas.clone());
+        }
+        cfg.compile(&opts.lib);
+        ispc_version = Some(cfg.ispc_version().to_string());
+    }
+    // The objects are only needed while building
+    let _ = fs::remove_dir_all(opts.out.join("build"));
+    if let Some(version) = ispc_version {
+        write_manifest(&opts.out, opts, &version);
+    }
+}
+
+/// Add the library to the manifest of the package, keeping the targets it was
+/// built for before
+fn write_manifest(dir: &Path, opts: &BuildOptions, ispc_version: &str) {
+    let path = dir.join(MANIFEST);
+    let mut manifest = read_manifest(&path).unwrap_or_default();
+    let mut targets: Vec<String> = manifest
+        .get(&opts.lib)
+        .and_then(|lib| lib.get("targets"))
+        .and_then(Value::as_array)
+        .map(|t| {
+            t.iter()
+                .filter_map(Value::as_str)
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default();
+    for t in &opts.targets {
+        if !targets.contains(t) {
+            targets.push(t.clone());
+        }
+    }
+    targets.sort();
+
+    let mut lib = Table::new();
+    lib.insert("ispc_version".into(), ispc_version.into());
+    lib.insert(
+        "targets".into(),
+        Value::Array(targets.into_iter().map(Value::from).collect()),
+    );
+    lib.insert(
+        "isas".into(),
+        Value::Array(opts.isas.iter().map(|i| i.to_string().into()).collect()),
+    );
+    manifest.insert(opts.lib.clone(), Value::Table(lib));
+    if let Err(e) = fs::write(&path, manifest.to_string()) {
+        exit_failure!("failed to write package manifest {}: {e}", path.display());
+    }
+}
+
+/// Read the manifest of a package, if it has one
+pub(crate) fn read_manifest(path: &Path) -> Option<Table> {
+    let contents = fs::read_to_string(path).ok()?;
+    match contents.parse() {
+        Ok(manifest) => Some(manifest),
+        Err(e) => exit_failure!("failed to parse package manifest {}: {e}", path.display()),
+    }
+}
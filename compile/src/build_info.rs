@@ -0,0 +1,82 @@
+//! Generates the `build_info` module of the bindings, which records the ISPC version,
+//! flags, targets and a hash of the sources a library was built with, so the build of
+//! the kernels shipped in a binary can be checked at runtime.
+
+use std::fmt::Write;
+
+use semver::Version;
+
+/// The line the generated module starts with, used to leave it out of bindings snapshots.
+/// The module is documented from within, so it ends at its closing brace.
+pub(crate) const MODULE_START: &str = "pub mod build_info {";
+
+/// A 64-bit FNV-1a hash of the sources, which is stable across Rust versions and platforms
+/// unlike the hashers in `std`
+pub(crate) struct SourceHash(u64);
+
+impl SourceHash {
+    pub(crate) fn new() -> SourceHash {
+        SourceHash(0xcbf2_9ce4_8422_2325)
+    }
+    /// Add the `contents` of a source file to the hash
+    pub(crate) fn update(&mut self, contents: &[u8]) {
+        // Hash the length first so moving text between files changes the hash
+        for b in (contents.len() as u64).to_le_bytes().iter().chain(contents) {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    fn hex(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+/// Get the target ISAs from the `--target` flag in the ISPC `flags`, ISPC builds for
+/// the best ISA of the machine it runs on if there isn't one
+fn target_isas<'a>(flags: &[&'a str]) -> Vec<&'a str> {
+    flags
+        .iter()
+        .find_map(|f| f.strip_prefix("--target="))
+        .map(|t| t.split(',').collect())
+        .unwrap_or_else(|| vec!["host"])
+}
+
+/// Generate the `build_info` module for a library built by `ispc_version` with `flags`
+/// for the Rust `target`
+pub(crate) fn rust_module(
+    ispc_version: &Version,
+    flags: &[String],
+    target: &str,
+    hash: &SourceHash,
+) -> String {
+    let list = |items: &[&str]| {
+        let quoted: Vec<String> = items.iter().map(|i| format!("{i:?}")).collect();
+        quoted.join(", ")
+    };
+    let flags: Vec<&str> = flags.iter().map(|f| f.as_str()).collect();
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{MODULE_START}\n\
+         \x20   //! How the ISPC code in this module was compiled, to check which build of the\n\
+         \x20   //! kernels is running, e.g. when results differ between deployments\n\
+         \x20   /// The version of the ISPC compiler\n\
+         \x20   pub const ISPC_VERSION: &str = {:?};\n\
+         \x20   /// The flags passed to ISPC, without the input and output files\n\
+         \x20   pub const FLAGS: &[&str] = &[{}];\n\
+         \x20   /// The target ISAs compiled for, `host` if ISPC picked the best ISA of the\n\
+         \x20   /// machine it ran on\n\
+         \x20   pub const TARGET_ISAS: &[&str] = &[{}];\n\
+         \x20   /// The Rust target triple the library was built for\n\
+         \x20   pub const TARGET: &str = {target:?};\n\
+         \x20   /// A hash of the ISPC source files and the headers they include\n\
+         \x20   pub const SOURCE_HASH: &str = {:?};\n\
+         }}",
+        ispc_version.to_string(),
+        list(&flags),
+        list(&target_isas(&flags)),
+        hash.hex(),
+    )
+    .unwrap();
+    out
+}
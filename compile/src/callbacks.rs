@@ -0,0 +1,218 @@
+//! Generates the ISPC function pointer types for the callbacks into Rust declared
+//! with `Config::callback`, along with the Rust trampolines ISPC calls through them.
+
+use std::fmt::Write;
+
+use crate::naming::snake_case;
+
+/// ISPC types which can be passed to or returned from a callback, and the matching
+/// Rust types
+const CALLBACK_TYPES: &[(&str, &str)] = &[
+    ("bool", "bool"),
+    ("int8", "i8"),
+    ("uint8", "u8"),
+    ("int16", "i16"),
+    ("uint16", "u16"),
+    ("int", "i32"),
+    ("int32", "i32"),
+    ("uint", "u32"),
+    ("uint32", "u32"),
+    ("int64", "i64"),
+    ("uint64", "u64"),
+    ("float", "f32"),
+    ("double", "f64"),
+    ("void", "::core::ffi::c_void"),
+];
+
+/// A parameter of a callback
+struct Param {
+    name: String,
+    /// The ISPC type of the parameter, without the `uniform` qualifiers
+    ty: String,
+    pointer: bool,
+}
+
+/// A callback signature declared with `Config::callback`
+pub(crate) struct Callback {
+    name: String,
+    ret: Option<String>,
+    params: Vec<Param>,
+}
+
+impl Callback {
+    /// Parse the callback `name` with the ISPC `signature`, e.g. `void(uniform float progress)`
+    pub(crate) fn parse(name: &str, signature: &str) -> Result<Callback, String> {
+        let open = signature
+            .find('(')
+            .ok_or_else(|| format!("signature of {name} must have a parameter list"))?;
+        let params = signature[open + 1..]
+            .trim_end()
+            .strip_suffix(')')
+            .ok_or_else(|| format!("parameter list of {name} must end with `)`"))?;
+        let ret = strip_uniform(&signature[..open]);
+        let ret = match ret.as_str() {
+            "void" => None,
+            r if is_value_type(r) => Some(r.to_owned()),
+            r => {
+                return Err(format!(
+                    "{name} can't return {r}, it must return an atomic type"
+                ))
+            }
+        };
+        let mut callback = Callback {
+            name: name.to_owned(),
+            ret,
+            params: Vec::new(),
+        };
+        for p in params.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if p == "void" {
+                continue;
+            }
+            if p.split_whitespace().any(|w| w == "varying") {
+                return Err(format!(
+                    "parameter `{p}` of {name} must be uniform, callbacks are called with uniform values"
+                ));
+            }
+            // The name follows the last space or `*` of the declaration
+            let split = p
+                .rfind(|c: char| c.is_whitespace() || c == '*')
+                .ok_or_else(|| format!("parameter `{p}` of {name} must be named"))?;
+            let (decl, pname) = (&p[..=split], &p[split + 1..]);
+            let decl = strip_uniform(decl);
+            let (ty, pointer) = match decl.strip_suffix('*') {
+                Some(t) => (t.trim().to_owned(), true),
+                None => (decl, false),
+            };
+            let valid = if pointer {
+                CALLBACK_TYPES.iter().any(|t| t.0 == ty)
+            } else {
+                is_value_type(&ty)
+            };
+            let named = pname.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && pname.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid || !named {
+                return Err(format!(
+                    "parameter `{p}` of {name} must be a named atomic type or pointer to one"
+                ));
+            }
+            callback.params.push(Param {
+                name: pname.to_owned(),
+                ty,
+                pointer,
+            });
+        }
+        Ok(callback)
+    }
+    /// Get the Rust parameter types of the callback
+    fn rust_params(&self) -> Vec<String> {
+        self.params
+            .iter()
+            .map(|p| {
+                let ty = rust_type(&p.ty);
+                if p.pointer {
+                    format!("*mut {ty}")
+                } else {
+                    ty.to_owned()
+                }
+            })
+            .collect()
+    }
+}
+
+/// Generate the ISPC declarations of the function pointer types of the `callbacks`
+pub(crate) fn ispc_declarations(callbacks: &[Callback]) -> String {
+    let mut out = String::new();
+    for c in callbacks {
+        let params: Vec<String> = c
+            .params
+            .iter()
+            .map(|p| {
+                let ptr = if p.pointer { " * uniform" } else { "" };
+                format!("uniform {}{ptr} {}", p.ty, p.name)
+            })
+            .collect();
+        let ret = match c.ret {
+            Some(ref r) => format!("uniform {r}"),
+            None => String::from("void"),
+        };
+        writeln!(
+            out,
+            "typedef {ret} (* uniform {})({});",
+            c.name,
+            params.join(", ")
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Generate the Rust function pointer types of the `callbacks`, their trampolines and
+/// the functions to register the Rust functions they call, using the runtime crate `rt`
+pub(crate) fn rust_definitions(callbacks: &[Callback], rt: &str) -> String {
+    let mut out = String::new();
+    for c in callbacks {
+        let name = &c.name;
+        let snake = snake_case(name);
+        let types = c.rust_params();
+        let decls: Vec<String> = c
+            .params
+            .iter()
+            .zip(types.iter())
+            .map(|(p, ty)| format!("{}: {ty}", p.name))
+            .collect();
+        let names: Vec<&str> = c.params.iter().map(|p| p.name.as_str()).collect();
+        let ret = c
+            .ret
+            .as_ref()
+            .map(|r| format!(" -> {}", rust_type(r)))
+            .unwrap_or_default();
+        let fn_type = format!("dyn Fn({}){ret} + Send + Sync", types.join(", "));
+        writeln!(
+            out,
+            "/// The ISPC callback type `{name}`, see [`register_{snake}`].\n\
+             pub type {name} = ::core::option::Option<unsafe extern \"C\" fn({decls}){ret}>;\n\
+             static {upper}_CALLBACK: {rt}::callback::CallbackSlot<{fn_type}> =\n\
+             \x20   {rt}::callback::CallbackSlot::new(\"{name}\");\n\
+             unsafe extern \"C\" fn {snake}_trampoline({decls}){ret} {{\n\
+             \x20   ({upper}_CALLBACK.get())({names})\n\
+             }}\n\
+             /// Register the Rust function called by ISPC through a `{name}`, replacing the\n\
+             /// one registered before, returns the callback to pass to the ISPC code.\n\
+             pub fn register_{snake}<F>(callback: F) -> {name}\n\
+             where\n\
+             \x20   F: Fn({types}){ret} + Send + Sync + 'static,\n\
+             {{\n\
+             \x20   {upper}_CALLBACK.register(::std::boxed::Box::new(callback));\n\
+             \x20   Some({snake}_trampoline)\n\
+             }}\n\
+             /// Remove the Rust function registered for `{name}`, ISPC code still calling\n\
+             /// the callback aborts the process.\n\
+             pub fn unregister_{snake}() {{\n\
+             \x20   {upper}_CALLBACK.unregister();\n\
+             }}",
+            decls = decls.join(", "),
+            names = names.join(", "),
+            types = types.join(", "),
+            upper = snake.to_ascii_uppercase(),
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Remove the `uniform` qualifiers from the type `ty`
+fn strip_uniform(ty: &str) -> String {
+    let ty = ty.replace('*', " * ");
+    let words: Vec<&str> = ty.split_whitespace().filter(|w| *w != "uniform").collect();
+    words.join(" ").replace(" *", "*")
+}
+
+/// Check if `ty` is an ISPC type which can be passed by value to a callback
+fn is_value_type(ty: &str) -> bool {
+    ty != "void" && CALLBACK_TYPES.iter().any(|t| t.0 == ty)
+}
+
+/// Get the Rust type matching the ISPC type `ty`
+fn rust_type(ty: &str) -> &'static str {
+    CALLBACK_TYPES.iter().find(|t| t.0 == ty).unwrap().1
+}
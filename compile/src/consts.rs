@@ -0,0 +1,360 @@
+//! Collects the numeric constants defined in the ISPC sources, i.e. `#define`s of
+//! numeric literals and `const uniform` globals, to export them as Rust constants.
+
+use std::fmt::Write;
+
+use regex::Regex;
+
+/// A value which can be passed to the ISPC code as a typed constant with
+/// `Config::define_const`, implemented for the integer and floating point primitives
+/// and `bool`.
+pub trait ConstValue {
+    /// The Rust type of the constant generated in the bindings
+    const RUST_TYPE: &'static str;
+    /// The value as an ISPC literal of the same type, `None` if ISPC can't represent it,
+    /// e.g. an infinite float
+    fn ispc_literal(&self) -> Option<String>;
+    /// The value as a C literal, for the C and C++ files compiled into the library
+    fn c_literal(&self) -> Option<String> {
+        self.ispc_literal()
+    }
+    /// The value as a Rust literal
+    fn rust_literal(&self) -> String;
+}
+
+macro_rules! int_const {
+    ($($ty:ty => $suffix:literal),*) => {$(
+        impl ConstValue for $ty {
+            const RUST_TYPE: &'static str = stringify!($ty);
+            fn ispc_literal(&self) -> Option<String> {
+                // Parenthesize negative values so they can be used in any expression
+                if *self < 0 as $ty {
+                    Some(format!("({}{})", self, $suffix))
+                } else {
+                    Some(format!("{}{}", self, $suffix))
+                }
+            }
+            fn rust_literal(&self) -> String {
+                self.to_string()
+            }
+        }
+    )*};
+}
+
+// ISPC and C promote the narrower integers to `int` anyway, so they don't need a suffix
+int_const!(i8 => "", i16 => "", i32 => "", i64 => "ll", u8 => "u", u16 => "u", u32 => "u", u64 => "ull");
+
+impl ConstValue for f32 {
+    const RUST_TYPE: &'static str = "f32";
+    fn ispc_literal(&self) -> Option<String> {
+        self.is_finite().then(|| format!("({self:?}f)"))
+    }
+    fn rust_literal(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+impl ConstValue for f64 {
+    const RUST_TYPE: &'static str = "f64";
+    fn ispc_literal(&self) -> Option<String> {
+        // Float literals without a suffix are `float` in ISPC
+        self.is_finite().then(|| format!("({self:?}d)"))
+    }
+    fn c_literal(&self) -> Option<String> {
+        self.is_finite().then(|| format!("({self:?})"))
+    }
+    fn rust_literal(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+impl ConstValue for bool {
+    const RUST_TYPE: &'static str = "bool";
+    fn ispc_literal(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+    fn c_literal(&self) -> Option<String> {
+        Some(String::from(if *self { "1" } else { "0" }))
+    }
+    fn rust_literal(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// A constant passed to the ISPC code with `Config::define_const`
+pub(crate) struct TypedDefine {
+    pub name: String,
+    pub rust_type: &'static str,
+    pub rust: String,
+    pub ispc: String,
+    pub c: String,
+}
+
+/// A constant found in the ISPC sources
+struct Const {
+    name: String,
+    /// The Rust type of the constant
+    ty: &'static str,
+    value: String,
+}
+
+/// The constants defined in the ISPC sources, in the order they were found
+#[derive(Default)]
+pub(crate) struct Constants {
+    consts: Vec<Const>,
+}
+
+impl Constants {
+    /// Collect the constants defined in the ISPC `source`. `#define`s inside of
+    /// conditional blocks are skipped, as their values may depend on the target.
+    pub(crate) fn parse(&mut self, source: &str) {
+        let define = Regex::new(r"^#\s*define\s+(\w+)\s+(.+)$").unwrap();
+        let global = Regex::new(
+            r"^(?:(?:static|extern)\s+)?(?:const\s+uniform|uniform\s+const)\s+((?:unsigned\s+)?\w+)\s+(\w+)\s*=\s*([^;]+);",
+        )
+        .unwrap();
+        let cond_start = Regex::new(r"^#\s*if").unwrap();
+        let cond_end = Regex::new(r"^#\s*endif").unwrap();
+        let guard = Regex::new(r"^#\s*ifndef\s+(\w+)").unwrap();
+        let guard_define = Regex::new(r"^#\s*define\s+(\w+)\s*$").unwrap();
+
+        let mut depth = 0i32;
+        let mut guard_name = None;
+        for line in source.lines() {
+            let line = line.trim();
+            // Don't count the include guard as a conditional block
+            if let Some(name) = guard_name.take() {
+                if guard_define.captures(line).is_some_and(|c| c[1] == name) {
+                    continue;
+                }
+                depth += 1;
+            }
+            if depth == 0 {
+                if let Some(c) = guard.captures(line) {
+                    guard_name = Some(c[1].to_owned());
+                    continue;
+                }
+            }
+            if cond_start.is_match(line) {
+                depth += 1;
+            } else if cond_end.is_match(line) {
+                depth = (depth - 1).max(0);
+            } else if let Some(c) = define.captures(line) {
+                if depth == 0 {
+                    if let Some((ty, value)) = parse_literal(&c[2], None) {
+                        self.push(&c[1], ty, value);
+                    }
+                }
+            } else if let Some(c) = global.captures(line) {
+                if let Some(ty) = rust_type(&c[1]) {
+                    if let Some((ty, value)) = parse_literal(&c[3], Some(ty)) {
+                        self.push(&c[2], ty, value);
+                    }
+                }
+            }
+        }
+    }
+    /// Add a constant defined through the ISPC flags, which takes precedence over those
+    /// found in the sources
+    pub(crate) fn define(&mut self, define: &TypedDefine) {
+        self.push(&define.name, define.rust_type, define.rust.clone());
+    }
+    /// Generate the Rust constants, skipping those with names already defined in `defined`
+    pub(crate) fn rust_definitions(&self, defined: &str) -> String {
+        let mut out = String::new();
+        for c in self.consts.iter() {
+            let existing = Regex::new(&format!(r"\b(?:const|static)\s+{}\s*:", c.name)).unwrap();
+            if existing.is_match(defined) {
+                continue;
+            }
+            writeln!(out, "pub const {}: {} = {};", c.name, c.ty, c.value).unwrap();
+        }
+        out
+    }
+    fn push(&mut self, name: &str, ty: &'static str, value: String) {
+        if self.consts.iter().any(|c| c.name == name) {
+            return;
+        }
+        self.consts.push(Const {
+            name: name.to_owned(),
+            ty,
+            value,
+        });
+    }
+}
+
+/// Get the Rust type matching the ISPC type `ty`, if it's a numeric type
+fn rust_type(ty: &str) -> Option<&'static str> {
+    let ty = match ty.split_whitespace().collect::<Vec<_>>()[..] {
+        ["unsigned", "int"] | ["unsigned", "int32"] => "u32",
+        ["unsigned", "int8"] => "u8",
+        ["unsigned", "int16"] => "u16",
+        ["unsigned", "int64"] => "u64",
+        ["bool"] => "bool",
+        ["int8"] => "i8",
+        ["uint8"] => "u8",
+        ["int16"] => "i16",
+        ["uint16"] => "u16",
+        ["int" | "int32"] => "i32",
+        ["uint" | "uint32"] => "u32",
+        ["int64"] => "i64",
+        ["uint64"] => "u64",
+        ["float"] => "f32",
+        ["double"] => "f64",
+        _ => return None,
+    };
+    Some(ty)
+}
+
+/// Parse the numeric literal `s`, returns the Rust type and value of the constant.
+/// The type is taken from the literal unless the constant is declared with type `ty`.
+fn parse_literal(s: &str, ty: Option<&'static str>) -> Option<(&'static str, String)> {
+    let mut s = s.trim();
+    // Strip a trailing comment and any parentheses around the value
+    if let Some(i) = s.find("//").or_else(|| s.find("/*")) {
+        s = s[..i].trim();
+    }
+    while let Some(inner) = s.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+        s = inner.trim();
+    }
+    if ty == Some("bool") {
+        return matches!(s, "true" | "false").then(|| ("bool", s.to_owned()));
+    }
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(d) => (true, d.trim_start()),
+        None => (false, s),
+    };
+    let sign = if negative { "-" } else { "" };
+
+    let lower = digits.to_ascii_lowercase();
+    let int = Regex::new(r"^(0x[0-9a-f]+|[0-9]+)(u?l{0,2}|l{0,2}u?)$").unwrap();
+    if let Some(c) = int.captures(&lower) {
+        let value = match c[1].strip_prefix("0x") {
+            Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+            None => c[1].parse::<i128>().ok()?,
+        };
+        let value = if negative { -value } else { value };
+        let ty = ty.unwrap_or_else(|| {
+            let unsigned = c[2].contains('u');
+            let long = c[2].contains('l') || i32::try_from(value).is_err();
+            match (unsigned, long) {
+                (false, false) => "i32",
+                (true, false) => "u32",
+                (false, true) => "i64",
+                (true, true) => "u64",
+            }
+        });
+        let (min, max) = match ty {
+            "f32" | "f64" => return Some((ty, format!("{:?}", value as f64))),
+            "i8" => (i8::MIN as i128, i8::MAX as i128),
+            "u8" => (0, u8::MAX as i128),
+            "i16" => (i16::MIN as i128, i16::MAX as i128),
+            "u16" => (0, u16::MAX as i128),
+            "i32" => (i32::MIN as i128, i32::MAX as i128),
+            "u32" => (0, u32::MAX as i128),
+            "i64" => (i64::MIN as i128, i64::MAX as i128),
+            _ => (0, u64::MAX as i128),
+        };
+        return (min..=max)
+            .contains(&value)
+            .then(|| (ty, value.to_string()));
+    }
+    // ISPC float literals without a suffix are `float`, unlike in C
+    let float = Regex::new(r"^([0-9]*\.?[0-9]*(?:e[+-]?[0-9]+)?)([fd]?)$").unwrap();
+    let c = float.captures(&lower)?;
+    let value = c[1].parse::<f64>().ok()?;
+    let ty = match ty {
+        Some(t @ ("f32" | "f64")) => t,
+        Some(_) => return None,
+        None if &c[2] == "d" => "f64",
+        None => "f32",
+    };
+    Some((ty, format!("{sign}{value:?}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConstValue, Constants};
+
+    fn parse(source: &str) -> String {
+        let mut consts = Constants::default();
+        consts.parse(source);
+        consts.rust_definitions("")
+    }
+
+    #[test]
+    fn parses_defines_and_uniform_globals() {
+        let source = "#ifndef PARAMS_ISPH\n\
+                      #define PARAMS_ISPH\n\
+                      #define TILE_SIZE 16\n\
+                      #define MASK (0xffu) // the low byte\n\
+                      #define SCALE -0.5f\n\
+                      #define EPSILON 1e-6d\n\
+                      #define NAME \"tile\"\n\
+                      const uniform unsigned int8 MAX_DEPTH = 12;\n\
+                      static uniform const float PI = 3.14159;\n\
+                      const uniform bool DEBUG = false;\n\
+                      const varying int LANE = programIndex;\n\
+                      uniform int COUNTER = 0;\n\
+                      #endif\n";
+        assert_eq!(
+            parse(source),
+            "pub const TILE_SIZE: i32 = 16;\n\
+             pub const MASK: u32 = 255;\n\
+             pub const SCALE: f32 = -0.5;\n\
+             pub const EPSILON: f64 = 1e-6;\n\
+             pub const MAX_DEPTH: u8 = 12;\n\
+             pub const PI: f32 = 3.14159;\n\
+             pub const DEBUG: bool = false;\n"
+        );
+    }
+
+    #[test]
+    fn skips_defines_in_conditional_blocks() {
+        let source = "#if TARGET_WIDTH == 16\n\
+                      #define LANES 16\n\
+                      #else\n\
+                      #define LANES 8\n\
+                      #endif\n\
+                      #define ALWAYS 1\n";
+        assert_eq!(parse(source), "pub const ALWAYS: i32 = 1;\n");
+    }
+
+    #[test]
+    fn checks_the_range_of_typed_globals() {
+        let source = "const uniform int8 SMALL = 200;\n\
+                      const uniform uint16 WIDE = 0x1234;\n\
+                      const uniform int64 BIG = 5000000000;\n\
+                      #define LARGE 3000000000\n\
+                      #define HUGE 5000000000ull\n";
+        assert_eq!(
+            parse(source),
+            "pub const WIDE: u16 = 4660;\n\
+             pub const BIG: i64 = 5000000000;\n\
+             pub const LARGE: i64 = 3000000000;\n\
+             pub const HUGE: u64 = 5000000000;\n"
+        );
+    }
+
+    #[test]
+    fn skips_constants_already_in_the_bindings() {
+        let mut consts = Constants::default();
+        consts.parse("#define TILE_SIZE 16\n#define LANES 8\n");
+        let bindings = "pub const TILE_SIZE: u32 = 16;\n";
+        assert_eq!(
+            consts.rust_definitions(bindings),
+            "pub const LANES: i32 = 8;\n"
+        );
+    }
+
+    #[test]
+    fn formats_literals() {
+        assert_eq!((-3i32).ispc_literal().as_deref(), Some("(-3)"));
+        assert_eq!(7u64.ispc_literal().as_deref(), Some("7ull"));
+        assert_eq!(0.5f32.ispc_literal().as_deref(), Some("(0.5f)"));
+        assert_eq!(0.5f64.ispc_literal().as_deref(), Some("(0.5d)"));
+        assert_eq!(0.5f64.c_literal().as_deref(), Some("(0.5)"));
+        assert_eq!(f32::INFINITY.ispc_literal(), None);
+        assert_eq!(true.c_literal().as_deref(), Some("1"));
+    }
+}
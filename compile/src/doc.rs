@@ -0,0 +1,139 @@
+//! Extracts the documentation comments on exported functions and types in the
+//! ISPC source files, so they can be attached to the generated Rust bindings.
+
+use std::collections::HashMap;
+
+use regex::Regex;
+
+/// The documentation comments found in the ISPC sources, by the name of the item
+/// they document.
+#[derive(Default)]
+pub(crate) struct DocComments {
+    docs: HashMap<String, Vec<String>>,
+}
+
+impl DocComments {
+    /// Collect the `///` and `/** */` comments placed directly before `export`
+    /// functions, structs and enums in the ISPC `source`.
+    pub(crate) fn parse(&mut self, source: &str) {
+        let export_fn = Regex::new(r"\bexport\b[^;{(]*?\b(\w+)\s*\(").unwrap();
+        let type_decl = Regex::new(r"^(?:typedef\s+)?(?:struct|enum)\s+(\w+)").unwrap();
+
+        let mut pending: Vec<String> = Vec::new();
+        let mut lines = source.lines();
+        while let Some(line) = lines.next() {
+            let line = line.trim();
+            if let Some(doc) = line.strip_prefix("///") {
+                // A line of four or more slashes is a regular comment
+                if doc.starts_with('/') {
+                    pending.clear();
+                } else {
+                    pending.push(strip_space(doc).to_owned());
+                }
+                continue;
+            }
+            if let Some(doc) = line.strip_prefix("/**") {
+                if doc.starts_with('*') || doc.starts_with('/') {
+                    pending.clear();
+                    continue;
+                }
+                pending = block_comment(doc, &mut lines);
+                continue;
+            }
+            if pending.is_empty() {
+                continue;
+            }
+            // The declaration may be split over multiple lines before its name
+            let mut decl = line.to_owned();
+            while !decl.contains(['(', '{', ';']) {
+                match lines.next() {
+                    Some(l) => {
+                        decl.push(' ');
+                        decl.push_str(l.trim());
+                    }
+                    None => break,
+                }
+            }
+            let name = export_fn
+                .captures(&decl)
+                .or_else(|| type_decl.captures(&decl))
+                .map(|c| c[1].to_owned());
+            let doc = std::mem::take(&mut pending);
+            if let Some(name) = name {
+                self.docs.insert(name, doc);
+            }
+        }
+    }
+    /// Rename the documented items to the names they have in the bindings
+    pub(crate) fn rename<F: Fn(&str) -> String>(&mut self, rename: F) {
+        self.docs = std::mem::take(&mut self.docs)
+            .into_iter()
+            .map(|(name, doc)| (rename(&name), doc))
+            .collect();
+    }
+    /// Add the collected documentation to the items with the same names in the
+    /// `bindings` generated by bindgen.
+    pub(crate) fn apply(&self, bindings: &str) -> String {
+        if self.docs.is_empty() {
+            return bindings.to_owned();
+        }
+        let item = Regex::new(
+            r"(?P<indent>[ \t]*)(?P<attrs>(?:#\s*\[[^\]]*\]\s*)*)(?P<item>pub\s+(?:fn|struct|enum|union|type)\s+(?P<name>\w+))",
+        )
+        .unwrap();
+        item.replace_all(bindings, |c: &regex::Captures| {
+            let indent = &c["indent"];
+            let mut out = String::new();
+            if let Some(doc) = self.docs.get(&c["name"]) {
+                for l in doc {
+                    let l = if l.is_empty() {
+                        l.clone()
+                    } else {
+                        format!(" {l}")
+                    };
+                    out.push_str(&format!("{indent}#[doc = {l:?}]\n"));
+                }
+            }
+            out.push_str(indent);
+            out.push_str(&c["attrs"]);
+            out.push_str(&c["item"]);
+            out
+        })
+        .into_owned()
+    }
+}
+
+/// Strip the single space usually separating a comment marker from its text
+fn strip_space(s: &str) -> &str {
+    s.strip_prefix(' ').unwrap_or(s)
+}
+
+/// Read the rest of a `/** */` comment starting with `first`, returns its lines
+/// without the leading `*` of each line.
+fn block_comment<'a, I: Iterator<Item = &'a str>>(first: &str, lines: &mut I) -> Vec<String> {
+    let mut doc = Vec::new();
+    let mut line = first.to_owned();
+    loop {
+        let (text, done) = match line.find("*/") {
+            Some(end) => (&line[..end], true),
+            None => (&line[..], false),
+        };
+        let text = text.trim();
+        let text = text.strip_prefix('*').map(strip_space).unwrap_or(text);
+        doc.push(text.trim_end().to_owned());
+        if done {
+            break;
+        }
+        match lines.next() {
+            Some(l) => line = l.trim().to_owned(),
+            None => break,
+        }
+    }
+    // Drop the empty lines left by the comment's opening and closing markers
+    while doc.last().is_some_and(|l| l.is_empty()) {
+        doc.pop();
+    }
+    let start = doc.iter().take_while(|l| l.is_empty()).count();
+    doc.drain(..start);
+    doc
+}
@@ -0,0 +1,221 @@
+//! Builds the ISPC code into a shared library loaded at runtime by
+//! `ispc_rt::DynamicModule`, see `Config::shared_library`, and records the commands
+//! building it so the runtime can rebuild it when the sources change.
+
+use std::env;
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Header written at the start of the rebuild file, read by `ispc_rt::dynamic`
+const REBUILD_HEADER: &str = "ispc_rt rebuild v1";
+
+/// The name the struct holding the kernels is given in the bindings
+pub(crate) const KERNELS_STRUCT: &str = "Kernels";
+
+/// C code linked into the library to forward the task system calls of the ISPC code to
+/// the runtime of the process loading it, which passes its entry points when loading it.
+/// Shared libraries can't link against the symbols of the executable on all platforms.
+pub(crate) const TASK_SYSTEM_SHIM: &str = r#"// Generated by ispc_compile, forwards the task system calls of the ISPC code to the
+// ispc_rt runtime of the process loading the library
+#include <stdint.h>
+
+#ifdef _WIN32
+#define ISPC_RT_EXPORT __declspec(dllexport)
+#else
+#define ISPC_RT_EXPORT __attribute__((visibility("default")))
+#endif
+
+typedef void *(*ispc_rt_alloc)(void **, int64_t, int32_t);
+typedef void (*ispc_rt_launch)(void **, void *, void *, int32_t, int32_t, int32_t);
+typedef void (*ispc_rt_sync)(void *);
+
+static ispc_rt_alloc alloc_fn;
+static ispc_rt_launch launch_fn;
+static ispc_rt_sync sync_fn;
+
+ISPC_RT_EXPORT void ispc_rt_set_task_system(ispc_rt_alloc alloc, ispc_rt_launch launch,
+                                            ispc_rt_sync sync) {
+    alloc_fn = alloc;
+    launch_fn = launch;
+    sync_fn = sync;
+}
+
+void *ISPCAlloc(void **handle, int64_t size, int32_t alignment) {
+    return alloc_fn(handle, size, alignment);
+}
+
+void ISPCLaunch(void **handle, void *f, void *data, int32_t count0, int32_t count1,
+                int32_t count2) {
+    launch_fn(handle, f, data, count0, count1, count2);
+}
+
+void ISPCSync(void *handle) {
+    sync_fn(handle);
+}
+"#;
+
+/// Get the file name of the shared library `lib` for the `target`
+pub(crate) fn library_filename(lib: &str, target: &str) -> String {
+    if target.contains("windows") {
+        format!("{lib}.dll")
+    } else if target.contains("apple") {
+        format!("lib{lib}.dylib")
+    } else {
+        format!("lib{lib}.so")
+    }
+}
+
+/// Get the path of the rebuild file of the shared `library`
+pub(crate) fn rebuild_path(library: &Path) -> PathBuf {
+    library.with_extension("rebuild")
+}
+
+/// Make the bindings generated by bindgen for a shared library refer to `libloading`
+/// through the runtime crate `rt`, and implement `KernelLibrary` for the kernels
+pub(crate) fn kernels_bindings(bindings: &str, rt: &str) -> String {
+    let mut out = bindings.replace("::libloading::", &format!("{rt}::libloading::"));
+    writeln!(
+        out,
+        "impl {rt}::dynamic::KernelLibrary for {KERNELS_STRUCT} {{\n\
+         \x20   unsafe fn from_library(\n\
+         \x20       library: {rt}::libloading::Library,\n\
+         \x20   ) -> Result<Self, {rt}::libloading::Error> {{\n\
+         \x20       unsafe {{ {KERNELS_STRUCT}::from_library(library) }}\n\
+         \x20   }}\n\
+         }}"
+    )
+    .unwrap();
+    out
+}
+
+/// The commands which build a shared library and the files it's built from, written
+/// for `DynamicModule::watch` to rebuild the library
+#[derive(Default)]
+pub(crate) struct Rebuild {
+    text: String,
+}
+
+impl Rebuild {
+    /// Watch the file at `path` for changes
+    pub(crate) fn watch(&mut self, path: &Path) {
+        let path = match env::current_dir() {
+            Ok(dir) => dir.join(path),
+            Err(_) => path.to_path_buf(),
+        };
+        self.line("watch", path.as_os_str());
+    }
+    /// Run the `command` to rebuild the library, in the directory it's run in now
+    pub(crate) fn command(&mut self, command: &Command) {
+        self.line("run", command.get_program());
+        match command.get_current_dir() {
+            Some(dir) => self.line("dir", dir.as_os_str()),
+            None => {
+                if let Ok(dir) = env::current_dir() {
+                    self.line("dir", dir.as_os_str());
+                }
+            }
+        }
+        for (key, value) in command.get_envs() {
+            if let Some(value) = value {
+                let mut env = key.to_owned();
+                env.push("=");
+                env.push(value);
+                self.line("env", &env);
+            }
+        }
+        for arg in command.get_args() {
+            self.line("arg", arg);
+        }
+    }
+    /// Get the contents of the rebuild file
+    pub(crate) fn contents(&self) -> String {
+        format!("{REBUILD_HEADER}\n{}", self.text)
+    }
+    fn line(&mut self, key: &str, value: &OsStr) {
+        writeln!(self.text, "{key} {}", value.to_string_lossy()).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{kernels_bindings, library_filename, Rebuild};
+    use std::env;
+    use std::path::Path;
+    use std::process::Command;
+
+    #[test]
+    fn names_libraries_for_the_target() {
+        assert_eq!(
+            library_filename("simple", "x86_64-unknown-linux-gnu"),
+            "libsimple.so"
+        );
+        assert_eq!(
+            library_filename("simple", "aarch64-apple-darwin"),
+            "libsimple.dylib"
+        );
+        assert_eq!(
+            library_filename("simple", "x86_64-pc-windows-msvc"),
+            "simple.dll"
+        );
+    }
+
+    #[test]
+    fn records_the_rebuild_commands() {
+        let mut rebuild = Rebuild::default();
+        rebuild.watch(Path::new("/src/simple.ispc"));
+        let mut ispc = Command::new("ispc");
+        ispc.current_dir("/src")
+            .env("ISPC_HOME", "/opt/ispc")
+            .arg("simple.ispc")
+            .arg("-o")
+            .arg("/out/simple ispc.o");
+        rebuild.command(&ispc);
+        assert_eq!(
+            rebuild.contents(),
+            "ispc_rt rebuild v1\n\
+             watch /src/simple.ispc\n\
+             run ispc\n\
+             dir /src\n\
+             env ISPC_HOME=/opt/ispc\n\
+             arg simple.ispc\n\
+             arg -o\n\
+             arg /out/simple ispc.o\n"
+        );
+    }
+
+    #[test]
+    fn records_paths_relative_to_the_build_script() {
+        let mut rebuild = Rebuild::default();
+        rebuild.watch(Path::new("src/simple.ispc"));
+        rebuild.command(&Command::new("cc"));
+        let dir = env::current_dir().unwrap();
+        assert_eq!(
+            rebuild.contents(),
+            format!(
+                "ispc_rt rebuild v1\n\
+                 watch {}\n\
+                 run cc\n\
+                 dir {}\n",
+                dir.join("src/simple.ispc").display(),
+                dir.display()
+            )
+        );
+    }
+
+    #[test]
+    fn implements_kernel_library() {
+        let bindings = "pub struct Kernels {\n\
+                        \x20   __library: ::libloading::Library,\n\
+                        }\n";
+        let out = kernels_bindings(bindings, "ispc_rt");
+        assert!(out.starts_with(
+            "pub struct Kernels {\n\
+             \x20   __library: ispc_rt::libloading::Library,\n\
+             }\n"
+        ));
+        assert!(out.contains("impl ispc_rt::dynamic::KernelLibrary for Kernels {"));
+        assert!(out.contains("unsafe { Kernels::from_library(library) }"));
+    }
+}
@@ -0,0 +1,126 @@
+//! Checks the signatures of the functions exported from the ISPC sources for
+//! constructs the generated bindings can't represent, to fail the build with a
+//! message pointing at the function instead of producing wrong bindings.
+
+use regex::Regex;
+
+use crate::wrappers::matching_paren;
+
+/// ISPC atomic types, which are varying unless qualified as uniform
+const ATOMIC_TYPES: &[&str] = &[
+    "bool", "int", "int8", "int16", "int32", "int64", "uint", "uint8", "uint16", "uint32",
+    "uint64", "unsigned", "signed", "float", "double", "float16",
+];
+
+/// Check the functions exported from the ISPC `source`, returns a message for each
+/// unsupported construct found
+pub(crate) fn check_exports(source: &str) -> Vec<String> {
+    let source = strip_comments(source);
+    let export = Regex::new(r"\bexport\s+([^;{}()]*?)\b(\w+)\s*\(").unwrap();
+    let mut errors = Vec::new();
+    for c in export.captures_iter(&source) {
+        let name = &c[2];
+        let start = c.get(0).unwrap().end();
+        let params = match matching_paren(&source[start..]) {
+            Some(end) => &source[start..start + end],
+            None => continue,
+        };
+        if is_varying(&c[1]) {
+            errors.push(format!(
+                "{name} returns a varying value, exported functions can only return uniform values"
+            ));
+        }
+        for param in params.split(',').map(str::trim) {
+            if param.is_empty() || param == "void" {
+                continue;
+            }
+            let param_name = param
+                .rsplit(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '[' || c == ']'))
+                .next()
+                .and_then(|p| p.split('[').next())
+                .unwrap_or(param);
+            if param.contains('&') {
+                errors.push(format!(
+                    "{name}: parameter `{param_name}` is a reference, which the C bindings can't \
+                     represent, pass a pointer instead"
+                ));
+            } else if is_varying(param) {
+                errors.push(format!(
+                    "{name}: parameter `{param_name}` is varying, parameters of exported functions \
+                     must be uniform"
+                ));
+            }
+            // Only the first dimension of an array parameter can be unsized, as it's
+            // passed as a pointer to the array elements
+            let dims: Vec<&str> = param.split('[').skip(1).collect();
+            if dims.iter().skip(1).any(|d| d.trim_start().starts_with(']')) {
+                errors.push(format!(
+                    "{name}: parameter `{param_name}` is an array with an unsized inner dimension, \
+                     only the first dimension of an array parameter can be unsized"
+                ));
+            }
+        }
+    }
+    errors
+}
+
+/// Check if the declaration `decl` is explicitly varying, or has an atomic type which
+/// is varying as it isn't qualified as uniform
+fn is_varying(decl: &str) -> bool {
+    let words: Vec<&str> = decl
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .filter(|w| !w.is_empty())
+        .collect();
+    if words.contains(&"varying") {
+        return true;
+    }
+    let atomic = words.iter().any(|w| ATOMIC_TYPES.contains(w));
+    atomic && !words.contains(&"uniform") && !decl.contains(['*', '['])
+}
+
+/// Remove the comments from the ISPC `source`
+fn strip_comments(source: &str) -> String {
+    let comments = Regex::new(r"(?s)//[^\n]*|/\*.*?\*/").unwrap();
+    comments.replace_all(source, " ").into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_exports;
+
+    #[test]
+    fn accepts_uniform_pointers_and_arrays() {
+        let source = "export void scale(const uniform Params * uniform params,\n\
+                      uniform float vals[], uniform int count) {}";
+        assert!(check_exports(source).is_empty());
+    }
+
+    #[test]
+    fn rejects_references() {
+        let errors = check_exports("export void scale(const uniform Params &params) {}");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("`params` is a reference"));
+    }
+
+    #[test]
+    fn rejects_varying_parameters_and_returns() {
+        let errors = check_exports("export float sum(float x, varying int y) {}");
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].contains("sum returns a varying value"));
+        assert!(errors[1].contains("`x` is varying"));
+        assert!(errors[2].contains("`y` is varying"));
+    }
+
+    #[test]
+    fn rejects_unsized_inner_dimensions() {
+        let errors = check_exports("export void f(uniform float m[][]) {}");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("unsized inner dimension"));
+    }
+
+    #[test]
+    fn ignores_comments() {
+        let source = "// export void f(float &x) {}\n/* export void g(float y) {} */";
+        assert!(check_exports(source).is_empty());
+    }
+}
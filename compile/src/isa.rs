@@ -0,0 +1,82 @@
+//! Generates bindings to the entry points ISPC compiles for each target ISA of a
+//! multi-target library. The dispatcher ISPC links next to them calls the one for the
+//! best ISA the CPU supports, these bindings call a given one directly, e.g. to
+//! benchmark the ISAs against each other. A library compiled for a single ISA has no
+//! dispatcher, its functions are the entry points of that ISA.
+
+use std::fmt::Write;
+
+use crate::opt::TargetISA;
+use crate::wrappers::ExternFn;
+
+/// Generate the `isa` module with a module of entry points for each of the `isas`, and a
+/// function for each exported function to look up its entry point for an ISA by name.
+/// The `fns` are paired with the names of the symbols ISPC exports them as, and declared
+/// with the `abi` of the bindings.
+pub(crate) fn isa_entry_points(fns: &[(&ExternFn, &str)], isas: &[TargetISA], abi: &str) -> String {
+    let names: Vec<String> = isas.iter().map(|isa| isa.lib_suffix()).collect();
+    let suffixed = isas.len() > 1;
+    let mut out = String::new();
+    out.push_str(
+        "\n/// The entry points compiled for each target ISA, which call the ISA directly instead\n\
+         /// of going through the dispatcher picking the best one the CPU supports\n\
+         pub mod isa {\n    #[allow(unused_imports)]\n    use super::*;\n\n",
+    );
+    let quoted: Vec<String> = names.iter().map(|n| format!("{n:?}")).collect();
+    writeln!(
+        out,
+        "    /// The names of the target ISAs the library was compiled for\n    \
+         pub const ALL: &[&str] = &[{}];",
+        quoted.join(", ")
+    )
+    .unwrap();
+    for name in &names {
+        writeln!(
+            out,
+            "\n    /// The entry points compiled for the {name} target ISA, which must only be called\n    \
+             /// if the CPU supports it\n    \
+             pub mod {name} {{\n        \
+             #[allow(unused_imports)]\n        \
+             use super::super::*;\n\n        \
+             unsafe extern {abi:?} {{"
+        )
+        .unwrap();
+        for (f, symbol) in fns {
+            let ret = f.ret.as_ref().map_or(String::new(), |r| format!(" -> {r}"));
+            let symbol = if suffixed {
+                format!("{symbol}_{name}")
+            } else {
+                symbol.to_string()
+            };
+            writeln!(
+                out,
+                "            #[link_name = \"{symbol}\"]\n            \
+                 pub fn {}({}){ret};",
+                f.name,
+                f.param_decls()
+            )
+            .unwrap();
+        }
+        out.push_str("        }\n    }\n");
+    }
+    for (f, _) in fns {
+        let types: Vec<&str> = f.params.iter().map(|(_, ty)| ty.as_str()).collect();
+        let ret = f.ret.as_ref().map_or(String::new(), |r| format!(" -> {r}"));
+        writeln!(
+            out,
+            "\n    /// Get the entry point of `{0}` compiled for the target ISA `isa`, if the\n    \
+             /// library was compiled for it\n    \
+             pub fn {0}(isa: &str) -> Option<unsafe extern {abi:?} fn({1}){ret}> {{\n        \
+             match isa {{",
+            f.name,
+            types.join(", ")
+        )
+        .unwrap();
+        for name in &names {
+            writeln!(out, "            {name:?} => Some({name}::{}),", f.name).unwrap();
+        }
+        out.push_str("            _ => None,\n        }\n    }\n");
+    }
+    out.push_str("}\n");
+    out
+}
@@ -19,15 +19,74 @@
 //! and Clang link with MSVC on Windows. For bindgen to find libclang you'll need to copy
 //! `libclang.lib` to `clang.lib` and place it in your path.
 //!
+//! Windows on ARM, `aarch64-pc-windows-msvc`, is built with the NEON target ISA by default.
+//! The library is archived with the ARM64 `lib.exe` of Visual Studio, or `llvm-lib` if it
+//! isn't installed, which can also be selected with `AR_aarch64-pc-windows-msvc`.
+//!
+//! ## Android
+//!
+//! When building for an Android target, e.g. `aarch64-linux-android` or
+//! `armv7-linux-androideabi`, the NEON target ISA and `--target-os=android` are selected by
+//! default, and the library is archived with the `llvm-ar` of the NDK found through the
+//! `ANDROID_NDK_HOME` environment variable. The archiver can also be set with `AR_<target>`.
+//!
+//! ## Apple Silicon
+//!
+//! When building for `aarch64-apple-darwin` or another ARM target, the NEON target ISA is
+//! selected by default and any x86 ISAs passed to `target_isas` are skipped with a warning,
+//! as are NEON ISAs when building for x86, so one list of ISAs can be used for all
+//! architectures. If the build runs as an x86-64
+//! process under Rosetta a warning suggests installing the native toolchain.
+//!
+//! ## BSD
+//!
+//! Building for FreeBSD, NetBSD or OpenBSD targets passes `--target-os=freebsd` to ISPC,
+//! whose ELF objects link on all three, and archives the library with the `ar` of the
+//! system or the one set with `AR_<target>`. ISPC doesn't ship binaries for NetBSD or
+//! OpenBSD, so the library has to be cross compiled or built on another host and linked
+//! with `ispc_rt::PackagedModule`.
+//!
+//! ## RISC-V
+//!
+//! Building for `riscv64` targets passes `--arch=riscv64` to ISPC, for ISPC versions which
+//! support RISC-V with the vector extension. If the ISPC in your path doesn't list it in
+//! its `--support-matrix` the build fails with an error saying so.
+//!
+//! ## iOS and tvOS
+//!
+//! Building for `aarch64-apple-ios`, the simulator triples such as `aarch64-apple-ios-sim`
+//! and `x86_64-apple-ios`, or the tvOS triples passes `--target-os=ios` to ISPC. The objects
+//! are then marked with the platform and deployment target of the Rust target using Xcode's
+//! `vtool`, taking the version from `IPHONEOS_DEPLOYMENT_TARGET` or `TVOS_DEPLOYMENT_TARGET`
+//! like `rustc` does, so the library links into apps built with Xcode.
+//!
 
+mod build_info;
+mod callbacks;
+mod consts;
+mod doc;
+mod dynamic;
+mod exports;
+mod isa;
+mod naming;
 pub mod opt;
+mod perf;
+mod platform;
+mod pod;
+mod shared;
+pub mod signatures;
+mod snapshot;
+mod test_kernels;
+mod vectors;
+mod wrappers;
 
 pub use bindgen;
+pub use cc;
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashSet};
 use std::env;
 use std::fmt::Display;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus};
@@ -35,8 +94,19 @@ use std::process::{Command, ExitStatus};
 use regex::Regex;
 use semver::{BuildMetadata, Prerelease, Version};
 
+use crate::build_info::SourceHash;
+use crate::callbacks::Callback;
+use crate::consts::{Constants, TypedDefine};
+use crate::doc::DocComments;
+use crate::dynamic::Rebuild;
+use crate::naming::{FileFunctions, Naming, Renames};
+use crate::shared::SharedTypes;
+use crate::signatures::Signatures;
+use crate::vectors::VectorMappings;
+
+pub use crate::consts::ConstValue;
 pub use crate::opt::{
-    Addressing, Architecture, MathLib, OptimizationOpt, TargetISA, TargetOS, CPU,
+    Addressing, Architecture, MathLib, OptimizationOpt, Sanitizer, TargetISA, TargetOS, CPU,
 };
 
 /// Compile the list of ISPC files into a static library and generate bindings
@@ -61,6 +131,27 @@ pub fn compile_library(lib: &str, files: &[&str]) {
     cfg.compile(lib)
 }
 
+/// Get the target ISAs the `ispc` compiler in the path supports, from the list of ISAs
+/// in its `--help`. ISAs which aren't in `TargetISA` yet are left out, and the list is
+/// empty if `ispc` couldn't be run.
+///
+/// # Example
+/// ```no_run
+/// use ispc_compile::TargetISA;
+///
+/// let mut cfg = ispc_compile::Config::new();
+/// if ispc_compile::supported_targets().contains(&TargetISA::AVX512SPRx16) {
+///     cfg.target_isas(vec![TargetISA::AVX2i32x8, TargetISA::AVX512SPRx16]);
+/// }
+/// ```
+pub fn supported_targets() -> Vec<TargetISA> {
+    Command::new("ispc")
+        .arg("--help")
+        .output()
+        .map(|out| platform::help_targets(&String::from_utf8_lossy(&out.stdout)))
+        .unwrap_or_default()
+}
+
 /// Handy wrapper around calling exit that will log the message passed first
 /// then exit with a failure exit code.
 macro_rules! exit_failure {
@@ -74,11 +165,106 @@ macro_rules! exit_failure {
     }}
 }
 
+/// Compile the ISPC snippets of the `ispc_rt::test_kernel!` invocations in the Rust files
+/// under `dir`, e.g. `tests/`, each into its own library named after the invocation, so
+/// tests can call the kernels they define inline. See `compile_test_kernels_with`.
+///
+/// # Example
+/// ```no_run
+/// // build.rs
+/// ispc_compile::compile_test_kernels("tests/");
+/// ```
+pub fn compile_test_kernels<P: AsRef<Path>>(dir: P) {
+    compile_test_kernels_with(dir, |_| {});
+}
+
+/// Compile the ISPC snippets of the `ispc_rt::test_kernel!` invocations in the Rust files
+/// under `dir` as in `compile_test_kernels`, calling `configure` on the `Config` of each
+/// snippet to set the options it's compiled with, e.g. the target ISA.
+///
+/// The snippets are written to `OUT_DIR` and compiled by the build script, so the tests
+/// are rebuilt when their kernels change, and the libraries are linked with all targets
+/// of the crate. Each snippet is a separate library, so its name must be unique in the
+/// crate and can't be used by another library.
+///
+/// # Example
+/// ```no_run
+/// // build.rs
+/// use ispc_compile::TargetISA;
+///
+/// ispc_compile::compile_test_kernels_with("tests/", |cfg| {
+///     cfg.target_isa(TargetISA::SSE2i32x4);
+/// });
+/// ```
+pub fn compile_test_kernels_with<P, F>(dir: P, configure: F)
+where
+    P: AsRef<Path>,
+    F: Fn(&mut Config),
+{
+    let dir = dir.as_ref();
+    println!("cargo:rerun-if-changed={}", dir.display());
+    let mut sources = Vec::new();
+    find_rust_files(dir, &mut sources);
+    let snippet_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("test_kernels");
+    if let Err(e) = fs::create_dir_all(&snippet_dir) {
+        exit_failure!("Failed to create test kernel directory: {}", e);
+    }
+    let mut names = HashSet::new();
+    for file in &sources {
+        println!("cargo:rerun-if-changed={}", file.display());
+        let Ok(source) = fs::read_to_string(file) else {
+            continue;
+        };
+        for kernel in test_kernels::find_test_kernels(&source) {
+            if !names.insert(kernel.name.clone()) {
+                exit_failure!(
+                    "Test kernel {} in {} has the same name as another test kernel",
+                    kernel.name,
+                    file.display()
+                );
+            }
+            let ispc_file = snippet_dir.join(&kernel.name).with_extension("ispc");
+            // Keep the snippet's modification time if it didn't change
+            if fs::read_to_string(&ispc_file).ok().as_ref() != Some(&kernel.source) {
+                if let Err(e) = fs::write(&ispc_file, &kernel.source) {
+                    exit_failure!("Failed to write test kernel {}: {}", kernel.name, e);
+                }
+            }
+            let mut cfg = Config::new();
+            cfg.file(&ispc_file);
+            configure(&mut cfg);
+            cfg.compile(&kernel.name);
+        }
+    }
+}
+
+/// Collect the Rust source files in `dir` and its subdirectories
+fn find_rust_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        exit_failure!("Failed to read test kernel directory {}", dir.display());
+    };
+    let mut entries: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_rust_files(&path, files);
+        } else if path.extension().is_some_and(|e| e == "rs") {
+            files.push(path);
+        }
+    }
+}
+
 /// Extra configuration to be passed to ISPC
 pub struct Config {
     ispc_version: Version,
     ispc_files: Vec<PathBuf>,
+    source_strs: Vec<(String, String)>,
+    c_files: Vec<PathBuf>,
     include_paths: Vec<PathBuf>,
+    generated_headers: Vec<(String, String)>,
+    shared_types: Vec<PathBuf>,
+    callbacks: Vec<(String, String)>,
+    header_dir: Option<PathBuf>,
     // These options are set from the environment if not set by the user
     out_dir: Option<PathBuf>,
     debug: Option<bool>,
@@ -87,6 +273,7 @@ pub struct Config {
     cargo_metadata: bool,
     // Additional ISPC compiler options that the user can set
     defines: Vec<(String, Option<String>)>,
+    typed_defines: Vec<TypedDefine>,
     math_lib: MathLib,
     addressing: Option<Addressing>,
     optimization_opts: BTreeSet<OptimizationOpt>,
@@ -95,16 +282,41 @@ pub struct Config {
     no_omit_frame_ptr: bool,
     no_stdlib: bool,
     no_cpp: bool,
+    no_std_bindings: bool,
+    shared_library: bool,
+    async_wrappers: bool,
+    kernel_registry: bool,
+    call_builders: Option<usize>,
+    naming: Naming,
+    result_wrappers: Vec<String>,
+    export_only: Vec<String>,
+    skip_exports: Vec<String>,
+    error_mapping: Option<String>,
+    handle_prefixes: Option<(String, String)>,
+    isa_entry_points: bool,
+    bindings_snapshot: Option<PathBuf>,
+    vector_mappings: VectorMappings,
+    float16_half: bool,
+    bytemuck_derives: bool,
+    runtime_crate: String,
     quiet: bool,
     werror: bool,
     woff: bool,
     wno_perf: bool,
+    perf_report: Option<PathBuf>,
+    perf_warnings: bool,
     instrument: bool,
+    route_print: bool,
+    route_asserts: bool,
+    vectorcall: bool,
     enable_llvm_intrinsics: bool,
+    sanitizer: Option<Sanitizer>,
     target_isa: Option<Vec<TargetISA>>,
+    auto_targets: bool,
     architecture: Option<Architecture>,
     target_os: Option<TargetOS>,
     bindgen_builder: bindgen::Builder,
+    c_builder: cc::Build,
 }
 
 impl Config {
@@ -134,13 +346,20 @@ impl Config {
         Config {
             ispc_version: ispc_ver,
             ispc_files: Vec::new(),
+            source_strs: Vec::new(),
+            c_files: Vec::new(),
             include_paths: Vec::new(),
+            generated_headers: Vec::new(),
+            shared_types: Vec::new(),
+            callbacks: Vec::new(),
+            header_dir: None,
             out_dir: None,
             debug: None,
             opt_level: None,
             target: None,
             cargo_metadata: true,
             defines: Vec::new(),
+            typed_defines: Vec::new(),
             math_lib: MathLib::ISPCDefault,
             addressing: None,
             optimization_opts: BTreeSet::new(),
@@ -149,16 +368,41 @@ impl Config {
             no_omit_frame_ptr: false,
             no_stdlib: false,
             no_cpp: false,
+            no_std_bindings: false,
+            shared_library: false,
+            async_wrappers: false,
+            kernel_registry: false,
+            call_builders: None,
+            naming: Naming::default(),
+            result_wrappers: Vec::new(),
+            export_only: Vec::new(),
+            skip_exports: Vec::new(),
+            error_mapping: None,
+            handle_prefixes: None,
+            isa_entry_points: false,
+            bindings_snapshot: None,
+            vector_mappings: VectorMappings::default(),
+            float16_half: false,
+            bytemuck_derives: false,
+            runtime_crate: String::from("ispc_rt"),
             quiet: false,
             werror: false,
             woff: false,
             wno_perf: false,
+            perf_report: None,
+            perf_warnings: true,
             instrument: false,
+            route_print: false,
+            route_asserts: false,
+            vectorcall: false,
             enable_llvm_intrinsics: false,
+            sanitizer: None,
             target_isa: None,
+            auto_targets: false,
             architecture: None,
             target_os: None,
             bindgen_builder: Default::default(),
+            c_builder: cc::Build::new(),
         }
     }
     /// Add an ISPC file to be compiled
@@ -166,6 +410,47 @@ impl Config {
         self.ispc_files.push(file.as_ref().to_path_buf());
         self
     }
+    /// Add ISPC source `code` generated by the build script to be compiled as the file
+    /// `name`, e.g. `blur.ispc`, which names its header and module like for files added
+    /// with `file`. The code is written to the build directory of the library when it's
+    /// compiled, and only rewritten if it changed so the library isn't rebuilt needlessly.
+    ///
+    /// Headers included by the code are searched for in the include paths and the
+    /// generated headers, as there's no directory of its own to search.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let taps = 5;
+    /// let code = format!(
+    ///     "export void blur(uniform float src[], uniform float dst[], uniform int n) {{\n\
+    ///          foreach (i = {r} ... n - {r}) {{\n\
+    ///              float sum = 0;\n\
+    ///              for (uniform int t = -{r}; t <= {r}; t++) sum += src[i + t];\n\
+    ///              dst[i] = sum / {taps};\n\
+    ///          }}\n\
+    ///      }}\n",
+    ///     r = taps / 2
+    /// );
+    /// ispc_compile::Config::new()
+    ///     .add_source_str("blur.ispc", &code)
+    ///     .compile("blur");
+    /// ```
+    pub fn add_source_str(&mut self, name: &str, code: &str) -> &mut Config {
+        self.source_strs.push((name.to_owned(), code.to_owned()));
+        self
+    }
+    /// Add a C or C++ file to be compiled with the `cc` crate and archived into the library
+    /// along with the ISPC code, e.g. glue code loading the data passed to the kernels.
+    /// Files ending in `.cpp`, `.cc` or `.cxx` are compiled as C++ and the C++ standard
+    /// library is linked, which can be changed through `CXXSTDLIB` like for `cc`.
+    ///
+    /// The files are compiled with the defines and include paths of the ISPC files, and can
+    /// include the headers ISPC generates for them, e.g. `foo_ispc.h` for `foo.ispc`, to call
+    /// the exported functions.
+    pub fn c_file<P: AsRef<Path>>(&mut self, file: P) -> &mut Config {
+        self.c_files.push(file.as_ref().to_path_buf());
+        self
+    }
     /// Set the output directory to override the default of `env!("OUT_DIR")`
     pub fn out_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Config {
         self.out_dir = Some(dir.as_ref().to_path_buf());
@@ -194,6 +479,28 @@ impl Config {
             .push((define.to_string(), value.map(|s| s.to_string())));
         self
     }
+    /// Define a typed constant for the ISPC code, e.g. `define_const("MAX_LIGHTS", 8u32)`,
+    /// which is passed as a define, `-DMAX_LIGHTS=8u`, and exported from the bindings as
+    /// `pub const MAX_LIGHTS: u32 = 8;` so the Rust code and the kernels share one value.
+    ///
+    /// The define is a literal of the matching ISPC type, e.g. `64ll` for an `i64` or
+    /// `(0.5d)` for an `f64`. The C and C++ files compiled into the library get it too.
+    pub fn define_const<T: ConstValue>(&mut self, name: &str, value: T) -> &mut Config {
+        let (Some(ispc), Some(c)) = (value.ispc_literal(), value.c_literal()) else {
+            exit_failure!(
+                "Can't define the ISPC constant {name} as {}",
+                value.rust_literal()
+            );
+        };
+        self.typed_defines.push(TypedDefine {
+            name: name.to_owned(),
+            rust_type: T::RUST_TYPE,
+            rust: value.rust_literal(),
+            ispc,
+            c,
+        });
+        self
+    }
     /// Select the 32 or 64 bit addressing calculations for addressing calculations in ISPC.
     pub fn addressing(&mut self, addressing: Addressing) -> &mut Config {
         self.addressing = Some(addressing);
@@ -225,6 +532,269 @@ impl Config {
         self.include_paths.push(path.as_ref().to_path_buf());
         self
     }
+    /// Generate a header `name` holding the ISPC `declarations` for the ISPC code
+    /// to include, e.g. the declarations of structs shared with Rust from
+    /// `IspcStruct::ispc_declaration` in `ispc_rt`. The header is written to the
+    /// build directory, which is added to the include path.
+    pub fn generated_header(&mut self, name: &str, declarations: &[String]) -> &mut Config {
+        self.generated_headers
+            .push((name.to_owned(), declarations.join("\n")));
+        self
+    }
+    /// Also install a C/C++ header declaring the functions exported from the library
+    /// to `dir`, so C and C++ code can call the same kernels. The header is named after
+    /// the library, e.g. `example.h` for `compile("example")`, and combines the headers
+    /// ISPC generates for each source file.
+    ///
+    /// The header declares the functions with the names they're exported with from
+    /// ISPC, the naming options for the Rust bindings don't apply to it.
+    pub fn header_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Config {
+        self.header_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+    /// Generate the ISPC and Rust definitions of the constants, enums and structs
+    /// shared between them from the TOML description at `path`, so the two can't
+    /// diverge.
+    ///
+    /// The ISPC definitions are written to a header with the same name as the
+    /// description, e.g. `types.isph` for `types.toml`, for the ISPC code to include.
+    /// The Rust definitions are placed in the generated bindings module, which uses
+    /// them in place of the ones bindgen would generate.
+    ///
+    /// The description lists the constants, enums and structs in the order they're
+    /// declared in, so types must be listed before the structs using them. Fields can
+    /// have the ISPC atomic types, the types declared before them, or be pointers to
+    /// these types by adding a `*` to the type.
+    ///
+    /// ```toml
+    /// [[const]]
+    /// name = "MAX_LIGHTS"
+    /// type = "int32"
+    /// value = 8
+    ///
+    /// [[enum]]
+    /// name = "LightKind"
+    /// variants = ["Point", "Spot", { name = "Area", value = 4 }]
+    ///
+    /// [[struct]]
+    /// name = "Light"
+    /// fields = [
+    ///     { name = "kind", type = "LightKind" },
+    ///     { name = "position", type = "float", count = 3 },
+    ///     { name = "intensity", type = "float" },
+    /// ]
+    ///
+    /// [[struct]]
+    /// name = "Lights"
+    /// fields = [
+    ///     { name = "lights", type = "Light", count = "MAX_LIGHTS" },
+    ///     { name = "num_lights", type = "int32" },
+    ///     { name = "shadow_map", type = "float *" },
+    /// ]
+    /// ```
+    pub fn shared_types<P: AsRef<Path>>(&mut self, path: P) -> &mut Config {
+        self.shared_types.push(path.as_ref().to_path_buf());
+        self
+    }
+    /// Declare a callback type `name` with the ISPC `signature`, which ISPC code can
+    /// call to run a Rust function, e.g. to report its progress. The signature takes
+    /// uniform atomic types or pointers to them, e.g. `void(uniform float progress)` or
+    /// `uniform float(void * uniform user, uniform float u, uniform float v)`.
+    ///
+    /// The ISPC function pointer types of the callbacks are declared in the generated
+    /// header `callbacks.isph`. The bindings define the matching Rust type along with a
+    /// `register_{name}` function, in snake case, which registers the Rust function to
+    /// call and returns the callback to pass to the ISPC code, see `ispc_rt::callback`.
+    /// Callbacks need std and can't be used with `no_std_bindings`.
+    pub fn callback(&mut self, name: &str, signature: &str) -> &mut Config {
+        self.callbacks.push((name.to_owned(), signature.to_owned()));
+        self
+    }
+    /// Generate an async version of each exported function, `foo_async` for `foo`,
+    /// which runs the function on a blocking thread and returns a future resolving
+    /// once it returns, see `ispc_rt::future`. Needs std and can't be used with
+    /// `no_std_bindings`.
+    pub fn async_wrappers(&mut self) -> &mut Config {
+        self.async_wrappers = true;
+        self
+    }
+    /// Generate a `KERNELS` registry listing the exported functions, with the name and
+    /// parameter types of each and an invoker calling it with type-erased arguments, to
+    /// select the kernel to run at runtime, e.g. by name from the command line, see
+    /// `ispc_rt::kernel`.
+    pub fn kernel_registry(&mut self) -> &mut Config {
+        self.kernel_registry = true;
+        self
+    }
+    /// Rename the exported functions to snake case and the exported structs and enums
+    /// to camel case in the bindings, e.g. `renderTile` to `render_tile` and `light_params`
+    /// to `LightParams`, so they follow Rust's naming conventions. The functions still
+    /// link against the names exported from ISPC.
+    ///
+    /// The wrappers generated for the functions, e.g. by `async_wrappers`, and the
+    /// patterns passed to `result_wrappers` use the new names. Types shared with Rust
+    /// through `shared_types` and short vector types keep their names.
+    pub fn snake_case_names(&mut self) -> &mut Config {
+        self.naming.snake_case = true;
+        self
+    }
+    /// Strip the `prefix` from the names of the exported functions, structs and enums
+    /// in the bindings, e.g. `ispc_` to import `ispc_render` as `render`. This is
+    /// applied before the conversion of `snake_case_names`.
+    pub fn strip_name_prefix(&mut self, prefix: &str) -> &mut Config {
+        self.naming.strip_prefix = Some(prefix.to_owned());
+        self
+    }
+    /// Group the exported functions into a module for each ISPC source file, named
+    /// after the file, e.g. `rt::geom::make_sphere` for the functions in `geom.ispc`.
+    /// The functions are still available at the top of the library's module as well.
+    pub fn modules_per_file(&mut self) -> &mut Config {
+        self.naming.modules_per_file = true;
+        self
+    }
+    /// Generate a builder for each exported function with more than `max_params`
+    /// parameters, `FooCall` for `foo`, so the call names each parameter instead of
+    /// depending on their order:
+    ///
+    /// ```ignore
+    /// unsafe {
+    ///     rt::RenderCall::new()
+    ///         .camera(&camera)
+    ///         .width(width)
+    ///         .height(height)
+    ///         // ...
+    ///         .invoke()
+    /// };
+    /// ```
+    ///
+    /// `invoke` is unsafe like the function it calls. Calling it before setting all
+    /// parameters panics, the builder checks this at runtime rather than at compile time.
+    pub fn call_builders(&mut self, max_params: usize) -> &mut Config {
+        self.call_builders = Some(max_params);
+        self
+    }
+    /// Only generate bindings for the exported functions and types whose names match the
+    /// regex `pattern`, e.g. `render_.*` to leave out the helpers a library exports for
+    /// its tests. Can be called multiple times to select more items. The pattern must
+    /// match the whole ISPC name, before any renaming by `snake_case_names` or
+    /// `strip_name_prefix`.
+    ///
+    /// The types used by the selected functions are always included. The functions left
+    /// out are still compiled into the library, they just aren't declared in Rust.
+    pub fn export_only(&mut self, pattern: &str) -> &mut Config {
+        self.export_only.push(pattern.to_owned());
+        self
+    }
+    /// Leave the exported functions and types whose names match the regex `pattern` out
+    /// of the bindings, e.g. `debug_.*`. Like `export_only` the pattern must match the
+    /// whole ISPC name, and is applied after the patterns passed to `export_only`. Types
+    /// used by functions which are still exported can't be skipped.
+    pub fn skip_exports(&mut self, pattern: &str) -> &mut Config {
+        self.skip_exports.push(pattern.to_owned());
+        self
+    }
+    /// Generate a wrapper returning a `Result<(), ispc_rt::IspcError>`, `foo_checked` for
+    /// `foo`, for the exported functions returning an integer status code whose names
+    /// match the regex `pattern`. The pattern must match the whole name, like the patterns
+    /// passed to bindgen, so it can name a single function, e.g. `render`, or select the
+    /// functions following a naming convention, e.g. `try_.*`.
+    ///
+    /// The codes are checked with `ispc_rt::IspcError::check` unless another mapping is
+    /// set with `error_mapping`. The wrappers are unsafe to call like the functions they
+    /// wrap.
+    pub fn result_wrappers(&mut self, pattern: &str) -> &mut Config {
+        self.result_wrappers.push(pattern.to_owned());
+        self
+    }
+    /// Set the path of the function deciding which status codes returned by the functions
+    /// wrapped by `result_wrappers` are failures, e.g. `crate::kernel_status`. The function
+    /// is passed the name of the ISPC function and its code, and must have the signature
+    /// `fn(&'static str, i64) -> Result<(), ispc_rt::IspcError>`.
+    pub fn error_mapping(&mut self, path: &str) -> &mut Config {
+        self.error_mapping = Some(path.to_owned());
+        self
+    }
+    /// Generate an owning handle type for each kind of object the ISPC code creates
+    /// with a function named `{make_prefix}{kind}` and destroys with one named
+    /// `{drop_prefix}{kind}`, which destroys the object when dropped. The handle is
+    /// named after the kind, e.g. `CameraHandle` for `make_camera` and `drop_camera`.
+    ///
+    /// The constructor should either return the handle or write it to its first
+    /// parameter, and the destructor should take the handle as its only parameter.
+    pub fn handle_types(&mut self, make_prefix: &str, drop_prefix: &str) -> &mut Config {
+        self.handle_prefixes = Some((make_prefix.to_owned(), drop_prefix.to_owned()));
+        self
+    }
+    /// Generate bindings to the entry points compiled for each of the `target_isas`, which
+    /// call an ISA directly instead of going through the dispatcher, e.g. to compare the
+    /// ISAs with the benchmark helpers of `ispc_rt::bench`. They're put in an `isa` module,
+    /// with a module for each ISA, e.g. `isa::avx2::add_lists`, and a function for each
+    /// exported function looking up its entry point by the name of the ISA, e.g.
+    /// `isa::add_lists("avx2")`. The target ISAs have to be selected, a library compiled
+    /// for a single one only has the entry points of that ISA.
+    pub fn isa_entry_points(&mut self) -> &mut Config {
+        self.isa_entry_points = true;
+        self
+    }
+    /// Compare the generated bindings with the golden file at `path`, failing the build
+    /// with a diff of the changes if they differ, so changes to the signatures, struct
+    /// layouts or names from ISPC or bindgen upgrades are caught in review.
+    ///
+    /// The golden file is written if it doesn't exist, and is updated instead of compared
+    /// when the `ISPC_UPDATE_SNAPSHOTS` environment variable is set, after which the
+    /// changes can be reviewed and checked in. The version of bindgen the bindings start
+    /// with, blank lines and whether extern blocks are marked `unsafe` are left out, so
+    /// upgrading bindgen alone doesn't change them.
+    pub fn bindings_snapshot<P: AsRef<Path>>(&mut self, path: P) -> &mut Config {
+        self.bindings_snapshot = Some(path.as_ref().to_path_buf());
+        self
+    }
+    /// Map the short vector types used by the exported functions and structs, e.g.
+    /// `float<3>`, to the vector types of `glam`. Vectors with the same layout as a
+    /// `glam` type are replaced by it, i.e. `float<3>` by `Vec3A` and `float<4>` by
+    /// `Vec4`, and conversions to and from the `glam` type with the same elements are
+    /// generated for the others. Requires the `glam` feature of `ispc_rt`.
+    pub fn glam_vectors(&mut self) -> &mut Config {
+        self.vector_mappings.glam = true;
+        self
+    }
+    /// Generate conversions between the short vector types used by the exported
+    /// functions and structs, e.g. `float<3>`, and the `mint` vector types with the
+    /// same elements, e.g. `mint::Vector3<f32>`. Requires the `mint` feature of `ispc_rt`.
+    ///
+    /// The `mint` types can't replace the ISPC vectors as ISPC aligns them to more than
+    /// their elements. Vectors replaced by `glam` types with `glam_vectors` use the
+    /// conversions provided by `glam` instead.
+    pub fn mint_vectors(&mut self) -> &mut Config {
+        self.vector_mappings.mint = true;
+        self
+    }
+    /// Map the ISPC `float16` type to `half::f16` in the function signatures and structs
+    /// of the bindings, instead of the `u16` wrapper bindgen generates for it. Requires
+    /// the `half` feature of `ispc_rt`.
+    pub fn half_float16(&mut self) -> &mut Config {
+        self.float16_half = true;
+        self
+    }
+    /// Implement `bytemuck::Pod` and `Zeroable` for the structs in the bindings whose
+    /// fields are all plain data, i.e. numbers, arrays of them and other such structs,
+    /// so buffers of bytes can be cast to them without copying. Structs holding pointers
+    /// or `bool`s are skipped. Requires the `bytemuck` feature of `ispc_rt`.
+    ///
+    /// The padding in the structs is made explicit in the bindings, as `Pod` types can't
+    /// have any implicit padding. Each implementation is checked at compile time for
+    /// fields which aren't `Pod` or padding which wasn't accounted for.
+    pub fn bytemuck_derives(&mut self) -> &mut Config {
+        self.bytemuck_derives = true;
+        self
+    }
+    /// Set the path the generated wrappers use to refer to the `ispc_rt` crate, for
+    /// crates which use it through another crate. Defaults to `ispc_rt`, crates
+    /// depending on the `ispc` crate instead should set it to `ispc`.
+    pub fn runtime_crate(&mut self, path: &str) -> &mut Config {
+        self.runtime_crate = path.to_owned();
+        self
+    }
     /// Disable frame pointer omission. It may be useful for profiling to
     /// disable omission.
     pub fn no_omit_frame_pointer(&mut self) -> &mut Config {
@@ -241,6 +811,36 @@ impl Config {
         self.no_cpp = true;
         self
     }
+    /// Generate bindings which only use `core`, with the C types from `core::ffi`,
+    /// so the ISPC code can be called from `#![no_std]` crates. These should use
+    /// `ispc_rt` without its default `std` feature, which provides a task system that
+    /// doesn't need `std`. Options using parts of the runtime which need `std`, i.e.
+    /// `callback`, `async_wrappers`, `route_print` and `route_asserts`, fail the build.
+    pub fn no_std_bindings(&mut self) -> &mut Config {
+        self.no_std_bindings = true;
+        self
+    }
+    /// Build the ISPC code into a shared library loaded at runtime with
+    /// `ispc_rt::DynamicModule`, instead of a static library linked into the crate, so
+    /// the kernels can be rebuilt and reloaded while the program runs with
+    /// `DynamicModule::watch`. This is meant for developing the kernels, and needs the
+    /// `dynamic` feature of `ispc_rt`.
+    ///
+    /// The bindings declare the kernels as methods of a `Kernels` struct, which is looked
+    /// up in the library when it's loaded, e.g. `simple::Kernels` for the library `simple`.
+    /// The path of the library is passed to the crate in the `ISPC_LIBRARY_simple`
+    /// environment variable, and the commands building it are written next to it for
+    /// `DynamicModule::watch`.
+    ///
+    /// The options generating wrappers which call the kernels directly, renaming them or
+    /// calling into the runtime from the ISPC code can't be combined with this, i.e.
+    /// `async_wrappers`, `kernel_registry`, `call_builders`, `result_wrappers`,
+    /// `handle_types`, `isa_entry_points`, `snake_case_names`, `strip_name_prefix`,
+    /// `instrument`, `route_print`, `route_asserts`, `sanitize` and `no_std_bindings`.
+    pub fn shared_library(&mut self) -> &mut Config {
+        self.shared_library = true;
+        self
+    }
     /// Enable suppression of all ispc compiler output.
     pub fn quiet(&mut self) -> &mut Config {
         self.quiet = true;
@@ -261,6 +861,24 @@ impl Config {
         self.wno_perf = true;
         self
     }
+    /// Write a JSON report of the performance warnings ISPC issues, e.g. for gathers,
+    /// scatters and divergent control flow, to `path`. The warnings are grouped by the
+    /// function they're in and counted by kind, so CI can compare the report against a
+    /// previous one to catch kernels whose vectorization regressed.
+    ///
+    /// The report is rewritten by each call to `compile`, so a separate path should be
+    /// used for each library. It's empty if performance warnings are disabled with
+    /// `wno_perf` or `woff`.
+    pub fn perf_report<P: AsRef<Path>>(&mut self, path: P) -> &mut Config {
+        self.perf_report = Some(path.as_ref().to_path_buf());
+        self
+    }
+    /// Set whether ISPC's performance warnings are passed on as cargo warnings, which
+    /// they are by default. Unlike `wno_perf` this keeps them in the `perf_report`.
+    pub fn perf_warnings(&mut self, warn: bool) -> &mut Config {
+        self.perf_warnings = warn;
+        self
+    }
     /// Emit instrumentation code for ISPC to gather performance data such
     /// as vector utilization.
     pub fn instrument(&mut self) -> &mut Config {
@@ -280,11 +898,70 @@ impl Config {
         self.instrument = true;
         self
     }
+    /// Route the output of `print()` in the ISPC code through the print handler of
+    /// `ispc_rt` instead of writing it directly to stdout, see `ispc_rt::print`.
+    ///
+    /// The `fputs` and `fflush` calls ISPC prints with are redirected to the runtime by
+    /// renaming the symbols in the compiled objects with `objcopy`, or the tool set in the
+    /// `OBJCOPY` environment variable. Apple targets need `llvm-objcopy`, which is used
+    /// by default for them. MSVC targets aren't supported, as the symbols of their COFF
+    /// objects can't be renamed this way.
+    ///
+    /// Every call to `fputs` and `fflush` in the library's ISPC code is redirected, so ISPC
+    /// code calling them through its own `extern "C"` declarations, e.g. to write to
+    /// stderr, is routed to the print handler as well. The print handler needs std, so
+    /// this can't be used with `no_std_bindings`.
+    pub fn route_print(&mut self) -> &mut Config {
+        self.route_print = true;
+        self
+    }
+    /// Panic with the kernel, task and message of failed `assert()`s and `abort()` calls
+    /// in the ISPC code instead of aborting the process without context, see `ispc_rt::abort`.
+    ///
+    /// The `printf`, `puts` and `abort` calls of failed assertions are redirected to the
+    /// runtime with `objcopy` as in `route_print`, which isn't supported on MSVC targets.
+    /// The symbols are renamed for the whole library, so ISPC code calling `printf`, `puts`
+    /// or `abort` itself through `extern "C"` declarations has its output recorded as the
+    /// message of a failed assertion, and panics when it aborts. ISPC code can't be unwound
+    /// through, so the panic still aborts the process after reporting the failure. Needs
+    /// std and can't be used with `no_std_bindings`.
+    pub fn route_asserts(&mut self) -> &mut Config {
+        self.route_asserts = true;
+        self
+    }
+    /// Compile the exported functions with the `vectorcall` calling convention on Windows,
+    /// which passes more vector and floating point arguments in registers, and declare
+    /// the bindings with the matching `extern "vectorcall"` ABI. Requires ISPC 1.13 or newer.
+    ///
+    /// The `vectorcall` ABI is still unstable in Rust, so the crate including the bindings
+    /// needs a nightly compiler and `#![feature(abi_vectorcall)]`. The option is ignored
+    /// with a warning for targets other than x86 and x86-64 MSVC.
+    pub fn vectorcall(&mut self) -> &mut Config {
+        let min_ver = Version::new(1, 13, 0);
+        if self.ispc_version < min_ver {
+            exit_failure!("Error: vectorcall is not supported on ISPC versions older than 1.13.0");
+        }
+        self.vectorcall = true;
+        self
+    }
     /// Enable support for LLVM intrinsics
     pub fn enable_llvm_intrinsics(&mut self) -> &mut Config {
         self.enable_llvm_intrinsics = true;
         self
     }
+    /// Instrument the ISPC code with a sanitizer, so e.g. out of bounds accesses in the
+    /// kernels are reported by AddressSanitizer instead of silently corrupting memory.
+    /// The build fails if the ISPC compiler doesn't list the sanitizer in its `--help`.
+    ///
+    /// If the crate is built with `-Zsanitizer=address` rustc links the sanitizer runtime,
+    /// otherwise the shared runtime of clang is linked, or `libasan` if clang isn't found
+    /// on Linux. The runtime is found through `clang -print-file-name`, and the path to it
+    /// is set as the rpath of the crate's binaries so they run without setting
+    /// `LD_LIBRARY_PATH`.
+    pub fn sanitize(&mut self, sanitizer: Sanitizer) -> &mut Config {
+        self.sanitizer = Some(sanitizer);
+        self
+    }
     /// Select the target ISA and vector width. If none is specified ispc will
     /// choose the host CPU ISA and vector width.
     pub fn target_isa(&mut self, target: TargetISA) -> &mut Config {
@@ -299,6 +976,15 @@ impl Config {
         self.target_isa = Some(targets);
         self
     }
+    /// Select a set of target ISAs for the target architecture from those the installed
+    /// ISPC supports, see `supported_targets`, when none are selected with `target_isa` or
+    /// `target_isas`. On x86 this is the best supported SSE2, SSE4, AVX, AVX2 and AVX-512
+    /// ISA with 32-bit lanes, so the kernels dispatch to the best one the CPU running them
+    /// supports, and NEON on ARM. Other architectures use the default ISA.
+    pub fn auto_targets(&mut self) -> &mut Config {
+        self.auto_targets = true;
+        self
+    }
     /// Select the CPU architecture to target
     pub fn target_arch(&mut self, arch: Architecture) -> &mut Config {
         self.architecture = Some(arch);
@@ -318,49 +1004,195 @@ impl Config {
         self.bindgen_builder = builder;
         self
     }
+    /// Set the `cc::Build` used to compile the files added with `c_file`, e.g. to pass
+    /// additional flags. The target, optimization level, debug info, defines and include
+    /// paths are set from this `Config` when compiling.
+    pub fn c_builder(&mut self, builder: cc::Build) -> &mut Config {
+        self.c_builder = builder;
+        self
+    }
     /// The library name should not have any prefix or suffix, e.g. instead of
     /// `libexample.a` or `example.lib` simply pass `example`
+    ///
+    /// The library name is also the name of the module holding the bindings, which is
+    /// imported with `ispc_module!(example)`. The output directory of the bindings is
+    /// passed to the crate in the `ISPC_OUT_DIR_example` environment variable, so a
+    /// crate can compile and import multiple ISPC libraries. Note that the functions
+    /// exported from the libraries must still have different names, since they share
+    /// the C symbol namespace.
+    ///
+    /// Documentation comments (`///` or `/** */`) placed directly before exported
+    /// functions, structs and enums in the ISPC sources and the headers they include
+    /// are attached to the generated bindings, so they show up in `cargo doc`.
+    ///
+    /// Numeric constants defined in the ISPC sources and the headers they include,
+    /// either with `#define` or as `const uniform` globals, are exported as Rust
+    /// constants in the module, e.g. `#define TILE_SIZE 16` as `pub const TILE_SIZE: i32`.
+    /// `#define`s inside of `#if` blocks are skipped, as their values depend on the build.
+    ///
+    /// The module also contains a `build_info` module recording the ISPC version, flags,
+    /// target ISAs and a hash of the sources the library was built with, e.g.
+    /// `foo::build_info::SOURCE_HASH`, to check which build of the kernels is running.
+    ///
+    /// The build fails with a message naming the function and parameter if an exported
+    /// function uses a construct the bindings can't represent, e.g. a reference parameter.
     pub fn compile(&self, lib: &str) {
+        if !is_module_name(lib) {
+            exit_failure!(
+                "ISPC library name {lib} must be a valid Rust identifier to import its bindings"
+            );
+        }
         let dst = self.get_out_dir();
-        let build_dir = self.get_build_dir();
-        let default_args = self.default_args();
+        let shared_types = self.load_shared_types();
+        let callbacks = self.load_callbacks();
+        let header_dir = self.write_generated_headers(&shared_types, &callbacks);
+        // Build each library in its own directory so libraries built from
+        // source files with the same names don't overwrite each other's objects
+        let build_dir = self.get_build_dir().join(lib);
+        if let Err(e) = fs::create_dir_all(&build_dir) {
+            exit_failure!("Failed to create build directory for {}: {}", lib, e);
+        }
+        if platform::running_under_rosetta() {
+            self.print(
+                &"cargo:warning=ispc-rs: Building under Rosetta, install the aarch64-apple-darwin \
+                  Rust toolchain and an arm64 ISPC to build native NEON kernels",
+            );
+        }
+        let target = self.get_target();
+        let arch = platform::target_arch(&target);
+        let riscv = arch == "riscv64" || matches!(self.architecture, Some(Architecture::Riscv64));
+        if riscv && !platform::ispc_supports_arch("riscv64") {
+            exit_failure!(
+                "Your ISPC {} doesn't support rv64, which is needed to build for {target}",
+                self.ispc_version
+            );
+        }
+        if self.vectorcall && !self.use_vectorcall() {
+            self.print(&format!(
+                "cargo:warning=ispc-rs: Ignoring vectorcall, which is only supported on x86 and \
+                 x86-64 MSVC targets, not {target}"
+            ));
+        }
+        if self.no_std_bindings {
+            // These generate code or redirect calls into parts of the runtime which need std
+            let needs_std = [
+                ("callback", !self.callbacks.is_empty()),
+                ("async_wrappers", self.async_wrappers),
+                ("route_print", self.route_print),
+                ("route_asserts", self.route_asserts),
+            ];
+            if let Some((option, _)) = needs_std.iter().find(|(_, used)| *used) {
+                exit_failure!("{option} needs std and can't be combined with no_std_bindings");
+            }
+        }
+        if self.shared_library {
+            // These call the kernels directly, rename the symbols the kernels are looked
+            // up by, or call into the runtime from the ISPC code
+            let unsupported = [
+                ("async_wrappers", self.async_wrappers),
+                ("kernel_registry", self.kernel_registry),
+                ("call_builders", self.call_builders.is_some()),
+                ("result_wrappers", !self.result_wrappers.is_empty()),
+                ("handle_types", self.handle_prefixes.is_some()),
+                ("isa_entry_points", self.isa_entry_points),
+                ("snake_case_names", self.naming.snake_case),
+                ("strip_name_prefix", self.naming.strip_prefix.is_some()),
+                ("instrument", self.instrument),
+                ("route_print", self.route_print),
+                ("route_asserts", self.route_asserts),
+                ("sanitize", self.sanitizer.is_some()),
+                ("no_std_bindings", self.no_std_bindings),
+            ];
+            if let Some((option, _)) = unsupported.iter().find(|(_, used)| *used) {
+                exit_failure!("{option} can't be combined with shared_library");
+            }
+        }
+        let sanitize_flag = self.sanitizer.map(|s| {
+            platform::ispc_sanitize_flag(s).unwrap_or_else(|| {
+                exit_failure!(
+                    "Your ISPC {} doesn't support instrumenting code with the {s} sanitizer",
+                    self.ispc_version
+                )
+            })
+        });
+        let ispc_files = self.source_files(lib);
+        self.write_source_strs(lib);
+        let target_isas = self.get_target_isas();
+        let mut default_args = self.default_args(target_isas.as_deref());
+        default_args.extend(sanitize_flag);
         let mut objects = vec![];
         let mut headers = vec![];
-        for s in &self.ispc_files {
+        let mut docs = DocComments::default();
+        let mut constants = Constants::default();
+        for d in &self.typed_defines {
+            constants.define(d);
+        }
+        let mut perf_warnings = Vec::new();
+        let mut source_hash = SourceHash::new();
+        let mut rebuild = Rebuild::default();
+        let source_str_dir = self.source_str_dir(lib);
+        for s in &ispc_files {
             let fname = s
                 .file_stem()
                 .expect("ISPC source files must be files")
                 .to_str()
                 .expect("ISPC source file names must be valid UTF-8");
-            self.print(&format!("cargo:rerun-if-changed={}", s.display()));
+            // Code added from strings changes with the build script, which cargo already
+            // reruns the build for
+            if !s.starts_with(&source_str_dir) {
+                self.print(&format!("cargo:rerun-if-changed={}", s.display()));
+            }
+            if let Ok(source) = fs::read_to_string(s) {
+                let errors = exports::check_exports(&source);
+                if !errors.is_empty() {
+                    exit_failure!(
+                        "Unsupported exported functions in ISPC source file {}:\n{}",
+                        s.display(),
+                        errors.join("\n")
+                    );
+                }
+            }
 
             let ispc_fname = String::from(fname) + "_ispc";
             let object = build_dir.join(ispc_fname.clone()).with_extension("o");
             let header = build_dir.join(ispc_fname.clone()).with_extension("h");
             let deps = build_dir.join(ispc_fname.clone()).with_extension("idep");
-            let output = Command::new("ispc")
-                .args(&default_args)
+            let mut ispc = Command::new("ispc");
+            ispc.args(&default_args)
+                .args(header_dir.iter().map(|d| format!("-I{}", d.display())))
                 .arg(s)
                 .arg("-o")
                 .arg(&object)
                 .arg("-h")
                 .arg(&header)
                 .arg("-MMM")
-                .arg(&deps)
-                .output()
-                .unwrap();
+                .arg(&deps);
+            rebuild.watch(s);
+            rebuild.command(&ispc);
+            let output = ispc.output().unwrap();
 
             if !output.stderr.is_empty() {
                 let stderr = String::from_utf8_lossy(&output.stderr);
-                for l in stderr.lines() {
+                let lines = if self.perf_warnings {
+                    stderr.lines().collect()
+                } else {
+                    perf::strip_warnings(&stderr)
+                };
+                for l in lines {
                     self.print(&format!("cargo:warning=(ISPC) {l}"));
                 }
+                perf_warnings.extend(perf::parse_warnings(&stderr));
             }
             if !output.status.success() {
                 exit_failure!("Failed to compile ISPC source file {}", s.display());
             }
             objects.push(object);
             headers.push(header);
+            if let Ok(source) = fs::read_to_string(s) {
+                docs.parse(&source);
+                constants.parse(&source);
+                source_hash.update(source.as_bytes());
+            }
 
             // Go this files dependencies and add them to Cargo's watch list
             let deps_list = File::open(deps)
@@ -370,10 +1202,18 @@ impl Config {
                 // Don't depend on the ISPC "stdlib" file which is output as a dependency
                 let dep_name = d.unwrap();
                 self.print(&format!("cargo:rerun-if-changed={dep_name}"));
+                rebuild.watch(Path::new(&dep_name));
+                // Types exported from included headers may be documented there, and
+                // constants used by the kernels are often defined in them
+                if let Ok(source) = fs::read_to_string(&dep_name) {
+                    docs.parse(&source);
+                    constants.parse(&source);
+                    source_hash.update(source.as_bytes());
+                }
             }
 
             // Push on the additional ISA-specific object files if any were generated
-            if let Some(ref t) = self.target_isa {
+            if let Some(ref t) = target_isas {
                 if t.len() > 1 {
                     for isa in t.iter() {
                         let isa_fname = ispc_fname.clone() + "_" + &isa.lib_suffix();
@@ -383,25 +1223,138 @@ impl Config {
                 }
             }
         }
-        let libfile = lib.to_owned() + &self.get_target();
-        if !self.assemble(&libfile, &objects).success() {
-            exit_failure!("Failed to assemble ISPC objects into library {lib}");
+        if let Some(ref path) = self.perf_report {
+            if let Err(e) = fs::write(path, perf::report(lib, &perf_warnings)) {
+                exit_failure!(
+                    "Failed to write the performance report {}: {e}",
+                    path.display()
+                );
+            }
+        }
+        self.redirect_symbols(&objects);
+        self.set_apple_build_version(&objects);
+        objects.extend(self.compile_c_files(lib, &build_dir));
+        if self.shared_library {
+            let library = self.link_shared_library(lib, &build_dir, &objects, &mut rebuild);
+            if let Err(e) = fs::write(dynamic::rebuild_path(&library), rebuild.contents()) {
+                exit_failure!("Failed to write the rebuild file of {lib}: {e}");
+            }
+            self.print(&format!(
+                "cargo:rustc-env=ISPC_LIBRARY_{lib}={}",
+                library.display()
+            ));
+        } else {
+            let libfile = lib.to_owned() + &self.get_target();
+            if !self.assemble(&libfile, &objects).success() {
+                exit_failure!("Failed to assemble ISPC objects into library {lib}");
+            }
+            self.print(&format!("cargo:rustc-link-lib=static={libfile}"));
+        }
+        if let Some(sanitizer) = self.sanitizer {
+            self.link_sanitizer_runtime(sanitizer);
+        }
+
+        if let Some(ref dir) = self.header_dir {
+            self.install_header(lib, dir, &headers);
         }
-        self.print(&format!("cargo:rustc-link-lib=static={libfile}"));
 
         // Now generate a header we can give to bindgen and generate bindings
         let bindgen_header = self.generate_bindgen_header(lib, &headers);
-        let bindings = self
+        let mut bindings = self
             .bindgen_builder
             .clone()
             .header(bindgen_header.to_str().unwrap());
+        if self.no_std_bindings {
+            bindings = bindings.use_core().ctypes_prefix("::core::ffi");
+        }
+        if self.bytemuck_derives {
+            bindings = bindings.explicit_padding(true);
+        }
+        if self.use_vectorcall() {
+            bindings = bindings.override_abi(bindgen::Abi::Vectorcall, ".*");
+        }
+        if self.shared_library {
+            bindings = bindings
+                .dynamic_library_name(dynamic::KERNELS_STRUCT)
+                .dynamic_link_require_all(true);
+        }
+        let mut vector_types = Vec::new();
+        for h in &headers {
+            if let Ok(header) = fs::read_to_string(h) {
+                vectors::find_vector_types(&header, &mut vector_types);
+            }
+        }
+        for name in vectors::aliased_types(&vector_types, self.vector_mappings) {
+            bindings = bindings.blocklist_type(name);
+        }
+        let (export_only, skip_exports) = self.export_patterns();
+        for p in &self.export_only {
+            bindings = bindings.allowlist_function(p).allowlist_type(p);
+        }
+        for p in &self.skip_exports {
+            bindings = bindings.blocklist_function(p).blocklist_type(p);
+        }
+        let exported = |name: &str| {
+            (export_only.is_empty() || export_only.iter().any(|p| p.is_match(name)))
+                && !skip_exports.iter().any(|p| p.is_match(name))
+        };
+        // Find the functions exported by each file and the types they use to rename them
+        let mut file_functions = Vec::new();
+        let mut exported_types = HashSet::new();
+        for (s, h) in ispc_files.iter().zip(headers.iter()) {
+            let header = fs::read_to_string(h).unwrap_or_default();
+            let stem = s.file_stem().unwrap().to_str().unwrap();
+            file_functions.push(FileFunctions {
+                module: naming::module_name(stem),
+                file: s.file_name().unwrap().to_string_lossy().into_owned(),
+                functions: naming::exported_functions(&header)
+                    .into_iter()
+                    .filter(|f| exported(f))
+                    .collect(),
+            });
+            naming::exported_types(&header, &mut exported_types);
+        }
+        for t in vector_types.iter() {
+            exported_types.remove(&t.name);
+        }
+        for (_, types) in &shared_types {
+            for name in types.type_names() {
+                exported_types.remove(name);
+            }
+        }
+        let functions: Vec<String> = file_functions
+            .iter()
+            .flat_map(|f| f.functions.iter().cloned())
+            .collect();
+        let exported_types: Vec<String> = exported_types.into_iter().collect();
+        let renames = Renames::new(&self.naming, &functions, &exported_types);
+        if self.naming.renames() {
+            bindings = bindings.parse_callbacks(Box::new(renames.clone()));
+            docs.rename(|name| renames.get(name).to_owned());
+        }
+        for (_, types) in &shared_types {
+            for name in types.type_names() {
+                bindings = bindings.blocklist_type(name);
+            }
+            // The constants bindgen generates for the variants of an enum
+            for name in types.enum_names() {
+                bindings = bindings.blocklist_item(format!("{name}_.*"));
+            }
+        }
 
         let bindgen_file = dst.join(lib).with_extension("rs");
 
-        let generated_bindings = match bindings.generate() {
-            Ok(b) => b.to_string(),
+        let mut generated_bindings = match bindings.generate() {
+            Ok(b) => docs.apply(&b.to_string()),
             Err(_) => exit_failure!("Failed to generating Rust bindings to {}", lib),
         };
+        if self.float16_half {
+            generated_bindings = vectors::map_float16(&generated_bindings, &self.runtime_crate);
+        }
+        if self.shared_library {
+            generated_bindings =
+                dynamic::kernels_bindings(&generated_bindings, &self.runtime_crate);
+        }
         let mut file = match File::create(bindgen_file) {
             Ok(f) => f,
             Err(e) => exit_failure!("Failed to open bindgen mod file for writing: {}", e),
@@ -410,20 +1363,162 @@ impl Config {
                        .as_bytes()).unwrap();
         file.write_all(format!("pub mod {lib} {{\n").as_bytes())
             .unwrap();
-        file.write_all(generated_bindings.as_bytes()).unwrap();
+        let mut definitions = String::new();
+        for (_, types) in &shared_types {
+            definitions.push_str(&types.rust_definitions());
+        }
+        definitions.push_str(&generated_bindings);
+        definitions.push_str(&callbacks::rust_definitions(
+            &callbacks,
+            &self.runtime_crate,
+        ));
+        file.write_all(definitions.as_bytes()).unwrap();
+        if self.bytemuck_derives {
+            // The types mapped to half and glam types are Pod through their crates
+            let mut extra_pod: Vec<&str> =
+                vectors::aliased_types(&vector_types, self.vector_mappings).collect();
+            if self.float16_half {
+                extra_pod.push("__BindgenFloat16");
+            }
+            let impls = pod::pod_impls(&definitions, &extra_pod, &self.runtime_crate);
+            file.write_all(impls.as_bytes()).unwrap();
+        }
+        // Export the constants the kernels use which aren't in the bindings already
+        file.write_all(constants.rust_definitions(&definitions).as_bytes())
+            .unwrap();
+        let extern_fns = wrappers::extern_fns(&generated_bindings);
+        if self.async_wrappers {
+            let wrappers = wrappers::async_wrappers(&extern_fns, &self.runtime_crate);
+            file.write_all(wrappers.as_bytes()).unwrap();
+        }
+        if self.kernel_registry {
+            let registry = wrappers::kernel_registry(&extern_fns, &self.runtime_crate);
+            file.write_all(registry.as_bytes()).unwrap();
+        }
+        if self.vector_mappings.glam || self.vector_mappings.mint {
+            let mappings =
+                vectors::vector_mappings(&vector_types, self.vector_mappings, &self.runtime_crate);
+            file.write_all(mappings.as_bytes()).unwrap();
+        }
+        if self.naming.modules_per_file {
+            let modules = naming::file_modules(&file_functions, &renames);
+            file.write_all(modules.as_bytes()).unwrap();
+        }
+        if let Some(max_params) = self.call_builders {
+            let builders = wrappers::call_builders(&extern_fns, max_params);
+            file.write_all(builders.as_bytes()).unwrap();
+        }
+        if !self.result_wrappers.is_empty() {
+            let wrappers = self.generate_result_wrappers(&extern_fns);
+            file.write_all(wrappers.as_bytes()).unwrap();
+        }
+        if let Some((ref make, ref drop)) = self.handle_prefixes {
+            let handles = wrappers::handle_types(&extern_fns, make, drop);
+            file.write_all(handles.as_bytes()).unwrap();
+        }
+        if self.isa_entry_points {
+            let isas = target_isas.as_deref().unwrap_or_default();
+            if isas.is_empty() {
+                exit_failure!(
+                    "ISA entry points of {lib} need the target ISAs to be selected with \
+                     target_isas"
+                );
+            }
+            let abi = if self.use_vectorcall() {
+                "vectorcall"
+            } else {
+                "C"
+            };
+            // The entry points are exported under the original names of the functions
+            let fns: Vec<(&wrappers::ExternFn, &str)> = extern_fns
+                .iter()
+                .filter_map(|f| {
+                    let original = functions.iter().find(|o| renames.get(o) == f.name)?;
+                    Some((f, original.as_str()))
+                })
+                .collect();
+            let entry_points = isa::isa_entry_points(&fns, isas, abi);
+            file.write_all(entry_points.as_bytes()).unwrap();
+        }
+        let build_info = build_info::rust_module(
+            &self.ispc_version,
+            &default_args,
+            &self.get_target(),
+            &source_hash,
+        );
+        file.write_all(build_info.as_bytes()).unwrap();
         file.write_all(b"}").unwrap();
+        if let Some(ref path) = self.bindings_snapshot {
+            self.check_bindings_snapshot(lib, &dst.join(lib).with_extension("rs"), path);
+        }
 
         self.print(&format!("cargo:rustc-link-search=native={}", dst.display()));
         self.print(&format!("cargo:rustc-env=ISPC_OUT_DIR={}", dst.display()));
+        self.print(&format!(
+            "cargo:rustc-env=ISPC_OUT_DIR_{lib}={}",
+            dst.display()
+        ));
+    }
+    /// Get the signatures of the functions and structs exported by the library `lib`,
+    /// parsed from the headers ISPC generated when it was built with `compile`. This lets
+    /// build scripts generate their own wrappers around the kernels, e.g. RPC stubs.
+    ///
+    /// The names are those in the ISPC sources, before any renaming of the bindings.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let mut cfg = ispc_compile::Config::new();
+    /// cfg.file("src/simple.ispc");
+    /// cfg.compile("simple");
+    /// for f in cfg.signatures("simple").functions {
+    ///     let params: Vec<String> = f.params.iter().map(|p| p.ty.clone()).collect();
+    ///     println!("cargo:warning={} {}({})", f.ret, f.name, params.join(", "));
+    /// }
+    /// ```
+    pub fn signatures(&self, lib: &str) -> Signatures {
+        let build_dir = self.get_build_dir().join(lib);
+        let mut signatures = Signatures::default();
+        for s in &self.source_files(lib) {
+            let fname = s
+                .file_stem()
+                .expect("ISPC source files must be files")
+                .to_str()
+                .expect("ISPC source file names must be valid UTF-8");
+            let header = build_dir.join(String::from(fname) + "_ispc.h");
+            match fs::read_to_string(&header) {
+                Ok(h) => signatures.parse(&h, Some(s.clone())),
+                Err(e) => exit_failure!(
+                    "Failed to read the header {} of {lib}, it must be compiled before getting \
+                     its signatures: {e}",
+                    header.display()
+                ),
+            }
+        }
+        signatures
     }
     /// Get the ISPC compiler version.
     pub fn ispc_version(&self) -> &Version {
         &self.ispc_version
     }
-    /// Link the ISPC code into a static library on Unix using `ar`
-    #[cfg(unix)]
+    /// Link the ISPC code into a static library using `lib.exe` for MSVC targets, or `ar`
+    /// otherwise, using the archiver for the target when cross compiling, e.g. the NDK's
+    /// `llvm-ar` for Android
     fn assemble(&self, lib: &str, objects: &[PathBuf]) -> ExitStatus {
-        Command::new("ar")
+        let target = self.get_target();
+        for var in platform::tool_env_vars(&target) {
+            self.print(&format!("cargo:rerun-if-env-changed={var}"));
+        }
+        if target.contains("msvc") {
+            return platform::msvc_librarian(&target)
+                .arg(format!("/OUT:{lib}.lib"))
+                .args(objects)
+                .current_dir(self.get_out_dir())
+                .status()
+                .unwrap_or_else(|e| {
+                    exit_failure!("Failed to find lib.exe or llvm-lib for {target}: {e}")
+                });
+        }
+        platform::archiver(&target)
             .arg("crus")
             .arg(format!("lib{lib}.a"))
             .args(objects)
@@ -431,19 +1526,451 @@ impl Config {
             .status()
             .unwrap()
     }
-    /// Link the ISPC code into a static library on Windows using `lib.exe`
-    #[cfg(windows)]
-    fn assemble(&self, lib: &str, objects: &[PathBuf]) -> ExitStatus {
+    /// Link the ISPC `objects` of the library `lib` into a shared library in the output
+    /// directory, along with the code forwarding the task system calls to the runtime,
+    /// returns the path of the library. The link command is recorded in `rebuild`.
+    fn link_shared_library(
+        &self,
+        lib: &str,
+        build_dir: &Path,
+        objects: &[PathBuf],
+        rebuild: &mut Rebuild,
+    ) -> PathBuf {
         let target = self.get_target();
-        let mut lib_cmd = cc::windows_registry::find_tool(&target, "lib.exe")
-            .expect("Failed to find lib.exe for MSVC toolchain, aborting")
-            .to_command();
-        lib_cmd
-            .arg(format!("/OUT:{lib}.lib"))
-            .args(objects)
-            .current_dir(self.get_out_dir())
-            .status()
-            .unwrap()
+        let shim = build_dir.join("ispc_rt_task_system.c");
+        if let Err(e) = fs::write(&shim, dynamic::TASK_SYSTEM_SHIM) {
+            exit_failure!("Failed to write the task system forwarding of {lib}: {e}");
+        }
+        let library = self
+            .get_out_dir()
+            .join(dynamic::library_filename(lib, &target));
+        let compiler = match self
+            .c_builder
+            .clone()
+            .target(&target)
+            .opt_level(self.get_opt_level())
+            .debug(self.get_debug())
+            .cargo_metadata(false)
+            .try_get_compiler()
+        {
+            Ok(c) => c,
+            Err(e) => exit_failure!("Failed to find a C compiler to link {lib}: {e}"),
+        };
+        let mut link = compiler.to_command();
+        if compiler.is_like_msvc() {
+            link.arg("/LD")
+                .arg(&shim)
+                .args(objects)
+                .arg(format!("/Fe{}", library.display()));
+        } else {
+            link.arg("-shared")
+                .arg("-o")
+                .arg(&library)
+                .arg(&shim)
+                .args(objects);
+            let cpp = self.c_files.iter().any(|f| {
+                let ext = f.extension().and_then(|e| e.to_str()).unwrap_or_default();
+                matches!(ext, "cpp" | "cc" | "cxx")
+            });
+            if let Some(stdlib) = platform::cpp_stdlib(&target).filter(|_| cpp) {
+                link.arg(format!("-l{stdlib}"));
+            }
+        }
+        link.current_dir(build_dir);
+        rebuild.command(&link);
+        match link.status() {
+            Ok(status) if status.success() => library,
+            Ok(_) => exit_failure!("Failed to link ISPC objects into shared library {lib}"),
+            Err(e) => exit_failure!("Failed to run the C compiler to link {lib}: {e}"),
+        }
+    }
+    /// Link the runtime of the `sanitizer` the ISPC code was instrumented with, unless
+    /// rustc links it as the crate itself is built with the sanitizer
+    fn link_sanitizer_runtime(&self, sanitizer: Sanitizer) {
+        let sanitizers = env::var("CARGO_CFG_SANITIZE").unwrap_or_default();
+        if sanitizers.split(',').any(|s| s == sanitizer.to_string()) {
+            return;
+        }
+        let target = self.get_target();
+        match platform::sanitizer_runtime(sanitizer, &target) {
+            Some((Some(dir), lib)) => {
+                self.print(&format!("cargo:rustc-link-search=native={}", dir.display()));
+                self.print(&format!("cargo:rustc-link-lib=dylib={lib}"));
+                if !target.contains("windows") {
+                    self.print(&format!(
+                        "cargo:rustc-link-arg=-Wl,-rpath,{}",
+                        dir.display()
+                    ));
+                }
+            }
+            Some((None, lib)) => self.print(&format!("cargo:rustc-link-lib=dylib={lib}")),
+            None => exit_failure!(
+                "Failed to find the {sanitizer} sanitizer runtime for {target}, install clang \
+                 or build the crate with -Zsanitizer={sanitizer}"
+            ),
+        }
+    }
+    /// Compile the files added with `c_file` into objects in `build_dir`, which holds the
+    /// headers generated by ISPC for the library `lib`
+    fn compile_c_files(&self, lib: &str, build_dir: &Path) -> Vec<PathBuf> {
+        let target = self.get_target();
+        let (cpp_files, c_files): (Vec<&PathBuf>, Vec<&PathBuf>) =
+            self.c_files.iter().partition(|f| {
+                let ext = f.extension().and_then(|e| e.to_str()).unwrap_or_default();
+                matches!(ext, "cpp" | "cc" | "cxx")
+            });
+        let mut objects = Vec::new();
+        for (files, cpp) in [(c_files, false), (cpp_files.clone(), true)] {
+            if files.is_empty() {
+                continue;
+            }
+            for f in &files {
+                self.print(&format!("cargo:rerun-if-changed={}", f.display()));
+            }
+            let mut build = self.c_builder.clone();
+            build
+                .cpp(cpp)
+                .files(files)
+                .include(build_dir)
+                .includes(&self.include_paths)
+                .target(&target)
+                .opt_level(self.get_opt_level())
+                .debug(self.get_debug())
+                .cargo_metadata(self.cargo_metadata)
+                .out_dir(build_dir);
+            for (name, value) in &self.defines {
+                build.define(name, value.as_deref());
+            }
+            for d in &self.typed_defines {
+                build.define(&d.name, d.c.as_str());
+            }
+            match build.try_compile_intermediates() {
+                Ok(o) => objects.extend(o),
+                Err(e) => exit_failure!("Failed to compile the C/C++ files of {lib}: {e}"),
+            }
+        }
+        if !cpp_files.is_empty() {
+            self.print(&"cargo:rerun-if-env-changed=CXXSTDLIB");
+            if let Some(stdlib) = platform::cpp_stdlib(&target) {
+                self.print(&format!("cargo:rustc-link-lib={stdlib}"));
+            }
+        }
+        objects
+    }
+    /// Rename the C library functions ISPC prints and aborts with in the `objects` to the
+    /// handlers in the runtime, for `route_print` and `route_asserts`
+    fn redirect_symbols(&self, objects: &[PathBuf]) {
+        let mut redirects = Vec::new();
+        if self.route_print {
+            redirects.push(("fputs", "ispc_rt_fputs"));
+            redirects.push(("fflush", "ispc_rt_fflush"));
+        }
+        if self.route_asserts {
+            redirects.push(("printf", "ispc_rt_assert_printf"));
+            redirects.push(("puts", "ispc_rt_assert_puts"));
+            redirects.push(("abort", "ispc_rt_abort"));
+        }
+        if redirects.is_empty() {
+            return;
+        }
+        let target = self.get_target();
+        if target.contains("msvc") {
+            exit_failure!(
+                "route_print and route_asserts aren't supported on {target}, the calls can't be \
+                 redirected in the COFF objects of MSVC targets"
+            );
+        }
+        let (objcopy, prefix) = if target.contains("apple") {
+            ("llvm-objcopy", "_")
+        } else {
+            ("objcopy", "")
+        };
+        let objcopy = env::var("OBJCOPY").unwrap_or_else(|_| objcopy.to_owned());
+        self.print(&"cargo:rerun-if-env-changed=OBJCOPY");
+        for o in objects {
+            let status = Command::new(&objcopy)
+                .args(
+                    redirects
+                        .iter()
+                        .map(|(from, to)| format!("--redefine-sym={prefix}{from}={prefix}{to}")),
+                )
+                .arg(o)
+                .status();
+            match status {
+                Ok(s) if s.success() => {}
+                Ok(_) => exit_failure!(
+                    "Failed to redirect print and abort calls in ISPC object {}",
+                    o.display()
+                ),
+                Err(e) => exit_failure!(
+                    "Failed to run {objcopy} to redirect ISPC print and abort calls, set OBJCOPY to the objcopy to use: {e}"
+                ),
+            }
+        }
+    }
+    /// Mark the `objects` with the Apple platform and deployment target they're built for,
+    /// so Xcode's linker accepts them in simulator and tvOS builds, see
+    /// `platform::apple_build_version`
+    fn set_apple_build_version(&self, objects: &[PathBuf]) {
+        let Some((platform, version)) = platform::apple_build_version(&self.get_target()) else {
+            return;
+        };
+        if !cfg!(target_os = "macos") {
+            self.print(&format!(
+                "cargo:warning=ispc-rs: Can't mark ISPC objects for {platform} {version} without \
+                 Xcode's vtool, they're left marked for iOS"
+            ));
+            return;
+        }
+        for o in objects {
+            let status = Command::new("xcrun")
+                .args(["vtool", "-set-build-version", platform, &version, &version])
+                .args(["-replace", "-output"])
+                .arg(o)
+                .arg(o)
+                .status();
+            match status {
+                Ok(s) if s.success() => {}
+                Ok(_) => exit_failure!(
+                    "Failed to set the build version of ISPC object {} to {platform} {version}",
+                    o.display()
+                ),
+                Err(e) => exit_failure!("Failed to run xcrun vtool, is Xcode installed? {e}"),
+            }
+        }
+    }
+    /// Compare the bindings of `lib` written to `bindings` with the golden file `snapshot`,
+    /// or update it, see `bindings_snapshot`
+    fn check_bindings_snapshot(&self, lib: &str, bindings: &Path, snapshot: &Path) {
+        self.print(&"cargo:rerun-if-env-changed=ISPC_UPDATE_SNAPSHOTS");
+        self.print(&format!("cargo:rerun-if-changed={}", snapshot.display()));
+        let actual = match fs::read_to_string(bindings) {
+            Ok(b) => snapshot::normalize(&b),
+            Err(e) => exit_failure!("Failed to read the bindings of {lib}: {e}"),
+        };
+        let update = env::var_os("ISPC_UPDATE_SNAPSHOTS").is_some();
+        match fs::read_to_string(snapshot) {
+            Ok(expected) if !update => {
+                let expected = snapshot::normalize(&expected);
+                if expected != actual {
+                    exit_failure!(
+                        "The bindings of {lib} differ from the snapshot {}, rebuild with \
+                         ISPC_UPDATE_SNAPSHOTS=1 to update it if the changes are expected:\n{}",
+                        snapshot.display(),
+                        snapshot::diff(&expected, &actual)
+                    );
+                }
+            }
+            _ => {
+                if let Some(dir) = snapshot.parent() {
+                    let _ = fs::create_dir_all(dir);
+                }
+                if let Err(e) = fs::write(snapshot, &actual) {
+                    exit_failure!(
+                        "Failed to write the bindings snapshot {}: {e}",
+                        snapshot.display()
+                    );
+                }
+            }
+        }
+    }
+    /// Compile the patterns passed to `export_only` and `skip_exports`
+    fn export_patterns(&self) -> (Vec<Regex>, Vec<Regex>) {
+        let compile = |patterns: &[String]| -> Vec<Regex> {
+            patterns
+                .iter()
+                .map(|p| match Regex::new(&format!("^(?:{p})$")) {
+                    Ok(r) => r,
+                    Err(e) => exit_failure!("Invalid export pattern {p}: {e}"),
+                })
+                .collect()
+        };
+        (compile(&self.export_only), compile(&self.skip_exports))
+    }
+    /// Generate the wrappers for the functions selected with `result_wrappers`
+    fn generate_result_wrappers(&self, fns: &[wrappers::ExternFn]) -> String {
+        let patterns: Vec<Regex> = self
+            .result_wrappers
+            .iter()
+            .map(|p| match Regex::new(&format!("^(?:{p})$")) {
+                Ok(r) => r,
+                Err(e) => exit_failure!("Invalid result wrapper pattern {p}: {e}"),
+            })
+            .collect();
+        let mut selected = Vec::new();
+        for f in fns {
+            if !patterns.iter().any(|p| p.is_match(&f.name)) {
+                continue;
+            }
+            match f.ret {
+                Some(ref ret) if wrappers::is_status_type(ret) => selected.push(f),
+                _ => self.print(&format!(
+                    "cargo:warning=(ISPC) Not generating a Result wrapper for {}, it doesn't return an integer status code",
+                    f.name
+                )),
+            }
+        }
+        let default_mapping = format!("{}::IspcError::check", self.runtime_crate);
+        let mapping = self.error_mapping.as_deref().unwrap_or(&default_mapping);
+        wrappers::result_wrappers(selected, mapping, &self.runtime_crate)
+    }
+    /// Load the descriptions of the types added with `shared_types`, along with the
+    /// names of the ISPC headers to generate for them.
+    fn load_shared_types(&self) -> Vec<(String, SharedTypes)> {
+        let mut shared = Vec::new();
+        for path in &self.shared_types {
+            self.print(&format!("cargo:rerun-if-changed={}", path.display()));
+            let header = path
+                .with_extension("isph")
+                .file_name()
+                .and_then(|f| f.to_str())
+                .map(|f| f.to_owned())
+                .expect("Shared type descriptions must be files with UTF-8 names");
+            match SharedTypes::load(path) {
+                Ok(types) => shared.push((header, types)),
+                Err(e) => exit_failure!("Invalid shared types in {}: {}", path.display(), e),
+            }
+        }
+        shared
+    }
+    /// Parse the signatures of the callbacks declared with `callback`
+    fn load_callbacks(&self) -> Vec<Callback> {
+        let mut callbacks = Vec::new();
+        for (name, signature) in &self.callbacks {
+            if !is_module_name(name) {
+                exit_failure!("Callback name {name} must be a valid identifier");
+            }
+            match Callback::parse(name, signature) {
+                Ok(c) => callbacks.push(c),
+                Err(e) => exit_failure!("Invalid callback signature {signature}: {e}"),
+            }
+        }
+        callbacks
+    }
+    /// Write the headers added with `generated_header` and those for the `shared`
+    /// types and `callbacks`, returns the directory holding them if there are any.
+    fn write_generated_headers(
+        &self,
+        shared: &[(String, SharedTypes)],
+        callbacks: &[Callback],
+    ) -> Option<PathBuf> {
+        if self.generated_headers.is_empty() && shared.is_empty() && callbacks.is_empty() {
+            return None;
+        }
+        let dir = self.get_build_dir().join("generated_headers");
+        if let Err(e) = fs::create_dir_all(&dir) {
+            exit_failure!("Failed to create generated header directory: {}", e);
+        }
+        let shared = shared
+            .iter()
+            .map(|(name, types)| (name.clone(), types.ispc_declarations()));
+        let callbacks = (!callbacks.is_empty()).then(|| {
+            (
+                String::from("callbacks.isph"),
+                callbacks::ispc_declarations(callbacks),
+            )
+        });
+        for (name, declarations) in self
+            .generated_headers
+            .iter()
+            .cloned()
+            .chain(shared)
+            .chain(callbacks)
+        {
+            let guard = name
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() {
+                        c.to_ascii_uppercase()
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>();
+            let contents = format!(
+                "// Generated by ispc_compile, do not edit\n\
+                 #ifndef ISPC_GENERATED_{guard}\n\
+                 #define ISPC_GENERATED_{guard}\n\n\
+                 {declarations}\n\
+                 #endif\n"
+            );
+            // Only rewrite the header if it changed to not trigger needless rebuilds
+            let path = dir.join(&name);
+            if fs::read_to_string(&path).ok().as_deref() != Some(&contents) {
+                if let Err(e) = fs::write(&path, contents) {
+                    exit_failure!("Failed to write generated header {}: {}", name, e);
+                }
+            }
+        }
+        Some(dir)
+    }
+    /// The directory the code added with `add_source_str` is written to for `lib`
+    fn source_str_dir(&self, lib: &str) -> PathBuf {
+        self.get_build_dir().join(lib).join("sources")
+    }
+    /// Get the paths of the ISPC files compiled into `lib`, including the code added
+    /// with `add_source_str`
+    fn source_files(&self, lib: &str) -> Vec<PathBuf> {
+        let dir = self.source_str_dir(lib);
+        let mut files = self.ispc_files.clone();
+        files.extend(self.source_strs.iter().map(|(name, _)| dir.join(name)));
+        files
+    }
+    /// Write the code added with `add_source_str` to the build directory of `lib`
+    fn write_source_strs(&self, lib: &str) {
+        if self.source_strs.is_empty() {
+            return;
+        }
+        let dir = self.source_str_dir(lib);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            exit_failure!("Failed to create source directory for {}: {}", lib, e);
+        }
+        let mut names = HashSet::new();
+        for (name, code) in &self.source_strs {
+            if Path::new(name).file_name() != Some(name.as_ref()) {
+                exit_failure!("ISPC source name {name} must be a file name without directories");
+            }
+            if !names.insert(name) {
+                exit_failure!("ISPC source {name} was added to {lib} more than once");
+            }
+            // Only rewrite the file if it changed to not trigger needless rebuilds
+            let path = dir.join(name);
+            if fs::read_to_string(&path).ok().as_deref() != Some(code) {
+                if let Err(e) = fs::write(&path, code) {
+                    exit_failure!("Failed to write ISPC source {}: {}", name, e);
+                }
+            }
+        }
+    }
+    /// Combine the ISPC generated `headers` into a single header for the library `lib`
+    /// and write it to `dir`, for C and C++ code calling the library
+    fn install_header(&self, lib: &str, dir: &Path, headers: &[PathBuf]) {
+        if let Err(e) = fs::create_dir_all(dir) {
+            exit_failure!("Failed to create header directory {}: {}", dir.display(), e);
+        }
+        let guard = lib.to_ascii_uppercase();
+        let mut contents = format!(
+            "// Generated by ispc_compile from the ISPC library {lib}, do not edit\n\
+             #ifndef ISPC_LIBRARY_{guard}_H\n\
+             #define ISPC_LIBRARY_{guard}_H\n"
+        );
+        for h in headers {
+            let header = match fs::read_to_string(h) {
+                Ok(h) => h,
+                Err(e) => exit_failure!("Failed to read ISPC header {}: {}", h.display(), e),
+            };
+            // The headers guard each struct declaration, so they can be combined
+            let name = h.file_name().unwrap().to_string_lossy();
+            contents.push_str(&format!("\n// From {name}\n{header}"));
+        }
+        contents.push_str(&format!("\n#endif // ISPC_LIBRARY_{guard}_H\n"));
+        // Only rewrite the header if it changed to not trigger needless rebuilds
+        let path = dir.join(lib).with_extension("h");
+        if fs::read_to_string(&path).ok().as_deref() != Some(&contents) {
+            if let Err(e) = fs::write(&path, contents) {
+                exit_failure!("Failed to install header {}: {}", path.display(), e);
+            }
+        }
     }
     /// Generate a single header that includes all of our ISPC headers which we can
     /// pass to bindgen
@@ -462,7 +1989,7 @@ impl Config {
         bindgen_header
     }
     /// Build up list of basic args for each target, debug, opt level, etc.
-    fn default_args(&self) -> Vec<String> {
+    fn default_args(&self, target_isas: Option<&[TargetISA]>) -> Vec<String> {
         let mut ispc_args = Vec::new();
         let opt_level = self.get_opt_level();
         if self.get_debug() {
@@ -483,17 +2010,18 @@ impl Config {
             ispc_args.push(format!("-O{opt_level}"));
         }
 
-        // If we're on Unix we need position independent code
-        if cfg!(unix) {
+        let target = self.get_target();
+        // If we're on Unix we need position independent code, as do Android's
+        // shared libraries when cross compiling from Windows
+        if cfg!(unix) || target.contains("android") {
             ispc_args.push(String::from("--pic"));
         }
-        let target = self.get_target();
-        if target.starts_with("i686") {
-            ispc_args.push(String::from("--arch=x86"));
-        } else if target.starts_with("x86_64") {
-            ispc_args.push(String::from("--arch=x86-64"));
-        } else if target.starts_with("aarch64") {
-            ispc_args.push(String::from("--arch=aarch64"));
+        // The exported functions have to be exported from the DLL to look them up
+        if self.shared_library && target.contains("windows") {
+            ispc_args.push(String::from("--dllexport"));
+        }
+        if let Some(arch) = platform::arch_flag(&target) {
+            ispc_args.push(String::from(arch));
         }
         for (name, value) in &self.defines {
             match value {
@@ -501,6 +2029,9 @@ impl Config {
                 None => ispc_args.push(format!("-D{name}")),
             }
         }
+        for d in &self.typed_defines {
+            ispc_args.push(format!("-D{}={}", d.name, d.ispc));
+        }
         ispc_args.push(self.math_lib.to_string());
         if let Some(ref s) = self.addressing {
             ispc_args.push(s.to_string());
@@ -541,7 +2072,10 @@ impl Config {
         if self.enable_llvm_intrinsics {
             ispc_args.push(String::from("--enable-llvm-intrinsics"));
         }
-        if let Some(ref t) = self.target_isa {
+        if self.use_vectorcall() {
+            ispc_args.push(String::from("--vectorcall"));
+        }
+        if let Some(t) = target_isas {
             let mut isa_str = String::from("--target=");
             for (i, isa) in t.iter().enumerate() {
                 if i > 0 {
@@ -551,21 +2085,55 @@ impl Config {
                 let _ = write!(isa_str, "{isa}");
             }
             ispc_args.push(isa_str);
-        } else if target.starts_with("aarch64") {
-            // For arm we may need to override the default target ISA,
-            // e.g. on macOS with ISPC running in Rosetta, ISPC will default to
-            // SSE4, but we need NEON
-
-            ispc_args.push(String::from("--target=neon-i32x4"));
+        } else if let Some(isa) = platform::default_isa(&target) {
+            // For arm we may need to override the default target ISA, which ISPC
+            // picks for the host it runs on
+            ispc_args.push(format!("--target={isa}"));
         }
         if let Some(ref a) = self.architecture {
             ispc_args.push(a.to_string());
         }
         if let Some(ref o) = self.target_os {
             ispc_args.push(o.to_string());
+        } else if let Some(o) = platform::target_os(&target) {
+            ispc_args.push(o.to_string());
         }
         ispc_args
     }
+    /// Check if `vectorcall` was enabled and the target supports it
+    fn use_vectorcall(&self) -> bool {
+        let target = self.get_target();
+        let arch = platform::target_arch(&target);
+        self.vectorcall && target.contains("msvc") && matches!(arch.as_str(), "x86" | "x86_64")
+    }
+    /// Get the target ISAs to compile for, dropping the x86 ISAs when building for ARM and
+    /// the NEON ISAs when building for x86, so one list of ISAs works for both architectures
+    fn get_target_isas(&self) -> Option<Vec<TargetISA>> {
+        let target = self.get_target();
+        let auto_isas;
+        let isas = match self.target_isa {
+            Some(ref isas) => isas,
+            None if self.auto_targets => {
+                auto_isas = platform::auto_isas(&target, &supported_targets());
+                &auto_isas
+            }
+            None => return None,
+        };
+        let arm = platform::is_arm(&target);
+        let x86 = matches!(platform::target_arch(&target).as_str(), "x86" | "x86_64");
+        let (kept, skipped): (Vec<TargetISA>, Vec<TargetISA>) = isas
+            .iter()
+            .partition(|isa| !(arm && isa.is_x86() || x86 && isa.is_neon()));
+        if !skipped.is_empty() {
+            let skipped: Vec<String> = skipped.iter().map(|isa| isa.to_string()).collect();
+            self.print(&format!(
+                "cargo:warning=ispc-rs: Skipping target ISAs {} which can't run on {target}",
+                skipped.join(", ")
+            ));
+        }
+        // Fall back to the default ISA of the target if none of the selected ones apply
+        (!kept.is_empty()).then_some(kept)
+    }
     /// Returns the user-set output directory if they've set one, otherwise
     /// returns env("OUT_DIR")
     fn get_out_dir(&self) -> PathBuf {
@@ -612,6 +2180,16 @@ impl Config {
     }
 }
 
+/// Check if `lib` can be used as the name of the module holding its bindings
+fn is_module_name(lib: &str) -> bool {
+    let mut chars = lib.chars();
+    chars
+        .next()
+        .is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+        && lib != "_"
+}
+
 impl Default for Config {
     fn default() -> Config {
         Config::new()
@@ -0,0 +1,274 @@
+//! Renames the functions and types in the generated bindings to follow Rust's naming
+//! conventions, see `Config::snake_case_names` and `Config::strip_name_prefix`, and
+//! groups the functions into modules by the source files exporting them.
+
+use std::collections::{HashMap, HashSet};
+
+use bindgen::callbacks::ParseCallbacks;
+use regex::Regex;
+
+/// The naming options set on the `Config`
+#[derive(Clone, Default)]
+pub(crate) struct Naming {
+    pub snake_case: bool,
+    pub strip_prefix: Option<String>,
+    pub modules_per_file: bool,
+}
+
+impl Naming {
+    /// Check if any of the items are renamed
+    pub(crate) fn renames(&self) -> bool {
+        self.snake_case || self.strip_prefix.is_some()
+    }
+    /// Get the Rust name of the exported function `name`
+    pub(crate) fn function_name(&self, name: &str) -> String {
+        let name = self.strip(name);
+        if self.snake_case {
+            snake_case(name)
+        } else {
+            name.to_owned()
+        }
+    }
+    /// Get the Rust name of the struct or enum `name`
+    pub(crate) fn type_name(&self, name: &str) -> String {
+        let name = self.strip(name);
+        if self.snake_case {
+            camel_case(name)
+        } else {
+            name.to_owned()
+        }
+    }
+    /// Strip the prefix from `name`, unless that doesn't leave a valid identifier
+    fn strip<'a>(&self, name: &'a str) -> &'a str {
+        match self
+            .strip_prefix
+            .as_deref()
+            .and_then(|p| name.strip_prefix(p))
+        {
+            Some(s) if s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') => s,
+            _ => name,
+        }
+    }
+}
+
+/// Renames the exported functions and types through bindgen, which also keeps the
+/// original names of the functions to link against
+#[derive(Clone, Debug)]
+pub(crate) struct Renames {
+    names: HashMap<String, String>,
+}
+
+impl Renames {
+    /// Find the names of the exported `functions` and `types` after applying the `naming`
+    pub(crate) fn new(naming: &Naming, functions: &[String], types: &[String]) -> Renames {
+        let functions = functions
+            .iter()
+            .map(|f| (f.clone(), naming.function_name(f)));
+        let types = types.iter().map(|t| (t.clone(), naming.type_name(t)));
+        Renames {
+            names: functions.chain(types).filter(|(a, b)| a != b).collect(),
+        }
+    }
+    /// Get the new name of the item `name`
+    pub(crate) fn get<'a>(&'a self, name: &'a str) -> &'a str {
+        self.names.get(name).map(|n| n.as_str()).unwrap_or(name)
+    }
+}
+
+impl ParseCallbacks for Renames {
+    fn item_name(&self, original_item_name: &str) -> Option<String> {
+        self.names.get(original_item_name).cloned()
+    }
+}
+
+/// Find the names of the functions exported in an ISPC generated `header`
+pub(crate) fn exported_functions(header: &str) -> Vec<String> {
+    let decl = Regex::new(r#"(?m)^\s*extern\s+[^"(;{]*?\b(\w+)\s*\("#).unwrap();
+    let mut names: Vec<String> = Vec::new();
+    for c in decl.captures_iter(header) {
+        if !names.iter().any(|n| *n == c[1]) {
+            names.push(c[1].to_owned());
+        }
+    }
+    names
+}
+
+/// Find the names of the structs and enums declared in an ISPC generated `header`
+pub(crate) fn exported_types(header: &str, types: &mut HashSet<String>) {
+    let decl = Regex::new(r"(?m)^\s*(?:struct|enum)\s+(\w+)\s*\{").unwrap();
+    types.extend(decl.captures_iter(header).map(|c| c[1].to_owned()));
+}
+
+/// The functions exported by an ISPC source file
+pub(crate) struct FileFunctions {
+    /// Name of the module to group the functions in
+    pub module: String,
+    /// Name of the source file
+    pub file: String,
+    pub functions: Vec<String>,
+}
+
+/// Generate a module for each ISPC source file, re-exporting the functions it exports
+pub(crate) fn file_modules(files: &[FileFunctions], renames: &Renames) -> String {
+    let mut out = String::new();
+    for f in files {
+        if f.functions.is_empty() {
+            continue;
+        }
+        let names: Vec<&str> = f.functions.iter().map(|f| renames.get(f)).collect();
+        out.push_str(&format!(
+            "/// The functions exported from `{}`\n\
+             pub mod {} {{\n\
+             \x20   pub use super::{{{}}};\n\
+             }}\n",
+            f.file,
+            f.module,
+            names.join(", ")
+        ));
+    }
+    out
+}
+
+/// Get a module name for the ISPC source file with the name `stem`
+pub(crate) fn module_name(stem: &str) -> String {
+    let name = snake_case(stem)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{name}")
+    } else {
+        name
+    }
+}
+
+/// Convert a camel case name to snake case, e.g. `renderTileAO` to `render_tile_ao`
+pub(crate) fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut out = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_ascii_lowercase());
+            // Start a new word after a lowercase letter, or at the last capital of
+            // an acronym or number followed by a lowercase word
+            if prev.is_ascii_lowercase()
+                || ((prev.is_ascii_uppercase() || prev.is_ascii_digit()) && next_lower)
+            {
+                out.push('_');
+            }
+        }
+        out.push(c.to_ascii_lowercase());
+    }
+    out
+}
+
+/// Convert a snake case name to camel case, e.g. `transfer_function` to `TransferFunction`
+pub(crate) fn camel_case(name: &str) -> String {
+    name.split('_')
+        .filter(|w| !w.is_empty())
+        .map(|w| {
+            let mut c = w.chars();
+            c.next()
+                .map(|f| f.to_ascii_uppercase().to_string() + c.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{camel_case, exported_functions, exported_types, module_name, snake_case};
+    use super::{file_modules, FileFunctions, Naming, Renames};
+
+    /// Declarations as ISPC writes them to its headers
+    const HEADER: &str = "#if defined(__cplusplus) && (! defined(__ISPC_NO_EXTERN_C) || !__ISPC_NO_EXTERN_C )\n\
+                          extern \"C\" {\n\
+                          #endif // __cplusplus\n\
+                          struct Scene;\n\
+                          enum Mode { MODE_FAST = 0, MODE_EXACT = 1 };\n\
+                          struct Params {\n\
+                          \x20   float scale;\n\
+                          };\n\
+                          \x20   extern void ispc_addLists(const float * a, const float * b, float * c, int32_t count);\n\
+                          \x20   extern struct Params * ispc_makeParams(void);\n\
+                          \x20   extern int32_t renderTileAO(struct Scene * scene,\n\
+                          \x20       int32_t x, int32_t y);\n\
+                          \x20   extern void ispc_addLists(const float * a, const float * b, float * c, int32_t count);\n\
+                          }\n";
+
+    #[test]
+    fn finds_exported_functions_and_types() {
+        assert_eq!(
+            exported_functions(HEADER),
+            ["ispc_addLists", "ispc_makeParams", "renderTileAO"]
+        );
+        let mut types = HashSet::new();
+        exported_types(HEADER, &mut types);
+        let mut types: Vec<String> = types.into_iter().collect();
+        types.sort();
+        assert_eq!(types, ["Mode", "Params"]);
+    }
+
+    #[test]
+    fn converts_case() {
+        assert_eq!(snake_case("renderTileAO"), "render_tile_ao");
+        assert_eq!(snake_case("HTTPServer"), "http_server");
+        assert_eq!(snake_case("blur2DImage"), "blur2d_image");
+        assert_eq!(snake_case("add_lists"), "add_lists");
+        assert_eq!(camel_case("transfer_function"), "TransferFunction");
+        assert_eq!(camel_case("_volume__grid"), "VolumeGrid");
+    }
+
+    #[test]
+    fn renames_items() {
+        let naming = Naming {
+            snake_case: true,
+            strip_prefix: Some(String::from("ispc_")),
+            modules_per_file: false,
+        };
+        assert_eq!(naming.function_name("ispc_addLists"), "add_lists");
+        assert_eq!(naming.type_name("ispc_volume_grid"), "VolumeGrid");
+        // Stripping the prefix can't leave an invalid identifier
+        assert_eq!(naming.function_name("ispc_2d"), "ispc_2d");
+
+        let functions = exported_functions(HEADER);
+        let renames = Renames::new(&naming, &functions, &[String::from("Params")]);
+        assert_eq!(renames.get("ispc_makeParams"), "make_params");
+        assert_eq!(renames.get("Params"), "Params");
+        assert_eq!(renames.get("other"), "other");
+    }
+
+    #[test]
+    fn groups_functions_by_file() {
+        assert_eq!(module_name("2d-blur"), "_2d_blur");
+        assert_eq!(module_name("ddVolume"), "dd_volume");
+        let naming = Naming {
+            snake_case: true,
+            ..Naming::default()
+        };
+        let functions = exported_functions(HEADER);
+        let renames = Renames::new(&naming, &functions, &[]);
+        let files = [
+            FileFunctions {
+                module: module_name("ao"),
+                file: String::from("ao.ispc"),
+                functions: vec![String::from("renderTileAO")],
+            },
+            FileFunctions {
+                module: module_name("empty"),
+                file: String::from("empty.ispc"),
+                functions: Vec::new(),
+            },
+        ];
+        assert_eq!(
+            file_modules(&files, &renames),
+            "/// The functions exported from `ao.ispc`\n\
+             pub mod ao {\n\
+             \x20   pub use super::{render_tile_ao};\n\
+             }\n"
+        );
+    }
+}
@@ -31,6 +31,8 @@ pub enum Architecture {
     X86,
     X64,
     Xe64,
+    /// 64-bit RISC-V with the vector extension, for ISPC versions supporting it
+    Riscv64,
 }
 
 impl std::fmt::Display for Architecture {
@@ -41,6 +43,7 @@ impl std::fmt::Display for Architecture {
             Architecture::X86 => write!(f, "--arch=x86"),
             Architecture::X64 => write!(f, "--arch=x86_64"),
             Architecture::Xe64 => write!(f, "--arch=xe64"),
+            Architecture::Riscv64 => write!(f, "--arch=riscv64"),
         }
     }
 }
@@ -422,6 +425,25 @@ impl TargetISA {
             TargetISA::XEHPCx16 | TargetISA::XEHPCx32 => String::from("xehpc"),
         }
     }
+    /// Check if the target is one of the ARM NEON ISAs
+    pub fn is_neon(&self) -> bool {
+        self.lib_suffix() == "neon"
+    }
+    /// Check if the target is one of the x86 SSE or AVX ISAs
+    pub fn is_x86(&self) -> bool {
+        matches!(
+            self.lib_suffix().as_str(),
+            "sse2"
+                | "sse4"
+                | "avx"
+                | "avx2"
+                | "avx2vnni"
+                | "avx512knl"
+                | "avx512skx"
+                | "avx512icl"
+                | "avx512spr"
+        )
+    }
 }
 
 impl std::fmt::Display for TargetISA {
@@ -523,6 +545,105 @@ impl std::fmt::Display for TargetISA {
     }
 }
 
+impl TargetISA {
+    /// All target ISAs which aren't deprecated, with the SSE4 aliases left out in favor
+    /// of the SSE4.2 ISAs they're equal to
+    pub const ALL: &'static [TargetISA] = &[
+        TargetISA::Host,
+        TargetISA::Generici16x16,
+        TargetISA::Generici16x8,
+        TargetISA::Generici1x16,
+        TargetISA::Generici1x32,
+        TargetISA::Generici1x4,
+        TargetISA::Generici1x64,
+        TargetISA::Generici1x8,
+        TargetISA::Generici32x16,
+        TargetISA::Generici32x4,
+        TargetISA::Generici32x8,
+        TargetISA::Generici64x4,
+        TargetISA::Generici8x16,
+        TargetISA::Generici8x32,
+        TargetISA::SSE2i32x4,
+        TargetISA::SSE2i32x8,
+        TargetISA::SSE41i8x16,
+        TargetISA::SSE41i16x8,
+        TargetISA::SSE41i32x4,
+        TargetISA::SSE41i32x8,
+        TargetISA::SSE42i8x16,
+        TargetISA::SSE42i16x8,
+        TargetISA::SSE42i32x4,
+        TargetISA::SSE42i32x8,
+        TargetISA::AVX1i32x4,
+        TargetISA::AVX1i32x8,
+        TargetISA::AVX1i32x16,
+        TargetISA::AVX1i64x4,
+        TargetISA::AVX2i32x8,
+        TargetISA::AVX2i32x16,
+        TargetISA::AVX2i64x4,
+        TargetISA::AVX2i8x32,
+        TargetISA::AVX2i16x16,
+        TargetISA::AVX2i32x4,
+        TargetISA::AVX2VNNIi32x4,
+        TargetISA::AVX2VNNIi32x8,
+        TargetISA::AVX2VNNIi32x16,
+        TargetISA::AVX512SKXx4,
+        TargetISA::AVX512SKXx8,
+        TargetISA::AVX512SKXx16,
+        TargetISA::AVX512SKXx32,
+        TargetISA::AVX512SKXx64,
+        TargetISA::AVX512ICLx4,
+        TargetISA::AVX512ICLx8,
+        TargetISA::AVX512ICLx16,
+        TargetISA::AVX512ICLx32,
+        TargetISA::AVX512ICLx64,
+        TargetISA::AVX512SPRx4,
+        TargetISA::AVX512SPRx8,
+        TargetISA::AVX512SPRx16,
+        TargetISA::AVX512SPRx32,
+        TargetISA::AVX512SPRx64,
+        TargetISA::Neoni8x16,
+        TargetISA::Neoni8x32,
+        TargetISA::Neoni16x8,
+        TargetISA::Neoni16x16,
+        TargetISA::Neoni32x4,
+        TargetISA::Neoni32x8,
+        TargetISA::XELPx8,
+        TargetISA::XELPx16,
+        TargetISA::XEHPGx8,
+        TargetISA::XEHPGx16,
+        TargetISA::XEHPCx16,
+        TargetISA::XEHPCx32,
+    ];
+}
+
+impl std::str::FromStr for TargetISA {
+    type Err = String;
+
+    /// Parse the name ISPC uses for a target ISA, e.g. `avx2-i32x8`
+    fn from_str(s: &str) -> Result<TargetISA, String> {
+        TargetISA::ALL
+            .iter()
+            .find(|isa| isa.to_string() == s)
+            .copied()
+            .ok_or_else(|| format!("Unknown ISPC target ISA {s}"))
+    }
+}
+
+/// Sanitizers to instrument the ISPC code with, see `Config::sanitize`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sanitizer {
+    /// Detect out of bounds and use after free accesses with AddressSanitizer
+    Address,
+}
+
+impl std::fmt::Display for Sanitizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Sanitizer::Address => write!(f, "address"),
+        }
+    }
+}
+
 /// Target OS to specialize for.
 pub enum TargetOS {
     Windows,
@@ -531,6 +652,7 @@ pub enum TargetOS {
     Macos,
     Android,
     Ios,
+    FreeBsd,
 }
 
 impl TargetOS {
@@ -542,6 +664,7 @@ impl TargetOS {
             TargetOS::Macos => String::from("macos"),
             TargetOS::Android => String::from("android"),
             TargetOS::Ios => String::from("ios"),
+            TargetOS::FreeBsd => String::from("freebsd"),
         }
     }
 }
@@ -555,6 +678,7 @@ impl std::fmt::Display for TargetOS {
             TargetOS::Macos => write!(f, "--target-os=macos"),
             TargetOS::Android => write!(f, "--target-os=android"),
             TargetOS::Ios => write!(f, "--target-os=ios"),
+            TargetOS::FreeBsd => write!(f, "--target-os=freebsd"),
         }
     }
 }
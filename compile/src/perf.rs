@@ -0,0 +1,189 @@
+//! Collects the performance warnings ISPC issues while compiling, e.g. for gathers and
+//! scatters, into a report of the warnings in each function, see `Config::perf_report`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+
+use regex::Regex;
+
+/// A performance warning issued by ISPC
+pub(crate) struct PerfWarning {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl PerfWarning {
+    /// Classify the warning as a gather, scatter, divergent control flow or other issue
+    fn kind(&self) -> &'static str {
+        let message = self.message.to_lowercase();
+        if message.contains("gather") {
+            "gather"
+        } else if message.contains("scatter") {
+            "scatter"
+        } else if message.contains("varying")
+            && [
+                "condition",
+                "control flow",
+                "switch",
+                "loop",
+                "cif",
+                "cfor",
+                "cwhile",
+            ]
+            .iter()
+            .any(|w| message.contains(w))
+        {
+            "divergence"
+        } else {
+            "other"
+        }
+    }
+}
+
+/// Diagnostics printed by ISPC, e.g. `src/foo.ispc:12:5: Performance Warning: ...`
+fn diagnostic() -> Regex {
+    Regex::new(r"^(.+?):(\d+):(\d+):\s*(.*)$").unwrap()
+}
+
+/// Find the performance warnings in the `stderr` of ISPC
+pub(crate) fn parse_warnings(stderr: &str) -> Vec<PerfWarning> {
+    let diag = diagnostic();
+    stderr
+        .lines()
+        .filter_map(|l| {
+            let c = diag.captures(l)?;
+            let message = c[4].strip_prefix("Performance Warning:")?;
+            Some(PerfWarning {
+                file: c[1].to_owned(),
+                line: c[2].parse().ok()?,
+                column: c[3].parse().ok()?,
+                message: message.trim().to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Remove the performance warnings from the `stderr` of ISPC, along with the source
+/// lines ISPC prints after each to show where it is
+pub(crate) fn strip_warnings(stderr: &str) -> Vec<&str> {
+    let diag = diagnostic();
+    let mut skipping = false;
+    stderr
+        .lines()
+        .filter(|l| {
+            if let Some(c) = diag.captures(l) {
+                skipping = c[4].starts_with("Performance Warning:");
+            }
+            !skipping
+        })
+        .collect()
+}
+
+/// Find the function whose body contains line `line` (1-based) of the ISPC `source`
+fn function_at(source: &str, line: usize) -> Option<String> {
+    let name = Regex::new(r"(\w+)\s*\([^;{}]*\)\s*$").unwrap();
+    let mut depth = 0;
+    // The declaration before the body being parsed, and the function it defines
+    let mut signature = String::new();
+    let mut current = None;
+    for (i, l) in source.lines().enumerate() {
+        for c in l.split("//").next().unwrap_or_default().chars() {
+            match c {
+                '{' if depth == 0 => {
+                    current = name.captures(&signature).map(|c| c[1].to_owned());
+                    depth += 1;
+                }
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        if i + 1 >= line {
+                            return current;
+                        }
+                        signature.clear();
+                    }
+                }
+                ';' if depth == 0 => signature.clear(),
+                c if depth == 0 => signature.push(c),
+                _ => {}
+            }
+        }
+        signature.push(' ');
+        // The line is outside of any function body
+        if i + 1 >= line && depth == 0 {
+            return None;
+        }
+    }
+    None
+}
+
+/// Generate a JSON report of the performance `warnings` issued building the library
+/// `lib`, grouped by the function they're in
+pub(crate) fn report(lib: &str, warnings: &[PerfWarning]) -> String {
+    let mut sources = BTreeMap::new();
+    let mut functions: BTreeMap<(String, String), Vec<&PerfWarning>> = BTreeMap::new();
+    for w in warnings {
+        let source = sources
+            .entry(w.file.clone())
+            .or_insert_with(|| fs::read_to_string(&w.file).unwrap_or_default());
+        let function = function_at(source, w.line).unwrap_or_default();
+        functions
+            .entry((w.file.clone(), function))
+            .or_default()
+            .push(w);
+    }
+    let mut out = String::new();
+    writeln!(out, "{{").unwrap();
+    writeln!(out, "  \"library\": {},", json_string(lib)).unwrap();
+    writeln!(out, "  \"warnings\": {},", warnings.len()).unwrap();
+    writeln!(out, "  \"functions\": [").unwrap();
+    for (i, ((file, function), warnings)) in functions.iter().enumerate() {
+        let count = |kind| warnings.iter().filter(|w| w.kind() == kind).count();
+        writeln!(out, "    {{").unwrap();
+        writeln!(out, "      \"file\": {},", json_string(file)).unwrap();
+        writeln!(out, "      \"function\": {},", json_string(function)).unwrap();
+        writeln!(out, "      \"gathers\": {},", count("gather")).unwrap();
+        writeln!(out, "      \"scatters\": {},", count("scatter")).unwrap();
+        writeln!(out, "      \"divergence\": {},", count("divergence")).unwrap();
+        writeln!(out, "      \"other\": {},", count("other")).unwrap();
+        writeln!(out, "      \"warnings\": [").unwrap();
+        for (j, w) in warnings.iter().enumerate() {
+            let sep = if j + 1 < warnings.len() { "," } else { "" };
+            writeln!(
+                out,
+                "        {{ \"line\": {}, \"column\": {}, \"kind\": \"{}\", \"message\": {} }}{sep}",
+                w.line,
+                w.column,
+                w.kind(),
+                json_string(&w.message)
+            )
+            .unwrap();
+        }
+        writeln!(out, "      ]").unwrap();
+        let sep = if i + 1 < functions.len() { "," } else { "" };
+        writeln!(out, "    }}{sep}").unwrap();
+    }
+    writeln!(out, "  ]").unwrap();
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// Quote and escape `s` as a JSON string
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,315 @@
+//! Derives the ISPC options and tools to build for a Rust target triple, so cross
+//! compiling for a platform works without configuring it by hand.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::opt::{Sanitizer, TargetISA, TargetOS};
+
+/// Get the Rust architecture of the `target` triple. For the target Cargo is building
+/// for this is `CARGO_CFG_TARGET_ARCH`, otherwise it's parsed from the triple.
+pub(crate) fn target_arch(target: &str) -> String {
+    if env::var("TARGET").is_ok_and(|t| t == target) {
+        if let Ok(arch) = env::var("CARGO_CFG_TARGET_ARCH") {
+            return arch;
+        }
+    }
+    let arch = target.split('-').next().unwrap_or(target);
+    match arch {
+        "i386" | "i586" | "i686" => String::from("x86"),
+        _ if arch.starts_with("arm") || arch.starts_with("thumb") => String::from("arm"),
+        _ if arch.starts_with("riscv64") => String::from("riscv64"),
+        _ => arch.to_owned(),
+    }
+}
+
+/// Get the `--arch` flag for the architecture of the `target` triple
+pub(crate) fn arch_flag(target: &str) -> Option<&'static str> {
+    match target_arch(target).as_str() {
+        "x86" => Some("--arch=x86"),
+        "x86_64" => Some("--arch=x86-64"),
+        "aarch64" => Some("--arch=aarch64"),
+        "arm" if is_arm32(target) => Some("--arch=arm"),
+        "riscv64" => Some("--arch=riscv64"),
+        _ => None,
+    }
+}
+
+/// Check if the `ispc` compiler can generate code for the architecture `arch`, which
+/// it lists in its support matrix. ISPC versions without the matrix only support the
+/// x86 and ARM architectures.
+pub(crate) fn ispc_supports_arch(arch: &str) -> bool {
+    Command::new("ispc")
+        .arg("--support-matrix")
+        .output()
+        .is_ok_and(|out| {
+            out.status.success() && String::from_utf8_lossy(&out.stdout).contains(arch)
+        })
+}
+
+/// Parse the target ISAs listed in the `--help` of ISPC as `<t>={host, sse2-i32x4, ...}`
+pub(crate) fn help_targets(help: &str) -> Vec<TargetISA> {
+    let list = match help.find("<t>={") {
+        Some(start) => &help[start + 5..],
+        None => return Vec::new(),
+    };
+    let list = &list[..list.find('}').unwrap_or(list.len())];
+    list.split(',')
+        .filter_map(|isa| isa.trim().parse().ok())
+        .collect()
+}
+
+/// Pick a set of the `supported` target ISAs to dispatch between at runtime on `target`
+pub(crate) fn auto_isas(target: &str, supported: &[TargetISA]) -> Vec<TargetISA> {
+    let groups: &[&[TargetISA]] = match target_arch(target).as_str() {
+        "x86" | "x86_64" => &[
+            &[TargetISA::SSE2i32x4],
+            &[TargetISA::SSE42i32x4, TargetISA::SSE41i32x4],
+            &[TargetISA::AVX1i32x8],
+            &[TargetISA::AVX2i32x8],
+            &[TargetISA::AVX512SKXx16],
+        ],
+        "aarch64" | "arm" => &[&[TargetISA::Neoni32x4]],
+        _ => &[],
+    };
+    // Take the first ISA of each group which is supported
+    groups
+        .iter()
+        .filter_map(|group| group.iter().find(|isa| supported.contains(isa)).copied())
+        .collect()
+}
+
+/// Get the flag instrumenting the code with the `sanitizer`, if the `ispc` compiler lists
+/// one in its `--help`
+pub(crate) fn ispc_sanitize_flag(sanitizer: Sanitizer) -> Option<String> {
+    let out = Command::new("ispc").arg("--help").output().ok()?;
+    let help = String::from_utf8_lossy(&out.stdout);
+    if help.contains("--sanitize=") {
+        Some(format!("--sanitize={sanitizer}"))
+    } else if help.contains(&format!("--{sanitizer}-sanitizer")) {
+        Some(format!("--{sanitizer}-sanitizer"))
+    } else {
+        None
+    }
+}
+
+/// Find the shared runtime of the `sanitizer` for `target`, returns the directory it's in
+/// and its name to link, or just the name if it's in the linker's default search path
+pub(crate) fn sanitizer_runtime(
+    sanitizer: Sanitizer,
+    target: &str,
+) -> Option<(Option<PathBuf>, String)> {
+    let arch = target_arch(target);
+    let candidates = match sanitizer {
+        Sanitizer::Address if target.contains("apple") => {
+            vec![String::from("libclang_rt.asan_osx_dynamic.dylib")]
+        }
+        Sanitizer::Address if target.contains("msvc") => {
+            vec![format!("clang_rt.asan_dynamic-{arch}.lib")]
+        }
+        Sanitizer::Address => vec![
+            String::from("libclang_rt.asan.so"),
+            format!("libclang_rt.asan-{arch}.so"),
+        ],
+    };
+    for file in candidates {
+        let found = Command::new("clang")
+            .arg(format!("--target={target}"))
+            .arg(format!("-print-file-name={file}"))
+            .output()
+            .ok()
+            .map(|out| PathBuf::from(String::from_utf8_lossy(&out.stdout).trim()))
+            .filter(|path| path.is_absolute() && path.exists());
+        if let Some(path) = found {
+            let name = path.file_stem()?.to_str()?;
+            let name = if target.contains("msvc") {
+                name
+            } else {
+                name.strip_prefix("lib").unwrap_or(name)
+            };
+            return Some((path.parent().map(PathBuf::from), name.to_owned()));
+        }
+    }
+    // GCC's runtime is in the default search path on Linux
+    (target.contains("linux") && sanitizer == Sanitizer::Address)
+        .then(|| (None, String::from("asan")))
+}
+
+/// Get the OS to pass with `--target-os` for the `target` triple, when ISPC wouldn't
+/// default to it from the host it runs on
+pub(crate) fn target_os(target: &str) -> Option<TargetOS> {
+    if target.contains("android") {
+        Some(TargetOS::Android)
+    } else if target.contains("bsd") {
+        // ISPC has no NetBSD or OpenBSD targets, but their ELF objects match FreeBSD's
+        Some(TargetOS::FreeBsd)
+    } else if target.contains("windows") {
+        // Needed for COFF objects when cross compiling from another host
+        Some(TargetOS::Windows)
+    } else if target.contains("apple-ios") || target.contains("apple-tvos") {
+        // ISPC has no tvOS target, the objects are marked for tvOS after compiling
+        Some(TargetOS::Ios)
+    } else {
+        None
+    }
+}
+
+/// Get the target ISA to use for the `target` triple when none was selected, for
+/// architectures where ISPC's default, chosen for the host, may not apply
+pub(crate) fn default_isa(target: &str) -> Option<&'static str> {
+    // E.g. on macOS with ISPC running in Rosetta, ISPC will default to SSE4, but we need NEON
+    if target.starts_with("aarch64") || is_arm32(target) {
+        Some("neon-i32x4")
+    } else {
+        None
+    }
+}
+
+/// Check if the `target` triple is for an ARM architecture, which can't run x86 ISAs
+pub(crate) fn is_arm(target: &str) -> bool {
+    matches!(target_arch(target).as_str(), "aarch64" | "arm")
+}
+
+/// Check if the build script is an x86-64 binary translated by Rosetta on an Apple
+/// Silicon Mac, in which case ISPC and the Rust toolchain likely are too
+pub(crate) fn running_under_rosetta() -> bool {
+    if !cfg!(all(target_os = "macos", target_arch = "x86_64")) {
+        return false;
+    }
+    Command::new("sysctl")
+        .args(["-n", "sysctl.proc_translated"])
+        .output()
+        .is_ok_and(|out| out.stdout.trim_ascii() == b"1")
+}
+
+/// Check if the `target` triple is for 32-bit ARM with NEON, which ISPC requires
+fn is_arm32(target: &str) -> bool {
+    target.starts_with("armv7") || target.starts_with("thumbv7neon")
+}
+
+/// Get the Apple platform and minimum OS version to mark the objects built for `target`
+/// with, as ISPC marks them for the iOS device platform without a deployment target.
+/// The version is read from the same environment variables as `rustc` uses.
+pub(crate) fn apple_build_version(target: &str) -> Option<(&'static str, String)> {
+    let simulator = target.ends_with("-sim") || target.starts_with("x86_64");
+    let (platform, var, default) = if target.contains("apple-ios") {
+        let default = if simulator && target.starts_with("aarch64") {
+            "14.0"
+        } else {
+            "10.0"
+        };
+        let platform = if simulator { "iossim" } else { "ios" };
+        (platform, "IPHONEOS_DEPLOYMENT_TARGET", default)
+    } else if target.contains("apple-tvos") {
+        let platform = if simulator { "tvossim" } else { "tvos" };
+        (platform, "TVOS_DEPLOYMENT_TARGET", "10.0")
+    } else {
+        return None;
+    };
+    let version = env::var(var).unwrap_or_else(|_| default.to_owned());
+    Some((platform, version))
+}
+
+/// Get the command to archive objects into static libraries for `target` with. As in
+/// the `cc` crate the archiver can be set through the `AR_<target>`, `TARGET_AR` or `AR`
+/// environment variables. Android targets use the `llvm-ar` of the NDK, found through
+/// `ANDROID_NDK_HOME`, `ANDROID_NDK_ROOT` or `NDK_HOME`. Apple targets cross compiled
+/// from another host use `llvm-ar` to write the archive format Apple's linker expects.
+pub(crate) fn archiver(target: &str) -> Command {
+    if let Some(ar) = ar_env_vars(target).iter().find_map(env::var_os) {
+        return Command::new(ar);
+    }
+    if target.contains("android") {
+        if let Some(ar) = ndk_tool("llvm-ar") {
+            return Command::new(ar);
+        }
+    }
+    if target.contains("apple") && !cfg!(target_os = "macos") {
+        let mut ar = Command::new("llvm-ar");
+        ar.arg("--format=darwin");
+        return ar;
+    }
+    Command::new("ar")
+}
+
+/// Get the librarian to create static libraries for MSVC `target`s with. This is the
+/// archiver set through the environment variables read by `archiver`, or else the
+/// `lib.exe` of Visual Studio, falling back to `llvm-lib` when it can't be found, e.g.
+/// when cross compiling or on Windows ARM64 machines without the ARM64 build tools.
+pub(crate) fn msvc_librarian(target: &str) -> Command {
+    let mut lib = if let Some(ar) = ar_env_vars(target).iter().find_map(env::var_os) {
+        Command::new(ar)
+    } else if let Some(tool) = cc::windows_registry::find_tool(target, "lib.exe") {
+        tool.to_command()
+    } else {
+        Command::new("llvm-lib")
+    };
+    if target.starts_with("aarch64") {
+        lib.arg("/MACHINE:ARM64");
+    }
+    lib
+}
+
+/// Get the C++ standard library to link for `target`, which can be overridden or disabled
+/// with `CXXSTDLIB` like for the `cc` crate
+pub(crate) fn cpp_stdlib(target: &str) -> Option<String> {
+    if let Ok(stdlib) = env::var("CXXSTDLIB") {
+        return (!stdlib.is_empty()).then_some(stdlib);
+    }
+    if target.contains("msvc") {
+        None
+    } else if target.contains("apple") || target.contains("freebsd") || target.contains("openbsd") {
+        Some(String::from("c++"))
+    } else if target.contains("android") {
+        Some(String::from("c++_shared"))
+    } else {
+        Some(String::from("stdc++"))
+    }
+}
+
+/// Get the environment variables read to find the tools for `target`, to rerun the
+/// build script when they change
+pub(crate) fn tool_env_vars(target: &str) -> Vec<String> {
+    let mut vars = ar_env_vars(target).to_vec();
+    vars.extend(NDK_ENV_VARS.iter().map(|v| v.to_string()));
+    vars.extend(["IPHONEOS_DEPLOYMENT_TARGET", "TVOS_DEPLOYMENT_TARGET"].map(String::from));
+    vars
+}
+
+/// The environment variables pointing to the Android NDK, in order of preference
+const NDK_ENV_VARS: [&str; 3] = ["ANDROID_NDK_HOME", "ANDROID_NDK_ROOT", "NDK_HOME"];
+
+/// The environment variables which set the archiver for `target`, in order of preference
+fn ar_env_vars(target: &str) -> [String; 4] {
+    [
+        format!("AR_{target}"),
+        format!("AR_{}", target.replace('-', "_")),
+        String::from("TARGET_AR"),
+        String::from("AR"),
+    ]
+}
+
+/// Find the `tool` in the LLVM toolchain of the Android NDK
+fn ndk_tool(tool: &str) -> Option<PathBuf> {
+    let ndk = NDK_ENV_VARS.iter().find_map(env::var_os)?;
+    // The NDK only ships x86-64 host tools, which run through Rosetta on Apple Silicon
+    let host = if cfg!(target_os = "macos") {
+        "darwin-x86_64"
+    } else if cfg!(windows) {
+        "windows-x86_64"
+    } else {
+        "linux-x86_64"
+    };
+    let exe = if cfg!(windows) {
+        format!("{tool}.exe")
+    } else {
+        tool.to_owned()
+    };
+    let path = PathBuf::from(ndk)
+        .join("toolchains/llvm/prebuilt")
+        .join(host)
+        .join("bin")
+        .join(exe);
+    path.exists().then_some(path)
+}
@@ -0,0 +1,143 @@
+//! Implements `bytemuck::Pod` and `Zeroable` for the structs in the generated bindings
+//! whose fields are all plain data, see `Config::bytemuck_derives`.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use regex::Regex;
+
+/// Primitive types which are plain data
+const POD_TYPES: &[&str] = &[
+    "i8",
+    "u8",
+    "i16",
+    "u16",
+    "i32",
+    "u32",
+    "i64",
+    "u64",
+    "isize",
+    "usize",
+    "f32",
+    "f64",
+    "c_char",
+    "c_schar",
+    "c_uchar",
+    "c_short",
+    "c_ushort",
+    "c_int",
+    "c_uint",
+    "c_long",
+    "c_ulong",
+    "c_longlong",
+    "c_ulonglong",
+    "c_float",
+    "c_double",
+];
+
+/// Generate the `Pod` and `Zeroable` implementations for the structs in the `bindings`
+/// whose fields are plain data, using the runtime crate `rt`. `extra_pod` are other
+/// types which are known to be plain data, e.g. the `float16` type mapped to `half::f16`.
+pub(crate) fn pod_impls(bindings: &str, extra_pod: &[&str], rt: &str) -> String {
+    let struct_decl =
+        Regex::new(r"((?:#\s*\[[^\]]*\]\s*)*)pub\s+struct\s+(\w+)\s*\{([^}]*)\}").unwrap();
+    let repr_c = Regex::new(r"#\s*\[\s*repr\s*\(\s*C\b").unwrap();
+    let alias = Regex::new(r"pub\s+type\s+(\w+)\s*=\s*([^;]+);").unwrap();
+    let field = Regex::new(r"pub\s+(\w+)\s*:\s*([^,]+),").unwrap();
+
+    let aliases: HashMap<&str, &str> = alias
+        .captures_iter(bindings)
+        .map(|c| {
+            (
+                c.get(1).unwrap().as_str(),
+                c.get(2).unwrap().as_str().trim(),
+            )
+        })
+        .collect();
+    let mut structs = Vec::new();
+    for c in struct_decl.captures_iter(bindings) {
+        // Only repr(C) structs have a defined layout to check, and opaque structs
+        // declared without their fields can't be created at all
+        if !repr_c.is_match(&c[1]) || field.captures_iter(&c[3]).any(|f| &f[1] == "_unused") {
+            continue;
+        }
+        let fields: Vec<String> = field
+            .captures_iter(&c[3])
+            .map(|f| f[2].trim().to_owned())
+            .collect();
+        structs.push((c[2].to_owned(), fields));
+    }
+
+    // Find the structs whose fields are plain data, which may be other structs found
+    // to be plain data before
+    let mut pod: HashSet<String> = extra_pod.iter().map(|t| t.to_string()).collect();
+    loop {
+        let before = pod.len();
+        for (name, fields) in structs.iter() {
+            if !pod.contains(name)
+                && !fields.is_empty()
+                && fields.iter().all(|f| is_pod(f, &aliases, &pod))
+            {
+                pod.insert(name.clone());
+            }
+        }
+        if pod.len() == before {
+            break;
+        }
+    }
+
+    let mut out = String::new();
+    for (name, fields) in structs.iter().filter(|s| pod.contains(&s.0)) {
+        let asserts: Vec<String> = fields
+            .iter()
+            .map(|f| format!("        assert_pod::<{f}>();"))
+            .collect();
+        let sizes: Vec<String> = fields
+            .iter()
+            .map(|f| format!("::core::mem::size_of::<{f}>()"))
+            .collect();
+        writeln!(
+            out,
+            "unsafe impl {rt}::bytemuck::Zeroable for {name} {{}}\n\
+             unsafe impl {rt}::bytemuck::Pod for {name} {{}}\n\
+             const _: () = {{\n\
+             \x20   fn assert_pod<T: {rt}::bytemuck::Pod>() {{}}\n\
+             \x20   fn assert_fields() {{\n\
+             {}\n\
+             \x20   }}\n\
+             \x20   assert!(\n\
+             \x20       ::core::mem::size_of::<{name}>() == {},\n\
+             \x20       \"{name} has padding, so it can't be Pod\"\n\
+             \x20   );\n\
+             }};",
+            asserts.join("\n"),
+            sizes.join(" + "),
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Check if the field type `ty` is plain data, resolving the type `aliases`
+fn is_pod(ty: &str, aliases: &HashMap<&str, &str>, pod: &HashSet<String>) -> bool {
+    let ty = ty.trim();
+    // Arrays of plain data are plain data
+    if let Some(inner) = ty.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        return match inner.rsplit_once(';') {
+            Some((elem, _)) => is_pod(elem, aliases, pod),
+            None => false,
+        };
+    }
+    // Pointers, function pointers and generic types like bitfields aren't
+    if ty.contains(['*', '<', '(']) {
+        return false;
+    }
+    let name = ty.rsplit("::").next().unwrap_or(ty);
+    if POD_TYPES.contains(&name) || pod.contains(name) {
+        return true;
+    }
+    match aliases.get(name) {
+        Some(target) if *target != ty => is_pod(target, aliases, pod),
+        _ => false,
+    }
+}
@@ -0,0 +1,296 @@
+//! Generates the ISPC and Rust definitions of the types shared between them from a
+//! single TOML description, see `Config::shared_types`.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use toml::{Table, Value};
+
+/// ISPC atomic types and the matching Rust types
+const ATOMIC_TYPES: &[(&str, &str)] = &[
+    ("bool", "bool"),
+    ("int8", "i8"),
+    ("uint8", "u8"),
+    ("int16", "i16"),
+    ("uint16", "u16"),
+    ("int32", "i32"),
+    ("uint32", "u32"),
+    ("int64", "i64"),
+    ("uint64", "u64"),
+    ("float", "f32"),
+    ("double", "f64"),
+];
+
+/// A constant shared with ISPC, declared as a `#define` in ISPC
+struct Const {
+    name: String,
+    ty: String,
+    value: String,
+}
+
+/// An enum shared with ISPC
+struct Enum {
+    name: String,
+    variants: Vec<(String, i64)>,
+}
+
+/// A field of a struct shared with ISPC
+struct Field {
+    name: String,
+    ty: String,
+    pointer: bool,
+    count: Option<String>,
+}
+
+/// A struct shared with ISPC
+struct Struct {
+    name: String,
+    fields: Vec<Field>,
+}
+
+/// The types described in a shared types file
+pub(crate) struct SharedTypes {
+    consts: Vec<Const>,
+    enums: Vec<Enum>,
+    structs: Vec<Struct>,
+}
+
+impl SharedTypes {
+    /// Load the shared types described in the TOML file at `path`
+    pub(crate) fn load(path: &Path) -> Result<SharedTypes, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let table = text.parse::<Table>().map_err(|e| e.to_string())?;
+        let mut types = SharedTypes {
+            consts: Vec::new(),
+            enums: Vec::new(),
+            structs: Vec::new(),
+        };
+        if let Some(key) = table
+            .keys()
+            .find(|k| !["const", "enum", "struct"].contains(&k.as_str()))
+        {
+            return Err(format!("unknown kind of shared type `{key}`"));
+        }
+        // Constants can be used by the struct fields and enums as their types, so
+        // these are parsed first
+        for key in ["const", "enum", "struct"] {
+            let items = match table.get(key) {
+                Some(Value::Array(items)) => items,
+                Some(_) => return Err(format!("`{key}` must be an array of tables")),
+                None => continue,
+            };
+            for item in items {
+                let item = item
+                    .as_table()
+                    .ok_or_else(|| format!("`{key}` must be an array of tables"))?;
+                match key {
+                    "const" => types.parse_const(item)?,
+                    "enum" => types.parse_enum(item)?,
+                    _ => types.parse_struct(item)?,
+                }
+            }
+        }
+        Ok(types)
+    }
+    /// Get the names of the enums and structs, which bindgen should use the shared
+    /// definitions of instead of generating its own
+    pub(crate) fn type_names(&self) -> impl Iterator<Item = &str> {
+        let enums = self.enums.iter().map(|e| e.name.as_str());
+        enums.chain(self.structs.iter().map(|s| s.name.as_str()))
+    }
+    /// Get the names of the enums
+    pub(crate) fn enum_names(&self) -> impl Iterator<Item = &str> {
+        self.enums.iter().map(|e| e.name.as_str())
+    }
+    /// Generate the ISPC declarations of the types
+    pub(crate) fn ispc_declarations(&self) -> String {
+        let mut out = String::new();
+        for c in self.consts.iter() {
+            let suffix = if c.ty == "double" { "d" } else { "" };
+            writeln!(out, "#define {} {}{suffix}", c.name, c.value).unwrap();
+        }
+        for e in self.enums.iter() {
+            writeln!(out, "\nenum {} {{", e.name).unwrap();
+            for (name, value) in e.variants.iter() {
+                writeln!(out, "    {name} = {value},").unwrap();
+            }
+            writeln!(out, "}};").unwrap();
+        }
+        for s in self.structs.iter() {
+            writeln!(out, "\nstruct {} {{", s.name).unwrap();
+            for f in s.fields.iter() {
+                let ptr = if f.pointer { " * uniform" } else { "" };
+                let count = f
+                    .count
+                    .as_ref()
+                    .map(|c| format!("[{c}]"))
+                    .unwrap_or_default();
+                writeln!(out, "    {}{ptr} {}{count};", f.ty, f.name).unwrap();
+            }
+            writeln!(out, "}};").unwrap();
+        }
+        out
+    }
+    /// Generate the Rust definitions of the types
+    pub(crate) fn rust_definitions(&self) -> String {
+        let mut out = String::new();
+        for c in self.consts.iter() {
+            writeln!(
+                out,
+                "pub const {}: {} = {};",
+                c.name,
+                rust_type(&c.ty),
+                c.value
+            )
+            .unwrap();
+        }
+        for e in self.enums.iter() {
+            // Use a newtype for the enum as ISPC may hand back values that aren't variants
+            let repr = if e.variants.iter().all(|v| v.1 >= 0) {
+                "u32"
+            } else {
+                "i32"
+            };
+            writeln!(out, "#[repr(transparent)]").unwrap();
+            writeln!(out, "#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]").unwrap();
+            writeln!(out, "pub struct {}(pub {repr});", e.name).unwrap();
+            writeln!(out, "impl {} {{", e.name).unwrap();
+            for (name, value) in e.variants.iter() {
+                writeln!(out, "    pub const {name}: {0} = {0}({value});", e.name).unwrap();
+            }
+            writeln!(out, "}}").unwrap();
+        }
+        for s in self.structs.iter() {
+            writeln!(out, "#[repr(C)]").unwrap();
+            writeln!(out, "#[derive(Clone, Copy, Debug)]").unwrap();
+            writeln!(out, "pub struct {} {{", s.name).unwrap();
+            for f in s.fields.iter() {
+                let mut ty = rust_type(&f.ty);
+                if f.pointer {
+                    ty = format!("*mut {ty}");
+                }
+                if let Some(ref count) = f.count {
+                    let count = if count.parse::<usize>().is_ok() {
+                        count.clone()
+                    } else {
+                        format!("{count} as usize")
+                    };
+                    ty = format!("[{ty}; {count}]");
+                }
+                writeln!(out, "    pub {}: {ty},", f.name).unwrap();
+            }
+            writeln!(out, "}}").unwrap();
+        }
+        out
+    }
+    fn parse_const(&mut self, item: &Table) -> Result<(), String> {
+        let name = get_str(item, "name", "const")?;
+        let ty = get_str(item, "type", &name)?;
+        if !ATOMIC_TYPES.iter().any(|t| t.0 == ty) {
+            return Err(format!("constant {name} must have an atomic type"));
+        }
+        let value = match (ty.as_str(), item.get("value")) {
+            ("bool", Some(Value::Boolean(b))) => b.to_string(),
+            ("float" | "double", Some(Value::Float(f))) => format!("{f:?}"),
+            ("float" | "double", Some(Value::Integer(i))) => format!("{:?}", *i as f64),
+            (_, Some(Value::Integer(i))) if ty != "bool" => i.to_string(),
+            _ => return Err(format!("value of constant {name} must be of type {ty}")),
+        };
+        self.consts.push(Const { name, ty, value });
+        Ok(())
+    }
+    fn parse_enum(&mut self, item: &Table) -> Result<(), String> {
+        let name = get_str(item, "name", "enum")?;
+        let list = item
+            .get("variants")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| format!("enum {name} must have an array of variants"))?;
+        let mut variants = Vec::new();
+        let mut next = 0;
+        for v in list {
+            let variant = match v {
+                Value::String(s) => (s.clone(), next),
+                Value::Table(t) => {
+                    let value = t
+                        .get("value")
+                        .and_then(|v| v.as_integer())
+                        .ok_or_else(|| format!("variant of enum {name} must have a value"))?;
+                    (get_str(t, "name", &name)?, value)
+                }
+                _ => return Err(format!("invalid variant of enum {name}")),
+            };
+            next = variant.1 + 1;
+            variants.push(variant);
+        }
+        self.enums.push(Enum { name, variants });
+        Ok(())
+    }
+    fn parse_struct(&mut self, item: &Table) -> Result<(), String> {
+        let name = get_str(item, "name", "struct")?;
+        let list = item
+            .get("fields")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| format!("struct {name} must have an array of fields"))?;
+        let mut fields = Vec::new();
+        for f in list {
+            let f = f
+                .as_table()
+                .ok_or_else(|| format!("fields of struct {name} must be tables"))?;
+            let field = get_str(f, "name", &name)?;
+            let ty = get_str(f, "type", &field)?;
+            let (ty, pointer) = match ty.strip_suffix('*') {
+                Some(t) => (t.trim().to_owned(), true),
+                None => (ty, false),
+            };
+            if !self.is_declared(&ty) {
+                return Err(format!(
+                    "type {ty} of {name}::{field} must be an atomic type or declared before it"
+                ));
+            }
+            let count = match f.get("count") {
+                None => None,
+                Some(Value::Integer(i)) if *i > 0 => Some(i.to_string()),
+                Some(Value::String(c)) if self.consts.iter().any(|k| k.name == *c) => {
+                    Some(c.clone())
+                }
+                _ => {
+                    return Err(format!(
+                        "count of {name}::{field} must be a positive integer or a constant"
+                    ))
+                }
+            };
+            fields.push(Field {
+                name: field,
+                ty,
+                pointer,
+                count,
+            });
+        }
+        self.structs.push(Struct { name, fields });
+        Ok(())
+    }
+    /// Check if `ty` is an atomic type or was declared before
+    fn is_declared(&self, ty: &str) -> bool {
+        ATOMIC_TYPES.iter().any(|t| t.0 == ty)
+            || self.enums.iter().any(|e| e.name == ty)
+            || self.structs.iter().any(|s| s.name == ty)
+    }
+}
+
+/// Get the string `key` of an item described in `what`
+fn get_str(item: &Table, key: &str, what: &str) -> Result<String, String> {
+    item.get(key)
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_owned())
+        .ok_or_else(|| format!("{what} is missing a `{key}` string"))
+}
+
+/// Get the Rust type matching the ISPC type `ty`
+fn rust_type(ty: &str) -> String {
+    ATOMIC_TYPES
+        .iter()
+        .find(|t| t.0 == ty)
+        .map(|t| t.1.to_owned())
+        .unwrap_or_else(|| ty.to_owned())
+}
@@ -0,0 +1,180 @@
+//! Describes the functions and structs exported by an ISPC library, as declared in the
+//! headers generated by ISPC, so build scripts can generate their own wrappers around
+//! the kernels without parsing the C headers themselves, see `Config::signatures`.
+
+use std::path::PathBuf;
+
+use regex::Regex;
+
+use crate::wrappers::matching_paren;
+
+/// The functions and structs exported by an ISPC library
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Signatures {
+    /// The exported functions, in the order they're declared
+    pub functions: Vec<Function>,
+    /// The structs used by the exported functions, including the short vector types
+    pub structs: Vec<Struct>,
+}
+
+/// A function exported from ISPC
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    /// Name of the function in ISPC, the bindings may rename it, see `Config::snake_case_names`
+    pub name: String,
+    /// The ISPC source file exporting the function, if known
+    pub file: Option<PathBuf>,
+    /// The C return type, `void` if it doesn't return anything
+    pub ret: String,
+    pub params: Vec<Param>,
+}
+
+/// A parameter of an exported function, or a field of a struct
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    pub name: String,
+    /// The C type, e.g. `const struct Vec3f *` or `float[3]` for array fields
+    pub ty: String,
+}
+
+/// A struct declared in the header of an ISPC library
+#[derive(Clone, Debug, PartialEq)]
+pub struct Struct {
+    pub name: String,
+    pub fields: Vec<Param>,
+    /// The alignment the struct is declared with, set for the short vector types
+    pub align: Option<usize>,
+}
+
+impl Signatures {
+    /// Parse the functions and structs declared in a header generated by ISPC. Structs
+    /// and functions which were already found, e.g. in the header of another file of
+    /// the same library, are skipped.
+    ///
+    /// # Example
+    /// ```
+    /// use ispc_compile::signatures::Signatures;
+    ///
+    /// let header = "struct Vec3f {\n    float x;\n    float y;\n    float z;\n};\n\
+    ///               extern void scale(struct Vec3f * v, const float s);\n";
+    /// let mut sigs = Signatures::default();
+    /// sigs.parse(header, None);
+    /// assert_eq!(sigs.functions[0].name, "scale");
+    /// assert_eq!(sigs.functions[0].params[0].ty, "struct Vec3f *");
+    /// assert_eq!(sigs.structs[0].fields.len(), 3);
+    /// ```
+    pub fn parse(&mut self, header: &str, file: Option<PathBuf>) {
+        let header = strip_comments(header);
+        let decl = Regex::new(
+            r"struct\s+(\w+)\s*\{([^{}]*)\}\s*(?:__attribute__\s*\(\(\s*aligned\((\d+)\)\s*\)\))?",
+        )
+        .unwrap();
+        for c in decl.captures_iter(&header) {
+            let align = c.get(3).and_then(|a| a.as_str().parse().ok());
+            // The vector types are declared once for MSVC, with the alignment before the
+            // name, and once with the alignment attribute we pick up here
+            if let Some(s) = self.structs.iter_mut().find(|s| s.name == c[1]) {
+                s.align = s.align.or(align);
+                continue;
+            }
+            let fields = c[2]
+                .split(';')
+                .filter_map(|f| parse_decl(f.trim()))
+                .collect();
+            self.structs.push(Struct {
+                name: c[1].to_owned(),
+                fields,
+                align,
+            });
+        }
+
+        let export = Regex::new(r#"(?m)^\s*extern\s+([^"(;{]*?)\b(\w+)\s*\("#).unwrap();
+        for c in export.captures_iter(&header) {
+            if self.functions.iter().any(|f| f.name == c[2]) {
+                continue;
+            }
+            let start = c.get(0).unwrap().end();
+            let params = match matching_paren(&header[start..]) {
+                Some(end) => &header[start..start + end],
+                None => continue,
+            };
+            self.functions.push(Function {
+                name: c[2].to_owned(),
+                file: file.clone(),
+                ret: normalize(&c[1]),
+                params: split_params(params)
+                    .into_iter()
+                    .filter_map(parse_decl)
+                    .collect(),
+            });
+        }
+    }
+    /// Get the exported function named `name`
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+    /// Get the struct named `name`
+    pub fn struct_def(&self, name: &str) -> Option<&Struct> {
+        self.structs.iter().find(|s| s.name == name)
+    }
+}
+
+/// Parse a parameter or field declaration into its name and type, returns `None` for
+/// an empty declaration or `void`
+fn parse_decl(decl: &str) -> Option<Param> {
+    let decl = decl.trim();
+    if decl.is_empty() || decl == "void" {
+        return None;
+    }
+    // Function pointers are declared as `ret (*name)(params)`
+    if let Some(open) = decl.find("(*") {
+        let close = open + decl[open..].find(')')?;
+        let name = decl[open + 2..close].trim().to_owned();
+        let ty = format!("{}(*){}", &decl[..open], &decl[close + 1..]);
+        return Some(Param {
+            name,
+            ty: normalize(&ty),
+        });
+    }
+    // Array dimensions follow the name, and are kept with the type
+    let (decl, dims) = match decl.find('[') {
+        Some(i) => (decl[..i].trim_end(), decl[i..].replace(' ', "")),
+        None => (decl, String::new()),
+    };
+    let split = decl.rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))?;
+    Some(Param {
+        name: decl[split + 1..].to_owned(),
+        ty: normalize(&decl[..=split]) + &dims,
+    })
+}
+
+/// Split a parameter list at the commas which aren't in a function pointer's parameters
+fn split_params(params: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in params.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&params[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&params[start..]);
+    parts
+}
+
+/// Collapse the whitespace in a C type to single spaces
+fn normalize(ty: &str) -> String {
+    ty.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Remove the comments from the `header`
+fn strip_comments(header: &str) -> String {
+    let comments = Regex::new(r"(?s)/\*.*?\*/|//[^\n]*").unwrap();
+    comments.replace_all(header, "").into_owned()
+}
@@ -0,0 +1,146 @@
+//! Compares the generated bindings with a golden file checked in with the crate, so
+//! changes to the bindings from ISPC or bindgen upgrades show up in review.
+
+use crate::build_info::MODULE_START;
+
+/// The line bindgen starts the bindings with, which changes with every bindgen release
+/// without affecting the bindings
+const BINDGEN_VERSION_LINE: &str = "/* automatically generated by rust-bindgen";
+
+/// The maximum number of changed lines shown in a diff
+const MAX_DIFF_LINES: usize = 40;
+
+/// Remove the parts of the bindings which change without changing the bindings
+pub(crate) fn normalize(bindings: &str) -> String {
+    let mut out = String::with_capacity(bindings.len());
+    let mut in_build_info = false;
+    for line in bindings.lines() {
+        let line = line.trim_end();
+        // The build info changes with every change to the sources, not just the bindings
+        if line == MODULE_START {
+            in_build_info = true;
+        } else if in_build_info {
+            in_build_info = line != "}";
+        } else if !line.is_empty() && !line.trim_start().starts_with(BINDGEN_VERSION_LINE) {
+            // The blank lines depend on whether bindgen formatted the bindings with
+            // rustfmt or prettyplease, and the extern blocks are only marked unsafe
+            // when bindgen targets Rust 1.82 or later
+            let line = match line.strip_prefix("unsafe extern ") {
+                Some(rest) => format!("extern {rest}"),
+                None => line.to_owned(),
+            };
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Show the lines which differ between the `expected` and `actual` bindings, after the
+/// lines they have in common at the start and end
+pub(crate) fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let prefix = expected
+        .iter()
+        .zip(&actual)
+        .take_while(|(e, a)| e == a)
+        .count();
+    let suffix = expected[prefix..]
+        .iter()
+        .rev()
+        .zip(actual[prefix..].iter().rev())
+        .take_while(|(e, a)| e == a)
+        .count();
+    let removed = &expected[prefix..expected.len() - suffix];
+    let added = &actual[prefix..actual.len() - suffix];
+
+    let mut out = format!("@@ line {} @@\n", prefix + 1);
+    let lines = removed
+        .iter()
+        .map(|l| format!("-{l}"))
+        .chain(added.iter().map(|l| format!("+{l}")));
+    let total = removed.len() + added.len();
+    for line in lines.take(MAX_DIFF_LINES) {
+        out.push_str(&line);
+        out.push('\n');
+    }
+    if total > MAX_DIFF_LINES {
+        out.push_str(&format!(
+            "... and {} more changed lines\n",
+            total - MAX_DIFF_LINES
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, normalize};
+    use crate::build_info::{self, SourceHash};
+    use semver::Version;
+
+    #[test]
+    fn normalize_skips_unstable_lines() {
+        let bindings = "pub mod simple {\n\
+                        /* automatically generated by rust-bindgen 0.71.1 */\n\
+                        \n\
+                        unsafe extern \"C\" {   \n\
+                        \x20   pub fn add_lists(count: i32);\n\
+                        }\n\
+                        pub mod build_info {\n\
+                        \x20   pub const SOURCE_HASH: &str = \"0123456789abcdef\";\n\
+                        }\n\
+                        }";
+        assert_eq!(
+            normalize(bindings),
+            "pub mod simple {\n\
+             extern \"C\" {\n\
+             \x20   pub fn add_lists(count: i32);\n\
+             }\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn normalize_skips_the_documented_build_info() {
+        let flags = vec![String::from("--target=avx2-i32x8")];
+        let module = build_info::rust_module(
+            &Version::new(1, 24, 0),
+            &flags,
+            "x86_64-unknown-linux-gnu",
+            &SourceHash::new(),
+        );
+        let bindings = format!(
+            "pub mod simple {{\n\
+             extern \"C\" {{\n\
+             \x20   pub fn add_lists(count: i32);\n\
+             }}\n\
+             {module}\n\
+             }}"
+        );
+        assert_eq!(
+            normalize(&bindings),
+            "pub mod simple {\n\
+             extern \"C\" {\n\
+             \x20   pub fn add_lists(count: i32);\n\
+             }\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn diff_shows_changed_lines() {
+        let expected = "a\nb\nc\nd\n";
+        let actual = "a\nx\ny\nd\n";
+        assert_eq!(diff(expected, actual), "@@ line 2 @@\n-b\n-c\n+x\n+y\n");
+    }
+
+    #[test]
+    fn diff_limits_long_changes() {
+        let expected: String = (0..50).map(|i| format!("{i}\n")).collect();
+        let out = diff(&expected, "");
+        assert_eq!(out.lines().count(), 1 + 40 + 1);
+        assert!(out.ends_with("... and 10 more changed lines\n"));
+    }
+}
@@ -0,0 +1,145 @@
+//! Finds the ISPC snippets passed to `ispc_rt::test_kernel!` in Rust source files, so the
+//! build script can compile each of them into a library for the tests calling it.
+
+/// An ISPC snippet to compile into the library `name` for the tests
+#[derive(Debug, PartialEq)]
+pub(crate) struct TestKernel {
+    pub name: String,
+    pub source: String,
+}
+
+/// Find the `test_kernel!(name, "source")` invocations in the Rust `source`. The ISPC
+/// source can be a raw string literal or a string literal with simple escapes.
+pub(crate) fn find_test_kernels(source: &str) -> Vec<TestKernel> {
+    let mut kernels = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("test_kernel!") {
+        rest = &rest[start + "test_kernel!".len()..];
+        if let Some((kernel, len)) = parse_invocation(rest) {
+            kernels.push(kernel);
+            rest = &rest[len..];
+        }
+    }
+    kernels
+}
+
+/// Parse the arguments of an invocation, returning the kernel and the length parsed
+fn parse_invocation(s: &str) -> Option<(TestKernel, usize)> {
+    let mut pos = skip_whitespace(s, 0);
+    if !s[pos..].starts_with(['(', '{', '[']) {
+        return None;
+    }
+    pos = skip_whitespace(s, pos + 1);
+    let name_len = s[pos..]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(s.len() - pos);
+    if name_len == 0 {
+        return None;
+    }
+    let name = s[pos..pos + name_len].to_owned();
+    pos = skip_whitespace(s, pos + name_len);
+    if !s[pos..].starts_with(',') {
+        return None;
+    }
+    pos = skip_whitespace(s, pos + 1);
+    let (source, len) = parse_string(&s[pos..])?;
+    Some((TestKernel { name, source }, pos + len))
+}
+
+fn skip_whitespace(s: &str, pos: usize) -> usize {
+    s[pos..]
+        .find(|c: char| !c.is_whitespace())
+        .map_or(s.len(), |n| pos + n)
+}
+
+/// Parse the string literal at the start of `s`, returning its contents and length
+fn parse_string(s: &str) -> Option<(String, usize)> {
+    if let Some(raw) = s.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let body = raw[hashes..].strip_prefix('"')?;
+        let end = format!("\"{}", "#".repeat(hashes));
+        let len = body.find(&end)?;
+        return Some((body[..len].to_owned(), 1 + hashes + 1 + len + end.len()));
+    }
+    let body = s.strip_prefix('"')?;
+    let mut contents = String::new();
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((contents, i + 2)),
+            '\\' => match chars.next()?.1 {
+                'n' => contents.push('\n'),
+                't' => contents.push('\t'),
+                // A line continuation skips the newline and the indentation after it
+                '\n' => {
+                    let rest = chars.as_str();
+                    let skip = rest.len() - rest.trim_start().len();
+                    for _ in rest[..skip].chars() {
+                        chars.next();
+                    }
+                }
+                e => contents.push(e),
+            },
+            c => contents.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_test_kernels, parse_string, TestKernel};
+
+    #[test]
+    fn parses_raw_strings() {
+        assert_eq!(
+            parse_string(r###"r#"a "quoted" \n"# rest"###),
+            Some((String::from(r#"a "quoted" \n"#), 18))
+        );
+        assert_eq!(
+            parse_string(r#"r"plain""#),
+            Some((String::from("plain"), 8))
+        );
+        assert_eq!(parse_string(r##"r#"unterminated""##), None);
+    }
+
+    #[test]
+    fn parses_escaped_strings() {
+        assert_eq!(
+            parse_string(r#""a\tb\n\"c\"\\" rest"#),
+            Some((String::from("a\tb\n\"c\"\\"), 15))
+        );
+        assert_eq!(
+            parse_string("\"one\\n\\\n     two\""),
+            Some((String::from("one\ntwo"), 17))
+        );
+        assert_eq!(parse_string("\"unterminated"), None);
+        assert_eq!(parse_string("not a string"), None);
+    }
+
+    #[test]
+    fn finds_invocations() {
+        let source = r###"
+            #[test]
+            fn scale() {
+                ispc_rt::test_kernel!(scale_kernel, r#"export void scale() {}"#);
+                test_kernel! { other, "export void other() {}" }
+                // Invocations which aren't literals are skipped
+                test_kernel!(dynamic, SOURCE);
+            }
+        "###;
+        assert_eq!(
+            find_test_kernels(source),
+            [
+                TestKernel {
+                    name: String::from("scale_kernel"),
+                    source: String::from("export void scale() {}"),
+                },
+                TestKernel {
+                    name: String::from("other"),
+                    source: String::from("export void other() {}"),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! Maps the short vector types used by exported ISPC functions and structs, e.g.
+//! `float<3>`, to the vector types of the `glam` and `mint` math crates.
+//!
+//! ISPC declares each short vector as a struct holding an array in the header, which
+//! is aligned to the alignment of the vector type in ISPC. Where the math type has the
+//! same layout the ISPC vector is replaced by it, otherwise conversions between the
+//! two are generated.
+//!
+//! The `float16` type is similarly mapped to `half::f16`, see `map_float16`.
+
+use std::fmt::Write;
+
+use regex::Regex;
+
+/// A short vector type declared in the ISPC headers
+pub(crate) struct VectorType {
+    /// Name of the struct declaring the type, e.g. `float3`
+    pub name: String,
+    /// The Rust type of the elements
+    elem: &'static str,
+    count: usize,
+    align: usize,
+}
+
+/// Math crates to map the vector types to
+#[derive(Clone, Copy, Default)]
+pub(crate) struct VectorMappings {
+    pub glam: bool,
+    pub mint: bool,
+}
+
+impl VectorType {
+    /// Size of the type, the size of its elements rounded up to its alignment
+    fn size(&self) -> usize {
+        let elem_size = match self.elem {
+            "bool" | "i8" | "u8" => 1,
+            "i16" | "u16" => 2,
+            "f32" | "i32" | "u32" => 4,
+            _ => 8,
+        };
+        (elem_size * self.count).div_ceil(self.align) * self.align
+    }
+    /// Get the glam type with the same elements, if there is one
+    fn glam_type(&self) -> Option<String> {
+        let prefix = match self.elem {
+            "f32" => "",
+            "f64" => "D",
+            "i32" => "I",
+            "u32" => "U",
+            "i8" => "I8",
+            "u8" => "U8",
+            "i16" => "I16",
+            "u16" => "U16",
+            "i64" => "I64",
+            "u64" => "U64",
+            _ => return None,
+        };
+        (2..=4)
+            .contains(&self.count)
+            .then(|| format!("{prefix}Vec{}", self.count))
+    }
+    /// Get the glam type with the same layout as the ISPC type, if there is one
+    fn glam_alias(&self) -> Option<&'static str> {
+        match (self.elem, self.count, self.align) {
+            ("f32", 3, 16) => Some("Vec3A"),
+            ("f32", 4, 16) => Some("Vec4"),
+            _ => None,
+        }
+    }
+}
+
+/// Find the short vector types declared in an ISPC generated `header`
+pub(crate) fn find_vector_types(header: &str, types: &mut Vec<VectorType>) {
+    let decl = Regex::new(
+        r"struct\s+(\w+)\s*\{\s*(\w+)\s+v\[(\d+)\];\s*\}\s*__attribute__\s*\(\(\s*aligned\((\d+)\)\s*\)\)",
+    )
+    .unwrap();
+    for c in decl.captures_iter(header) {
+        let elem = match &c[2] {
+            "bool" => "bool",
+            "int8_t" => "i8",
+            "uint8_t" => "u8",
+            "int16_t" => "i16",
+            "uint16_t" => "u16",
+            "int32_t" => "i32",
+            "uint32_t" => "u32",
+            "int64_t" => "i64",
+            "uint64_t" => "u64",
+            "float" => "f32",
+            "double" => "f64",
+            _ => continue,
+        };
+        if types.iter().any(|t| t.name == c[1]) {
+            continue;
+        }
+        types.push(VectorType {
+            name: c[1].to_owned(),
+            elem,
+            count: c[3].parse().unwrap(),
+            align: c[4].parse().unwrap(),
+        });
+    }
+}
+
+/// Get the vector types which should be replaced by a math type instead of
+/// having bindgen generate them
+pub(crate) fn aliased_types(
+    types: &[VectorType],
+    mappings: VectorMappings,
+) -> impl Iterator<Item = &str> {
+    types
+        .iter()
+        .filter(move |t| mappings.glam && t.glam_alias().is_some())
+        .map(|t| t.name.as_str())
+}
+
+/// Generate the aliases and conversions between the vector `types` and the
+/// math types, using the runtime crate `rt` which re-exports the math crates.
+pub(crate) fn vector_mappings(types: &[VectorType], mappings: VectorMappings, rt: &str) -> String {
+    let mut out = String::new();
+    for t in types.iter() {
+        let name = &t.name;
+        let n = t.count;
+        match t.glam_alias() {
+            Some(glam) if mappings.glam => {
+                writeln!(
+                    out,
+                    "pub type {name} = {rt}::glam::{glam};\n\
+                     const _: () = assert!(\n\
+                     \x20   ::core::mem::size_of::<{name}>() == {size}\n\
+                     \x20       && ::core::mem::align_of::<{name}>() == {align},\n\
+                     \x20   \"layout of {rt}::glam::{glam} doesn't match ISPC {name}\"\n\
+                     );",
+                    size = t.size(),
+                    align = t.align,
+                )
+                .unwrap();
+                continue;
+            }
+            _ => {}
+        }
+        let mut targets = Vec::new();
+        if mappings.glam {
+            targets.extend(t.glam_type().map(|g| format!("{rt}::glam::{g}")));
+        }
+        if mappings.mint && (2..=4).contains(&n) {
+            targets.push(format!("{rt}::mint::Vector{n}<{}>", t.elem));
+        }
+        for target in targets {
+            writeln!(
+                out,
+                "impl From<{target}> for {name} {{\n\
+                 \x20   fn from(v: {target}) -> {name} {{\n\
+                 \x20       let v: [{elem}; {n}] = v.into();\n\
+                 \x20       {name} {{ v }}\n\
+                 \x20   }}\n\
+                 }}\n\
+                 impl From<{name}> for {target} {{\n\
+                 \x20   fn from(v: {name}) -> {target} {{\n\
+                 \x20       v.v.into()\n\
+                 \x20   }}\n\
+                 }}",
+                elem = t.elem,
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+/// Replace the type bindgen generates for `float16` values in the `bindings` with
+/// `half::f16` from the runtime crate `rt`, which has the same layout
+pub(crate) fn map_float16(bindings: &str, rt: &str) -> String {
+    let float16 = Regex::new(
+        r"(?:#\s*\[[^\]]*\]\s*)*pub\s+struct\s+__BindgenFloat16\s*\(\s*pub\s+u16\s*\)\s*;",
+    )
+    .unwrap();
+    let alias = format!(
+        "pub type __BindgenFloat16 = {rt}::half::f16;\n\
+         const _: () = assert!(\n\
+         \x20   ::core::mem::size_of::<__BindgenFloat16>() == 2\n\
+         \x20       && ::core::mem::align_of::<__BindgenFloat16>() == 2,\n\
+         \x20   \"layout of {rt}::half::f16 doesn't match ISPC float16\"\n\
+         );"
+    );
+    float16.replace(bindings, alias.as_str()).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{aliased_types, find_vector_types, map_float16, vector_mappings, VectorMappings};
+
+    /// Short vector declarations as ISPC writes them to its headers
+    const HEADER: &str = "#ifndef __ISPC_VECTOR_float3__\n\
+                          #define __ISPC_VECTOR_float3__\n\
+                          #ifdef _MSC_VER\n\
+                          __declspec( align(16) ) struct float3 { float v[3]; };\n\
+                          #else\n\
+                          struct float3 { float v[3]; } __attribute__ ((aligned(16)));\n\
+                          #endif\n\
+                          #endif\n\
+                          struct int32_t2 {\n\
+                          \x20   int32_t v[2];\n\
+                          } __attribute__ ((aligned(8)));\n\
+                          struct Params { float3 *origin; int32_t2 size; };\n";
+
+    #[test]
+    fn finds_vector_types() {
+        let mut types = Vec::new();
+        find_vector_types(HEADER, &mut types);
+        // Headers of other files declare the same vectors again
+        find_vector_types(HEADER, &mut types);
+        let found: Vec<(&str, &str, usize, usize)> = types
+            .iter()
+            .map(|t| (t.name.as_str(), t.elem, t.count, t.align))
+            .collect();
+        assert_eq!(found, [("float3", "f32", 3, 16), ("int32_t2", "i32", 2, 8)]);
+        assert_eq!(types[0].size(), 16);
+    }
+
+    #[test]
+    fn aliases_vectors_with_the_same_layout() {
+        let mut types = Vec::new();
+        find_vector_types(HEADER, &mut types);
+        let both = VectorMappings {
+            glam: true,
+            mint: true,
+        };
+        assert_eq!(aliased_types(&types, both).collect::<Vec<_>>(), ["float3"]);
+        let out = vector_mappings(&types, both, "ispc_rt");
+        assert!(out.contains("pub type float3 = ispc_rt::glam::Vec3A;"));
+        assert!(out.contains("impl From<ispc_rt::glam::IVec2> for int32_t2 {"));
+        assert!(out.contains("impl From<int32_t2> for ispc_rt::mint::Vector2<i32> {"));
+        assert!(!out.contains("for float3"));
+    }
+
+    #[test]
+    fn converts_vectors_without_glam() {
+        let mut types = Vec::new();
+        find_vector_types(HEADER, &mut types);
+        let mint = VectorMappings {
+            glam: false,
+            mint: true,
+        };
+        assert_eq!(aliased_types(&types, mint).count(), 0);
+        let out = vector_mappings(&types, mint, "ispc_rt");
+        assert!(out.contains("impl From<ispc_rt::mint::Vector3<f32>> for float3 {"));
+        assert!(!out.contains("glam"));
+    }
+
+    #[test]
+    fn maps_float16() {
+        let bindings = "#[repr(transparent)]\n\
+                        #[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq)]\n\
+                        pub struct __BindgenFloat16(pub u16);\n\
+                        extern \"C\" {\n\
+                        \x20   pub fn scale(vals: *mut __BindgenFloat16, count: i32);\n\
+                        }\n";
+        let out = map_float16(bindings, "ispc_rt");
+        assert!(out.starts_with("pub type __BindgenFloat16 = ispc_rt::half::f16;\n"));
+        assert!(!out.contains("pub struct"));
+        assert!(out.contains("pub fn scale(vals: *mut __BindgenFloat16, count: i32);"));
+    }
+}
@@ -0,0 +1,545 @@
+//! Generates safer or more convenient Rust wrappers around the functions exported
+//! from ISPC, from the signatures in the bindings generated by bindgen.
+
+use std::fmt::Write;
+
+use regex::Regex;
+
+use crate::naming::camel_case;
+
+/// The signature of a function exported from ISPC, as declared in the bindings
+pub(crate) struct ExternFn {
+    pub name: String,
+    /// The names and types of the parameters
+    pub params: Vec<(String, String)>,
+    /// The return type, if the function returns anything
+    pub ret: Option<String>,
+}
+
+impl ExternFn {
+    /// Get the names of the parameters separated by commas, to forward them in a call
+    pub fn param_names(&self) -> String {
+        let names: Vec<&str> = self.params.iter().map(|p| p.0.as_str()).collect();
+        names.join(", ")
+    }
+    /// Get the parameters as declared in a function signature
+    pub fn param_decls(&self) -> String {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|(name, ty)| format!("{name}: {ty}"))
+            .collect();
+        params.join(", ")
+    }
+    /// Get the return type, `()` if the function doesn't return anything
+    pub fn ret_type(&self) -> &str {
+        self.ret.as_deref().unwrap_or("()")
+    }
+}
+
+/// Find the declarations of the external functions in the `bindings`
+pub(crate) fn extern_fns(bindings: &str) -> Vec<ExternFn> {
+    let decl = Regex::new(r"\bpub\s+fn\s+(\w+)\s*\(").unwrap();
+    let mut fns = Vec::new();
+    for c in decl.captures_iter(bindings) {
+        let start = c.get(0).unwrap().end();
+        let end = match matching_paren(&bindings[start..]) {
+            Some(end) => start + end,
+            None => continue,
+        };
+        // Functions with a body are methods generated by bindgen, not ISPC exports
+        let rest = &bindings[end + 1..];
+        let tail_end = match rest.find([';', '{']) {
+            Some(i) if rest.as_bytes()[i] == b';' => i,
+            _ => continue,
+        };
+        let ret = rest[..tail_end]
+            .trim()
+            .strip_prefix("->")
+            .map(|r| r.trim().to_owned());
+        let params = split_top_level(&bindings[start..end])
+            .into_iter()
+            .filter_map(|p| {
+                let colon = param_colon(p)?;
+                Some((
+                    p[..colon].trim().to_owned(),
+                    p[colon + 1..].trim().to_owned(),
+                ))
+            })
+            .collect();
+        fns.push(ExternFn {
+            name: c[1].to_owned(),
+            params,
+            ret,
+        });
+    }
+    fns
+}
+
+/// Generate the async versions of the `fns`, using the runtime crate `rt`
+pub(crate) fn async_wrappers(fns: &[ExternFn], rt: &str) -> String {
+    let mut out = String::new();
+    for f in fns {
+        let names = f.param_names();
+        let args = if f.params.is_empty() {
+            String::from("()")
+        } else {
+            format!("({names},)")
+        };
+        writeln!(
+            out,
+            "/// Asynchronous version of [`{name}`], which runs it on a blocking thread and\n\
+             /// resolves once it returns, see `{rt}::future::spawn_kernel`.\n\
+             ///\n\
+             /// # Safety\n\
+             /// The data passed to the function must stay valid until the future resolves or\n\
+             /// is dropped, and not be accessed by other threads while the function runs.\n\
+             pub unsafe fn {name}_async({decls}) -> {rt}::future::KernelFuture<{ret}> {{\n\
+             \x20   let args = unsafe {{ {rt}::future::AssertSend::new({args}) }};\n\
+             \x20   unsafe {{\n\
+             \x20       {rt}::future::spawn_kernel(move || {{\n\
+             \x20           let {args} = args.into_inner();\n\
+             \x20           {name}({names})\n\
+             \x20       }})\n\
+             \x20   }}\n\
+             }}",
+            name = f.name,
+            decls = f.param_decls(),
+            ret = f.ret_type(),
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Generate the `KERNELS` registry describing the `fns`, with an invoker for each which
+/// reads the arguments from type-erased pointers, using the runtime crate `rt`
+pub(crate) fn kernel_registry(fns: &[ExternFn], rt: &str) -> String {
+    let mut entries = String::new();
+    for f in fns {
+        let mut params = String::new();
+        let mut args = String::new();
+        for (i, (name, ty)) in f.params.iter().enumerate() {
+            writeln!(
+                params,
+                "\x20           {rt}::kernel::ParamDesc {{ name: {name:?}, ty: {ty:?} }},"
+            )
+            .unwrap();
+            writeln!(
+                args,
+                "\x20                       ::core::ptr::read(*args.add({i}) as *const {ty}),"
+            )
+            .unwrap();
+        }
+        let call = format!(
+            "{name}(\n\
+             {args}\
+             \x20                   )",
+            name = f.name
+        );
+        // The return pointer is ignored for functions which don't return anything
+        let (ret_param, body) = match f.ret {
+            Some(ref ret) => (
+                "ret",
+                format!(
+                    "let r = {call};\n\
+                     \x20                   ::core::ptr::write(ret as *mut {ret}, r);"
+                ),
+            ),
+            None => ("_ret", format!("{call};")),
+        };
+        writeln!(
+            entries,
+            "\x20   {rt}::kernel::KernelDesc {{\n\
+             \x20       name: {name:?},\n\
+             \x20       params: &[\n\
+             {params}\
+             \x20       ],\n\
+             \x20       ret: {ret:?},\n\
+             \x20       invoker: {{\n\
+             \x20           unsafe fn invoke(\n\
+             \x20               args: *const *mut ::core::ffi::c_void,\n\
+             \x20               {ret_param}: *mut ::core::ffi::c_void,\n\
+             \x20           ) {{\n\
+             \x20               unsafe {{\n\
+             \x20                   {body}\n\
+             \x20               }}\n\
+             \x20           }}\n\
+             \x20           invoke\n\
+             \x20       }},\n\
+             \x20   }},",
+            name = f.name,
+            ret = f.ret_type(),
+        )
+        .unwrap();
+    }
+    format!(
+        "/// The functions exported from the library, to look them up and call them by name,\n\
+         /// see `{rt}::kernel`\n\
+         pub static KERNELS: &[{rt}::kernel::KernelDesc] = &[\n\
+         {entries}\
+         ];\n"
+    )
+}
+
+/// Check if `ty` is an integer type, which a function can return a status code as
+pub(crate) fn is_status_type(ty: &str) -> bool {
+    let ty = ty.rsplit("::").next().unwrap_or(ty);
+    matches!(
+        ty,
+        "i8" | "u8" | "i16" | "u16" | "i32" | "u32" | "i64" | "u64" | "c_int" | "c_uint"
+    )
+}
+
+/// Generate a wrapper returning a `Result`, `foo_checked` for `foo`, for each of the
+/// `fns` returning a status code. The code is passed to the error mapping function
+/// at the path `mapping`, along with the name of the function.
+pub(crate) fn result_wrappers<'a, I>(fns: I, mapping: &str, rt: &str) -> String
+where
+    I: IntoIterator<Item = &'a ExternFn>,
+{
+    let mut out = String::new();
+    for f in fns {
+        writeln!(
+            out,
+            "/// Calls [`{name}`], returning an error if the status code it returns is a\n\
+             /// failure according to `{mapping}`.\n\
+             ///\n\
+             /// # Safety\n\
+             /// The arguments must be valid to pass to [`{name}`].\n\
+             pub unsafe fn {name}_checked({decls}) -> Result<(), {rt}::IspcError> {{\n\
+             \x20   let code = unsafe {{ {name}({names}) }};\n\
+             \x20   {mapping}(\"{name}\", {code})\n\
+             }}",
+            name = f.name,
+            decls = f.param_decls(),
+            names = f.param_names(),
+            code = if f.ret.as_deref() == Some("i64") {
+                "code"
+            } else {
+                "code as i64"
+            },
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Generate an owning handle type for each kind of object created by a function
+/// named `{make_prefix}{kind}` and destroyed by one named `{drop_prefix}{kind}`.
+/// The constructor either returns the handle or writes it to its first parameter.
+pub(crate) fn handle_types(fns: &[ExternFn], make_prefix: &str, drop_prefix: &str) -> String {
+    let mut out = String::new();
+    for make in fns {
+        let kind = match make.name.strip_prefix(make_prefix) {
+            Some(k) if !k.is_empty() => k,
+            _ => continue,
+        };
+        let drop_name = format!("{drop_prefix}{kind}");
+        let handle = match fns.iter().find(|f| f.name == drop_name) {
+            Some(f) if f.params.len() == 1 => f.params[0].1.clone(),
+            _ => continue,
+        };
+        // Find how the constructor hands back the handle
+        let out_param = format!("*mut {handle}");
+        let (params, create) = if make.ret.as_deref() == Some(handle.as_str()) {
+            let create = format!(
+                "let handle = unsafe {{ {}({}) }};",
+                make.name,
+                make.param_names()
+            );
+            (&make.params[..], create)
+        } else if make.params.first().is_some_and(|p| p.1 == out_param) {
+            let params = &make.params[1..];
+            let mut args = vec![String::from("&mut handle")];
+            args.extend(params.iter().map(|p| p.0.clone()));
+            let create = format!(
+                "let mut handle: {handle} = unsafe {{ ::core::mem::zeroed() }};\n\
+                 \x20       unsafe {{ {}({}) }};",
+                make.name,
+                args.join(", ")
+            );
+            (params, create)
+        } else {
+            continue;
+        };
+        let decls: Vec<String> = params.iter().map(|(n, t)| format!("{n}: {t}")).collect();
+        let release = if handle.starts_with("*mut") || handle.starts_with("*const") {
+            format!(
+                "if !self.handle.is_null() {{\n\
+                 \x20           unsafe {{ {drop_name}(self.handle) }};\n\
+                 \x20       }}"
+            )
+        } else {
+            format!("unsafe {{ {drop_name}(self.handle) }};")
+        };
+        let ty = format!("{}Handle", camel_case(kind));
+        writeln!(
+            out,
+            "/// Owns an object created by [`{make}`], which is destroyed with\n\
+             /// [`{drop_name}`] when the handle is dropped.\n\
+             pub struct {ty} {{\n\
+             \x20   handle: {handle},\n\
+             }}\n\
+             impl {ty} {{\n\
+             \x20   /// Create a new object with [`{make}`]\n\
+             \x20   ///\n\
+             \x20   /// # Safety\n\
+             \x20   /// The arguments must be valid to pass to [`{make}`].\n\
+             \x20   pub unsafe fn new({decls}) -> {ty} {{\n\
+             \x20       {create}\n\
+             \x20       {ty} {{ handle }}\n\
+             \x20   }}\n\
+             \x20   /// Take ownership of an object previously created by [`{make}`]\n\
+             \x20   ///\n\
+             \x20   /// # Safety\n\
+             \x20   /// `handle` must not be owned by another handle or destroyed elsewhere.\n\
+             \x20   pub unsafe fn from_raw(handle: {handle}) -> {ty} {{\n\
+             \x20       {ty} {{ handle }}\n\
+             \x20   }}\n\
+             \x20   /// Get the raw handle to pass to other ISPC functions\n\
+             \x20   pub fn as_raw(&self) -> {handle} {{\n\
+             \x20       self.handle\n\
+             \x20   }}\n\
+             \x20   /// Release ownership of the object, which must then be destroyed with\n\
+             \x20   /// [`{drop_name}`] by the caller.\n\
+             \x20   pub fn into_raw(self) -> {handle} {{\n\
+             \x20       let handle = self.handle;\n\
+             \x20       ::core::mem::forget(self);\n\
+             \x20       handle\n\
+             \x20   }}\n\
+             }}\n\
+             impl Drop for {ty} {{\n\
+             \x20   fn drop(&mut self) {{\n\
+             \x20       {release}\n\
+             \x20   }}\n\
+             }}",
+            make = make.name,
+            decls = decls.join(", "),
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Generate a builder for each of the `fns` with more than `max_params` parameters,
+/// `FooCall` for `foo`, which sets each parameter by name before calling the function.
+pub(crate) fn call_builders(fns: &[ExternFn], max_params: usize) -> String {
+    let mut out = String::new();
+    for f in fns.iter().filter(|f| f.params.len() > max_params) {
+        // The parameter setters can't share the names of the builder's other methods
+        if f.params.iter().any(|p| p.0 == "new" || p.0 == "invoke") {
+            continue;
+        }
+        let name = &f.name;
+        let ty = format!("{}Call", camel_case(name));
+        let mut fields = String::new();
+        let mut setters = String::new();
+        let mut args = Vec::new();
+        for (param, param_ty) in f.params.iter() {
+            writeln!(fields, "    {param}: ::core::option::Option<{param_ty}>,").unwrap();
+            writeln!(
+                setters,
+                "    /// Set the `{param}` parameter of [`{name}`]\n\
+                 \x20   pub fn {param}(&mut self, {param}: {param_ty}) -> &mut {ty} {{\n\
+                 \x20       self.{param} = Some({param});\n\
+                 \x20       self\n\
+                 \x20   }}"
+            )
+            .unwrap();
+            args.push(format!(
+                "self.{param}.expect(\"parameter {param} of {name} was not set\")"
+            ));
+        }
+        writeln!(
+            out,
+            "/// Builds a call to [`{name}`], setting each of its parameters by name.\n\
+             /// All parameters must be set before calling `invoke`, which is checked when\n\
+             /// the call is made rather than at compile time.\n\
+             #[derive(Clone, Copy, Default)]\n\
+             pub struct {ty} {{\n\
+             {fields}\
+             }}\n\
+             impl {ty} {{\n\
+             \x20   /// Start building a call with none of the parameters set\n\
+             \x20   pub fn new() -> {ty} {{\n\
+             \x20       ::core::default::Default::default()\n\
+             \x20   }}\n\
+             {setters}\
+             \x20   /// Call [`{name}`] with the parameters set, panics if any parameter\n\
+             \x20   /// wasn't set.\n\
+             \x20   ///\n\
+             \x20   /// # Safety\n\
+             \x20   /// The parameters must be valid to pass to [`{name}`].\n\
+             \x20   pub unsafe fn invoke(&self){ret} {{\n\
+             \x20       unsafe {{\n\
+             \x20           {name}(\n\
+             \x20               {args},\n\
+             \x20           )\n\
+             \x20       }}\n\
+             \x20   }}\n\
+             }}",
+            ret = f
+                .ret
+                .as_ref()
+                .map(|r| format!(" -> {r}"))
+                .unwrap_or_default(),
+            args = args.join(",\n                "),
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Find the parenthesis closing the one opened before the start of `s`
+pub(crate) fn matching_paren(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split a parameter list at the commas which aren't nested in another type
+fn split_top_level(s: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    let mut prev = ' ';
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '[' | '<' => depth += 1,
+            // Skip the arrows in function pointer types
+            '>' if prev == '-' => {}
+            ')' | ']' | '>' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    parts.push(&s[start..]);
+    parts.retain(|p| !p.trim().is_empty());
+    parts
+}
+
+/// Find the colon separating a parameter's name from its type, skipping paths
+fn param_colon(p: &str) -> Option<usize> {
+    let bytes = p.as_bytes();
+    (0..bytes.len()).find(|&i| {
+        bytes[i] == b':' && bytes.get(i + 1) != Some(&b':') && (i == 0 || bytes[i - 1] != b':')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{async_wrappers, call_builders, extern_fns};
+
+    /// Bindings as bindgen generates them, with a signature split over multiple lines
+    /// and a method which isn't an ISPC export
+    const BINDINGS: &str = "extern \"C\" {\n\
+                            \x20   pub fn add_lists(\n\
+                            \x20       a: *const f32,\n\
+                            \x20       b: *const f32,\n\
+                            \x20       c: *mut f32,\n\
+                            \x20       count: i32,\n\
+                            \x20   );\n\
+                            \x20   pub fn sum(vals: *const f32, count: i32) -> f32;\n\
+                            \x20   pub fn frame_count() -> u64;\n\
+                            }\n\
+                            impl Params {\n\
+                            \x20   pub fn scaled(&self, s: f32) -> Params {\n\
+                            \x20       Params { s }\n\
+                            \x20   }\n\
+                            }\n";
+
+    #[test]
+    fn finds_multi_line_signatures() {
+        let fns = extern_fns(BINDINGS);
+        let names: Vec<&str> = fns.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["add_lists", "sum", "frame_count"]);
+        assert_eq!(
+            fns[0].param_decls(),
+            "a: *const f32, b: *const f32, c: *mut f32, count: i32"
+        );
+        assert_eq!(fns[0].ret, None);
+        assert_eq!(fns[1].ret.as_deref(), Some("f32"));
+        assert!(fns[2].params.is_empty());
+    }
+
+    #[test]
+    fn keeps_nested_types_in_one_parameter() {
+        let bindings = "extern \"C\" {\n\
+                        \x20   pub fn run(\n\
+                        \x20       cb: ::core::option::Option<unsafe extern \"C\" fn(p: f32, q: f32) -> i32>,\n\
+                        \x20       user: *mut ::core::ffi::c_void,\n\
+                        \x20       grid: *mut [[f32; 4usize]; 4usize],\n\
+                        \x20   ) -> ::core::ffi::c_int;\n\
+                        }\n";
+        let fns = extern_fns(bindings);
+        assert_eq!(fns.len(), 1);
+        let params: Vec<(&str, &str)> = fns[0]
+            .params
+            .iter()
+            .map(|(n, t)| (n.as_str(), t.as_str()))
+            .collect();
+        assert_eq!(
+            params,
+            [
+                (
+                    "cb",
+                    "::core::option::Option<unsafe extern \"C\" fn(p: f32, q: f32) -> i32>"
+                ),
+                ("user", "*mut ::core::ffi::c_void"),
+                ("grid", "*mut [[f32; 4usize]; 4usize]"),
+            ]
+        );
+        assert_eq!(fns[0].ret.as_deref(), Some("::core::ffi::c_int"));
+    }
+
+    #[test]
+    fn async_wrappers_forward_the_parameters() {
+        let out = async_wrappers(&extern_fns(BINDINGS), "ispc_rt");
+        assert!(out.contains(
+            "pub unsafe fn sum_async(vals: *const f32, count: i32) \
+             -> ispc_rt::future::KernelFuture<f32> {"
+        ));
+        assert!(out.contains("let (vals, count,) = args.into_inner();"));
+        assert!(out.contains("sum(vals, count)"));
+        // Functions without parameters move an empty tuple to the blocking thread
+        assert!(out
+            .contains("pub unsafe fn frame_count_async() -> ispc_rt::future::KernelFuture<u64> {"));
+        assert!(out.contains("let () = args.into_inner();"));
+        assert!(!out.contains("scaled_async"));
+    }
+
+    #[test]
+    fn builds_calls_with_many_parameters() {
+        let out = call_builders(&extern_fns(BINDINGS), 3);
+        assert!(out.contains("pub struct AddListsCall {"));
+        assert!(out.contains("    c: ::core::option::Option<*mut f32>,"));
+        assert!(out.contains("pub fn count(&mut self, count: i32) -> &mut AddListsCall {"));
+        assert!(out.contains("pub unsafe fn invoke(&self) {"));
+        assert!(out.contains("self.a.expect(\"parameter a of add_lists was not set\"),"));
+        // Functions with fewer parameters are called directly
+        assert!(!out.contains("SumCall"));
+    }
+
+    #[test]
+    fn skips_builders_with_clashing_setters() {
+        let bindings = "extern \"C\" {\n\
+                        \x20   pub fn make(new: i32, a: i32, b: i32, c: i32) -> f32;\n\
+                        \x20   pub fn eval(x: f32, y: f32, z: f32, w: f32) -> f32;\n\
+                        }\n";
+        let out = call_builders(&extern_fns(bindings), 3);
+        assert!(!out.contains("MakeCall"));
+        assert!(out.contains("pub unsafe fn invoke(&self) -> f32 {"));
+    }
+}
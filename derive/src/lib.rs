@@ -0,0 +1,139 @@
+//! Derive macros for `ispc_rt`, these are re-exported by `ispc_rt` when its
+//! `derive` feature is enabled.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr, Path};
+
+/// Derive `ispc_rt::IspcStruct` to generate the declaration of the struct in ISPC from
+/// its Rust definition, so Rust can be the single source of truth for data layouts
+/// shared with ISPC code. The struct must be `#[repr(C)]` and its fields must implement
+/// `ispc_rt::IspcType`.
+///
+/// The declarations are written to a header included by the ISPC code from the build
+/// script with `Config::generated_header`, so the shared structs should be defined in a
+/// crate that's a build dependency of the crate compiling the ISPC code.
+///
+/// If `ispc_rt` is used through the `ispc` crate, set the path to it with
+/// `#[ispc(crate = "ispc")]`.
+///
+/// # Example
+/// ```
+/// use ispc_rt::IspcStruct;
+///
+/// #[derive(ispc_derive::IspcStruct)]
+/// #[repr(C)]
+/// struct Ray {
+///     origin: [f32; 3],
+///     dir: [f32; 3],
+///     depth: i32,
+/// }
+///
+/// assert_eq!(
+///     Ray::ispc_declaration(),
+///     "struct Ray {\n    float origin[3];\n    float dir[3];\n    int32 depth;\n};\n"
+/// );
+/// ```
+///
+/// Structs without a C layout can't be shared with ISPC:
+///
+/// ```compile_fail
+/// #[derive(ispc_derive::IspcStruct)]
+/// struct Ray {
+///     origin: [f32; 3],
+///     dir: [f32; 3],
+/// }
+/// ```
+#[proc_macro_derive(IspcStruct, attributes(ispc))]
+pub fn derive_ispc_struct(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_ispc_struct(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_ispc_struct(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "IspcStruct can't be derived for generic structs",
+        ));
+    }
+    let fields = match input.data {
+        Data::Struct(ref s) => match s.fields {
+            Fields::Named(ref f) => &f.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    name,
+                    "IspcStruct can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "IspcStruct can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut repr_c = false;
+    let mut krate: Path = syn::parse_quote!(::ispc_rt);
+    for attr in input.attrs.iter() {
+        if attr.path().is_ident("repr") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("C") {
+                    repr_c = true;
+                    Ok(())
+                } else {
+                    // ISPC has no way to declare a packed or overaligned struct
+                    Err(meta.error("IspcStruct only supports #[repr(C)]"))
+                }
+            })?;
+        } else if attr.path().is_ident("ispc") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("crate") {
+                    krate = meta.value()?.parse::<LitStr>()?.parse()?;
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported ispc attribute"))
+                }
+            })?;
+        }
+    }
+    if !repr_c {
+        return Err(syn::Error::new_spanned(
+            name,
+            "IspcStruct requires #[repr(C)] to match the layout of the ISPC struct",
+        ));
+    }
+
+    let field_names = fields.iter().map(|f| f.ident.as_ref().unwrap().to_string());
+    let field_types = fields.iter().map(|f| &f.ty);
+    let ispc_name = name.to_string();
+    Ok(quote! {
+        impl #krate::IspcType for #name {
+            fn ispc_type() -> #krate::__private::String {
+                #krate::__private::String::from(#ispc_name)
+            }
+        }
+
+        impl #krate::IspcStruct for #name {
+            fn ispc_declaration() -> #krate::__private::String {
+                let mut decl = #krate::__private::format!("struct {} {{\n", #ispc_name);
+                #(
+                    decl.push_str("    ");
+                    decl.push_str(&<#field_types as #krate::IspcType>::ispc_field(#field_names));
+                    decl.push_str(";\n");
+                )*
+                decl.push_str("};\n");
+                decl
+            }
+        }
+    })
+}
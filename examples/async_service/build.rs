@@ -0,0 +1,10 @@
+extern crate ispc_compile;
+
+fn main() {
+    // The async wrappers let the service await the filter instead of blocking one of
+    // tokio's executor threads while it runs
+    ispc_compile::Config::new()
+        .file("src/blur.ispc")
+        .async_wrappers()
+        .compile("blur");
+}
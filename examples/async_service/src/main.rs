@@ -0,0 +1,115 @@
+extern crate axum;
+extern crate image;
+#[macro_use]
+extern crate ispc_rt;
+extern crate serde;
+extern crate tokio;
+extern crate tokio_stream;
+
+use std::convert::Infallible;
+use std::env;
+use std::sync::Arc;
+
+use axum::body::{Body, Bytes};
+use axum::extract::{DefaultBodyLimit, Query};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+ispc_module!(blur);
+
+/// The number of rows filtered by each kernel call, and sent back in each chunk of the
+/// response
+const BAND_ROWS: u32 = 64;
+
+/// The largest blur radius a request may ask for
+const MAX_RADIUS: i32 = 32;
+
+#[derive(Deserialize)]
+struct BlurParams {
+    radius: Option<i32>,
+}
+
+/// Blur the image in the request body, which can be any format the `image` crate
+/// decodes, and stream back the result as a binary PGM image one band of rows at a time
+async fn blur(Query(params): Query<BlurParams>, body: Bytes) -> Result<Response, Response> {
+    let radius = params.radius.unwrap_or(4);
+    if !(0..=MAX_RADIUS).contains(&radius) {
+        let msg = format!("The radius must be between 0 and {MAX_RADIUS}\n");
+        return Err((StatusCode::BAD_REQUEST, msg).into_response());
+    }
+    // Decoding is just as blocking as running the kernel, so it's done on the blocking
+    // pool as well
+    let image = tokio::task::spawn_blocking(move || image::load_from_memory(&body))
+        .await
+        .unwrap()
+        .map_err(|e| {
+            let msg = format!("Failed to decode the image: {e}\n");
+            (StatusCode::BAD_REQUEST, msg).into_response()
+        })?
+        .into_luma8();
+    let (width, height) = image.dimensions();
+    let image = Arc::new(image.into_raw());
+
+    let (tx, rx) = mpsc::channel::<Result<Vec<u8>, Infallible>>(2);
+    tokio::spawn(async move {
+        let header = format!("P5\n{width} {height}\n255\n").into_bytes();
+        if tx.send(Ok(header)).await.is_err() {
+            return;
+        }
+        for first_row in (0..height).step_by(BAND_ROWS as usize) {
+            let rows = BAND_ROWS.min(height - first_row);
+            let mut band = vec![0u8; (rows * width) as usize];
+            // The kernel runs on tokio's blocking pool and launches its tasks on the ISPC
+            // task system, the executor thread is free to serve other requests until it
+            // returns. Dropping the future waits for the kernel to return, so `image` and
+            // `band` outlive it even if the runtime shuts down while it runs.
+            unsafe {
+                blur::blur_band_async(
+                    image.as_ptr(),
+                    band.as_mut_ptr(),
+                    width as i32,
+                    height as i32,
+                    first_row as i32,
+                    rows as i32,
+                    radius,
+                )
+            }
+            .await;
+            // Stop filtering once the client disconnected
+            if tx.send(Ok(band)).await.is_err() {
+                return;
+            }
+        }
+    });
+    let headers = [(header::CONTENT_TYPE, "image/x-portable-graymap")];
+    Ok((headers, Body::from_stream(ReceiverStream::new(rx))).into_response())
+}
+
+async fn usage() -> &'static str {
+    "POST an image to /blur?radius=<0-32> to get back a blurred grayscale PGM image\n"
+}
+
+#[tokio::main]
+async fn main() {
+    // Run the ISPC calls made through the async wrappers on tokio's blocking pool instead
+    // of spawning a thread for each call
+    ispc_rt::future::set_blocking_spawner(|job| {
+        tokio::task::spawn_blocking(job);
+    });
+
+    let app = Router::new()
+        .route("/", get(usage))
+        .route("/blur", post(blur))
+        .layer(DefaultBodyLimit::max(64 << 20));
+
+    let addr = env::var("ADDR").unwrap_or_else(|_| String::from("127.0.0.1:3000"));
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    println!("Listening on http://{addr}, blur an image with");
+    println!("    curl --data-binary @image.png 'http://{addr}/blur?radius=8' -o blurred.pgm");
+    axum::serve(listener, app).await.unwrap();
+}
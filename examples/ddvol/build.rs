@@ -12,5 +12,7 @@ fn main() {
     for s in &ispc_files[..] {
         cfg.file(*s);
     }
+    // Generate handles owning the objects created by the make_* functions
+    cfg.handle_types("make_", "drop_");
     cfg.compile("ddvol");
 }
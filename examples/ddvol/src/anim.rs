@@ -0,0 +1,76 @@
+//! Renders an animation of the camera orbiting its target. Each frame is rendered with the
+//! same camera and volume handles, with the camera moved between frames, while the previous
+//! frame is converted and written out on another thread. Two framebuffers are passed back
+//! and forth between the threads, so rendering the next frame doesn't wait on the image
+//! being saved and the task system runs the tasks of both at once.
+
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+use crate::fb::Framebuffer;
+use crate::scene::Scene;
+use crate::vec3::Vec3f;
+
+/// Rotate `v` by `angle` radians about the normalized `axis`
+fn rotate(v: Vec3f, axis: Vec3f, angle: f32) -> Vec3f {
+    let (sin, cos) = angle.sin_cos();
+    v * cos + axis.cross(&v) * sin + axis * (axis.dot(&v) * (1.0 - cos))
+}
+
+/// Get the file to write frame `i` to, e.g. `ddvol_0003.png` for `ddvol.png`
+fn frame_file(out_file: &str, i: usize) -> String {
+    let path = Path::new(out_file);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("ddvol");
+    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("png");
+    path.with_file_name(format!("{stem}_{i:04}.{ext}"))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Render `frames` frames of the camera making a full orbit around its target, about its
+/// up vector, writing them to `out_file` with the frame number appended
+pub fn render_animation(scene: &mut Scene, frames: usize, out_file: &str) {
+    let up = scene.camera.up.normalized();
+    let target = scene.camera.target;
+    let offset = scene.camera.pos - target;
+
+    // Rendered frames go to the writer, which sends the framebuffers back once saved
+    let (rendered_tx, rendered_rx) = mpsc::channel::<(usize, Framebuffer)>();
+    let (free_tx, free_rx) = mpsc::channel();
+    for _ in 0..2 {
+        free_tx
+            .send(Framebuffer::new(scene.width, scene.height))
+            .unwrap();
+    }
+    let out_file = out_file.to_owned();
+    let writer = thread::spawn(move || {
+        for (i, framebuffer) in rendered_rx {
+            framebuffer.save(&frame_file(&out_file, i));
+            // The renderer may have finished already and stopped taking framebuffers back
+            let _ = free_tx.send(framebuffer);
+        }
+    });
+
+    let start = Instant::now();
+    for i in 0..frames {
+        let mut framebuffer = free_rx.recv().unwrap();
+        framebuffer.clear();
+        let angle = 2.0 * PI * i as f32 / frames as f32;
+        scene
+            .camera
+            .set_view(target + rotate(offset, up, angle), target, up);
+        let elapsed = crate::render_frame(scene, &mut framebuffer);
+        println!("Frame {i} took {elapsed:?}");
+        rendered_tx.send((i, framebuffer)).unwrap();
+    }
+    drop(rendered_tx);
+    writer.join().unwrap();
+    let elapsed = start.elapsed();
+    println!(
+        "Rendered {frames} frames in {elapsed:?}, {:.2} frames/s",
+        frames as f64 / elapsed.as_secs_f64()
+    );
+}
@@ -1,40 +1,53 @@
 use crate::ddvol;
-use crate::empty_handle;
 use crate::vec3::Vec3f;
 use crate::ISPCHandle;
 
 /// The camera that the scene is being rendered from
 pub struct Camera {
-    ispc_handle: ISPCHandle,
+    handle: ddvol::CameraHandle,
+    pub pos: Vec3f,
+    pub target: Vec3f,
+    pub up: Vec3f,
+    pub fovy: f32,
 }
 
 impl Camera {
     pub fn new(pos: Vec3f, target: Vec3f, up: Vec3f, fovy: f32, width: u32, height: u32) -> Camera {
-        let mut cam = empty_handle();
-        unsafe {
-            ddvol::make_camera(
-                &mut cam as *mut ISPCHandle,
+        let handle = unsafe {
+            ddvol::CameraHandle::new(
                 &pos as *const Vec3f,
                 &target as *const Vec3f,
                 &up as *const Vec3f,
                 fovy,
                 width,
                 height,
+            )
+        };
+        Camera {
+            handle,
+            pos,
+            target,
+            up,
+            fovy,
+        }
+    }
+    /// Move the camera to look from `pos` towards `target`. The ISPC camera is updated in
+    /// place, so the handle passed to the renderer stays the same.
+    pub fn set_view(&mut self, pos: Vec3f, target: Vec3f, up: Vec3f) {
+        self.pos = pos;
+        self.target = target;
+        self.up = up;
+        unsafe {
+            ddvol::camera_set_view(
+                self.handle.as_raw(),
+                &pos as *const Vec3f,
+                &target as *const Vec3f,
+                &up as *const Vec3f,
+                self.fovy,
             );
         }
-        Camera { ispc_handle: cam }
     }
     pub fn ispc_equiv(&self) -> ISPCHandle {
-        self.ispc_handle
-    }
-}
-
-impl Drop for Camera {
-    fn drop(&mut self) {
-        if !self.ispc_handle.is_null() {
-            unsafe {
-                ddvol::drop_camera(self.ispc_handle);
-            }
-        }
+        self.handle.as_raw()
     }
 }
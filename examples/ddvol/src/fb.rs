@@ -1,32 +1,44 @@
 //! Provides a Framebuffer type for writing tiles of the rendered image
 
+use ispc::Image2D;
+
 use crate::ddvol;
 
 /// An RGBA_F32 framebuffer
 pub struct Framebuffer {
-    pub width: usize,
-    pub height: usize,
-    pub data: Vec<f32>,
+    pub data: Image2D<f32>,
 }
 
 impl Framebuffer {
     pub fn new(width: usize, height: usize) -> Framebuffer {
         Framebuffer {
-            width,
-            height,
-            data: vec![0.0; width * height * 4],
+            data: Image2D::new(width, height, 4, 0.0),
         }
     }
+    /// Clear the framebuffer to black, the renderer accumulates its samples into it
+    pub fn clear(&mut self) {
+        self.data.as_mut_slice().fill(0.0);
+    }
+    /// Save the framebuffer to `file` as an SRGB8 image
+    pub fn save(&self, file: &str) {
+        let srgb_img = self.srgb8();
+        match image::save_buffer(
+            file,
+            srgb_img.as_slice(),
+            self.data.width() as u32,
+            self.data.height() as u32,
+            image::ColorType::Rgb8,
+        ) {
+            Ok(_) => println!("Rendered image saved to {file}"),
+            Err(e) => panic!("Error saving image: {e}"),
+        };
+    }
     /// Convert the framebuffer to SRGB8 and return the color buffer
-    pub fn srgb8(&self) -> Vec<u8> {
-        let mut srgb = vec![0u8; self.width * self.height * 3];
+    pub fn srgb8(&self) -> Image2D<u8> {
+        let mut srgb = Image2D::new(self.data.width(), self.data.height(), 3, 0u8);
+        let (fb, width, height, _) = self.data.kernel_args();
         unsafe {
-            ddvol::framebuffer_to_srgb(
-                self.data.as_ptr(),
-                srgb.as_mut_ptr(),
-                self.width as u32,
-                self.height as u32,
-            );
+            ddvol::framebuffer_to_srgb(fb, srgb.as_mut_ptr(), width as u32, height as u32);
         }
         srgb
     }
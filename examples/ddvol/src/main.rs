@@ -8,8 +8,7 @@ extern crate serde_json;
 extern crate serde_derive;
 extern crate docopt;
 
-use std::ptr;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use docopt::Docopt;
 use rand::distributions::Standard;
@@ -18,6 +17,7 @@ use rand::{thread_rng, Rng};
 use crate::fb::Framebuffer;
 use crate::scene::{RenderParams, Scene};
 
+mod anim;
 mod camera;
 mod fb;
 mod raw;
@@ -29,10 +29,6 @@ mod vol;
 ispc_module!(ddvol);
 
 pub type ISPCHandle = *mut ::std::os::raw::c_void;
-/// Create a new null ISPCHandle
-pub fn empty_handle() -> ISPCHandle {
-    ptr::null::<*mut ::std::os::raw::c_void>() as ISPCHandle
-}
 
 const USAGE: &str = "
 Usage:
@@ -42,6 +38,8 @@ Usage:
 Options:
   -o OUT                Specify a file to writing the render to, defaults to 'ddvol.png'.
   -i, --isovalue VAL    Set an isovalue to render an implicit isosurface with the volume.
+  -f, --frames N        Render an animation of N frames orbiting the camera around its
+                        target, writing each frame to OUT with the frame number appended.
   -h, --help            Show this message.
 ";
 
@@ -50,24 +48,19 @@ pub struct Args {
     arg_scene: String,
     flag_o: Option<String>,
     flag_i: Option<f32>,
+    flag_frames: Option<usize>,
 }
 
-fn main() {
-    let args: Args = Docopt::new(USAGE)
-        .and_then(|d| d.deserialize())
-        .unwrap_or_else(|e| e.exit());
-    let mut scene = Scene::load(&args.arg_scene[..]);
-    if let Some(val) = args.flag_i {
-        scene.volume.set_isovalue(val);
-    }
-    let mut framebuffer = Framebuffer::new(scene.width, scene.height);
+/// Render the scene into the framebuffer, which must be cleared first. Returns the time
+/// spent rendering.
+pub fn render_frame(scene: &Scene, framebuffer: &mut Framebuffer) -> Duration {
     // We need a random seed for each scanline of the image
     let scanline_seeds: Vec<i32> = thread_rng()
         .sample_iter(&Standard)
         .take(scene.height)
         .collect();
+    let start = Instant::now();
     unsafe {
-        let start = Instant::now();
         ddvol::render(
             scene.camera.ispc_equiv(),
             scene.volume.ispc_equiv(),
@@ -77,25 +70,31 @@ fn main() {
             scene.height as u32,
             framebuffer.data.as_mut_ptr(),
         );
-        let elapsed = start.elapsed();
-        println!(
-            "Rendering took {}s",
-            elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9
-        );
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+    let mut scene = Scene::load(&args.arg_scene[..]);
+    if let Some(val) = args.flag_i {
+        scene.volume.set_isovalue(val);
     }
     let out_file = match args.flag_o {
         Some(s) => s,
         None => String::from("ddvol.png"),
     };
-    let srgb_img = framebuffer.srgb8();
-    match image::save_buffer(
-        &out_file[..],
-        &srgb_img[..],
-        scene.width as u32,
-        scene.height as u32,
-        image::ColorType::Rgb8,
-    ) {
-        Ok(_) => println!("Rendered image saved to {out_file}"),
-        Err(e) => panic!("Error saving image: {e}"),
-    };
+    if let Some(frames) = args.flag_frames {
+        anim::render_animation(&mut scene, frames, &out_file);
+        return;
+    }
+    let mut framebuffer = Framebuffer::new(scene.width, scene.height);
+    let elapsed = render_frame(&scene, &mut framebuffer);
+    println!(
+        "Rendering took {}s",
+        elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9
+    );
+    framebuffer.save(&out_file);
 }
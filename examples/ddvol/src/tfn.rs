@@ -1,11 +1,10 @@
 use crate::ddvol;
-use crate::empty_handle;
 use crate::vec3::Vec3f;
 use crate::ISPCHandle;
 
 /// A transfer function used to map values of the volume to colors
 pub struct TransferFunction {
-    ispc_handle: ISPCHandle,
+    handle: ddvol::TransferFunctionHandle,
 }
 
 impl TransferFunction {
@@ -41,29 +40,17 @@ impl TransferFunction {
         TransferFunction::new(&colors[..], &opacities[..])
     }
     pub fn new(colors: &[Vec3f], opacities: &[f32]) -> TransferFunction {
-        let mut tfn = empty_handle();
-        unsafe {
-            ddvol::make_transfer_function(
-                &mut tfn as *mut ISPCHandle,
+        let handle = unsafe {
+            ddvol::TransferFunctionHandle::new(
                 colors.as_ptr(),
                 colors.len() as i32,
                 opacities.as_ptr(),
                 opacities.len() as i32,
-            );
-        }
-        TransferFunction { ispc_handle: tfn }
+            )
+        };
+        TransferFunction { handle }
     }
     pub fn ispc_equiv(&self) -> ISPCHandle {
-        self.ispc_handle
-    }
-}
-
-impl Drop for TransferFunction {
-    fn drop(&mut self) {
-        if !self.ispc_handle.is_null() {
-            unsafe {
-                ddvol::drop_transfer_function(self.ispc_handle);
-            }
-        }
+        self.handle.as_raw()
     }
 }
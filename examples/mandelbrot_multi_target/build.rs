@@ -0,0 +1,21 @@
+extern crate ispc_compile;
+
+use ispc_compile::TargetISA;
+
+fn main() {
+    // The ISAs which can't run on the target are skipped, so on x86 the kernel is
+    // compiled for the SSE and AVX ISAs with a dispatcher picking the best one the CPU
+    // supports, and on ARM for NEON alone
+    ispc_compile::Config::new()
+        .file("src/mandelbrot.ispc")
+        .target_isas(vec![
+            TargetISA::SSE2i32x4,
+            TargetISA::SSE4i32x4,
+            TargetISA::AVX1i32x8,
+            TargetISA::AVX2i32x8,
+            TargetISA::AVX512SKXx16,
+            TargetISA::Neoni32x4,
+        ])
+        .isa_entry_points()
+        .compile("mandelbrot");
+}
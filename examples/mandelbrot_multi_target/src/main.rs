@@ -0,0 +1,93 @@
+extern crate criterion;
+extern crate image;
+#[macro_use]
+extern crate ispc_rt;
+
+use std::time::Duration;
+
+use criterion::Criterion;
+use ispc_rt::bench;
+
+ispc_module!(mandelbrot);
+
+const WIDTH: usize = 1080;
+const HEIGHT: usize = 720;
+const MAX_ITERS: i32 = 255;
+
+/// The signature of the Mandelbrot kernel compiled for each target ISA
+type Kernel = unsafe extern "C" fn(f32, f32, f32, f32, i32, i32, i32, *mut i32);
+
+/// Render the Mandelbrot set with the `kernel` into `counts`
+fn render(kernel: Kernel, counts: &mut [i32]) {
+    assert_eq!(counts.len(), WIDTH * HEIGHT);
+    unsafe {
+        kernel(
+            -2.0,
+            1.0,
+            -1.0,
+            1.0,
+            WIDTH as i32,
+            HEIGHT as i32,
+            MAX_ITERS,
+            counts.as_mut_ptr(),
+        );
+    }
+}
+
+fn main() {
+    let supported: Vec<&str> = mandelbrot::isa::ALL
+        .iter()
+        .copied()
+        .filter(|isa| bench::isa_supported(isa))
+        .collect();
+    println!("Compiled for the target ISAs {:?}", mandelbrot::isa::ALL);
+    println!("The CPU supports {supported:?}");
+
+    // The dispatcher calls the entry point of the best ISA the CPU supports, which is the
+    // one returning the same ID
+    let (id, gang_width) = unsafe { (mandelbrot::target_id(), mandelbrot::gang_width()) };
+    let picked = supported
+        .iter()
+        .find(|isa| unsafe { mandelbrot::isa::target_id(isa).unwrap()() } == id);
+    match picked {
+        Some(isa) => println!("The dispatcher picked {isa} with a gang width of {gang_width}"),
+        None => println!("The dispatcher picked an unknown ISA with ID {id}"),
+    }
+
+    let mut counts = vec![0; WIDTH * HEIGHT];
+    render(mandelbrot::mandelbrot, &mut counts);
+
+    // Every ISA should render the same image, up to the rounding of fused multiply-adds
+    for isa in &supported {
+        let mut isa_counts = vec![0; WIDTH * HEIGHT];
+        render(mandelbrot::isa::mandelbrot(isa).unwrap(), &mut isa_counts);
+        let differing = counts
+            .iter()
+            .zip(&isa_counts)
+            .filter(|(a, b)| a != b)
+            .count();
+        println!("{isa} differs from the dispatched kernel in {differing} pixels");
+    }
+
+    let img = counts.iter().map(|x| 255 - *x as u8).collect::<Vec<u8>>();
+    match image::save_buffer(
+        "mandelbrot.png",
+        &img[..],
+        WIDTH as u32,
+        HEIGHT as u32,
+        image::ColorType::L8,
+    ) {
+        Ok(_) => println!("Mandelbrot image saved to mandelbrot.png"),
+        Err(e) => panic!("Error saving Mandelbrot image: {e}"),
+    };
+
+    // Benchmark the kernel of each ISA the CPU supports against each other
+    let mut c = Criterion::default()
+        .warm_up_time(Duration::from_millis(500))
+        .measurement_time(Duration::from_secs(2))
+        .sample_size(20);
+    bench::bench_isas(&mut c, "mandelbrot", mandelbrot::isa::ALL, |b, isa| {
+        let kernel = mandelbrot::isa::mandelbrot(isa).unwrap();
+        b.iter(|| render(kernel, &mut counts));
+    });
+}
@@ -0,0 +1,12 @@
+extern crate ispc;
+
+fn main() {
+    // Each library gets its own bindings module, even though both are built from
+    // source files with the same name and declare a struct with the same name
+    ispc::Config::new()
+        .file("src/kernels_a/kernels.ispc")
+        .compile("kernels_a");
+    ispc::Config::new()
+        .file("src/kernels_b/kernels.ispc")
+        .compile("kernels_b");
+}
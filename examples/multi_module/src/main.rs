@@ -0,0 +1,20 @@
+#[macro_use]
+extern crate ispc;
+
+// The bindings of each library are looked up by the library's name
+ispc_module!(kernels_a);
+ispc_module!(kernels_b);
+
+fn main() {
+    let mut vals = vec![1.0, 2.0, 3.0, 4.0];
+    let a = kernels_a::Params { scale: 2.0 };
+    let b = kernels_b::Params {
+        offset: 0.5,
+        repeat: 2,
+    };
+    unsafe {
+        kernels_a::scale(&a, vals.as_mut_ptr(), vals.len() as i32);
+        kernels_b::offset(&b, vals.as_mut_ptr(), vals.len() as i32);
+    }
+    println!("vals = {vals:?}");
+}
@@ -0,0 +1,29 @@
+#[macro_use]
+extern crate ispc;
+
+use std::mem;
+
+ispc_module!(kernels_a);
+ispc_module!(kernels_b);
+
+#[test]
+fn structs_with_the_same_name_are_separate() {
+    assert_eq!(mem::size_of::<kernels_a::Params>(), 4);
+    assert_eq!(mem::size_of::<kernels_b::Params>(), 8);
+}
+
+#[test]
+fn kernels_run_independently() {
+    let mut vals = vec![1.0, 2.0, 3.0, 4.0];
+    let a = kernels_a::Params { scale: 2.0 };
+    let b = kernels_b::Params {
+        offset: 0.5,
+        repeat: 2,
+    };
+    unsafe {
+        kernels_a::scale(&a, vals.as_mut_ptr(), vals.len() as i32);
+        assert_eq!(vals, [2.0, 4.0, 6.0, 8.0]);
+        kernels_b::offset(&b, vals.as_mut_ptr(), vals.len() as i32);
+    }
+    assert_eq!(vals, [3.0, 5.0, 7.0, 9.0]);
+}
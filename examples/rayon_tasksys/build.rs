@@ -0,0 +1,5 @@
+extern crate ispc;
+
+fn main() {
+    ispc::compile_library("rayon_tasksys", &["src/rayon_tasksys.ispc"]);
+}
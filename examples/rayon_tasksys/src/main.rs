@@ -0,0 +1,125 @@
+#[macro_use]
+extern crate ispc;
+extern crate libc;
+extern crate rayon;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use ispc::exec::TaskSystem;
+use ispc::task::{Context, ISPCTaskFn};
+
+ispc_module!(rayon_tasksys);
+
+/// The number of tasks each rayon job runs at a time before taking the next chunk
+const CHUNK_SIZE: usize = 4;
+
+/// This task system runs the tasks launched by ISPC on rayon's global thread pool. Each
+/// ISPC function launching tasks gets a `Context` through its handle, which tracks the
+/// memory allocated for the tasks and the groups of tasks launched. The chunks of each
+/// group are run by rayon jobs, and by the thread syncing the context.
+#[derive(Default)]
+struct RayonTaskSys {
+    /// Used to give each context a unique id
+    next_context_id: AtomicUsize,
+}
+
+impl TaskSystem for RayonTaskSys {
+    unsafe fn alloc(
+        &self,
+        handle_ptr: *mut *mut libc::c_void,
+        size: i64,
+        align: i32,
+    ) -> *mut libc::c_void {
+        // The first alloc made by a function creates its context, which is released again
+        // once the function syncs it
+        if (*handle_ptr).is_null() {
+            let id = self.next_context_id.fetch_add(1, Ordering::SeqCst);
+            *handle_ptr = Box::into_raw(Box::new(Context::new(id))) as *mut libc::c_void;
+        }
+        let context = &*(*handle_ptr as *const Context);
+        context.alloc(size as usize, align as usize)
+    }
+    unsafe fn launch(
+        &self,
+        handle_ptr: *mut *mut libc::c_void,
+        f: ISPCTaskFn,
+        data: *mut libc::c_void,
+        count0: i32,
+        count1: i32,
+        count2: i32,
+    ) {
+        let context = &*(*handle_ptr as *const Context);
+        context.launch((count0, count1, count2), data, f);
+        // Only the function owning the context launches tasks in it, so the group we just
+        // launched is the last one
+        let group = context.group(context.group_count() - 1).unwrap();
+        let chunks = (count0 * count1 * count2) as usize;
+        let jobs = chunks
+            .div_ceil(CHUNK_SIZE)
+            .min(rayon::current_num_threads());
+        for _ in 0..jobs {
+            let group = Arc::clone(&group);
+            rayon::spawn(move || {
+                let total_threads = rayon::current_num_threads();
+                let thread = rayon::current_thread_index().unwrap_or(0);
+                for chunk in group.chunks(CHUNK_SIZE) {
+                    chunk.execute(thread as i32, total_threads as i32);
+                }
+            });
+        }
+    }
+    unsafe fn sync(&self, handle: *mut libc::c_void) {
+        let context = Box::from_raw(handle as *mut Context);
+        context.mark_syncing();
+        // Help run the tasks in our context instead of blocking, this thread may be one of
+        // rayon's workers which the jobs we're waiting on need to run. The syncing thread
+        // gets the index one past rayon's threads if it's not in the pool.
+        let total_threads = rayon::current_num_threads();
+        let thread = rayon::current_thread_index().unwrap_or(total_threads);
+        for group in context.iter() {
+            for chunk in group.chunks(CHUNK_SIZE) {
+                chunk.execute(thread as i32, total_threads as i32);
+            }
+        }
+        // The remaining chunks were taken by rayon jobs which are still running. Run other
+        // jobs on the pool while waiting, so tasks syncing on a worker can't starve it.
+        while !context.current_tasks_done() {
+            if rayon::yield_now().is_none() {
+                std::thread::yield_now();
+            }
+        }
+    }
+}
+
+fn main() {
+    // Tell ispc-rs to use our task system **before** calling any ISPC functions which
+    // launch tasks
+    ispc::set_task_system(|| Arc::new(RayonTaskSys::default()));
+
+    let (blocks, block_tiles, tile_rows, row_len) = (16, 8, 8, 512);
+    let rows = blocks * block_tiles * tile_rows;
+    let mut out = vec![0.0f32; (rows * row_len) as usize];
+    let start = Instant::now();
+    unsafe {
+        rayon_tasksys::compute(out.as_mut_ptr(), blocks, block_tiles, tile_rows, row_len);
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "Ran {} tasks on {} rayon threads in {elapsed:?}",
+        blocks + blocks * block_tiles + rows,
+        rayon::current_num_threads()
+    );
+
+    for (i, row) in out.chunks(row_len as usize).enumerate() {
+        for (j, x) in row.iter().enumerate() {
+            let expected = unsafe { rayon_tasksys::expected(i as i32, j as i32) };
+            assert!(
+                (x - expected).abs() <= 1e-5 * expected.abs().max(1.0),
+                "Element [{i}, {j}] is {x} but should be {expected}"
+            );
+        }
+    }
+    println!("All {} elements were computed correctly", out.len());
+}
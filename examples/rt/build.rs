@@ -5,6 +5,7 @@ fn main() {
     let ispc_files = [
         "src/rt.ispc",
         "src/geom.ispc",
+        "src/bvh.ispc",
         "src/material.ispc",
         "src/lights.ispc",
         "src/mc.ispc",
@@ -12,5 +13,7 @@ fn main() {
     for s in &ispc_files[..] {
         cfg.file(*s);
     }
+    // Generate a builder for render, which takes nine parameters
+    cfg.call_builders(8);
     cfg.compile("rt");
 }
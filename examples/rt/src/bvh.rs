@@ -0,0 +1,75 @@
+//! A bounding volume hierarchy over the scene's geometry. The node and primitive buffers
+//! are owned by Rust and filled in by the ISPC builder, which builds the tree in parallel
+//! with tasks, so the same layout is used by Rust to inspect the tree and by ISPC to
+//! traverse it when rendering.
+
+use crate::geom::ISPCGeometry;
+use crate::rt;
+
+/// Type alias for the BVH node struct shared with ISPC
+pub type BVHNode = rt::BVHNode;
+
+pub struct Bvh {
+    nodes: Vec<BVHNode>,
+    /// The indices of the bounded geometry, in the order the leaves refer to them
+    prims: Vec<i32>,
+    /// The indices of the geometry without bounds, which every ray is tested against
+    unbounded: Vec<i32>,
+}
+
+impl Bvh {
+    /// Build a BVH over the bounded geometry in `geometry`. The leaves refer to the
+    /// geometry by its index in the list, so the renderer must be passed the same list.
+    pub fn build(geometry: &[Box<dyn ISPCGeometry>]) -> Bvh {
+        let ispc_geom: Vec<_> = geometry.iter().map(|x| x.ispc_equiv()).collect();
+        let (mut prims, unbounded): (Vec<i32>, Vec<i32>) =
+            (0..geometry.len() as i32).partition(|&i| geometry[i as usize].bounded());
+        // A binary tree with a leaf per primitive has at most 2n - 1 nodes
+        let max_nodes = (2 * prims.len()).saturating_sub(1);
+        let mut nodes: Vec<BVHNode> = vec![unsafe { std::mem::zeroed() }; max_nodes];
+        let n_nodes = unsafe {
+            rt::build_bvh(
+                ispc_geom.as_ptr(),
+                prims.as_mut_ptr(),
+                prims.len() as i32,
+                nodes.as_mut_ptr(),
+            )
+        };
+        nodes.truncate(n_nodes as usize);
+        Bvh {
+            nodes,
+            prims,
+            unbounded,
+        }
+    }
+    pub fn nodes(&self) -> &[BVHNode] {
+        &self.nodes
+    }
+    /// Get the depth of the tree, which is 0 if there's no bounded geometry
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push((0, 1));
+        }
+        while let Some((node, d)) = stack.pop() {
+            let node: &BVHNode = &self.nodes[node];
+            depth = depth.max(d);
+            if node.count == 0 {
+                stack.push((node.offset as usize, d + 1));
+                stack.push((node.offset as usize + 1, d + 1));
+            }
+        }
+        depth
+    }
+    /// Get the struct referring to the BVH to pass to ISPC, which must not outlive it
+    pub fn ispc_equiv(&self) -> rt::BVH {
+        rt::BVH {
+            nodes: self.nodes.as_ptr(),
+            n_nodes: self.nodes.len() as i32,
+            prims: self.prims.as_ptr(),
+            unbounded: self.unbounded.as_ptr(),
+            n_unbounded: self.unbounded.len() as i32,
+        }
+    }
+}
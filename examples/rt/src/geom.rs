@@ -12,6 +12,10 @@ pub type Geometry = crate::rt::Geometry;
 
 pub trait ISPCGeometry {
     fn ispc_equiv(&self) -> *const Geometry;
+    /// Check if the geometry has finite bounds, so it can be put in the BVH
+    fn bounded(&self) -> bool {
+        true
+    }
 }
 
 /// A simple sphere with some radius located at `center`
@@ -96,6 +100,9 @@ impl ISPCGeometry for Plane {
     fn ispc_equiv(&self) -> *const Geometry {
         self.ispc_geom
     }
+    fn bounded(&self) -> bool {
+        false
+    }
 }
 
 impl Drop for Plane {
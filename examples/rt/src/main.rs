@@ -13,12 +13,15 @@ extern crate docopt;
 use std::time::Instant;
 
 use docopt::Docopt;
+use ispc::Image2D;
 use rand::distributions::Standard;
 use rand::{thread_rng, Rng};
 
+use crate::bvh::Bvh;
 use crate::camera::Camera;
 use crate::scene::Scene;
 
+mod bvh;
 mod camera;
 mod geom;
 mod lights;
@@ -49,38 +52,44 @@ fn main() {
         .and_then(|d| d.deserialize())
         .unwrap_or_else(|e| e.exit());
     let scene = Scene::load(&args.arg_scene[..]);
-    let mut framebuffer = vec![0.0; scene.width * scene.height * 3];
-    let mut srgb_img_buf = vec![0u8; scene.width * scene.height * 3];
+    let mut framebuffer = Image2D::new(scene.width, scene.height, 3, 0.0f32);
+    let mut srgb_img = Image2D::new(scene.width, scene.height, 3, 0u8);
     // We need a random seed for each scanline of the image
     let scanline_seeds: Vec<i32> = thread_rng()
         .sample_iter(&Standard)
         .take(scene.height)
         .collect();
+    let start = Instant::now();
+    let bvh = Bvh::build(&scene.geometry);
+    println!(
+        "Building the BVH over {} objects took {:?}, it has {} nodes and a depth of {}",
+        scene.geometry.len(),
+        start.elapsed(),
+        bvh.nodes().len(),
+        bvh.depth()
+    );
     unsafe {
         let geom: Vec<_> = scene.geometry.iter().map(|x| x.ispc_equiv()).collect();
+        let ispc_bvh = bvh.ispc_equiv();
         let start = Instant::now();
-        rt::render(
-            &scene.camera as *const Camera,
-            geom.as_ptr(),
-            geom.len() as i32,
-            scene.light.ispc_equiv(),
-            scanline_seeds.as_ptr(),
-            scene.width as i32,
-            scene.height as i32,
-            framebuffer.as_mut_ptr(),
-            scene.n_samples as i32,
-        );
+        let (img, width, height, _) = framebuffer.kernel_args_mut();
+        rt::RenderCall::new()
+            .camera(&scene.camera as *const Camera)
+            .geom(geom.as_ptr())
+            .bvh(&ispc_bvh as *const rt::BVH)
+            .light(scene.light.ispc_equiv())
+            .seeds(scanline_seeds.as_ptr())
+            .width(width)
+            .height(height)
+            .img(img)
+            .n_samples(scene.n_samples as i32)
+            .invoke();
         let elapsed = start.elapsed();
         println!(
             "Rendering took {}s",
             elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9
         );
-        rt::framebuffer_to_srgb(
-            framebuffer.as_ptr(),
-            srgb_img_buf.as_mut_ptr(),
-            scene.width as i32,
-            scene.height as i32,
-        );
+        rt::framebuffer_to_srgb(framebuffer.as_ptr(), srgb_img.as_mut_ptr(), width, height);
     }
     let out_file = match args.flag_o {
         Some(s) => s,
@@ -88,7 +97,7 @@ fn main() {
     };
     match image::save_buffer(
         &out_file[..],
-        &srgb_img_buf[..],
+        srgb_img.as_slice(),
         scene.width as u32,
         scene.height as u32,
         image::ColorType::Rgb8,
@@ -37,6 +37,21 @@
 //!     "n_samples": 8
 //! }
 //! ```
+//!
+//! Besides single spheres and planes a `sphere_grid` can be placed to fill the
+//! scene with many small spheres without listing each one, the spheres are laid
+//! out in a square on the XZ plane around the center and colored by cycling
+//! through hues:
+//!
+//! ```json
+//! {
+//!     "type": "sphere_grid",
+//!     "center": [0, -0.4, 1],
+//!     "size": 4,
+//!     "count": 24,
+//!     "radius": 0.08
+//! }
+//! ```
 
 use std::fs::File;
 use std::io::prelude::*;
@@ -128,7 +143,7 @@ impl Scene {
     fn load_geometry(e: &Value) -> Vec<Box<dyn ISPCGeometry>> {
         let geom = e.as_array().expect("Geometry must be an array of objects");
         geom.iter()
-            .map(|x| {
+            .flat_map(|x| {
                 if !x.is_object() {
                     panic!("Geometry must be specified as JSON objects, see the examples");
                 }
@@ -137,6 +152,9 @@ impl Scene {
                     .expect("A geometry type must be set")
                     .as_str()
                     .expect("Geometry type must be a string");
+                if ty == "sphere_grid" {
+                    return Scene::load_sphere_grid(x);
+                }
                 let lambertian =
                     Scene::load_vec3f(x.get("lambertian").expect("A lambertian color must be set"))
                         .unwrap();
@@ -150,7 +168,7 @@ impl Scene {
                         .expect("A sphere radius must be set")
                         .as_f64()
                         .unwrap() as f32;
-                    Box::new(Sphere::new(center, radius, mat)) as Box<dyn ISPCGeometry>
+                    vec![Box::new(Sphere::new(center, radius, mat)) as Box<dyn ISPCGeometry>]
                 } else if ty == "plane" {
                     let center =
                         Scene::load_vec3f(x.get("center").expect("A plane center must be set"))
@@ -158,13 +176,57 @@ impl Scene {
                     let normal =
                         Scene::load_vec3f(x.get("normal").expect("A plane normal must be set"))
                             .unwrap();
-                    Box::new(Plane::new(center, normal, mat)) as Box<dyn ISPCGeometry>
+                    vec![Box::new(Plane::new(center, normal, mat)) as Box<dyn ISPCGeometry>]
                 } else {
                     panic!("Unrecognized geometry type {ty}");
                 }
             })
             .collect()
     }
+    fn load_sphere_grid(e: &Value) -> Vec<Box<dyn ISPCGeometry>> {
+        let center =
+            Scene::load_vec3f(e.get("center").expect("A sphere grid center must be set")).unwrap();
+        let size = e
+            .get("size")
+            .expect("A sphere grid size must be set")
+            .as_f64()
+            .unwrap() as f32;
+        let count = e
+            .get("count")
+            .expect("A sphere grid count must be set")
+            .as_u64()
+            .expect("Sphere grid count must be a uint") as usize;
+        let radius = e
+            .get("radius")
+            .expect("A sphere grid radius must be set")
+            .as_f64()
+            .unwrap() as f32;
+        let step = if count > 1 {
+            size / (count - 1) as f32
+        } else {
+            0.0
+        };
+        let start = center - Vec3f::new(size / 2.0, 0.0, size / 2.0);
+        let mut geom = Vec::with_capacity(count * count);
+        for i in 0..count {
+            for j in 0..count {
+                let pos = start + Vec3f::new(i as f32 * step, 0.0, j as f32 * step);
+                let mat = Lambertian::new(Scene::hue_color((i + j) as f32 / 8.0));
+                geom.push(Box::new(Sphere::new(pos, radius, mat)) as Box<dyn ISPCGeometry>);
+            }
+        }
+        geom
+    }
+    /// Compute a saturated color for the hue `h`, where each whole number is a full
+    /// turn around the color wheel
+    fn hue_color(h: f32) -> Vec3f {
+        let channel = |offset: f32| {
+            let k = (offset + h.fract() * 6.0) % 6.0;
+            let t = k.min(4.0 - k).clamp(0.0, 1.0) * 0.63;
+            0.9 - t
+        };
+        Vec3f::new(channel(5.0), channel(3.0), channel(1.0))
+    }
     fn load_light(e: &Value) -> PointLight {
         let pos = Scene::load_vec3f(e.get("pos").expect("A light position must be set")).unwrap();
         let intensity =
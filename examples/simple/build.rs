@@ -6,17 +6,15 @@ extern crate ispc_rt;
 fn link_ispc() {
     use ispc_compile::TargetISA;
 
-    #[cfg(target_arch = "x86_64")]
+    // The x86 ISAs are skipped when building for ARM, which uses NEON
     let target_isas = vec![
         TargetISA::SSE2i32x4,
         TargetISA::SSE4i32x4,
         TargetISA::AVX1i32x8,
         TargetISA::AVX2i32x8,
+        TargetISA::Neoni32x4,
     ];
 
-    #[cfg(target_arch = "aarch64")]
-    let target_isas = vec![TargetISA::Neoni32x4];
-
     let bindgen_builder = ispc_compile::bindgen::builder().allowlist_function("add_lists");
 
     // For a portable program we can explicitly compile for each target ISA
@@ -26,6 +24,7 @@ fn link_ispc() {
         .file("src/simple.ispc")
         .target_isas(target_isas)
         .bindgen_builder(bindgen_builder)
+        .bindings_snapshot("snapshots/simple.rs")
         .out_dir("src/")
         .compile("simple");
 }
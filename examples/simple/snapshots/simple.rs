@@ -0,0 +1,6 @@
+#[allow(non_camel_case_types,dead_code,non_upper_case_globals,non_snake_case,improper_ctypes)]
+pub mod simple {
+extern "C" {
+    pub fn add_lists(a: *const f32, b: *const f32, c: *mut f32, count: i32);
+}
+}
@@ -0,0 +1,6 @@
+extern crate ispc;
+
+fn main() {
+    // Compile the kernels defined with test_kernel! in the tests
+    ispc::compile_test_kernels("tests/");
+}
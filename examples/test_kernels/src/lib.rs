@@ -0,0 +1,2 @@
+//! Tests ISPC kernels function by function, with each kernel defined inline in the test
+//! calling it through `ispc::test_kernel!`, see `tests/kernels.rs`.
@@ -0,0 +1,35 @@
+#[macro_use]
+extern crate ispc;
+
+#[test]
+fn scale() {
+    test_kernel!(
+        scale_kernel,
+        r#"
+        export void scale(uniform float v[], uniform int n, uniform float s) {
+            foreach (i = 0 ... n) {
+                v[i] *= s;
+            }
+        }
+        "#
+    );
+    let mut v = [1.0f32, 2.0, 3.0, 4.0, 5.0];
+    unsafe { scale_kernel::scale(v.as_mut_ptr(), v.len() as i32, 2.0) };
+    assert_eq!(v, [2.0, 4.0, 6.0, 8.0, 10.0]);
+}
+
+#[test]
+fn sum() {
+    test_kernel!(
+        sum_kernel,
+        "export uniform int sum(uniform int v[], uniform int n) {\n\
+             int total = 0;\n\
+             foreach (i = 0 ... n) {\n\
+                 total += v[i];\n\
+             }\n\
+             return reduce_add(total);\n\
+         }"
+    );
+    let v: Vec<i32> = (1..=100).collect();
+    assert_eq!(unsafe { sum_kernel::sum(v.as_ptr(), v.len() as i32) }, 5050);
+}
@@ -0,0 +1,172 @@
+//! Turns failed `assert()`s and `abort()` calls in ISPC code into Rust panics which
+//! report the kernel and the failed assertion, instead of the process being killed
+//! with no context.
+//!
+//! ISPC code built with `Config::route_asserts` calls into the runtime instead of the
+//! C library when an assertion fails. The handler set with `set_abort_handler` is called
+//! with the details of the failure, after which the runtime panics with them. ISPC code
+//! can't be unwound through, so the process still aborts, but only after the panic
+//! message, and any panic hook, reported what failed.
+//!
+//! The runtime takes over all `printf`, `puts` and `abort` calls in the library's ISPC
+//! code, not just those of assertions, so output the code prints with them itself is
+//! reported as part of the next failure's message.
+//!
+//! Which kernel failed is reported if it was called within `with_kernel`.
+//!
+//! # Example
+//! ```ignore
+//! ispc_rt::abort::set_abort_handler(|abort| {
+//!     // e.g. "ISPC kernel simulate aborted in task 3/16: src/sim.ispc:42:5: Assertion failed: dt > 0"
+//!     log::error!("{abort}");
+//! });
+//! ispc_rt::abort::with_kernel("simulate", || unsafe { sim::simulate(/* ... */) });
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::ffi::{c_char, c_int, CStr};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// A handler called with the details of a failed assertion or abort in ISPC code
+type AbortHandler = dyn Fn(&IspcAbort) + Send + Sync;
+
+static ABORT_HANDLER: RwLock<Option<Arc<AbortHandler>>> = RwLock::new(None);
+
+thread_local! {
+    /// The kernel called within `with_kernel` on this thread, or whose tasks are running on it
+    static CURRENT_KERNEL: Cell<Option<&'static str>> = const { Cell::new(None) };
+    /// The message ISPC printed before aborting
+    static MESSAGE: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Details of a failed assertion or abort in ISPC code
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IspcAbort {
+    kernel: Option<&'static str>,
+    task: Option<(i32, i32)>,
+    message: String,
+}
+
+impl IspcAbort {
+    /// Get the name of the kernel which aborted, if it was called within `with_kernel`
+    pub fn kernel(&self) -> Option<&'static str> {
+        self.kernel
+    }
+    /// Get the index and count of the task which aborted, if it was running in a task
+    pub fn task(&self) -> Option<(i32, i32)> {
+        self.task
+    }
+    /// Get the message ISPC reported, e.g. `src/sim.ispc:42:5: Assertion failed: dt > 0`
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for IspcAbort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kernel {
+            Some(k) => write!(f, "ISPC kernel {k} aborted")?,
+            None => write!(f, "ISPC kernel aborted")?,
+        }
+        if let Some((task, count)) = self.task {
+            write!(f, " in task {task}/{count}")?;
+        }
+        if self.message.is_empty() {
+            Ok(())
+        } else {
+            write!(f, ": {}", self.message)
+        }
+    }
+}
+
+/// Set the handler to call when ISPC code aborts, e.g. to log the failure or flush
+/// output before the runtime panics. The handler is called on the thread the ISPC code
+/// runs on.
+pub fn set_abort_handler<F: Fn(&IspcAbort) + Send + Sync + 'static>(handler: F) {
+    *ABORT_HANDLER.write().unwrap() = Some(Arc::new(handler));
+}
+
+/// Remove the handler set with `set_abort_handler`
+pub fn reset_abort_handler() {
+    *ABORT_HANDLER.write().unwrap() = None;
+}
+
+/// Restores the previous kernel of the thread when dropped
+struct RestoreKernel(Option<&'static str>);
+
+impl Drop for RestoreKernel {
+    fn drop(&mut self) {
+        CURRENT_KERNEL.with(|k| k.set(self.0));
+    }
+}
+
+/// Run `f`, reporting `kernel` as the kernel which failed if the ISPC code it calls,
+/// including the tasks it launches, aborts
+pub fn with_kernel<R, F: FnOnce() -> R>(kernel: &'static str, f: F) -> R {
+    run_with(Some(kernel), f)
+}
+
+/// Get the kernel associated with the code running on this thread
+pub fn current_kernel() -> Option<&'static str> {
+    CURRENT_KERNEL.with(|k| k.get())
+}
+
+/// Run `f` with `kernel` as the current kernel of this thread, used by the task
+/// system when running tasks of a context.
+pub(crate) fn run_with<R, F: FnOnce() -> R>(kernel: Option<&'static str>, f: F) -> R {
+    let _restore = RestoreKernel(CURRENT_KERNEL.with(|k| k.replace(kernel)));
+    f()
+}
+
+/// Collect the details of the abort on the current thread and call the handler
+fn report() -> IspcAbort {
+    let message = MESSAGE.with(|m| std::mem::take(&mut *m.borrow_mut()));
+    let info = IspcAbort {
+        kernel: current_kernel(),
+        task: crate::print::current_task().map(|(_, task, count)| (task, count)),
+        message: message.trim_end().to_owned(),
+    };
+    let handler = ABORT_HANDLER.read().unwrap().clone();
+    if let Some(h) = handler {
+        h(&info);
+    }
+    info
+}
+
+/// Record the `message` ISPC printed before aborting
+unsafe fn record_message(message: *const c_char) {
+    if !message.is_null() {
+        let message = CStr::from_ptr(message).to_string_lossy();
+        MESSAGE.with(|m| m.borrow_mut().push_str(&message));
+    }
+}
+
+/// Replaces `printf` in ISPC objects built with `Config::route_asserts`, which ISPC
+/// only calls with the message of a failed assertion before aborting. The message
+/// is recorded for the panic rather than formatted.
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn ispc_rt_assert_printf(message: *const c_char) -> c_int {
+    record_message(message);
+    0
+}
+
+/// Replaces `puts` in ISPC objects built with `Config::route_asserts`, which the
+/// `printf` call of a failed assertion may be optimized to
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn ispc_rt_assert_puts(message: *const c_char) -> c_int {
+    record_message(message);
+    0
+}
+
+/// Replaces `abort` in ISPC objects built with `Config::route_asserts`. The panic
+/// can't unwind out of this `extern "C"` function, so the process aborts after the
+/// panic message is printed.
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn ispc_rt_abort() -> ! {
+    let info = report();
+    panic!("{info}");
+}
@@ -0,0 +1,160 @@
+//! Adapters turning `ndarray` arrays into the pointer, dimensions and strides arguments
+//! taken by exported kernels, with the same layout as `Image2D` and `Volume3D`.
+//!
+//! ISPC kernels index arrays through a pointer and the number of elements between rows
+//! and slices, so the innermost axis of the array must be contiguous and the strides
+//! of the outer axes positive. The adapters check this at runtime and panic otherwise,
+//! e.g. for a transposed or reversed view, which can be copied to a standard layout
+//! array with `as_standard_layout` first.
+//!
+//! # Example
+//! ```
+//! use ispc_rt::array;
+//! use ispc_rt::ndarray::{s, Array2};
+//!
+//! let mut img = Array2::<f32>::zeros((480, 640));
+//! // Pass the image to a kernel taking `uniform float img[], uniform int width,
+//! // uniform int height, uniform int pitch`
+//! let (ptr, width, height, pitch) = array::array2_args_mut(&mut img);
+//! assert_eq!((width, height, pitch), (640, 480, 640));
+//!
+//! // Views of a region keep the pitch of the full array
+//! let region = img.slice(s![10..20, 100..200]);
+//! let (ptr, width, height, pitch) = array::array2_args(&region);
+//! assert_eq!((width, height, pitch), (100, 10, 640));
+//! ```
+
+use ndarray::{ArrayBase, Data, DataMut, Dimension, Ix1, Ix2, Ix3};
+
+/// Convert a dimension or stride to the `int` ISPC kernels take
+fn kernel_dim(value: usize, name: &str) -> i32 {
+    i32::try_from(value)
+        .unwrap_or_else(|_| panic!("ispc_rt: array {name} {value} doesn't fit in an i32"))
+}
+
+/// Check the array can be passed to a kernel and get its shape and the strides of its
+/// axes in elements, outermost first
+fn kernel_layout<S: Data, D: Dimension>(a: &ArrayBase<S, D>) -> (Vec<usize>, Vec<usize>) {
+    let shape = a.shape().to_vec();
+    let strides: Vec<usize> = a
+        .strides()
+        .iter()
+        .zip(shape.iter())
+        .map(|(&s, &len)| {
+            // The stride of an axis with at most one element is never used
+            if len <= 1 {
+                return 0;
+            }
+            usize::try_from(s).unwrap_or_else(|_| {
+                panic!("ispc_rt: array with negative stride {s} can't be passed to ISPC")
+            })
+        })
+        .collect();
+    if let (Some(&len), Some(&stride)) = (shape.last(), strides.last()) {
+        assert!(
+            len <= 1 || stride == 1,
+            "ispc_rt: array with the innermost stride {stride} can't be passed to ISPC, \
+             the elements of each row must be contiguous"
+        );
+    }
+    (shape, strides)
+}
+
+/// Get the stride of an outer axis as the pitch, which is the size of the axis it
+/// steps over if it holds at most one element
+fn pitch(stride: usize, inner: usize) -> usize {
+    if stride == 0 {
+        inner
+    } else {
+        assert!(
+            stride >= inner,
+            "ispc_rt: array stride {stride} overlaps the {inner} elements of the axis it steps over"
+        );
+        stride
+    }
+}
+
+/// Get the pointer and length to pass to a kernel reading the 1D array `a`
+///
+/// # Panics
+/// Panics if the array isn't contiguous or its length doesn't fit in an `i32`.
+pub fn array1_args<S: Data>(a: &ArrayBase<S, Ix1>) -> (*const S::Elem, i32) {
+    let (shape, _) = kernel_layout(a);
+    (a.as_ptr(), kernel_dim(shape[0], "length"))
+}
+
+/// Get the pointer and length to pass to a kernel writing the 1D array `a`
+///
+/// # Panics
+/// Panics if the array isn't contiguous or its length doesn't fit in an `i32`.
+pub fn array1_args_mut<S: DataMut>(a: &mut ArrayBase<S, Ix1>) -> (*mut S::Elem, i32) {
+    let (shape, _) = kernel_layout(a);
+    (a.as_mut_ptr(), kernel_dim(shape[0], "length"))
+}
+
+/// Get the pointer, width, height and pitch to pass to a kernel reading the 2D array `a`,
+/// indexed as `[y, x]`
+///
+/// # Panics
+/// Panics if the rows of the array aren't contiguous, a stride is negative or a
+/// dimension doesn't fit in an `i32`.
+pub fn array2_args<S: Data>(a: &ArrayBase<S, Ix2>) -> (*const S::Elem, i32, i32, i32) {
+    let (w, h, p) = array2_dims(a);
+    (a.as_ptr(), w, h, p)
+}
+
+/// Get the pointer, width, height and pitch to pass to a kernel writing the 2D array `a`,
+/// indexed as `[y, x]`
+///
+/// # Panics
+/// Panics if the rows of the array aren't contiguous, a stride is negative or a
+/// dimension doesn't fit in an `i32`.
+pub fn array2_args_mut<S: DataMut>(a: &mut ArrayBase<S, Ix2>) -> (*mut S::Elem, i32, i32, i32) {
+    let (w, h, p) = array2_dims(a);
+    (a.as_mut_ptr(), w, h, p)
+}
+
+fn array2_dims<S: Data>(a: &ArrayBase<S, Ix2>) -> (i32, i32, i32) {
+    let (shape, strides) = kernel_layout(a);
+    (
+        kernel_dim(shape[1], "width"),
+        kernel_dim(shape[0], "height"),
+        kernel_dim(pitch(strides[0], shape[1]), "pitch"),
+    )
+}
+
+/// Get the pointer, width, height, depth, pitch and slice pitch to pass to a kernel
+/// reading the 3D array `a`, indexed as `[z, y, x]`
+///
+/// # Panics
+/// Panics if the rows of the array aren't contiguous, a stride is negative or a
+/// dimension doesn't fit in an `i32`.
+pub fn array3_args<S: Data>(a: &ArrayBase<S, Ix3>) -> (*const S::Elem, i32, i32, i32, i32, i32) {
+    let (w, h, d, p, sp) = array3_dims(a);
+    (a.as_ptr(), w, h, d, p, sp)
+}
+
+/// Get the pointer, width, height, depth, pitch and slice pitch to pass to a kernel
+/// writing the 3D array `a`, indexed as `[z, y, x]`
+///
+/// # Panics
+/// Panics if the rows of the array aren't contiguous, a stride is negative or a
+/// dimension doesn't fit in an `i32`.
+pub fn array3_args_mut<S: DataMut>(
+    a: &mut ArrayBase<S, Ix3>,
+) -> (*mut S::Elem, i32, i32, i32, i32, i32) {
+    let (w, h, d, p, sp) = array3_dims(a);
+    (a.as_mut_ptr(), w, h, d, p, sp)
+}
+
+fn array3_dims<S: Data>(a: &ArrayBase<S, Ix3>) -> (i32, i32, i32, i32, i32) {
+    let (shape, strides) = kernel_layout(a);
+    let row_pitch = pitch(strides[1], shape[2]);
+    (
+        kernel_dim(shape[2], "width"),
+        kernel_dim(shape[1], "height"),
+        kernel_dim(shape[0], "depth"),
+        kernel_dim(row_pitch, "pitch"),
+        kernel_dim(pitch(strides[0], row_pitch * shape[1]), "slice pitch"),
+    )
+}
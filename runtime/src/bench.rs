@@ -0,0 +1,94 @@
+//! Helpers to benchmark a kernel on each target ISA it was compiled for with criterion,
+//! using the entry points generated by `ispc_compile::Config::isa_entry_points`.
+//!
+//! A library compiled for multiple target ISAs normally calls the best ISA the CPU
+//! supports through the dispatcher ISPC links in. The entry points call a given ISA
+//! directly, so `bench_isas` can benchmark each of them in one criterion group and
+//! compare e.g. SSE4, AVX2 and AVX-512 for the same kernel. ISAs the CPU doesn't support
+//! are skipped.
+//!
+//! # Example
+//! ```ignore
+//! // benches/add_lists.rs, with `simple` compiled for sse4-i32x4, avx2-i32x8 and
+//! // avx512skx-x16 with `isa_entry_points()`
+//! use criterion::{criterion_group, criterion_main, Criterion};
+//!
+//! ispc_rt::ispc_module!(simple);
+//!
+//! fn add_lists(c: &mut Criterion) {
+//!     let a = vec![1.0f32; 1 << 20];
+//!     let b = vec![2.0f32; 1 << 20];
+//!     let mut out = vec![0.0f32; 1 << 20];
+//!     ispc_rt::bench::bench_isas(c, "add_lists", simple::isa::ALL, |bencher, isa| {
+//!         let f = simple::isa::add_lists(isa).unwrap();
+//!         bencher.iter(|| unsafe { f(a.as_ptr(), b.as_ptr(), out.as_mut_ptr(), a.len() as i32) });
+//!     });
+//! }
+//!
+//! criterion_group!(benches, add_lists);
+//! criterion_main!(benches);
+//! ```
+
+use criterion::{Bencher, BenchmarkId, Criterion};
+
+/// Check if the CPU supports the target ISA `isa`, named as in the ISA entry points,
+/// e.g. `avx2`. Returns `false` for ISAs which aren't known.
+pub fn isa_supported(isa: &str) -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        use std::arch::is_x86_feature_detected as has;
+        let avx512skx = || {
+            has!("avx512f")
+                && has!("avx512cd")
+                && has!("avx512bw")
+                && has!("avx512dq")
+                && has!("avx512vl")
+        };
+        let avx512icl = || {
+            avx512skx()
+                && has!("avx512vbmi")
+                && has!("avx512vbmi2")
+                && has!("avx512vnni")
+                && has!("avx512bitalg")
+                && has!("avx512vpopcntdq")
+        };
+        match isa {
+            "generic" | "sse2" => has!("sse2"),
+            "sse4" => has!("sse4.2"),
+            "avx" => has!("avx"),
+            "avx2" => has!("avx2") && has!("fma") && has!("f16c"),
+            "avx2vnni" => has!("avx2") && has!("fma") && has!("avxvnni"),
+            "avx512skx" => avx512skx(),
+            "avx512icl" => avx512icl(),
+            "avx512spr" => avx512icl() && has!("avx512bf16") && has!("avx512fp16"),
+            _ => false,
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        matches!(isa, "generic" | "neon")
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        isa == "generic"
+    }
+}
+
+/// Benchmark a kernel on each of the target ISAs `isas` the CPU supports, in a criterion
+/// group called `name` with a benchmark for each ISA. `f` is called with the bencher and
+/// the name of the ISA to benchmark, e.g. to look up its entry point. ISAs the CPU
+/// doesn't support are skipped.
+pub fn bench_isas<F>(c: &mut Criterion, name: &str, isas: &[&str], mut f: F)
+where
+    F: FnMut(&mut Bencher, &str),
+{
+    let mut group = c.benchmark_group(name);
+    for &isa in isas {
+        if isa_supported(isa) {
+            group.bench_function(BenchmarkId::from_parameter(isa), |b| f(b, isa));
+        } else {
+            eprintln!("ispc_rt: skipping benchmark {name}/{isa}, the CPU doesn't support {isa}");
+        }
+    }
+    group.finish();
+}
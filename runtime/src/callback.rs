@@ -0,0 +1,76 @@
+//! Defines the storage for the Rust functions ISPC code calls back into through the
+//! callback types declared with `Config::callback` in `ispc_compile`.
+//!
+//! For each callback the generated bindings hold a `CallbackSlot` and an `extern "C"`
+//! trampoline calling the function registered in it, along with a safe function to
+//! register it. Registering returns the function pointer to pass to the ISPC code,
+//! which may call it from any of the task system's threads.
+//!
+//! # Example
+//! The build script declares the callback, which is written to `callbacks.isph`:
+//!
+//! ```ignore
+//! ispc_compile::Config::new()
+//!     .file("src/render.ispc")
+//!     .callback("ProgressFn", "void(uniform int tile, uniform float progress)")
+//!     .compile("render");
+//! ```
+//!
+//! The ISPC code includes the header and takes the callback as a parameter:
+//!
+//! ```c
+//! #include "callbacks.isph"
+//!
+//! export void render(uniform ProgressFn progress, /* ... */) {
+//!     // ...
+//!     progress(tile, 0.5);
+//! }
+//! ```
+//!
+//! And the Rust code registers the function to call:
+//!
+//! ```ignore
+//! let progress = render::register_progress_fn(|tile, p| println!("tile {tile}: {p}"));
+//! unsafe { render::render(progress, /* ... */) };
+//! ```
+
+use std::process;
+use std::sync::{Arc, RwLock};
+
+/// Holds the Rust function called by the trampoline of a callback type
+pub struct CallbackSlot<F: ?Sized> {
+    name: &'static str,
+    callback: RwLock<Option<Arc<F>>>,
+}
+
+impl<F: ?Sized> CallbackSlot<F> {
+    /// Create an empty slot for the callback type `name`
+    pub const fn new(name: &'static str) -> CallbackSlot<F> {
+        CallbackSlot {
+            name,
+            callback: RwLock::new(None),
+        }
+    }
+    /// Register the function to call, replacing the one registered before
+    pub fn register(&self, callback: Box<F>) {
+        *self.callback.write().unwrap() = Some(Arc::from(callback));
+    }
+    /// Remove the registered function
+    pub fn unregister(&self) {
+        *self.callback.write().unwrap() = None;
+    }
+    /// Get the registered function. Aborts the process if there is none, as this is
+    /// called from ISPC code which can't be unwound through.
+    pub fn get(&self) -> Arc<F> {
+        match *self.callback.read().unwrap() {
+            Some(ref f) => f.clone(),
+            None => {
+                eprintln!(
+                    "ispc_rt: ISPC code called the {} callback but no function is registered",
+                    self.name
+                );
+                process::abort();
+            }
+        }
+    }
+}
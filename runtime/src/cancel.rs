@@ -0,0 +1,106 @@
+//! Defines cancellation tokens which can be used to cooperatively cancel the tasks
+//! launched by ISPC code.
+//!
+//! A token is associated with the ISPC code called within `with_cancellation`. Once the
+//! token is cancelled the `Parallel` task system stops handing out the remaining tasks of
+//! the contexts created by that code, and long running tasks can poll `ispc_should_cancel`
+//! to return early. This is useful e.g. to abort rendering a frame in an interactive
+//! application when the camera moves.
+//!
+//! # Example
+//! The ISPC side declares and polls the helper exported by `ispc_rt`:
+//!
+//! ```c
+//! extern "C" uniform bool ispc_should_cancel();
+//!
+//! task void render_tile(/* ... */) {
+//!     for (uniform int y = y0; y < y1; ++y) {
+//!         if (ispc_should_cancel()) {
+//!             return;
+//!         }
+//!         // ...
+//!     }
+//! }
+//! ```
+//!
+//! While the host cancels the token from another thread, e.g. on user input:
+//!
+//! ```ignore
+//! let token = ispc_rt::CancellationToken::new();
+//! let render_token = token.clone();
+//! let render = std::thread::spawn(move || {
+//!     ispc_rt::with_cancellation(&render_token, || unsafe { rt::render(/* ... */) });
+//! });
+//! // ...
+//! token.cancel();
+//! render.join().unwrap();
+//! ```
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// The token associated with the ISPC code running on this thread, if any
+thread_local!(static CURRENT: RefCell<Option<CancellationToken>> = const { RefCell::new(None) });
+
+/// A shared flag used to request cancellation of the tasks launched by some ISPC code.
+/// Clones of a token refer to the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Create a new token which hasn't been cancelled
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+    /// Request cancellation of the tasks associated with this token
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+    /// Check if cancellation has been requested
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Restores the previous token of the thread when dropped, even if the code
+/// it was replaced for panicked.
+struct RestoreToken(Option<CancellationToken>);
+
+impl Drop for RestoreToken {
+    fn drop(&mut self) {
+        set_current(self.0.take());
+    }
+}
+
+/// Run `f` with `token` associated with any tasks launched by the ISPC code it calls,
+/// including tasks launched from within those tasks.
+pub fn with_cancellation<R, F: FnOnce() -> R>(token: &CancellationToken, f: F) -> R {
+    let _restore = RestoreToken(set_current(Some(token.clone())));
+    f()
+}
+
+/// Get the token associated with the code running on this thread
+pub fn current() -> Option<CancellationToken> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+/// Check if cancellation was requested for the code running on this thread
+pub fn is_cancelled() -> bool {
+    CURRENT.with(|c| c.borrow().as_ref().is_some_and(|t| t.is_cancelled()))
+}
+
+/// Set the token associated with the code running on this thread, returns the
+/// previously set token.
+pub(crate) fn set_current(token: Option<CancellationToken>) -> Option<CancellationToken> {
+    CURRENT.with(|c| c.replace(token))
+}
+
+/// Run `f` with `token` as the current token of this thread, used by the task
+/// system when running tasks of a context.
+pub(crate) fn run_with<R, F: FnOnce() -> R>(token: Option<&CancellationToken>, f: F) -> R {
+    let _restore = RestoreToken(set_current(token.cloned()));
+    f()
+}
@@ -0,0 +1,113 @@
+//! Helpers to pass Rust strings to exported kernels taking `const char *` parameters,
+//! e.g. debug labels or file paths, and to read strings returned by kernels.
+//!
+//! A kernel needs a nul terminated copy of a `&str` which lives until the call returns.
+//! `with_c_str` makes one for a single call, while a `CStrArena` keeps the copies of all
+//! strings passed to the calls in its scope alive until it's dropped.
+//!
+//! # Example
+//! ```
+//! use ispc_rt::cstr::{self, CStrArena};
+//!
+//! # unsafe fn load_volume(_path: *const std::ffi::c_char, _label: *const std::ffi::c_char) {}
+//! let arena = CStrArena::new();
+//! unsafe { load_volume(arena.get("data/skull.raw"), arena.get("skull")) };
+//!
+//! let len = cstr::with_c_str("label", |s| unsafe { libc::strlen(s) });
+//! assert_eq!(len, 5);
+//! ```
+
+use alloc::borrow::Cow;
+use alloc::ffi::CString;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::ffi::{c_char, CStr};
+
+/// Copy `s` to a nul terminated string
+///
+/// # Panics
+/// Panics if `s` contains a nul character.
+fn to_c_string(s: &str) -> CString {
+    CString::new(s).unwrap_or_else(|_| {
+        panic!("ispc_rt: string {s:?} contains a nul character and can't be passed to ISPC")
+    })
+}
+
+/// Call `f` with a pointer to a nul terminated copy of `s`, which is valid until `f` returns
+///
+/// # Panics
+/// Panics if `s` contains a nul character.
+pub fn with_c_str<R, F: FnOnce(*const c_char) -> R>(s: &str, f: F) -> R {
+    let c = to_c_string(s);
+    f(c.as_ptr())
+}
+
+/// Holds nul terminated copies of the strings passed to kernels, keeping them alive
+/// until the arena is dropped
+#[derive(Debug, Default)]
+pub struct CStrArena {
+    strings: RefCell<Vec<CString>>,
+}
+
+impl CStrArena {
+    /// Create an empty arena
+    pub fn new() -> CStrArena {
+        CStrArena::default()
+    }
+    /// Get a pointer to a nul terminated copy of `s` to pass to a kernel, which is
+    /// valid until the arena is dropped or cleared
+    ///
+    /// # Panics
+    /// Panics if `s` contains a nul character.
+    pub fn get(&self, s: &str) -> *const c_char {
+        self.c_str(s).as_ptr()
+    }
+    /// Get a nul terminated copy of `s` which lives as long as the arena
+    ///
+    /// # Panics
+    /// Panics if `s` contains a nul character.
+    pub fn c_str(&self, s: &str) -> &CStr {
+        let c = to_c_string(s);
+        let ptr = c.as_ptr();
+        self.strings.borrow_mut().push(c);
+        // The string's buffer doesn't move when the CString is moved into the vector
+        // and isn't freed until the arena is cleared, which requires a mutable borrow
+        unsafe { CStr::from_ptr(ptr) }
+    }
+    /// Get the number of strings held by the arena
+    pub fn len(&self) -> usize {
+        self.strings.borrow().len()
+    }
+    /// Check if the arena holds no strings
+    pub fn is_empty(&self) -> bool {
+        self.strings.borrow().is_empty()
+    }
+    /// Free the strings held by the arena, so it can be reused for the next calls
+    pub fn clear(&mut self) {
+        self.strings.get_mut().clear();
+    }
+}
+
+/// Get the string a kernel returned through `ptr`, replacing invalid UTF-8 with
+/// `U+FFFD`. Returns `None` if `ptr` is null.
+///
+/// # Safety
+/// `ptr` must be null or point to a nul terminated string which is valid for the
+/// lifetime `'a`.
+pub unsafe fn str_from_ptr<'a>(ptr: *const c_char) -> Option<Cow<'a, str>> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(ptr).to_string_lossy())
+    }
+}
+
+/// Copy the string a kernel returned through `ptr` to a `String`, replacing invalid
+/// UTF-8 with `U+FFFD`. Returns `None` if `ptr` is null.
+///
+/// # Safety
+/// `ptr` must be null or point to a valid nul terminated string.
+pub unsafe fn string_from_ptr(ptr: *const c_char) -> Option<String> {
+    str_from_ptr(ptr).map(Cow::into_owned)
+}
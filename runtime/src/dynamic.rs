@@ -0,0 +1,423 @@
+//! Loads ISPC libraries built as shared libraries with `Config::shared_library`, and
+//! reloads them while the program runs, e.g. to iterate on kernels without restarting it.
+//!
+//! The bindings of a shared library declare its kernels as methods of a `Kernels` struct
+//! instead of `extern` functions, which is filled in by looking up the kernels in the
+//! library when it's loaded. The library calls the task system of the process loading it,
+//! so tasks launched by its kernels run on the task system set with `set_task_system` as
+//! usual.
+//!
+//! `DynamicModule::watch` polls the ISPC sources of the library and the headers they
+//! include, rebuilds the library with the commands its build ran when they change and
+//! swaps in the new kernels. Kernels taken from the module before the swap keep using the
+//! library they were looked up in, which is unloaded once the last of them is dropped, so
+//! the kernels are only replaced between invocations.
+//!
+//! # Example
+//! ```ignore
+//! ispc_module!(simple);
+//!
+//! let module = Arc::new(unsafe {
+//!     DynamicModule::<simple::Kernels>::load(env!("ISPC_LIBRARY_simple"))
+//! }?);
+//! // Rebuild and reload the kernels when their sources change
+//! let _watcher = module.watch()?;
+//! loop {
+//!     let kernels = module.kernels();
+//!     unsafe { kernels.add_lists(a.as_ptr(), b.as_ptr(), c.as_mut_ptr(), n) };
+//! }
+//! ```
+
+use std::ffi::{c_int, c_void};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use libloading::Library;
+
+/// Header written at the start of the rebuild file `ispc_compile` writes next to a library
+const REBUILD_HEADER: &str = "ispc_rt rebuild v1";
+
+/// How often `DynamicModule::watch` checks if the sources changed
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Sets the task system entry points called by the ISPC code in a shared library
+type SetTaskSystem = unsafe extern "C" fn(
+    unsafe extern "C" fn(*mut *mut c_void, i64, i32) -> *mut c_void,
+    unsafe extern "C" fn(*mut *mut c_void, *mut c_void, *mut c_void, c_int, c_int, c_int),
+    unsafe extern "C" fn(*mut c_void),
+);
+
+/// The kernels of a library built with `Config::shared_library`, implemented by the
+/// `Kernels` struct in its bindings
+pub trait KernelLibrary: Sized + Send + Sync + 'static {
+    /// Look up the kernels in the `library`
+    ///
+    /// # Safety
+    /// The library must export the kernels with the signatures declared in the bindings.
+    unsafe fn from_library(library: Library) -> Result<Self, libloading::Error>;
+}
+
+/// Kernels looked up in a loaded copy of a library, which is unloaded and removed once
+/// they're dropped
+pub struct LoadedKernels<K> {
+    kernels: ManuallyDrop<K>,
+    copy: PathBuf,
+    generation: usize,
+}
+
+impl<K> LoadedKernels<K> {
+    /// Get the number of times the library was reloaded before these kernels were loaded
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+}
+
+impl<K> Deref for LoadedKernels<K> {
+    type Target = K;
+    fn deref(&self) -> &K {
+        &self.kernels
+    }
+}
+
+impl<K> Drop for LoadedKernels<K> {
+    fn drop(&mut self) {
+        // The library has to be unloaded before its file can be removed on Windows
+        unsafe { ManuallyDrop::drop(&mut self.kernels) };
+        let _ = fs::remove_file(&self.copy);
+    }
+}
+
+/// A shared library of ISPC kernels loaded at runtime, which can be reloaded with new
+/// kernels while the program runs
+pub struct DynamicModule<K> {
+    path: PathBuf,
+    current: RwLock<Arc<LoadedKernels<K>>>,
+    next_generation: AtomicUsize,
+}
+
+impl<K: KernelLibrary> DynamicModule<K> {
+    /// Load the library at `path` built with `Config::shared_library`, the build passes
+    /// its path to the crate in the `ISPC_LIBRARY_{lib}` environment variable.
+    ///
+    /// # Safety
+    /// The library must be built from the ISPC code the bindings of `K` were generated
+    /// for, both now and when it's reloaded.
+    pub unsafe fn load<P: AsRef<Path>>(path: P) -> io::Result<DynamicModule<K>> {
+        let path = path.as_ref().to_path_buf();
+        let kernels = unsafe { load_copy(&path, 0) }?;
+        Ok(DynamicModule {
+            path,
+            current: RwLock::new(Arc::new(kernels)),
+            next_generation: AtomicUsize::new(1),
+        })
+    }
+    /// Get the path of the library
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Get the kernels of the library loaded last. The library stays loaded until the
+    /// kernels are dropped, so keep them for a whole invocation rather than getting them
+    /// for each call.
+    pub fn kernels(&self) -> Arc<LoadedKernels<K>> {
+        self.current.read().unwrap().clone()
+    }
+    /// Load the library again, e.g. after it was rebuilt, and swap in its kernels. The
+    /// kernels taken from the module before remain valid until they're dropped.
+    pub fn reload(&self) -> io::Result<()> {
+        let generation = self.next_generation.fetch_add(1, Ordering::SeqCst);
+        // Loading the library again is safe as it was for `load`
+        let kernels = unsafe { load_copy(&self.path, generation) }?;
+        let mut current = self.current.write().unwrap();
+        // Don't replace the kernels of a reload which finished first but started later
+        if current.generation < generation {
+            *current = Arc::new(kernels);
+        }
+        Ok(())
+    }
+    /// Watch the ISPC sources of the library and the headers they include, and rebuild
+    /// and reload the library when they change, until the returned `Watcher` is dropped.
+    ///
+    /// The library is rebuilt with the commands its build ran, which `ispc_compile` writes
+    /// to a `.rebuild` file next to it, so the ISPC and C compilers must still be at the
+    /// same paths. Only the ISPC sources are recompiled, the objects of the C files added
+    /// with `Config::c_file` are linked as they were built. Failed rebuilds are reported on
+    /// stderr and keep the current kernels.
+    pub fn watch(self: &Arc<Self>) -> io::Result<Watcher> {
+        let rebuild_file = self.path.with_extension("rebuild");
+        let rebuild = Rebuild::read(BufReader::new(File::open(rebuild_file)?))?;
+        let module = Arc::clone(self);
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name(String::from("ispc_rt watcher"))
+                .spawn(move || {
+                    let mut modified = rebuild.modified_times();
+                    while !stop.load(Ordering::SeqCst) {
+                        thread::sleep(POLL_INTERVAL);
+                        let now = rebuild.modified_times();
+                        if now == modified {
+                            continue;
+                        }
+                        modified = now;
+                        if let Err(e) = rebuild.run().and_then(|_| module.reload()) {
+                            eprintln!("ispc_rt: Failed to reload {}: {e}", module.path.display());
+                        }
+                    }
+                })?
+        };
+        Ok(Watcher {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Watches the sources of a `DynamicModule` to reload it, until it's dropped
+pub struct Watcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Load a copy of the library at `path` for the `generation`, as a library can't be
+/// replaced while it's loaded on Windows, and loading the same path again would return
+/// the library which is already loaded
+unsafe fn load_copy<K: KernelLibrary>(
+    path: &Path,
+    generation: usize,
+) -> io::Result<LoadedKernels<K>> {
+    let copy = copy_path(path, generation);
+    fs::copy(path, &copy)?;
+    let kernels = unsafe { Library::new(&copy) }.and_then(|library| unsafe {
+        {
+            let set_task_system = library.get::<SetTaskSystem>(b"ispc_rt_set_task_system\0")?;
+            set_task_system(crate::ISPCAlloc, crate::ISPCLaunch, crate::ISPCSync);
+        }
+        K::from_library(library)
+    });
+    match kernels {
+        Ok(kernels) => Ok(LoadedKernels {
+            kernels: ManuallyDrop::new(kernels),
+            copy,
+            generation,
+        }),
+        Err(e) => {
+            let _ = fs::remove_file(&copy);
+            Err(io::Error::other(e))
+        }
+    }
+}
+
+/// Get the path of the copy of the library at `path` loaded for the `generation`, e.g.
+/// `libsimple-1234-2.so` for `libsimple.so`, which is unique to the process
+fn copy_path(path: &Path, generation: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{stem}-{}-{generation}", process::id());
+    if let Some(ext) = path.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+/// A command run to build a shared library
+#[derive(Debug, Default, PartialEq)]
+struct RebuildCommand {
+    program: String,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    dir: Option<PathBuf>,
+}
+
+/// The commands which build a shared library, and the files it's built from
+#[derive(Debug, Default, PartialEq)]
+struct Rebuild {
+    watch: Vec<PathBuf>,
+    commands: Vec<RebuildCommand>,
+}
+
+impl Rebuild {
+    /// Read the rebuild file written by `ispc_compile`. Each line holds one item, a
+    /// `run` line starts a command which the `dir`, `env` and `arg` lines after it set up.
+    fn read<R: BufRead>(input: R) -> io::Result<Rebuild> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+        let mut lines = input.lines();
+        match lines.next() {
+            Some(Ok(ref l)) if l == REBUILD_HEADER => {}
+            Some(Err(e)) => return Err(e),
+            _ => return Err(invalid("not an ispc_rt rebuild file")),
+        }
+        let mut rebuild = Rebuild::default();
+        for line in lines {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once(' ')
+                .ok_or_else(|| invalid("invalid rebuild line"))?;
+            if key == "watch" {
+                rebuild.watch.push(PathBuf::from(value));
+                continue;
+            } else if key == "run" {
+                rebuild.commands.push(RebuildCommand {
+                    program: value.to_owned(),
+                    ..RebuildCommand::default()
+                });
+                continue;
+            }
+            let command = rebuild
+                .commands
+                .last_mut()
+                .ok_or_else(|| invalid("rebuild command option before any command"))?;
+            match key {
+                "dir" => command.dir = Some(PathBuf::from(value)),
+                "arg" => command.args.push(value.to_owned()),
+                "env" => {
+                    let (k, v) = value
+                        .split_once('=')
+                        .ok_or_else(|| invalid("invalid rebuild environment variable"))?;
+                    command.envs.push((k.to_owned(), v.to_owned()));
+                }
+                _ => return Err(invalid("invalid rebuild line")),
+            }
+        }
+        Ok(rebuild)
+    }
+    /// Get the modification times of the watched files, `None` for files which don't
+    /// exist, e.g. while an editor replaces them
+    fn modified_times(&self) -> Vec<Option<SystemTime>> {
+        self.watch
+            .iter()
+            .map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+    /// Run the commands, stopping at the first which fails
+    fn run(&self) -> io::Result<()> {
+        for c in self.commands.iter() {
+            let mut command = Command::new(&c.program);
+            command.args(&c.args).envs(c.envs.iter().cloned());
+            if let Some(ref dir) = c.dir {
+                command.current_dir(dir);
+            }
+            let output = command.output()?;
+            if !output.status.success() {
+                return Err(io::Error::other(format!(
+                    "{} failed:\n{}",
+                    c.program,
+                    String::from_utf8_lossy(&output.stderr)
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy_path, Rebuild, RebuildCommand, REBUILD_HEADER};
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    #[test]
+    fn reads_rebuild_files() {
+        let text = format!(
+            "{REBUILD_HEADER}\n\
+             watch /src/simple.ispc\n\
+             watch /src/common dir/util.isph\n\
+             \n\
+             run ispc\n\
+             dir /src\n\
+             arg simple.ispc\n\
+             arg -o\n\
+             arg /out/simple ispc.o\n\
+             run cc\n\
+             env PATH=/usr/bin:/bin\n\
+             arg -shared\n"
+        );
+        let rebuild = Rebuild::read(text.as_bytes()).unwrap();
+        assert_eq!(
+            rebuild,
+            Rebuild {
+                watch: vec![
+                    PathBuf::from("/src/simple.ispc"),
+                    PathBuf::from("/src/common dir/util.isph"),
+                ],
+                commands: vec![
+                    RebuildCommand {
+                        program: String::from("ispc"),
+                        args: vec![
+                            String::from("simple.ispc"),
+                            String::from("-o"),
+                            String::from("/out/simple ispc.o"),
+                        ],
+                        envs: Vec::new(),
+                        dir: Some(PathBuf::from("/src")),
+                    },
+                    RebuildCommand {
+                        program: String::from("cc"),
+                        args: vec![String::from("-shared")],
+                        envs: vec![(String::from("PATH"), String::from("/usr/bin:/bin"))],
+                        dir: None,
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn read_rejects_invalid_rebuild_files() {
+        let invalid = |text: &str| {
+            let e = Rebuild::read(text.as_bytes()).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{text:?}");
+        };
+        invalid("ispc_rt schedule v1\n");
+        invalid(&format!("{REBUILD_HEADER}\narg -o\n"));
+        invalid(&format!("{REBUILD_HEADER}\nrun cc\nenv PATH\n"));
+        invalid(&format!("{REBUILD_HEADER}\nrun cc\nlink -shared\n"));
+        invalid(&format!("{REBUILD_HEADER}\nwatch\n"));
+    }
+
+    #[test]
+    fn copies_are_named_per_process_and_generation() {
+        let pid = process::id();
+        assert_eq!(
+            copy_path(Path::new("/out/libsimple.so"), 2),
+            PathBuf::from(format!("/out/libsimple-{pid}-2.so"))
+        );
+        assert_eq!(
+            copy_path(Path::new("/out/simple.dll"), 0),
+            PathBuf::from(format!("/out/simple-{pid}-0.dll"))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_stops_at_the_first_failed_command() {
+        let rebuild = Rebuild::read(
+            format!("{REBUILD_HEADER}\nrun false\nrun definitely-not-an-ispc-rt-command\n")
+                .as_bytes(),
+        )
+        .unwrap();
+        let e = rebuild.run().unwrap_err();
+        assert!(e.to_string().starts_with("false failed"), "{e}");
+    }
+}
@@ -0,0 +1,88 @@
+//! Defines the error returned by the `Result` wrappers generated around ISPC functions
+//! which return a status code, see `Config::result_wrappers` in `ispc_compile`.
+//!
+//! The wrappers pass the code returned by the function to an error mapping, which
+//! decides which codes are failures. The default mapping is `IspcError::check`, which
+//! treats any code other than zero as a failure. Applications with other conventions
+//! provide their own mapping with `Config::error_mapping`, for example:
+//!
+//! ```
+//! use ispc_rt::IspcError;
+//!
+//! // Negative codes are failures, and some have a known meaning
+//! pub fn kernel_status(function: &'static str, code: i64) -> Result<(), IspcError> {
+//!     match code {
+//!         0.. => Ok(()),
+//!         -1 => Err(IspcError::new(function, code).with_message("invalid dimensions")),
+//!         _ => Err(IspcError::new(function, code)),
+//!     }
+//! }
+//!
+//! assert!(kernel_status("render", 3).is_ok());
+//! let err = kernel_status("render", -1).unwrap_err();
+//! assert_eq!(err.to_string(), "ISPC function render failed with code -1: invalid dimensions");
+//! ```
+
+use core::fmt;
+
+/// An error code returned by an ISPC function
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IspcError {
+    function: &'static str,
+    code: i64,
+    message: Option<&'static str>,
+}
+
+impl IspcError {
+    /// Create an error for the `code` returned by `function`
+    pub fn new(function: &'static str, code: i64) -> IspcError {
+        IspcError {
+            function,
+            code,
+            message: None,
+        }
+    }
+    /// Attach a description of the error code
+    pub fn with_message(self, message: &'static str) -> IspcError {
+        IspcError {
+            message: Some(message),
+            ..self
+        }
+    }
+    /// The default error mapping, treating any code other than zero as a failure
+    pub fn check(function: &'static str, code: i64) -> Result<(), IspcError> {
+        if code == 0 {
+            Ok(())
+        } else {
+            Err(IspcError::new(function, code))
+        }
+    }
+    /// Get the name of the function which returned the error
+    pub fn function(&self) -> &'static str {
+        self.function
+    }
+    /// Get the code returned by the function
+    pub fn code(&self) -> i64 {
+        self.code
+    }
+    /// Get the description of the error code, if the error mapping provided one
+    pub fn message(&self) -> Option<&'static str> {
+        self.message
+    }
+}
+
+impl fmt::Display for IspcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ISPC function {} failed with code {}",
+            self.function, self.code
+        )?;
+        if let Some(message) = self.message {
+            write!(f, ": {message}")?;
+        }
+        Ok(())
+    }
+}
+
+impl core::error::Error for IspcError {}
@@ -2,15 +2,25 @@
 //! and provides a default threaded one for use.
 
 use libc;
-use num_cpus;
 
+use std::alloc::{self, Layout};
 use std::cell::RefCell;
-use std::sync::atomic::{self, AtomicUsize};
-use std::sync::{Arc, Mutex, RwLock};
-use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{self, AtomicPtr, AtomicU64, AtomicUsize};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
 
-use crate::task::{Context, ISPCTaskFn};
+use crate::abort;
+use crate::cancel;
+use crate::limit::{self, Permit};
+use crate::observer::{ChunkInfo, TaskObserver};
+use crate::replay::{self, Replayer, Schedule, ScheduleEvent, ScheduleRecorder};
+use crate::task::{Chunk, ChunkOrder, Context, Group, ISPCTaskFn};
+use crate::trace::{ChunkEvent, TraceRecorder};
 
 /// Trait to be implemented to provide ISPC task execution functionality.
 ///
@@ -84,6 +94,67 @@ pub trait TaskSystem {
     /// This function is unsafe as it is called directly from ISPC and must operate on the raw
     /// pointers passed by ISPC.
     unsafe fn sync(&self, handle: *mut libc::c_void);
+    /// Block until all contexts created by any thread have been synchronized and their tasks
+    /// have completed, see `ispc_rt::sync_all`.
+    ///
+    /// The default implementation returns immediately, which is correct for task systems
+    /// that run tasks to completion before returning from `launch` or `sync`.
+    fn wait_idle(&self) {}
+}
+
+/// How threads wait for new tasks once they've run out of work to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdleStrategy {
+    /// Go to sleep as soon as there's no work left (the default). Workers park until new
+    /// tasks are launched and threads waiting in `sync` sleep between checks, minimizing
+    /// CPU usage at the cost of wake up latency.
+    Park,
+    /// Busy-wait for `spins` iterations, then yield to the OS scheduler `yields` times,
+    /// before going to sleep as in `Park`. Trades CPU usage for lower latency when tasks
+    /// are launched in quick succession.
+    Backoff { spins: u32, yields: u32 },
+    /// Never go to sleep, only busy-wait for work. This is needed for threads which aren't
+    /// allowed to block, such as the main thread of a web page, see `ParallelBuilder::sync_idle_strategy`.
+    Spin,
+}
+
+/// Tracks how long a thread has been without work to apply the `IdleStrategy`
+struct Backoff {
+    strategy: IdleStrategy,
+    attempts: u32,
+}
+
+impl Backoff {
+    fn new(strategy: IdleStrategy) -> Backoff {
+        Backoff {
+            strategy,
+            attempts: 0,
+        }
+    }
+    /// Reset the backoff after the thread found some work to do
+    fn reset(&mut self) {
+        self.attempts = 0;
+    }
+    /// Wait a bit before looking for work again, calling `sleep` once the spinning and
+    /// yielding allowed by the strategy is used up
+    fn snooze<F: FnOnce()>(&mut self, sleep: F) {
+        match self.strategy {
+            IdleStrategy::Spin => std::hint::spin_loop(),
+            IdleStrategy::Backoff { spins, .. } if self.attempts < spins => {
+                std::hint::spin_loop();
+            }
+            IdleStrategy::Backoff { spins, yields } if self.attempts - spins < yields => {
+                thread::yield_now();
+            }
+            _ => sleep(),
+        }
+        self.attempts = self.attempts.saturating_add(1);
+    }
+}
+
+/// Get the number of threads the system can run in parallel
+fn num_cpus() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
 }
 
 // Thread local storage to store the thread's id, otherwise we don't know
@@ -91,79 +162,810 @@ pub trait TaskSystem {
 // upon thread launch
 thread_local!(static THREAD_ID: RefCell<usize> = const { RefCell::new(0) });
 
+/// Information about a context that tasks were launched in but `sync` was never called on,
+/// see `Parallel::unsynced_contexts`.
+#[derive(Clone, Debug)]
+pub struct UnsyncedContext {
+    /// The id of the context
+    pub id: usize,
+    /// The number of allocations made by `alloc` in the context
+    pub allocations: usize,
+    /// The total size in bytes of the allocations made in the context
+    pub allocated_bytes: usize,
+    /// The number of task groups launched in the context
+    pub task_groups: usize,
+    /// How long ago the context was created, None on platforms without a clock
+    pub age: Option<Duration>,
+}
+
+impl fmt::Display for UnsyncedContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "context {}: {} task group(s), {} allocation(s) totaling {} bytes",
+            self.id, self.task_groups, self.allocations, self.allocated_bytes
+        )?;
+        match self.age {
+            Some(age) => write!(f, ", created {age:?} ago"),
+            None => Ok(()),
+        }
+    }
+}
+
 /// A multithreaded execution environment for the tasks launched in ISPC
+///
+/// The worker threads only hold a weak reference to the task system and will exit once it's
+/// dropped. Note that a task system passed to `set_task_system` is only dropped if it's
+/// replaced before ISPC first launches tasks, the one ISPC uses is never dropped.
 pub struct Parallel {
+    this: Weak<Parallel>,
     context_list: RwLock<Vec<Arc<Context>>>,
     next_context_id: AtomicUsize,
-    threads: Mutex<Vec<JoinHandle<()>>>,
+    /// The state of each worker, indexed by the worker's thread id - 1
+    threads: Mutex<Vec<WorkerSlot>>,
+    total_threads: usize,
+    min_threads: usize,
+    idle_timeout: Option<Duration>,
+    worker_name: Option<String>,
+    start_handler: Option<Arc<WorkerStartFn>>,
+    spawn_handler: Option<Arc<WorkerSpawnFn>>,
+    chunk_size: usize,
+    chunk_order: ChunkOrder,
+    idle: IdleStrategy,
+    sync_idle: IdleStrategy,
+    trace: Option<Arc<TraceRecorder>>,
+    observers: Vec<Arc<dyn TaskObserver>>,
+    recorder: Option<Arc<ScheduleRecorder>>,
+    replayer: Option<Replayer>,
+    top_level_contexts: AtomicU64,
+    quarantine: Option<Mutex<Quarantine>>,
+    report_unsynced: bool,
+}
+
+/// Builder used to configure a `Parallel` task system before its worker threads are started.
+///
+/// # Example
+/// ```no_run
+/// use ispc_rt::trace::TraceRecorder;
+/// use ispc_rt::Parallel;
+///
+/// let trace = TraceRecorder::new();
+/// let task_sys = Parallel::builder()
+///     .oversubscribe(2.0)
+///     .trace(trace.clone())
+///     .build();
+/// ispc_rt::set_task_system(|| task_sys);
+/// // ... call ISPC code which launches tasks ...
+/// trace.save("trace.json").unwrap();
+/// ```
+pub struct ParallelBuilder {
+    oversubscribe: f32,
+    num_threads: Option<usize>,
     chunk_size: usize,
+    chunk_order: ChunkOrder,
+    idle: IdleStrategy,
+    sync_idle: Option<IdleStrategy>,
+    trace: Option<Arc<TraceRecorder>>,
+    observers: Vec<Arc<dyn TaskObserver>>,
+    record: Option<Arc<ScheduleRecorder>>,
+    replay: Option<Schedule>,
+    validate_allocations: bool,
+    report_unsynced: bool,
+    name: Option<String>,
+    start_handler: Option<Arc<WorkerStartFn>>,
+    spawn_handler: Option<Arc<WorkerSpawnFn>>,
+    dynamic: Option<(usize, Duration)>,
+}
+
+/// Callback run on each worker thread when it starts, see `ParallelBuilder::start_handler`
+pub type WorkerStartFn = dyn Fn(usize, &str) + Send + Sync;
+
+/// Callback used to start the worker threads, see `ParallelBuilder::spawn_handler`
+pub type WorkerSpawnFn = dyn Fn(WorkerThread) -> io::Result<()> + Send + Sync;
+
+/// Byte pattern freed task memory is filled with when validating allocations
+const POISON: u8 = 0xdd;
+/// Amount of freed task memory kept in quarantine when validating allocations
+const QUARANTINE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Freed task memory which is poisoned and kept around for a while to detect writes to it
+/// after its context was synced, see `ParallelBuilder::validate_allocations`
+#[derive(Default)]
+struct Quarantine {
+    blocks: VecDeque<(usize, AtomicPtr<libc::c_void>, Layout)>,
+    bytes: usize,
+}
+
+impl Quarantine {
+    /// Poison the memory allocated in `context` and quarantine it, freeing the oldest
+    /// quarantined memory if the quarantine is full. Returns an error if the freed memory
+    /// was written to while in quarantine.
+    unsafe fn push(
+        &mut self,
+        context: usize,
+        ptr: AtomicPtr<libc::c_void>,
+        layout: Layout,
+    ) -> Result<(), String> {
+        std::ptr::write_bytes(
+            ptr.load(atomic::Ordering::SeqCst) as *mut u8,
+            POISON,
+            layout.size(),
+        );
+        self.blocks.push_back((context, ptr, layout));
+        self.bytes += layout.size();
+        let mut result = Ok(());
+        while self.bytes > QUARANTINE_BYTES {
+            let (context, ptr, layout) = self.blocks.pop_front().unwrap();
+            self.bytes -= layout.size();
+            result = result.and(Quarantine::release(context, ptr, layout));
+        }
+        result
+    }
+    /// Check all quarantined memory is still poisoned and free it, returns an error if
+    /// any of it was written to while in quarantine
+    unsafe fn clear(&mut self) -> Result<(), String> {
+        self.bytes = 0;
+        let mut result = Ok(());
+        for (context, ptr, layout) in self.blocks.drain(..) {
+            result = result.and(Quarantine::release(context, ptr, layout));
+        }
+        result
+    }
+    /// Check the memory is still poisoned and free it, returns an error if it was
+    /// written to after being quarantined
+    unsafe fn release(
+        context: usize,
+        ptr: AtomicPtr<libc::c_void>,
+        layout: Layout,
+    ) -> Result<(), String> {
+        let ptr = ptr.load(atomic::Ordering::SeqCst) as *mut u8;
+        let mem = std::slice::from_raw_parts(ptr, layout.size());
+        let written = mem.iter().position(|&b| b != POISON);
+        alloc::dealloc(ptr, layout);
+        match written {
+            Some(offset) => Err(format!(
+                "ispc_rt: task memory allocated in context {context} was written to at offset \
+                 {offset} after the context was synced"
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Tracks whether a worker thread is running
+enum WorkerSlot {
+    /// The worker isn't running, either it was never started or it exited after being idle
+    Stopped,
+    /// The worker was handed to the spawn handler but hasn't started running yet
+    Starting,
+    /// The worker is running on this thread
+    Running(Thread),
+}
+
+/// A worker of a `Parallel` task system which has yet to be started on a thread,
+/// see `ParallelBuilder::spawn_handler`.
+pub struct WorkerThread {
+    task_sys: Weak<Parallel>,
+    id: usize,
+    name: String,
+    total_threads: usize,
+    chunk_size: usize,
+    idle_timeout: Option<Duration>,
+    start_handler: Option<Arc<WorkerStartFn>>,
+}
+
+impl WorkerThread {
+    /// Get the id of the worker, ids start at 1 since threads outside of the task system are 0
+    pub fn id(&self) -> usize {
+        self.id
+    }
+    /// Get the name of the worker, see `ParallelBuilder::name`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Run the worker on the calling thread, which must be dedicated to it. Returns once
+    /// the task system is dropped, or the worker is stopped after being idle when the
+    /// number of workers is dynamic.
+    pub fn run(self) {
+        // Register the thread so it's woken up when tasks are launched
+        match self.task_sys.upgrade() {
+            Some(t) => {
+                t.threads.lock().unwrap()[self.id - 1] = WorkerSlot::Running(thread::current())
+            }
+            None => return,
+        }
+        if let Some(ref f) = self.start_handler {
+            f(self.id, &self.name);
+        }
+        Parallel::worker_thread(
+            self.task_sys,
+            self.id,
+            self.total_threads,
+            self.chunk_size,
+            self.idle_timeout,
+        );
+    }
+}
+
+impl ParallelBuilder {
+    /// Use `oversubscribe * num_cpus` threads to run tasks, the default is 1.
+    pub fn oversubscribe(&mut self, oversubscribe: f32) -> &mut ParallelBuilder {
+        assert!(oversubscribe >= 1.0);
+        self.oversubscribe = oversubscribe;
+        self
+    }
+    /// Use exactly `num_threads` worker threads, instead of a multiple of the number of CPUs
+    /// set by `oversubscribe`. Note that the thread calling `sync` also runs tasks, so this
+    /// can be 0 to run all tasks on the syncing threads.
+    ///
+    /// This is needed on platforms where the number of CPUs can't be queried and is assumed
+    /// to be 1, e.g. `wasm32`.
+    pub fn num_threads(&mut self, num_threads: usize) -> &mut ParallelBuilder {
+        self.num_threads = Some(num_threads);
+        self
+    }
+    /// Set the number of tasks handed out to a thread at a time, the default is 8.
+    pub fn chunk_size(&mut self, chunk_size: usize) -> &mut ParallelBuilder {
+        assert!(chunk_size > 0);
+        self.chunk_size = chunk_size;
+        self
+    }
+    /// Set the order in which the tasks of 2D and 3D launches are handed out to threads,
+    /// the default is `ChunkOrder::Linear`.
+    pub fn chunk_order(&mut self, order: ChunkOrder) -> &mut ParallelBuilder {
+        self.chunk_order = order;
+        self
+    }
+    /// Set how threads wait for more work once they're out of tasks to run, the
+    /// default is `IdleStrategy::Park`.
+    pub fn idle_strategy(&mut self, idle: IdleStrategy) -> &mut ParallelBuilder {
+        self.idle = idle;
+        self
+    }
+    /// Set how threads waiting in `sync` or `Parallel::wait_idle` wait for the tasks running
+    /// on other threads to finish, the default is the strategy set by `idle_strategy`.
+    ///
+    /// Use `IdleStrategy::Spin` if ISPC code is called from a thread which isn't allowed to
+    /// block, like the main thread of a web page where `Atomics.wait` throws.
+    pub fn sync_idle_strategy(&mut self, idle: IdleStrategy) -> &mut ParallelBuilder {
+        self.sync_idle = Some(idle);
+        self
+    }
+    /// Record the execution of each chunk of tasks to `recorder`, which can then be
+    /// exported as a timeline for Perfetto or `chrome://tracing`.
+    pub fn trace(&mut self, recorder: Arc<TraceRecorder>) -> &mut ParallelBuilder {
+        self.trace = Some(recorder);
+        self
+    }
+    /// Register an observer to be notified as contexts are created and synced and chunks of
+    /// tasks are run. Can be called multiple times to register multiple observers, which
+    /// are notified in the order they were registered.
+    pub fn observer(&mut self, observer: Arc<dyn TaskObserver>) -> &mut ParallelBuilder {
+        self.observers.push(observer);
+        self
+    }
+    /// Record which thread ran each chunk of tasks and in what order to `recorder`, so the
+    /// schedule can be replayed later with `replay_schedule`, see the `replay` module.
+    pub fn record_schedule(&mut self, recorder: Arc<ScheduleRecorder>) -> &mut ParallelBuilder {
+        self.record = Some(recorder);
+        self
+    }
+    /// Hand out the chunks of tasks to the same threads in the same order as in `schedule`,
+    /// which was recorded with `record_schedule`. See the `replay` module for the requirements
+    /// on the replayed run.
+    pub fn replay_schedule(&mut self, schedule: Schedule) -> &mut ParallelBuilder {
+        self.replay = Some(schedule);
+        self
+    }
+    /// Validate the requests made by ISPC code to the task system, to pin down bugs from task
+    /// memory being used beyond the lifetime of its context. This checks that allocations are
+    /// aligned to a power of two, that the handles passed to `alloc`, `launch` and `sync` refer
+    /// to a context which hasn't been synced yet and that the data passed to `launch` was
+    /// allocated in the same context.
+    ///
+    /// Task memory is also filled with a poison pattern once its context is synced and kept in
+    /// quarantine for a while before being freed, at which point the task system panics if the
+    /// memory was written to after the sync. Quarantined memory is also checked when calling
+    /// `Parallel::wait_idle` and when the task system is dropped.
+    ///
+    /// This is meant for debugging and slows down the task system considerably.
+    pub fn validate_allocations(&mut self, validate: bool) -> &mut ParallelBuilder {
+        self.validate_allocations = validate;
+        self
+    }
+    /// Report any contexts which tasks were launched in but were never synchronized when
+    /// the task system is dropped. Such contexts leak the memory allocated for their tasks
+    /// and are typically caused by a `launch` without a terminating `sync`. The contexts can
+    /// also be queried at any time through `Parallel::unsynced_contexts`.
+    pub fn report_unsynced_contexts(&mut self, report: bool) -> &mut ParallelBuilder {
+        self.report_unsynced = report;
+        self
+    }
+    /// Set a name for the task system, used to name its worker threads `ispc-<name>-worker-<id>`
+    /// instead of the default `ispc-worker-<id>`. This helps tell apart the threads of
+    /// multiple task systems.
+    ///
+    /// The names are also set as the OS level thread names, so they show up in debuggers
+    /// and profilers such as perf. Note that some platforms truncate long thread names,
+    /// e.g. Linux limits them to 15 characters.
+    pub fn name(&mut self, name: &str) -> &mut ParallelBuilder {
+        self.name = Some(name.to_owned());
+        self
+    }
+    /// Set a function to be called on each worker thread when it starts, before it runs any
+    /// tasks. The function is passed the worker's thread id and name, and can be used to
+    /// register the thread with a profiler like Tracy which tracks threads on its own.
+    pub fn start_handler<F>(&mut self, handler: F) -> &mut ParallelBuilder
+    where
+        F: Fn(usize, &str) + Send + Sync + 'static,
+    {
+        self.start_handler = Some(Arc::new(handler));
+        self
+    }
+    /// Set a function used to start each worker thread instead of `std::thread`. The
+    /// function must arrange for `WorkerThread::run` to be called on a new thread, and can
+    /// be used on platforms where `std` can't spawn threads, e.g. to run the workers in
+    /// Web Workers on `wasm32-unknown-unknown`, see the `wasm` module.
+    ///
+    /// # Example
+    /// ```no_run
+    /// let task_sys = ispc_rt::Parallel::builder()
+    ///     .spawn_handler(|worker| {
+    ///         std::thread::Builder::new()
+    ///             .name(worker.name().to_owned())
+    ///             .stack_size(8 * 1024 * 1024)
+    ///             .spawn(move || worker.run())?;
+    ///         Ok(())
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn spawn_handler<F>(&mut self, handler: F) -> &mut ParallelBuilder
+    where
+        F: Fn(WorkerThread) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.spawn_handler = Some(Arc::new(handler));
+        self
+    }
+    /// Only keep `min_threads` worker threads running while idle, instead of all the threads
+    /// set by `oversubscribe` or `num_threads` which then become the maximum. More workers are
+    /// started as tasks are launched, and workers beyond the minimum stop once they haven't
+    /// found any tasks to run for `idle_timeout`.
+    ///
+    /// This avoids keeping a full pool of threads around in long running processes which only
+    /// run ISPC code occasionally, at the cost of starting threads again when it does.
+    pub fn dynamic_threads(
+        &mut self,
+        min_threads: usize,
+        idle_timeout: Duration,
+    ) -> &mut ParallelBuilder {
+        self.dynamic = Some((min_threads, idle_timeout));
+        self
+    }
+    /// Create the task system and start its worker threads.
+    ///
+    /// # Panics
+    /// Panics if a worker thread fails to start, or if a schedule is both recorded and replayed.
+    pub fn build(&self) -> Arc<Parallel> {
+        assert!(
+            self.record.is_none() || self.replay.is_none(),
+            "A schedule can't be recorded while replaying one"
+        );
+        let num_threads = self
+            .num_threads
+            .unwrap_or_else(|| (self.oversubscribe * num_cpus() as f32) as usize);
+        let (min_threads, idle_timeout) = match self.dynamic {
+            Some((min, timeout)) => (cmp::min(min, num_threads), Some(timeout)),
+            None => (num_threads, None),
+        };
+        let par = Arc::new_cyclic(|this| Parallel {
+            this: this.clone(),
+            context_list: RwLock::new(Vec::new()),
+            next_context_id: AtomicUsize::new(0),
+            threads: Mutex::new((0..num_threads).map(|_| WorkerSlot::Stopped).collect()),
+            // Include the thread ids of threads outside the task system, which are all 0
+            total_threads: num_threads + 1,
+            min_threads,
+            idle_timeout,
+            worker_name: self.name.clone(),
+            start_handler: self.start_handler.clone(),
+            spawn_handler: self.spawn_handler.clone(),
+            chunk_size: self.chunk_size,
+            chunk_order: self.chunk_order,
+            idle: self.idle,
+            sync_idle: self.sync_idle.unwrap_or(self.idle),
+            trace: self.trace.clone(),
+            observers: self.observers.clone(),
+            recorder: self.record.clone(),
+            replayer: self.replay.clone().map(Replayer::new),
+            top_level_contexts: AtomicU64::new(0),
+            quarantine: self
+                .validate_allocations
+                .then(|| Mutex::new(Quarantine::default())),
+            report_unsynced: self.report_unsynced,
+        });
+        par.start_workers(min_threads)
+            .expect("Failed to spawn ISPC task system worker thread");
+        par
+    }
+}
+
+impl Default for ParallelBuilder {
+    fn default() -> ParallelBuilder {
+        ParallelBuilder {
+            oversubscribe: 1.0,
+            num_threads: None,
+            chunk_size: 8,
+            chunk_order: ChunkOrder::Linear,
+            idle: IdleStrategy::Park,
+            sync_idle: None,
+            trace: None,
+            observers: Vec::new(),
+            record: None,
+            replay: None,
+            validate_allocations: false,
+            report_unsynced: false,
+            name: None,
+            start_handler: None,
+            spawn_handler: None,
+            dynamic: None,
+        }
+    }
 }
 
 impl Parallel {
     /// Create a parallel task execution environment that will use `num_cpus` threads
     /// to run tasks.
     pub fn new() -> Arc<Parallel> {
-        Parallel::oversubscribed(1.0)
+        Parallel::builder().build()
     }
     /// Create an oversubscribued parallel task execution environment that will use
     /// `oversubscribe * num_cpus` threads to run tasks.
     pub fn oversubscribed(oversubscribe: f32) -> Arc<Parallel> {
-        assert!(oversubscribe >= 1.0);
-        let par = Arc::new(Parallel {
-            context_list: RwLock::new(Vec::new()),
-            next_context_id: AtomicUsize::new(0),
-            threads: Mutex::new(Vec::new()),
-            chunk_size: 8,
-        });
-        {
-            let mut threads = par.threads.lock().unwrap();
-            let num_threads = (oversubscribe * num_cpus::get() as f32) as usize;
-            let chunk_size = par.chunk_size;
-            for i in 0..num_threads {
-                let task_sys = Arc::clone(&par);
-                // Note that the spawned thread ids start at 1 since the main thread is 0
-                threads.push(thread::spawn(move || {
-                    Parallel::worker_thread(task_sys, i + 1, num_threads + 1, chunk_size)
-                }));
+        Parallel::builder().oversubscribe(oversubscribe).build()
+    }
+    /// Get a builder to configure the task system further before creating it.
+    pub fn builder() -> ParallelBuilder {
+        ParallelBuilder::default()
+    }
+    /// Get the number of worker threads currently running or starting
+    pub fn running_threads(&self) -> usize {
+        let threads = self.threads.lock().unwrap();
+        threads
+            .iter()
+            .filter(|t| !matches!(t, WorkerSlot::Stopped))
+            .count()
+    }
+    /// Start stopped workers until at least `count` of them are running, limited to the
+    /// maximum number of workers
+    fn start_workers(&self, count: usize) -> io::Result<()> {
+        let mut threads = self.threads.lock().unwrap();
+        let mut running = threads
+            .iter()
+            .filter(|t| !matches!(t, WorkerSlot::Stopped))
+            .count();
+        for (i, slot) in threads.iter_mut().enumerate() {
+            if running >= count {
+                break;
+            }
+            if !matches!(slot, WorkerSlot::Stopped) {
+                continue;
             }
+            // Note that the spawned thread ids start at 1 since the main thread is 0
+            let id = i + 1;
+            let worker = WorkerThread {
+                task_sys: self.this.clone(),
+                id,
+                name: match self.worker_name {
+                    Some(ref n) => format!("ispc-{n}-worker-{id}"),
+                    None => format!("ispc-worker-{id}"),
+                },
+                total_threads: self.total_threads,
+                chunk_size: self.chunk_size,
+                idle_timeout: self.idle_timeout,
+                start_handler: self.start_handler.clone(),
+            };
+            // The worker registers its thread once it runs, which waits for the lock we hold
+            *slot = WorkerSlot::Starting;
+            let spawned = match self.spawn_handler {
+                Some(ref spawn) => spawn(worker),
+                None => thread::Builder::new()
+                    .name(worker.name.clone())
+                    .spawn(move || worker.run())
+                    .map(|_| ()),
+            };
+            if let Err(e) = spawned {
+                *slot = WorkerSlot::Stopped;
+                return Err(e);
+            }
+            running += 1;
+        }
+        Ok(())
+    }
+    /// Stop the idle worker `thread` if more than the minimum number of workers are running,
+    /// returns true if the worker should exit.
+    fn stop_idle_worker(&self, thread: usize) -> bool {
+        let mut threads = self.threads.lock().unwrap();
+        let running = threads
+            .iter()
+            .filter(|t| !matches!(t, WorkerSlot::Stopped))
+            .count();
+        if running > self.min_threads {
+            threads[thread - 1] = WorkerSlot::Stopped;
+            true
+        } else {
+            false
         }
-        par
+    }
+    /// Check that `handle` refers to a context which hasn't been synced yet when validating
+    /// allocations, `call` is the ISPC runtime function it was passed to.
+    fn validate_handle(&self, handle: *mut libc::c_void, call: &str) {
+        if self.quarantine.is_none() {
+            return;
+        }
+        let valid = self
+            .context_list
+            .read()
+            .unwrap()
+            .iter()
+            .any(|c| Arc::as_ptr(c) as *mut libc::c_void == handle);
+        assert!(
+            valid,
+            "ispc_rt: {call} was passed the handle {handle:p} which doesn't refer to a context \
+             that's still running, it was either synced already or never created"
+        );
     }
     /// Return a context that has remaining tasks left to be executed by a thread, returns None
-    /// if no contexts have remaining tasks.
+    /// if no contexts have remaining tasks. Contexts whose thread limit is already reached are
+    /// skipped, the returned permit must be held while running the context's tasks.
     ///
     /// Note that due to threading issues you shouldn't assume the context returned actually has
     /// outstanding tasks by the time it's returned to the caller and a chunk is requested.
-    fn get_context(&self) -> Option<Arc<Context>> {
+    fn get_context(&self) -> Option<(Arc<Context>, Permit)> {
+        self.context_list
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|c| !c.current_tasks_done())
+            .find_map(|c| limit::try_acquire(c.thread_limit()).map(|p| (c.clone(), p)))
+    }
+    /// Get the contexts which tasks have been launched in but which haven't been
+    /// synchronized yet. Contexts that a thread is currently waiting on in `sync` are
+    /// not included.
+    pub fn unsynced_contexts(&self) -> Vec<UnsyncedContext> {
         self.context_list
             .read()
             .unwrap()
             .iter()
-            .find(|c| !c.current_tasks_done())
-            .cloned()
+            .filter(|c| !c.is_syncing())
+            .map(|c| {
+                let (allocations, allocated_bytes) = c.allocations();
+                UnsyncedContext {
+                    id: c.id,
+                    allocations,
+                    allocated_bytes,
+                    task_groups: c.group_count(),
+                    age: c.created().map(|t| t.elapsed()),
+                }
+            })
+            .collect()
+    }
+    /// Block until all contexts launched by any thread have been synchronized and their
+    /// tasks have completed, helping to run the remaining tasks in the meantime. Once this
+    /// returns no tasks are running on the task system, e.g. so buffers referenced by the
+    /// tasks can be torn down, until ISPC code launches more tasks.
+    ///
+    /// Note that this will never return if some context is never synced, which can be
+    /// checked for with `Parallel::unsynced_contexts`. It must also not be called from
+    /// within a task, since that task's context can't be synced until it returns.
+    pub fn wait_idle(&self) {
+        let thread = THREAD_ID.with(|f| *f.borrow());
+        let mut backoff = Backoff::new(self.sync_idle);
+        while !self.context_list.read().unwrap().is_empty() {
+            if self.run_available(thread, self.total_threads, self.chunk_size) {
+                backoff.reset();
+            } else {
+                backoff.snooze(|| thread::sleep(Duration::from_millis(1)));
+            }
+        }
+        if let Some(ref quarantine) = self.quarantine {
+            let result = unsafe { quarantine.lock().unwrap().clear() };
+            result.unwrap_or_else(|e| panic!("{e}"));
+        }
     }
     fn worker_thread(
-        task_sys: Arc<Parallel>,
+        task_sys: Weak<Parallel>,
         thread: usize,
         total_threads: usize,
         chunk_size: usize,
+        idle_timeout: Option<Duration>,
     ) {
         THREAD_ID.with(|f| *f.borrow_mut() = thread);
+        let mut backoff = match task_sys.upgrade() {
+            Some(t) => Backoff::new(t.idle),
+            None => return,
+        };
+        let mut last_ran = idle_timeout.map(|_| Instant::now());
         loop {
-            // Get a task group to run
-            while let Some(c) = task_sys.get_context() {
-                for tg in c.iter() {
-                    for chunk in tg.chunks(chunk_size) {
-                        chunk.execute(thread as i32, total_threads as i32);
-                    }
+            // Only hold on to the task system while running tasks, so that it can be
+            // dropped while we're waiting for work
+            let ran_some = match task_sys.upgrade() {
+                Some(task_sys) => task_sys.run_available(thread, total_threads, chunk_size),
+                None => return,
+            };
+            if ran_some {
+                backoff.reset();
+                if let Some(ref mut t) = last_ran {
+                    *t = Instant::now();
                 }
+                continue;
             }
             // We ran out of contexts to get, so wait a bit for a new group to get launched
             // TODO: This could result in some threads remaining parked even if new contexts
             // have been launched if they're unparked then immediately park. Would be better to
             // set up a condition var or something that the workers can wait on to be signaled
             // when new work arrives.
-            thread::park();
+            match (idle_timeout, last_ran) {
+                (Some(timeout), Some(t)) => {
+                    if t.elapsed() >= timeout {
+                        match task_sys.upgrade() {
+                            Some(task_sys) if task_sys.stop_idle_worker(thread) => return,
+                            Some(_) => {}
+                            None => return,
+                        }
+                    }
+                    backoff.snooze(|| thread::park_timeout(timeout));
+                }
+                _ => backoff.snooze(thread::park),
+            }
+        }
+    }
+    /// Run chunks from the contexts with tasks remaining until there are none left,
+    /// returns true if any chunks were run.
+    fn run_available(&self, thread: usize, total_threads: usize, chunk_size: usize) -> bool {
+        if let Some(ref replayer) = self.replayer {
+            if replayer.is_active() {
+                return self.run_replayed(replayer, thread, total_threads);
+            }
+        }
+        let mut ran_some = false;
+        // Get a task group to run
+        while let Some((c, _permit)) = self.get_context() {
+            for tg in c.iter() {
+                while let Some(chunk) = self.take_chunk(&c, &tg, thread, chunk_size) {
+                    ran_some = true;
+                    self.execute_chunk(&c, &chunk, thread, total_threads);
+                }
+            }
+        }
+        ran_some
+    }
+    /// Take the next chunk of tasks from `group` to run on `thread`, recording it if the
+    /// schedule is being recorded
+    fn take_chunk<'a>(
+        &self,
+        context: &Context,
+        group: &'a Group,
+        thread: usize,
+        chunk_size: usize,
+    ) -> Option<Chunk<'a>> {
+        match self.recorder {
+            Some(ref recorder) => recorder.record(
+                || group.chunks(chunk_size).next(),
+                |chunk| ScheduleEvent {
+                    context: context.replay_key(),
+                    group: group.index(),
+                    tasks: chunk.tasks(),
+                    thread,
+                },
+            ),
+            None => group.chunks(chunk_size).next(),
+        }
+    }
+    /// Run the chunks the replayed schedule assigns to `thread` next, returns true if any
+    /// chunks were run. Returns once the next chunk is assigned to another thread or its
+    /// tasks haven't been launched yet.
+    fn run_replayed(&self, replayer: &Replayer, thread: usize, total_threads: usize) -> bool {
+        let mut ran_some = false;
+        while let Some((i, event)) = replayer.peek() {
+            if event.thread != thread || !replayer.is_active() {
+                break;
+            }
+            let context = self
+                .context_list
+                .read()
+                .unwrap()
+                .iter()
+                .find(|c| c.replay_key() == event.context)
+                .cloned();
+            let (context, group) = match context.and_then(|c| c.group(event.group).map(|g| (c, g)))
+            {
+                Some(cg) => cg,
+                None => break,
+            };
+            if !replayer.claim(i) {
+                continue;
+            }
+            let chunk = group.chunks(self.chunk_size).next();
+            let tasks = chunk.as_ref().map(|c| c.tasks());
+            if tasks.as_ref() != Some(&event.tasks) {
+                replayer.diverge(i, tasks);
+            }
+            // Wake up the workers so the thread the next chunk is assigned to can run it
+            self.unpark_workers();
+            if let Some(chunk) = chunk {
+                ran_some = true;
+                self.execute_chunk(&context, &chunk, thread, total_threads);
+            }
+        }
+        ran_some
+    }
+    /// Wake up any parked worker threads to look for tasks to run
+    fn unpark_workers(&self) {
+        let threads = self.threads.lock().unwrap();
+        for t in threads.iter() {
+            if let WorkerSlot::Running(ref t) = *t {
+                t.unpark();
+            }
+        }
+    }
+    /// Execute the chunk of tasks from `context` on the calling thread, recording it
+    /// in the trace if one is being captured.
+    fn execute_chunk(&self, context: &Context, chunk: &Chunk, thread: usize, total_threads: usize) {
+        let info = if self.observers.is_empty() {
+            None
+        } else {
+            Some(ChunkInfo {
+                context: context.id,
+                tasks: chunk.tasks(),
+                thread,
+            })
+        };
+        if let Some(ref info) = info {
+            self.observers.iter().for_each(|o| o.on_chunk_start(info));
+        }
+        // Make the context's cancellation token, thread limit and kernel visible to the tasks
+        // and any contexts they create
+        cancel::run_with(context.cancellation(), || {
+            limit::run_with(context.thread_limit(), || {
+                abort::run_with(context.kernel(), || {
+                    if self.recorder.is_some() || self.replayer.is_some() {
+                        // Key the contexts created by the tasks by this chunk
+                        replay::run_in_chunk(
+                            context.replay_key(),
+                            chunk.group_index(),
+                            chunk.tasks().start,
+                            || self.execute_chunk_traced(context, chunk, thread, total_threads),
+                        )
+                    } else {
+                        self.execute_chunk_traced(context, chunk, thread, total_threads)
+                    }
+                })
+            })
+        });
+        if let Some(ref info) = info {
+            self.observers.iter().for_each(|o| o.on_chunk_end(info));
+        }
+    }
+    fn execute_chunk_traced(
+        &self,
+        context: &Context,
+        chunk: &Chunk,
+        thread: usize,
+        total_threads: usize,
+    ) {
+        match self.trace {
+            Some(ref trace) => {
+                let start = trace.now();
+                chunk.execute(thread as i32, total_threads as i32);
+                trace.record(ChunkEvent {
+                    context: context.id,
+                    tasks: chunk.tasks(),
+                    thread,
+                    start,
+                    end: trace.now(),
+                });
+            }
+            None => chunk.execute(thread as i32, total_threads as i32),
         }
     }
 }
@@ -175,6 +977,13 @@ impl TaskSystem for Parallel {
         size: i64,
         align: i32,
     ) -> *mut libc::c_void {
+        if self.quarantine.is_some() {
+            assert!(
+                align > 0 && (align as u32).is_power_of_two(),
+                "ispc_rt: ISPCAlloc was asked for {size} bytes with alignment {align}, \
+                 which is not a power of two"
+            );
+        }
         // If the handle is null this is the first time this function has spawned tasks
         // and we should create a new Context structure in the TASK_LIST for it, otherwise
         // it's the pointer to where we should append the new Group
@@ -184,9 +993,17 @@ impl TaskSystem for Parallel {
             // unbox it into a raw ptr to get a ptr we can pass back to ISPC through
             // the handle_ptr and then re-box it into our TASK_LIST so it will
             // be free'd properly when we erase it from the vector in ISPCSync
-            let c = Arc::new(Context::new(
-                self.next_context_id.fetch_add(1, atomic::Ordering::SeqCst),
-            ));
+            let mut c = Context::new(self.next_context_id.fetch_add(1, atomic::Ordering::SeqCst));
+            c.set_cancellation(cancel::current());
+            c.set_thread_limit(limit::current());
+            c.set_kernel(abort::current_kernel());
+            if self.recorder.is_some() || self.replayer.is_some() {
+                c.set_replay_key(replay::next_context_key(&self.top_level_contexts));
+            }
+            for o in self.observers.iter() {
+                o.on_context_created(c.id);
+            }
+            let c = Arc::new(c);
             {
                 let h = &*c;
                 *handle_ptr = h as *const Context as *mut libc::c_void;
@@ -195,6 +1012,7 @@ impl TaskSystem for Parallel {
             let ctx = context_list.last().unwrap();
             ctx.alloc(size as usize, align as usize)
         } else {
+            self.validate_handle(*handle_ptr, "ISPCAlloc");
             let context_list = self.context_list.read().unwrap();
             let handle_ctx = *handle_ptr as *mut Context;
             let ctx = context_list
@@ -213,20 +1031,50 @@ impl TaskSystem for Parallel {
         count1: i32,
         count2: i32,
     ) {
+        self.validate_handle(*handle_ptr, "ISPCLaunch");
         // Push the tasks being launched on to the list of task groups for this function
         let context: &mut Context = &mut *(*handle_ptr as *mut Context);
-        context.launch((count0, count1, count2), data, f);
-        // Unpark any sleeping threads since we have jobs for them
-        let threads = self.threads.lock().unwrap();
-        for t in threads.iter() {
-            t.thread().unpark();
+        if self.quarantine.is_some() {
+            assert!(
+                context.owns(data),
+                "ispc_rt: ISPCLaunch was passed task data {data:p} which wasn't allocated \
+                 in context {}",
+                context.id
+            );
+        }
+        // Notify the observers first, the tasks may start running as soon as they're launched
+        for o in self.observers.iter() {
+            o.on_launch(context.id, (count0 * count1 * count2) as usize);
+        }
+        context.launch_ordered((count0, count1, count2), data, f, self.chunk_order);
+        if self.idle_timeout.is_some() {
+            // Start enough workers to run the chunks we just launched in parallel. If starting
+            // a worker fails the tasks will still be run by the other workers or in sync.
+            let chunks = (count0 * count1 * count2) as usize;
+            let _ = self.start_workers(chunks.div_ceil(self.chunk_size));
         }
+        // Unpark any sleeping threads since we have jobs for them
+        self.unpark_workers();
     }
     unsafe fn sync(&self, handle: *mut libc::c_void) {
+        self.validate_handle(handle, "ISPCSync");
         //let context: &mut Context = mem::transmute(handle);
         let context: &mut Context = &mut *(handle as *mut Context);
+        context.mark_syncing();
         let thread = THREAD_ID.with(|f| *f.borrow());
-        let total_threads = num_cpus::get();
+        let total_threads = self.total_threads;
+        if let Some(ref replayer) = self.replayer {
+            // Run the chunks assigned to us until our tasks are done, or the replay ends and
+            // we go back to running any tasks below
+            let mut backoff = Backoff::new(self.sync_idle);
+            while replayer.is_active() && !context.current_tasks_done() {
+                if self.run_replayed(replayer, thread, total_threads) {
+                    backoff.reset();
+                } else {
+                    backoff.snooze(|| thread::sleep(Duration::from_millis(1)));
+                }
+            }
+        }
         // Make sure all tasks are done, and execute them if not for this simple
         // serial version. TODO: In the future we'd wait on each Group's semaphore or atomic bool
         // Maybe the waiting thread could help execute tasks as well, otherwise it might be
@@ -236,9 +1084,9 @@ impl TaskSystem for Parallel {
         // so if our tasks aren't done and there's none left to run in our context we should start
         // running tasks from other contexts to help out
         for tg in context.iter() {
-            for chunk in tg.chunks(self.chunk_size) {
+            while let Some(chunk) = self.take_chunk(context, &tg, thread, self.chunk_size) {
                 // TODO: We need to figure out which thread we are
-                chunk.execute(thread as i32, total_threads as i32);
+                self.execute_chunk(context, &chunk, thread, total_threads);
             }
         }
         // If all the tasks for this context have been finished we're done sync'ing and can
@@ -249,20 +1097,45 @@ impl TaskSystem for Parallel {
         // and running them to at least ensure global forward progress, which will eventually get
         // the stuff we're waiting on to finish. After each chunk execution we should check if
         // our sync'ing context is done and break
+        let mut backoff = Backoff::new(self.sync_idle);
         while !context.current_tasks_done() {
             // Get a task group to run
-            while let Some(c) = self.get_context() {
+            while let Some((c, _permit)) = self.get_context() {
                 let mut ran_some = false;
                 for tg in c.iter() {
-                    for chunk in tg.chunks(self.chunk_size) {
+                    while let Some(chunk) = self.take_chunk(&c, &tg, thread, self.chunk_size) {
                         ran_some = true;
-                        chunk.execute(thread as i32, total_threads as i32);
+                        self.execute_chunk(&c, &chunk, thread, total_threads);
                     }
                 }
-                if !ran_some {
-                    thread::sleep(Duration::from_millis(50));
+                // Stop helping out once our own tasks are done, instead of waiting for the
+                // tasks in flight in other contexts
+                if context.current_tasks_done() {
+                    break;
+                }
+                if ran_some {
+                    backoff.reset();
+                } else {
+                    backoff.snooze(|| thread::sleep(Duration::from_millis(50)));
                 }
             }
+            // There's nothing we can run, e.g. as the thread limit of the other contexts is
+            // reached, so wait for the threads running our tasks instead of spinning
+            if !context.current_tasks_done() {
+                backoff.snooze(|| thread::sleep(Duration::from_millis(1)));
+            }
+        }
+        for o in self.observers.iter() {
+            o.on_sync(context.id);
+        }
+        if let Some(ref quarantine) = self.quarantine {
+            let mut quarantine = quarantine.lock().unwrap();
+            let mut result = Ok(());
+            for (ptr, layout) in context.take_allocations() {
+                result = result.and(quarantine.push(context.id, ptr, layout));
+            }
+            drop(quarantine);
+            result.unwrap_or_else(|e| panic!("{e}"));
         }
         // Now erase this context from our vector
         let mut context_list = self.context_list.write().unwrap();
@@ -272,4 +1145,132 @@ impl TaskSystem for Parallel {
             .unwrap();
         context_list.remove(pos);
     }
+    fn wait_idle(&self) {
+        Parallel::wait_idle(self);
+    }
+}
+
+impl Drop for Parallel {
+    fn drop(&mut self) {
+        if self.report_unsynced {
+            let unsynced = self.unsynced_contexts();
+            if !unsynced.is_empty() {
+                eprintln!(
+                    "ispc_rt: {} context(s) had tasks launched but were never synced:",
+                    unsynced.len()
+                );
+                for c in unsynced {
+                    eprintln!("\t{c}");
+                }
+            }
+        }
+        if let Some(ref mut quarantine) = self.quarantine {
+            let result = unsafe { quarantine.get_mut().unwrap().clear() };
+            if !thread::panicking() {
+                result.unwrap_or_else(|e| panic!("{e}"));
+            }
+        }
+        // Wake up the workers so they see the task system is gone and exit
+        for t in self.threads.get_mut().unwrap().iter() {
+            if let WorkerSlot::Running(ref t) = *t {
+                t.unpark();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+    use std::ptr;
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    extern "C" fn count_task(
+        data: *mut libc::c_void,
+        _thread_idx: libc::c_int,
+        _thread_cnt: libc::c_int,
+        _task_idx: libc::c_int,
+        _task_cnt: libc::c_int,
+        _task_idx0: libc::c_int,
+        _task_idx1: libc::c_int,
+        _task_idx2: libc::c_int,
+        _task_cnt0: libc::c_int,
+        _task_cnt1: libc::c_int,
+        _task_cnt2: libc::c_int,
+    ) {
+        let counter = unsafe { &**(data as *const &AtomicUsize) };
+        counter.fetch_add(1, atomic::Ordering::SeqCst);
+    }
+
+    /// Launch `tasks` tasks on `task_sys` and sync them like ISPC code would, returns the
+    /// number of tasks which ran
+    fn run_tasks(task_sys: &Parallel, tasks: i32) -> usize {
+        let counter = AtomicUsize::new(0);
+        unsafe {
+            let mut handle = ptr::null_mut();
+            let size = mem::size_of::<&AtomicUsize>();
+            let data = task_sys.alloc(&mut handle, size as i64, size as i32);
+            *(data as *mut &AtomicUsize) = &counter;
+            task_sys.launch(&mut handle, count_task, data, tasks, 1, 1);
+            task_sys.sync(handle);
+        }
+        counter.load(atomic::Ordering::SeqCst)
+    }
+
+    /// Record the schedule of running `tasks` tasks on a task system with two workers
+    fn record(tasks: i32) -> Schedule {
+        let recorder = ScheduleRecorder::new();
+        let task_sys = Parallel::builder()
+            .num_threads(2)
+            .chunk_size(2)
+            .record_schedule(recorder.clone())
+            .build();
+        assert_eq!(run_tasks(&task_sys, tasks), tasks as usize);
+        recorder.schedule()
+    }
+
+    fn replay(schedule: Schedule, tasks: i32) -> Arc<Parallel> {
+        let task_sys = Parallel::builder()
+            .num_threads(2)
+            .chunk_size(2)
+            .replay_schedule(schedule)
+            .build();
+        assert_eq!(run_tasks(&task_sys, tasks), tasks as usize);
+        task_sys
+    }
+
+    #[test]
+    fn replaying_the_recorded_schedule_follows_it() {
+        let schedule = record(16);
+        assert_eq!(schedule.events().len(), 8);
+        let task_sys = replay(schedule, 16);
+        let replayer = task_sys.replayer.as_ref().unwrap();
+        assert!(!replayer.diverged() && !replayer.is_active());
+    }
+
+    #[test]
+    fn replaying_a_mismatched_schedule_diverges() {
+        // Change the tasks of the first chunk in the saved schedule
+        let mut text = Vec::new();
+        record(16).write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let mut lines: Vec<String> = text.lines().map(str::to_owned).collect();
+        let mut fields: Vec<u64> = lines[1]
+            .split_whitespace()
+            .map(|f| f.parse().unwrap())
+            .collect();
+        fields[4] += 1;
+        lines[1] = fields
+            .iter()
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let schedule = Schedule::read(lines.join("\n").as_bytes()).unwrap();
+
+        // All tasks still run once the replay gives up
+        let task_sys = replay(schedule, 16);
+        assert!(task_sys.replayer.as_ref().unwrap().diverged());
+    }
 }
@@ -0,0 +1,182 @@
+//! Defines the futures used to call ISPC functions from async code without blocking
+//! the executor, see `Config::async_wrappers` in `ispc_compile`.
+//!
+//! The ISPC function is run on a separate thread, by default a new thread is spawned
+//! for each call. Applications using an async runtime with a pool for blocking tasks
+//! should hand the calls to it with `set_blocking_spawner`.
+//!
+//! # Example
+//! ```ignore
+//! // Run the ISPC calls on tokio's blocking pool
+//! ispc_rt::future::set_blocking_spawner(|job| {
+//!     tokio::task::spawn_blocking(job);
+//! });
+//!
+//! // The wrappers generated by async_wrappers for each exported function
+//! unsafe { simple::add_lists_async(a.as_ptr(), b.as_ptr(), c.as_mut_ptr(), n) }.await;
+//! ```
+
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+/// A function which runs a blocking job on some thread
+pub type BlockingSpawnFn = dyn Fn(Box<dyn FnOnce() + Send>) + Send + Sync;
+
+static SPAWNER: Mutex<Option<Arc<BlockingSpawnFn>>> = Mutex::new(None);
+
+/// Set the function used to run the ISPC functions called through a `KernelFuture`,
+/// e.g. to run them on the blocking task pool of an async runtime. The function must
+/// eventually run each job it's given, a future whose job is dropped without running
+/// panics when it's awaited.
+pub fn set_blocking_spawner<F>(spawn: F)
+where
+    F: Fn(Box<dyn FnOnce() + Send>) + Send + Sync + 'static,
+{
+    *SPAWNER.lock().unwrap() = Some(Arc::new(spawn));
+}
+
+/// Wraps a value to send it to another thread, even if it's not `Send`. Used to move
+/// the raw pointers passed to ISPC functions to the thread running them.
+pub struct AssertSend<T>(T);
+
+unsafe impl<T> Send for AssertSend<T> {}
+
+impl<T> AssertSend<T> {
+    /// Wrap `value` to send it to another thread
+    ///
+    /// # Safety
+    /// It must be safe to access `value` from the thread it's sent to, e.g. the data
+    /// pointed to by any pointers must not be accessed concurrently.
+    pub unsafe fn new(value: T) -> AssertSend<T> {
+        AssertSend(value)
+    }
+    /// Get the wrapped value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+/// The state shared between a `KernelFuture` and the thread running its function
+struct Shared<R> {
+    state: Mutex<State<R>>,
+    done: Condvar,
+}
+
+struct State<R> {
+    /// The result of the function, or the panic it raised. `None` once finished if the
+    /// job was dropped without running the function.
+    result: Option<AssertSend<thread::Result<R>>>,
+    finished: bool,
+    waker: Option<Waker>,
+}
+
+/// A future which resolves to the result of an ISPC function once it has returned
+/// on the thread running it, see `spawn_kernel`.
+///
+/// Dropping the future before it resolved blocks until the function returned, as the
+/// function may still be accessing the data passed to it.
+pub struct KernelFuture<R> {
+    shared: Arc<Shared<R>>,
+    taken: bool,
+}
+
+/// Run `kernel` on a blocking thread, see `set_blocking_spawner`, returns a future
+/// resolving to its result once it returns. A panic in `kernel` is propagated to the
+/// task awaiting the future.
+///
+/// # Safety
+/// `kernel` and its result are sent to another thread, so it must be safe to do so,
+/// and the data `kernel` accesses must stay valid until the future resolves or is
+/// dropped.
+pub unsafe fn spawn_kernel<F, R>(kernel: F) -> KernelFuture<R>
+where
+    F: FnOnce() -> R + 'static,
+    R: 'static,
+{
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            result: None,
+            finished: false,
+            waker: None,
+        }),
+        done: Condvar::new(),
+    });
+    // Marks the call finished when the job is dropped, so the future doesn't wait forever
+    // if the spawner drops the job without running it
+    struct Finish<R>(Arc<Shared<R>>, Option<AssertSend<thread::Result<R>>>);
+    impl<R> Drop for Finish<R> {
+        fn drop(&mut self) {
+            let mut state = self.0.state.lock().unwrap();
+            state.result = self.1.take();
+            state.finished = true;
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+            self.0.done.notify_all();
+        }
+    }
+    let job = {
+        let finish = Finish(shared.clone(), None);
+        let kernel = AssertSend(kernel);
+        move || {
+            // Move the whole guard into the closure, not just the field set below
+            let mut finish = finish;
+            let kernel = kernel.into_inner();
+            let result = panic::catch_unwind(AssertUnwindSafe(kernel));
+            finish.1 = Some(AssertSend(result));
+        }
+    };
+    let spawner = SPAWNER.lock().unwrap().clone();
+    match spawner {
+        Some(spawn) => spawn(Box::new(job)),
+        None => {
+            thread::Builder::new()
+                .name(String::from("ispc_rt kernel"))
+                .spawn(job)
+                .expect("Failed to spawn thread to run ISPC function");
+        }
+    }
+    KernelFuture {
+        shared,
+        taken: false,
+    }
+}
+
+impl<R> Future for KernelFuture<R> {
+    type Output = R;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.finished {
+            state.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let result = state.result.take();
+        drop(state);
+        self.taken = true;
+        match result.map(AssertSend::into_inner) {
+            Some(Ok(r)) => Poll::Ready(r),
+            Some(Err(p)) => panic::resume_unwind(p),
+            None => panic!(
+                "ispc_rt: The job running the ISPC function of a KernelFuture was dropped \
+                 without running it"
+            ),
+        }
+    }
+}
+
+impl<R> Drop for KernelFuture<R> {
+    fn drop(&mut self) {
+        if self.taken {
+            return;
+        }
+        let mut state = self.shared.state.lock().unwrap();
+        while !state.finished {
+            state = self.shared.done.wait(state).unwrap();
+        }
+    }
+}
@@ -0,0 +1,339 @@
+//! Defines 2D images and 3D volumes stored in aligned buffers with padded rows, along
+//! with their dimensions, so they don't have to be passed around and indexed by hand.
+//!
+//! Each pixel or voxel holds `channels` elements, e.g. 3 for an RGB image. The elements
+//! of a row are stored contiguously, followed by padding up to the row pitch, the number
+//! of elements from the start of one row to the next. Padding the rows to a multiple of
+//! the gang width lets kernels process whole gangs of each row, see the `padding` module.
+//! Volumes similarly store their slices a slice pitch apart.
+//!
+//! # Example
+//! ```
+//! use ispc_rt::Image2D;
+//!
+//! // An RGB image with its rows padded to a multiple of 8 elements
+//! let mut img = Image2D::with_gang_width(10, 4, 3, 8, 0.0f32);
+//! assert_eq!(img.pitch(), 32);
+//! img.pixel_mut(2, 1).copy_from_slice(&[1.0, 0.5, 0.25]);
+//! assert_eq!(img.row(1)[6..9], [1.0, 0.5, 0.25]);
+//!
+//! // Pass the image to a kernel taking `uniform float img[], uniform int width,
+//! // uniform int height, uniform int pitch`
+//! let (ptr, width, height, pitch) = img.kernel_args_mut();
+//! ```
+
+use crate::aligned::AlignedVec;
+use crate::padding::pad_len;
+
+/// Convert a dimension to the `int` ISPC kernels take
+fn kernel_dim(value: usize, name: &str) -> i32 {
+    i32::try_from(value).unwrap_or_else(|_| panic!("ispc_rt: {name} {value} doesn't fit in an i32"))
+}
+
+/// A 2D image of `width` by `height` pixels, each holding `channels` elements of type `T`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image2D<T> {
+    width: usize,
+    height: usize,
+    channels: usize,
+    pitch: usize,
+    data: AlignedVec<T>,
+}
+
+impl<T: Clone> Image2D<T> {
+    /// Create an image with tightly packed rows and all elements set to `value`
+    pub fn new(width: usize, height: usize, channels: usize, value: T) -> Image2D<T> {
+        Image2D::with_pitch(width, height, channels, width * channels, value)
+    }
+    /// Create an image with its rows padded to a multiple of `gang_width` elements and
+    /// all elements, including the padding, set to `value`
+    pub fn with_gang_width(
+        width: usize,
+        height: usize,
+        channels: usize,
+        gang_width: usize,
+        value: T,
+    ) -> Image2D<T> {
+        let pitch = pad_len(width * channels, gang_width);
+        Image2D::with_pitch(width, height, channels, pitch, value)
+    }
+    /// Create an image whose rows are `pitch` elements apart, with all elements set
+    /// to `value`
+    ///
+    /// # Panics
+    /// Panics if `pitch` is smaller than a row of `width * channels` elements.
+    pub fn with_pitch(
+        width: usize,
+        height: usize,
+        channels: usize,
+        pitch: usize,
+        value: T,
+    ) -> Image2D<T> {
+        assert!(
+            pitch >= width * channels,
+            "ispc_rt: image pitch {pitch} is smaller than a row of {} elements",
+            width * channels
+        );
+        Image2D {
+            width,
+            height,
+            channels,
+            pitch,
+            data: AlignedVec::from_elem(value, pitch * height),
+        }
+    }
+}
+
+impl<T> Image2D<T> {
+    /// Get the width of the image in pixels
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    /// Get the height of the image in pixels
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    /// Get the number of elements in each pixel
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+    /// Get the number of elements from the start of one row to the next
+    pub fn pitch(&self) -> usize {
+        self.pitch
+    }
+    /// Get the elements of row `y`, without the padding
+    pub fn row(&self, y: usize) -> &[T] {
+        let start = y * self.pitch;
+        &self.data[start..start + self.width * self.channels]
+    }
+    /// Get the mutable elements of row `y`, without the padding
+    pub fn row_mut(&mut self, y: usize) -> &mut [T] {
+        let start = y * self.pitch;
+        &mut self.data[start..start + self.width * self.channels]
+    }
+    /// Get the elements of the pixel at (`x`, `y`)
+    pub fn pixel(&self, x: usize, y: usize) -> &[T] {
+        assert!(x < self.width, "ispc_rt: pixel x {x} is out of bounds");
+        let start = y * self.pitch + x * self.channels;
+        &self.data[start..start + self.channels]
+    }
+    /// Get the mutable elements of the pixel at (`x`, `y`)
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> &mut [T] {
+        assert!(x < self.width, "ispc_rt: pixel x {x} is out of bounds");
+        let start = y * self.pitch + x * self.channels;
+        &mut self.data[start..start + self.channels]
+    }
+    /// Get all elements of the image, including the padding of the rows
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+    /// Get all elements of the image mutably, including the padding of the rows
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+    /// Get a pointer to the aligned data, to pass to ISPC
+    pub fn as_ptr(&self) -> *const T {
+        self.data.as_ptr()
+    }
+    /// Get a mutable pointer to the aligned data, to pass to ISPC
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.data.as_mut_ptr()
+    }
+    /// Get the pointer, width, height and pitch to pass to a kernel reading the image
+    ///
+    /// # Panics
+    /// Panics if a dimension doesn't fit in an `i32`.
+    pub fn kernel_args(&self) -> (*const T, i32, i32, i32) {
+        (
+            self.as_ptr(),
+            kernel_dim(self.width, "width"),
+            kernel_dim(self.height, "height"),
+            kernel_dim(self.pitch, "pitch"),
+        )
+    }
+    /// Get the pointer, width, height and pitch to pass to a kernel writing the image
+    ///
+    /// # Panics
+    /// Panics if a dimension doesn't fit in an `i32`.
+    pub fn kernel_args_mut(&mut self) -> (*mut T, i32, i32, i32) {
+        (
+            self.as_mut_ptr(),
+            kernel_dim(self.width, "width"),
+            kernel_dim(self.height, "height"),
+            kernel_dim(self.pitch, "pitch"),
+        )
+    }
+    /// Take the aligned buffer holding the image
+    pub fn into_inner(self) -> AlignedVec<T> {
+        self.data
+    }
+}
+
+/// A 3D volume of `width` by `height` by `depth` voxels, each holding `channels`
+/// elements of type `T`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Volume3D<T> {
+    width: usize,
+    height: usize,
+    depth: usize,
+    channels: usize,
+    pitch: usize,
+    slice_pitch: usize,
+    data: AlignedVec<T>,
+}
+
+impl<T: Clone> Volume3D<T> {
+    /// Create a volume with tightly packed rows and all elements set to `value`
+    pub fn new(
+        width: usize,
+        height: usize,
+        depth: usize,
+        channels: usize,
+        value: T,
+    ) -> Volume3D<T> {
+        Volume3D::with_pitch(width, height, depth, channels, width * channels, value)
+    }
+    /// Create a volume with its rows padded to a multiple of `gang_width` elements and
+    /// all elements, including the padding, set to `value`
+    pub fn with_gang_width(
+        width: usize,
+        height: usize,
+        depth: usize,
+        channels: usize,
+        gang_width: usize,
+        value: T,
+    ) -> Volume3D<T> {
+        let pitch = pad_len(width * channels, gang_width);
+        Volume3D::with_pitch(width, height, depth, channels, pitch, value)
+    }
+    /// Create a volume whose rows are `pitch` elements apart, with all elements set
+    /// to `value`. The slices are `pitch * height` elements apart.
+    ///
+    /// # Panics
+    /// Panics if `pitch` is smaller than a row of `width * channels` elements.
+    pub fn with_pitch(
+        width: usize,
+        height: usize,
+        depth: usize,
+        channels: usize,
+        pitch: usize,
+        value: T,
+    ) -> Volume3D<T> {
+        assert!(
+            pitch >= width * channels,
+            "ispc_rt: volume pitch {pitch} is smaller than a row of {} elements",
+            width * channels
+        );
+        Volume3D {
+            width,
+            height,
+            depth,
+            channels,
+            pitch,
+            slice_pitch: pitch * height,
+            data: AlignedVec::from_elem(value, pitch * height * depth),
+        }
+    }
+}
+
+impl<T> Volume3D<T> {
+    /// Get the width of the volume in voxels
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    /// Get the height of the volume in voxels
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    /// Get the depth of the volume in voxels
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+    /// Get the number of elements in each voxel
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+    /// Get the number of elements from the start of one row to the next
+    pub fn pitch(&self) -> usize {
+        self.pitch
+    }
+    /// Get the number of elements from the start of one slice to the next
+    pub fn slice_pitch(&self) -> usize {
+        self.slice_pitch
+    }
+    /// Get the elements of row `y` of slice `z`, without the padding
+    pub fn row(&self, y: usize, z: usize) -> &[T] {
+        assert!(y < self.height, "ispc_rt: volume row {y} is out of bounds");
+        let start = z * self.slice_pitch + y * self.pitch;
+        &self.data[start..start + self.width * self.channels]
+    }
+    /// Get the mutable elements of row `y` of slice `z`, without the padding
+    pub fn row_mut(&mut self, y: usize, z: usize) -> &mut [T] {
+        assert!(y < self.height, "ispc_rt: volume row {y} is out of bounds");
+        let start = z * self.slice_pitch + y * self.pitch;
+        &mut self.data[start..start + self.width * self.channels]
+    }
+    /// Get the elements of the voxel at (`x`, `y`, `z`)
+    pub fn voxel(&self, x: usize, y: usize, z: usize) -> &[T] {
+        assert!(x < self.width, "ispc_rt: voxel x {x} is out of bounds");
+        let start = &self.row(y, z)[x * self.channels..];
+        &start[..self.channels]
+    }
+    /// Get the mutable elements of the voxel at (`x`, `y`, `z`)
+    pub fn voxel_mut(&mut self, x: usize, y: usize, z: usize) -> &mut [T] {
+        assert!(x < self.width, "ispc_rt: voxel x {x} is out of bounds");
+        let channels = self.channels;
+        let start = &mut self.row_mut(y, z)[x * channels..];
+        &mut start[..channels]
+    }
+    /// Get all elements of the volume, including the padding of the rows
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+    /// Get all elements of the volume mutably, including the padding of the rows
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+    /// Get a pointer to the aligned data, to pass to ISPC
+    pub fn as_ptr(&self) -> *const T {
+        self.data.as_ptr()
+    }
+    /// Get a mutable pointer to the aligned data, to pass to ISPC
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.data.as_mut_ptr()
+    }
+    /// Get the pointer, width, height, depth, pitch and slice pitch to pass to a kernel
+    /// reading the volume
+    ///
+    /// # Panics
+    /// Panics if a dimension doesn't fit in an `i32`.
+    pub fn kernel_args(&self) -> (*const T, i32, i32, i32, i32, i32) {
+        (
+            self.as_ptr(),
+            kernel_dim(self.width, "width"),
+            kernel_dim(self.height, "height"),
+            kernel_dim(self.depth, "depth"),
+            kernel_dim(self.pitch, "pitch"),
+            kernel_dim(self.slice_pitch, "slice pitch"),
+        )
+    }
+    /// Get the pointer, width, height, depth, pitch and slice pitch to pass to a kernel
+    /// writing the volume
+    ///
+    /// # Panics
+    /// Panics if a dimension doesn't fit in an `i32`.
+    pub fn kernel_args_mut(&mut self) -> (*mut T, i32, i32, i32, i32, i32) {
+        (
+            self.as_mut_ptr(),
+            kernel_dim(self.width, "width"),
+            kernel_dim(self.height, "height"),
+            kernel_dim(self.depth, "depth"),
+            kernel_dim(self.pitch, "pitch"),
+            kernel_dim(self.slice_pitch, "slice pitch"),
+        )
+    }
+    /// Take the aligned buffer holding the volume
+    pub fn into_inner(self) -> AlignedVec<T> {
+        self.data
+    }
+}
@@ -0,0 +1,171 @@
+//! A minimal, threadless implementation of the ISPC task runtime used when the
+//! `std` feature is disabled.
+//!
+//! Tasks are run inline on the calling thread as soon as they're launched, so `sync`
+//! has nothing left to wait for. The memory for the tasks' parameters is taken from a
+//! buffer provided by the application through `set_task_memory` using a simple bump
+//! allocator, which is reset back to where the context started when it's synced. Since
+//! contexts nest, i.e. a context created by a task is synced before the task returns,
+//! this releases memory in the reverse order it was allocated.
+//!
+//! The runtime doesn't depend on `std`, threads or thread local storage, but the task
+//! memory is shared by all callers so ISPC code which launches tasks must not be called
+//! from multiple threads at the same time.
+//!
+//! When the `std` feature is enabled, e.g. by another crate in the dependency graph, the
+//! threaded task system runs the tasks instead and the task memory is left unused.
+//!
+//! # Example
+//! ```ignore
+//! static mut TASK_MEMORY: [u8; 4096] = [0; 4096];
+//!
+//! fn main() {
+//!     ispc_rt::inline::set_task_memory(unsafe { &mut *core::ptr::addr_of_mut!(TASK_MEMORY) });
+//!     unsafe { kernels::run(/* ... */) };
+//! }
+//! ```
+
+use core::ffi::{c_int, c_void};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// A pointer to an ISPC task function, see `task::ISPCTaskFn` in the threaded runtime.
+pub type ISPCTaskFn = extern "C" fn(
+    data: *mut c_void,
+    thread_idx: c_int,
+    thread_cnt: c_int,
+    task_idx: c_int,
+    task_cnt: c_int,
+    task_idx0: c_int,
+    task_idx1: c_int,
+    task_idx2: c_int,
+    task_cnt0: c_int,
+    task_cnt1: c_int,
+    task_cnt2: c_int,
+);
+
+static TASK_MEMORY: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+static TASK_MEMORY_SIZE: AtomicUsize = AtomicUsize::new(0);
+static TASK_MEMORY_USED: AtomicUsize = AtomicUsize::new(0);
+
+/// Provide the buffer that memory for the parameters of launched tasks is allocated from.
+/// This must be done before calling ISPC code which launches tasks, and the buffer must
+/// be large enough to hold the parameters of the deepest nesting of launches.
+///
+/// # Panics
+/// Panics if called while task memory is in use by ISPC code.
+pub fn set_task_memory(buffer: &'static mut [u8]) {
+    assert_eq!(
+        TASK_MEMORY_USED.load(Ordering::SeqCst),
+        0,
+        "ispc_rt: task memory can't be replaced while ISPC code is using it"
+    );
+    TASK_MEMORY.store(buffer.as_mut_ptr(), Ordering::SeqCst);
+    TASK_MEMORY_SIZE.store(buffer.len(), Ordering::SeqCst);
+}
+
+/// Get the number of bytes of task memory currently in use
+pub fn task_memory_used() -> usize {
+    TASK_MEMORY_USED.load(Ordering::SeqCst)
+}
+
+/// Allocate memory for the task parameters, when `handle_ptr` is null this creates a new
+/// context and the handle records the amount of memory in use to restore when it's synced.
+///
+/// # Panics
+/// Panics if no task memory was provided or it's not large enough for the allocation.
+#[cfg(not(feature = "std"))]
+#[allow(non_snake_case)]
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn ISPCAlloc(
+    handle_ptr: *mut *mut c_void,
+    size: i64,
+    align: i32,
+) -> *mut c_void {
+    let base = TASK_MEMORY.load(Ordering::SeqCst);
+    assert!(
+        !base.is_null(),
+        "ispc_rt: no task memory was set, see ispc_rt::inline::set_task_memory"
+    );
+    assert!(
+        align > 0 && (align as usize).is_power_of_two(),
+        "ispc_rt: task memory alignment must be a power of 2"
+    );
+    let used = TASK_MEMORY_USED.load(Ordering::SeqCst);
+    if (*handle_ptr).is_null() {
+        // Offset the handle by one so it's not null for a context starting at 0
+        *handle_ptr = (used + 1) as *mut c_void;
+    }
+    let align = align as usize;
+    let start = (base as usize + used).next_multiple_of(align) - base as usize;
+    let end = start + size as usize;
+    assert!(
+        end <= TASK_MEMORY_SIZE.load(Ordering::SeqCst),
+        "ispc_rt: out of task memory, provide a larger buffer to set_task_memory"
+    );
+    TASK_MEMORY_USED.store(end, Ordering::SeqCst);
+    base.add(start) as *mut c_void
+}
+
+/// Run the launched tasks inline in a nested loop
+#[cfg(not(feature = "std"))]
+#[allow(non_snake_case)]
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn ISPCLaunch(
+    _handle_ptr: *mut *mut c_void,
+    f: *mut c_void,
+    data: *mut c_void,
+    count0: c_int,
+    count1: c_int,
+    count2: c_int,
+) {
+    let task_fn: ISPCTaskFn = core::mem::transmute(f);
+    // Panicking aborts the process here, as ISPC can't be unwound through
+    let total_tasks = count0
+        .checked_mul(count1)
+        .and_then(|c| c.checked_mul(count2))
+        .unwrap_or_else(|| {
+            panic!(
+                "ispc_rt: launch[{count0}, {count1}, {count2}] has more tasks than fit in an int"
+            )
+        });
+    for z in 0..count2 {
+        for y in 0..count1 {
+            for x in 0..count0 {
+                let task_id = x + y * count0 + z * count0 * count1;
+                task_fn(
+                    data,
+                    0,
+                    1,
+                    task_id,
+                    total_tasks,
+                    x,
+                    y,
+                    z,
+                    count0,
+                    count1,
+                    count2,
+                );
+            }
+        }
+    }
+}
+
+/// Release the task memory allocated in the context, its tasks have already run
+#[cfg(not(feature = "std"))]
+#[allow(non_snake_case)]
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn ISPCSync(handle: *mut c_void) {
+    TASK_MEMORY_USED.store(handle as usize - 1, Ordering::SeqCst);
+}
+
+/// Tasks can't be cancelled in the threadless runtime, this always returns false.
+/// Provided so ISPC code polling it links in either configuration.
+#[cfg(not(feature = "std"))]
+#[no_mangle]
+pub extern "C" fn ispc_should_cancel() -> bool {
+    false
+}
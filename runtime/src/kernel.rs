@@ -0,0 +1,100 @@
+//! Describes the functions exported from an ISPC library at runtime, through the
+//! `KERNELS` registry generated in the bindings with `Config::kernel_registry` in
+//! `ispc_compile`. Each `KernelDesc` holds the name and signature of a function along
+//! with a type-erased invoker, so kernels can be listed and called by name, e.g. from
+//! a scripting language or a tool running each kernel of a library.
+//!
+//! # Example
+//! The registry holds an entry like this one for each exported function, here for a
+//! `float scale(uniform float x, uniform float s)` kernel:
+//!
+//! ```
+//! use core::ffi::c_void;
+//!
+//! use ispc_rt::kernel::{KernelDesc, ParamDesc};
+//!
+//! # unsafe extern "C" fn scale(x: f32, s: f32) -> f32 { x * s }
+//! static KERNELS: &[KernelDesc] = &[KernelDesc {
+//!     name: "scale",
+//!     params: &[
+//!         ParamDesc { name: "x", ty: "f32" },
+//!         ParamDesc { name: "s", ty: "f32" },
+//!     ],
+//!     ret: "f32",
+//!     invoker: {
+//!         unsafe fn invoke(args: *const *mut c_void, ret: *mut c_void) {
+//!             unsafe {
+//!                 let r = scale(
+//!                     core::ptr::read(*args.add(0) as *const f32),
+//!                     core::ptr::read(*args.add(1) as *const f32),
+//!                 );
+//!                 core::ptr::write(ret as *mut f32, r);
+//!             }
+//!         }
+//!         invoke
+//!     },
+//! }];
+//!
+//! let kernel = KernelDesc::find(KERNELS, "scale").unwrap();
+//! let (mut x, mut s, mut r) = (2.0f32, 1.5f32, 0.0f32);
+//! let args = [&mut x as *mut f32 as *mut c_void, &mut s as *mut f32 as *mut c_void];
+//! unsafe { kernel.invoke(&args, &mut r as *mut f32 as *mut c_void) };
+//! assert_eq!(r, 3.0);
+//! ```
+
+use core::ffi::c_void;
+
+/// A parameter of a kernel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParamDesc {
+    pub name: &'static str,
+    /// The Rust type of the parameter in the bindings, e.g. `*const f32`
+    pub ty: &'static str,
+}
+
+/// A function exported from an ISPC library, which can be called through `invoke`
+/// without knowing its signature at compile time
+#[derive(Clone, Copy, Debug)]
+pub struct KernelDesc {
+    /// The name of the function in the bindings
+    pub name: &'static str,
+    pub params: &'static [ParamDesc],
+    /// The Rust return type in the bindings, `()` if the function doesn't return anything
+    pub ret: &'static str,
+    /// Calls the function with the arguments read from the pointers in the array, and
+    /// writes its result to the return pointer, use `invoke` instead
+    #[doc(hidden)]
+    pub invoker: unsafe fn(*const *mut c_void, *mut c_void),
+}
+
+impl KernelDesc {
+    /// Find the kernel named `name` in the registry `kernels`
+    pub fn find(kernels: &'static [KernelDesc], name: &str) -> Option<&'static KernelDesc> {
+        kernels.iter().find(|k| k.name == name)
+    }
+    /// Check if the kernel returns a value, which `invoke` writes to its return pointer
+    pub fn returns(&self) -> bool {
+        self.ret != "()"
+    }
+    /// Call the kernel with the arguments pointed to by `args`, one per parameter in the
+    /// order they're declared. The result is written to `ret` if the kernel returns
+    /// anything, otherwise `ret` is ignored and can be null.
+    ///
+    /// Panics if the number of arguments doesn't match the number of parameters.
+    ///
+    /// # Safety
+    /// Each argument must point to a valid value of the type of its parameter, and `ret`
+    /// to memory where a value of the return type can be written. Calling the kernel must
+    /// be safe with the arguments passed, as for calling it through the bindings.
+    pub unsafe fn invoke(&self, args: &[*mut c_void], ret: *mut c_void) {
+        if args.len() != self.params.len() {
+            panic!(
+                "ispc_rt: Kernel {} takes {} arguments but was invoked with {}",
+                self.name,
+                self.params.len(),
+                args.len()
+            );
+        }
+        unsafe { (self.invoker)(args.as_ptr(), ret) }
+    }
+}
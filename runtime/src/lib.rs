@@ -7,25 +7,189 @@
 //! This crate also includes the various runtime components for the ISPC
 //! language, including the parallel task system and performance instrumentation.
 //!
+//! # Threadless Runtime
+//!
+//! The threaded task system, instrumentation and `PackagedModule` are provided by the
+//! `std` feature, which is enabled by default. Without it the crate is `no_std` and
+//! ISPC tasks are run inline when they're launched, with their parameters allocated from
+//! a buffer provided through `inline::set_task_memory`. This allows calling ISPC code on
+//! embedded and freestanding targets, see the `inline` module. The buffers, strings and
+//! shared type helpers only need `alloc` and are available either way. The bindings
+//! should be generated with `Config::no_std_bindings` so they don't use `std` either.
+//!
+//! # WebAssembly
+//!
+//! When targeting `wasm32` with the `atomics` target feature the `Parallel` task system can
+//! run tasks on a pool of Web Workers or wasi-threads, see the `wasm` module.
+//!
+//! # Shared Types
+//!
+//! With the `derive` feature enabled structs can `#[derive(IspcStruct)]` to generate their
+//! declaration in ISPC, which the build script writes to a header included by the ISPC code.
+//! This keeps the Rust definition of the struct as the single source of truth for its layout,
+//! see `IspcStruct`.
+//!
+//! # Status Codes
+//!
+//! ISPC functions reporting failures through a status code can be wrapped in functions
+//! returning a `Result<(), IspcError>` with `Config::result_wrappers`, see the `error` module.
+//!
+//! # Print Output
+//!
+//! The output of `print()` in ISPC code built with `Config::route_print` is passed line by
+//! line to a handler set with `print::set_print_handler`, or logged through the `log` crate
+//! with the `log` feature enabled, see the `print` module.
+//!
+//! # Arrays
+//!
+//! With the `ndarray` feature enabled arrays can be passed to kernels through the adapters
+//! in the `array` module, which check their layout and get the pointer, dimensions and
+//! strides to pass.
+//!
+//! # Assertions
+//!
+//! Failed `assert()`s in ISPC code built with `Config::route_asserts` panic with the
+//! kernel, task and assertion which failed, see the `abort` module.
+//!
+//! # Benchmarks
+//!
+//! With the `criterion` feature enabled the `bench` module benchmarks a kernel on each
+//! target ISA it was compiled for, through the entry points generated with
+//! `Config::isa_entry_points`.
+//!
+//! # Kernel Registry
+//!
+//! The bindings of a library built with `Config::kernel_registry` list its exported
+//! functions in `KERNELS`, which describes the signature of each and calls it through a
+//! type-erased invoker, to look up and call kernels by name, see the `kernel` module.
+//!
+//! # Metrics
+//!
+//! With the `metrics` feature enabled the `metrics` module provides a `TaskObserver` which
+//! publishes the number of active contexts, queued tasks, busy workers and the rate tasks
+//! are completed at through the `metrics` crate.
+//!
+//! # Hot Reloading
+//!
+//! With the `dynamic` feature enabled libraries built with `Config::shared_library` are
+//! loaded at runtime through `DynamicModule`, which can rebuild and reload the kernels
+//! when their sources change while the program runs, see the `dynamic` module.
+//!
 
+#![cfg_attr(not(feature = "std"), no_std)]
 #![allow(dead_code)]
 
+extern crate alloc;
 extern crate libc;
-extern crate num_cpus;
 
+#[cfg(feature = "std")]
+pub mod abort;
+pub mod aligned;
+#[cfg(feature = "ndarray")]
+pub mod array;
+#[cfg(feature = "criterion")]
+pub mod bench;
+#[cfg(feature = "std")]
+pub mod callback;
+#[cfg(feature = "std")]
+pub mod cancel;
+pub mod cstr;
+#[cfg(feature = "dynamic")]
+pub mod dynamic;
+pub mod error;
+#[cfg(feature = "std")]
 pub mod exec;
+#[cfg(feature = "std")]
+pub mod future;
+pub mod image;
+pub mod inline;
+#[cfg(feature = "std")]
 pub mod instrument;
+pub mod kernel;
+#[cfg(feature = "std")]
+pub mod limit;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod observer;
+pub mod padding;
+#[cfg(feature = "std")]
+pub mod print;
+#[cfg(feature = "std")]
+pub mod replay;
+#[cfg(feature = "std")]
+pub mod rows;
+pub mod soa;
+#[cfg(feature = "std")]
 pub mod task;
+#[cfg(feature = "std")]
+pub mod trace;
+pub mod types;
+#[cfg(all(feature = "std", target_arch = "wasm32", target_feature = "atomics"))]
+pub mod wasm;
 
+#[cfg(feature = "std")]
 use std::env;
-use std::ffi::CStr;
+#[cfg(feature = "std")]
+use std::ffi::{c_char, c_int, CStr};
+#[cfg(feature = "std")]
 use std::mem;
+#[cfg(feature = "std")]
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Once};
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, Once, PoisonError};
 
-pub use crate::exec::{Parallel, TaskSystem};
+pub use crate::aligned::AlignedVec;
+#[cfg(feature = "std")]
+pub use crate::cancel::{with_cancellation, CancellationToken};
+pub use crate::cstr::CStrArena;
+#[cfg(feature = "dynamic")]
+pub use crate::dynamic::DynamicModule;
+pub use crate::error::IspcError;
+#[cfg(feature = "std")]
+pub use crate::exec::{
+    IdleStrategy, Parallel, ParallelBuilder, TaskSystem, UnsyncedContext, WorkerSpawnFn,
+    WorkerStartFn, WorkerThread,
+};
+pub use crate::image::{Image2D, Volume3D};
+#[cfg(feature = "std")]
 pub use crate::instrument::{Instrument, SimpleInstrument};
-pub use crate::task::ISPCTaskFn;
+pub use crate::kernel::KernelDesc;
+#[cfg(feature = "std")]
+pub use crate::limit::{with_max_threads, ThreadLimit};
+#[cfg(feature = "std")]
+pub use crate::observer::TaskObserver;
+pub use crate::padding::{pad_len, padded_pitch};
+#[cfg(feature = "std")]
+pub use crate::rows::{for_each_row_chunk, for_each_row_chunk_sized};
+pub use crate::soa::{Soa, Soa2, Soa3, Soa4};
+#[cfg(feature = "std")]
+pub use crate::task::{ChunkOrder, ISPCTaskFn};
+#[cfg(feature = "std")]
+pub use crate::trace::TraceRecorder;
+pub use crate::types::{IspcStruct, IspcType};
+#[cfg(feature = "derive")]
+pub use ispc_derive::IspcStruct;
+
+/// Paths used by the code generated by `#[derive(IspcStruct)]`, which works in `no_std` crates
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::format;
+    pub use alloc::string::String;
+}
+
+#[cfg(feature = "bytemuck")]
+pub use bytemuck;
+#[cfg(feature = "glam")]
+pub use glam;
+#[cfg(feature = "half")]
+pub use half;
+#[cfg(feature = "dynamic")]
+pub use libloading;
+#[cfg(feature = "mint")]
+pub use mint;
+#[cfg(feature = "ndarray")]
+pub use ndarray;
 
 /// Convenience macro for generating the module to hold the raw/unsafe ISPC bindings.
 ///
@@ -33,6 +197,14 @@ pub use crate::task::ISPCTaskFn;
 /// a rust module containing bindings to the functions exported from ISPC. These
 /// can be imported by passing the name of your library to the `ispc_module` macro.
 ///
+/// The visibility of the module, extra attributes to put on it and the path of the
+/// bindings file can also be specified. The path is resolved relative to the file
+/// invoking the macro, as with `include!`, and defaults to the bindings of the library
+/// in the directory passed by the build script in `ISPC_OUT_DIR_<lib>`. Each library
+/// has its own variable, so multiple libraries can be imported in the same crate. The
+/// variables are set by `ispc_compile` and `PackagedModule` 3.0 and newer, libraries
+/// built with older versions only set `ISPC_OUT_DIR`, see the changelog.
+///
 /// # Example
 ///
 /// ```ignore
@@ -41,22 +213,175 @@ pub use crate::task::ISPCTaskFn;
 ///
 /// // Functions exported from foo will be callable under foo::*
 /// ispc_module!(foo);
+///
+/// // Other libraries built by the build script are imported the same way
+/// ispc_module!(foo_tasks);
+///
+/// // Functions exported from bar will be callable by dependent crates under bar::*
+/// // when the simd feature is enabled
+/// ispc_module!(pub bar, attrs(#[cfg(feature = "simd")]));
+///
+/// // Use bindings to baz which were packaged with the crate
+/// ispc_module!(pub(crate) baz, path = "bindings/baz.rs");
 /// ```
 #[macro_export]
 macro_rules! ispc_module {
+    (@include $lib:ident) => {
+        include!(concat!(
+            env!(concat!("ISPC_OUT_DIR_", stringify!($lib))),
+            "/",
+            stringify!($lib),
+            ".rs"
+        ));
+    };
+    (@include $lib:ident, $path:literal) => {
+        include!($path);
+    };
     ($lib:ident) => {
-        include!(concat!(env!("ISPC_OUT_DIR"), "/", stringify!($lib), ".rs"));
+        include!(concat!(
+            env!(concat!("ISPC_OUT_DIR_", stringify!($lib))),
+            "/",
+            stringify!($lib),
+            ".rs"
+        ));
+    };
+    ($vis:vis $lib:ident $(, attrs($(#[$attr:meta])*))? $(, path = $path:literal)?) => {
+        $($(#[$attr])*)?
+        $vis mod $lib {
+            // The bindings file declares its own module, which we re-export the contents of
+            $crate::ispc_module!(@include $lib $(, $path)?);
+            pub use self::$lib::*;
+        }
+    };
+}
+
+/// Define an ISPC kernel inline in a test and import its bindings, to test kernels
+/// function by function.
+///
+/// The ISPC source is compiled into a library named after the first argument by calling
+/// `ispc_compile::compile_test_kernels` on the directory of the test in the build script,
+/// which finds the invocations of this macro. The macro then imports the bindings of the
+/// library like `ispc_module!`, so the source has to be a string literal, preferably a
+/// raw one, for the build script to read it. The `test_kernels` example is a crate
+/// testing its kernels this way.
+///
+/// # Example
+///
+/// ```ignore
+/// // build.rs
+/// fn main() {
+///     ispc_compile::compile_test_kernels("tests/");
+/// }
+///
+/// // tests/kernels.rs
+/// #[test]
+/// fn scale() {
+///     ispc_rt::test_kernel!(scale_kernel, r#"
+///         export void scale(uniform float v[], uniform int n, uniform float s) {
+///             foreach (i = 0 ... n) {
+///                 v[i] *= s;
+///             }
+///         }
+///     "#);
+///     let mut v = [1.0f32, 2.0, 3.0];
+///     unsafe { scale_kernel::scale(v.as_mut_ptr(), v.len() as i32, 2.0) };
+///     assert_eq!(v, [2.0, 4.0, 6.0]);
+/// }
+/// ```
+#[macro_export]
+macro_rules! test_kernel {
+    ($lib:ident, $source:literal $(,)?) => {
+        $crate::ispc_module!($lib);
+    };
+}
+
+/// Check at compile time that a Rust struct has the same layout as a struct exported
+/// from ISPC, i.e. the one generated in the bindings.
+///
+/// The size and alignment of the structs are compared, along with the offsets of the
+/// fields listed. A field is matched to the ISPC field of the same name, or the one
+/// given after a `:` if they're named differently. Build errors will point out which
+/// of the checks failed when the layouts drift apart.
+///
+/// # Example
+///
+/// ```
+/// # mod rt {
+/// #     #[repr(C)]
+/// #     pub struct Camera { pub pos: [f32; 3], pub dir: [f32; 3], pub fovy: f32 }
+/// # }
+/// #[repr(C)]
+/// pub struct Camera {
+///     pos: [f32; 3],
+///     dir: [f32; 3],
+///     fov: f32,
+/// }
+///
+/// ispc_rt::assert_ispc_layout!(Camera, rt::Camera { pos, dir, fov: fovy });
+/// ```
+///
+/// A struct with a field in the wrong place fails to build:
+///
+/// ```compile_fail
+/// # mod rt {
+/// #     #[repr(C)]
+/// #     pub struct Camera { pub pos: [f32; 3], pub dir: [f32; 3], pub fovy: f32 }
+/// # }
+/// #[repr(C)]
+/// pub struct Camera {
+///     fov: f32,
+///     pos: [f32; 3],
+///     dir: [f32; 3],
+/// }
+///
+/// ispc_rt::assert_ispc_layout!(Camera, rt::Camera { pos, dir, fov: fovy });
+/// ```
+#[macro_export]
+macro_rules! assert_ispc_layout {
+    (@field $rust:ty, $ispc:path, $field:ident) => {
+        $crate::assert_ispc_layout!(@field $rust, $ispc, $field, $field);
+    };
+    (@field $rust:ty, $ispc:path, $field:ident, $ispc_field:ident) => {
+        assert!(
+            ::core::mem::offset_of!($rust, $field) == ::core::mem::offset_of!($ispc, $ispc_field),
+            concat!(
+                "offset of ", stringify!($rust), "::", stringify!($field),
+                " doesn't match ISPC field ", stringify!($ispc), "::", stringify!($ispc_field)
+            )
+        );
+    };
+    ($rust:ty, $ispc:path $({ $($field:ident $(: $ispc_field:ident)?),* $(,)? })?) => {
+        const _: () = {
+            assert!(
+                ::core::mem::size_of::<$rust>() == ::core::mem::size_of::<$ispc>(),
+                concat!(
+                    "size of ", stringify!($rust), " doesn't match ISPC struct ", stringify!($ispc)
+                )
+            );
+            assert!(
+                ::core::mem::align_of::<$rust>() == ::core::mem::align_of::<$ispc>(),
+                concat!(
+                    "alignment of ", stringify!($rust), " doesn't match ISPC struct ",
+                    stringify!($ispc)
+                )
+            );
+            $($(
+                $crate::assert_ispc_layout!(@field $rust, $ispc, $field $(, $ispc_field)?);
+            )*)?
+        };
     };
 }
 
 /// A `PackagedModule` refers to an ISPC module which was previously
 /// built using `ispc_compile`, and is now distributed with
 /// the crate.
+#[cfg(feature = "std")]
 pub struct PackagedModule {
     path: Option<PathBuf>,
     lib: String,
 }
 
+#[cfg(feature = "std")]
 impl PackagedModule {
     /// Create a new `PackagedModule` to link against the previously compiled
     /// library named `lib`. As in `ispc_compile`, the library name should not
@@ -90,6 +415,11 @@ impl PackagedModule {
         );
         println!("cargo:rustc-link-search=native={}", path.display());
         println!("cargo:rustc-env=ISPC_OUT_DIR={}", path.display());
+        println!(
+            "cargo:rustc-env=ISPC_OUT_DIR_{}={}",
+            self.lib,
+            path.display()
+        );
     }
     /// Returns the user-set output directory if they've set one, otherwise
     /// returns env("OUT_DIR")
@@ -106,56 +436,112 @@ impl PackagedModule {
     }
 }
 
+#[cfg(feature = "std")]
 fn get_lib_filename(libfile: &str) -> String {
-    if libfile.contains("windows") {
+    if libfile.contains("msvc") {
         format!("{libfile}.lib")
     } else {
         format!("lib{libfile}.a")
     }
 }
 
+#[cfg(feature = "std")]
 static mut TASK_SYSTEM: Option<&'static dyn TaskSystem> = None;
+#[cfg(feature = "std")]
 static TASK_INIT: Once = Once::new();
+/// Guards replacing `TASK_SYSTEM` through `set_task_system`. The flag is set once ISPC
+/// code has first called into the task system, after which it can no longer be changed.
+#[cfg(feature = "std")]
+static TASK_SYSTEM_IN_USE: Mutex<bool> = Mutex::new(false);
 
+#[cfg(feature = "std")]
 static mut INSTRUMENT: Option<&'static dyn Instrument> = None;
+#[cfg(feature = "std")]
 static INSTRUMENT_INIT: Once = Once::new();
 
 /// If you have implemented your own task system you can provide it for use instead
-/// of the default threaded one. This must be done prior to calling ISPC code which
-/// spawns tasks otherwise the task system will have already been initialized to
-/// `Parallel`, which you can also see as an example for implementing a task system.
+/// of the default threaded one, `Parallel`, which you can also see as an example for
+/// implementing a task system. If no task system is set, a default `Parallel` task system
+/// is created the first time ISPC code launches tasks.
+///
+/// The task system can be set (or replaced) any number of times until ISPC code first
+/// calls into it, after which it is locked in for the remainder of the program.
+///
+/// Use the function to do any extra initialization for your task system. A task system
+/// that is replaced before ISPC uses it is dropped, but the one that ends up being used
+/// will be leaked and not destroyed until the program exits and the memory space is
+/// cleaned up.
 ///
-/// Use the function to do any extra initialization for your task system. Note that
-/// the task system will be leaked and not destroyed until the program exits and the
-/// memory space is cleaned up.
+/// # Panics
+/// Panics if ISPC code has already launched tasks on the current task system, since
+/// the contexts it created can't be moved to a different one.
+#[cfg(feature = "std")]
 pub fn set_task_system<F: FnOnce() -> Arc<dyn TaskSystem>>(f: F) {
+    // Create the task system before taking the lock, so a panic in `f` can't poison it
+    let task_sys = f();
+    let in_use = TASK_SYSTEM_IN_USE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if *in_use {
+        drop(in_use);
+        panic!(
+            "ispc_rt::set_task_system called after ISPC code has already launched tasks, \
+             the task system must be set before calling any ISPC function that uses launch"
+        );
+    }
+    let previous = unsafe {
+        let previous = TASK_SYSTEM;
+        TASK_SYSTEM = Some(&*Arc::into_raw(task_sys));
+        previous
+    };
+    drop(in_use);
+    // ISPC never saw the task system being replaced, so we hold the only reference
+    // to it and can release it (and any worker threads it owns) here
+    if let Some(previous) = previous {
+        unsafe { drop(Arc::from_raw(previous as *const dyn TaskSystem)) };
+    }
+}
+
+#[cfg(feature = "std")]
+fn get_task_system() -> &'static dyn TaskSystem {
+    // The first call from ISPC locks in the task system set by the user, or creates
+    // the default one if none was set.
     TASK_INIT.call_once(|| {
-        let task_sys = f();
+        let mut in_use = TASK_SYSTEM_IN_USE
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *in_use = true;
         unsafe {
-            let s = &*task_sys as *const (dyn TaskSystem + 'static);
-            mem::forget(task_sys);
-            TASK_SYSTEM = Some(&*s);
+            let current = TASK_SYSTEM;
+            if current.is_none() {
+                let task_sys = Parallel::new() as Arc<dyn TaskSystem>;
+                let s = &*task_sys as *const (dyn TaskSystem + 'static);
+                mem::forget(task_sys);
+                TASK_SYSTEM = Some(&*s);
+            }
         }
     });
+    unsafe { TASK_SYSTEM.unwrap() }
 }
 
-fn get_task_system() -> &'static dyn TaskSystem {
-    // TODO: This is a bit nasty, but I'm not sure on a nicer solution. Maybe something that
-    // would let the user register the desired (or default) task system? But if
-    // mutable statics can't have destructors we still couldn't have an Arc or Box to something?
-    TASK_INIT.call_once(|| unsafe {
-        let task_sys = Parallel::new() as Arc<dyn TaskSystem>;
-        let s = &*task_sys as *const (dyn TaskSystem + 'static);
-        mem::forget(task_sys);
-        TASK_SYSTEM = Some(&*s);
-    });
-    unsafe { TASK_SYSTEM.unwrap() }
+/// Block until all tasks launched by ISPC code from any thread have completed and their
+/// contexts have been synchronized, see `TaskSystem::wait_idle`. This can be used before
+/// tearing down buffers referenced by the tasks or before exiting the process, to make
+/// sure the task system is quiescent.
+///
+/// Returns immediately if ISPC code has never launched any tasks.
+#[cfg(feature = "std")]
+pub fn sync_all() {
+    if TASK_INIT.is_completed() {
+        get_task_system().wait_idle();
+    }
 }
 
 /// If you have implemented your own instrument for logging ISPC performance
 /// data you can use this function to provide it for use instead of the
 /// default one. This function **must** be called before calling into ISPC code,
 /// otherwise the instrumenter will already be set to the default.
+#[cfg(feature = "std")]
 pub fn set_instrument<F: FnOnce() -> Arc<dyn Instrument>>(f: F) {
     INSTRUMENT_INIT.call_once(|| {
         let instrument = f();
@@ -170,10 +556,12 @@ pub fn set_instrument<F: FnOnce() -> Arc<dyn Instrument>>(f: F) {
 /// Print out a summary of performace data gathered from instrumenting ISPC.
 /// Must enable instrumenting to have this record and print data, see
 /// `Config::instrument`.
+#[cfg(feature = "std")]
 pub fn print_instrumenting_summary() {
     get_instrument().print_summary();
 }
 
+#[cfg(feature = "std")]
 fn get_instrument() -> &'static dyn Instrument {
     // TODO: This is a bit nasty, like above
     INSTRUMENT_INIT.call_once(|| unsafe {
@@ -185,6 +573,7 @@ fn get_instrument() -> &'static dyn Instrument {
     unsafe { INSTRUMENT.unwrap() }
 }
 
+#[cfg(feature = "std")]
 #[allow(non_snake_case)]
 #[doc(hidden)]
 #[no_mangle]
@@ -196,6 +585,7 @@ pub unsafe extern "C" fn ISPCAlloc(
     get_task_system().alloc(handle_ptr, size, align)
 }
 
+#[cfg(feature = "std")]
 #[allow(non_snake_case)]
 #[doc(hidden)]
 #[no_mangle]
@@ -203,14 +593,15 @@ pub unsafe extern "C" fn ISPCLaunch(
     handle_ptr: *mut *mut libc::c_void,
     f: *mut libc::c_void,
     data: *mut libc::c_void,
-    count0: libc::c_int,
-    count1: libc::c_int,
-    count2: libc::c_int,
+    count0: c_int,
+    count1: c_int,
+    count2: c_int,
 ) {
     let task_fn: ISPCTaskFn = mem::transmute(f);
     get_task_system().launch(handle_ptr, task_fn, data, count0, count1, count2);
 }
 
+#[cfg(feature = "std")]
 #[allow(non_snake_case)]
 #[doc(hidden)]
 #[no_mangle]
@@ -218,13 +609,14 @@ pub unsafe extern "C" fn ISPCSync(handle: *mut libc::c_void) {
     get_task_system().sync(handle);
 }
 
+#[cfg(feature = "std")]
 #[allow(non_snake_case)]
 #[doc(hidden)]
 #[no_mangle]
 pub unsafe extern "C" fn ISPCInstrument(
-    cfile: *const libc::c_char,
-    cnote: *const libc::c_char,
-    line: libc::c_int,
+    cfile: *const c_char,
+    cnote: *const c_char,
+    line: c_int,
     mask: u64,
 ) {
     let file_name = CStr::from_ptr(cfile);
@@ -232,3 +624,37 @@ pub unsafe extern "C" fn ISPCInstrument(
     let active_count = mask.count_ones();
     get_instrument().instrument(file_name, note, line, mask, active_count);
 }
+
+/// Check if cancellation was requested for the tasks running on the calling thread,
+/// see the `cancel` module. Long running ISPC tasks can declare and poll this function
+/// to return early when cancelled:
+///
+/// ```c
+/// extern "C" uniform bool ispc_should_cancel();
+/// ```
+#[cfg(feature = "std")]
+#[no_mangle]
+pub extern "C" fn ispc_should_cancel() -> bool {
+    cancel::is_cancelled()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::panic;
+
+    use super::*;
+
+    #[test]
+    fn misusing_set_task_system_does_not_poison_it() {
+        let failed = panic::catch_unwind(|| set_task_system(|| panic!("failed to create")));
+        assert!(failed.is_err());
+        set_task_system(|| Parallel::new() as Arc<dyn TaskSystem>);
+        set_task_system(|| Parallel::new() as Arc<dyn TaskSystem>);
+        let used = get_task_system() as *const dyn TaskSystem as *const ();
+
+        let late = panic::catch_unwind(|| set_task_system(|| Parallel::new()));
+        assert!(late.is_err());
+        let current = get_task_system() as *const dyn TaskSystem as *const ();
+        assert_eq!(used, current);
+    }
+}
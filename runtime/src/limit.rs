@@ -0,0 +1,140 @@
+//! Defines limits on the number of threads running the tasks launched by some ISPC code.
+//!
+//! A limit is associated with the ISPC code called within `with_max_threads`, and applies
+//! to all tasks it launches, including tasks launched from within those tasks. The
+//! `Parallel` task system won't hand out the tasks to more threads at a time than allowed,
+//! which keeps e.g. a background render from occupying every core while an interactive
+//! kernel also needs to run.
+//!
+//! # Example
+//! ```ignore
+//! // Render the preview on at most 2 threads, leaving the others free
+//! let preview = std::thread::spawn(|| {
+//!     ispc_rt::with_max_threads(2, || unsafe { rt::render(/* ... */) });
+//! });
+//! unsafe { interactive::update(/* ... */) };
+//! preview.join().unwrap();
+//! ```
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// The limit associated with the ISPC code running on this thread, if any
+thread_local!(static CURRENT: RefCell<Option<Arc<ThreadLimit>>> = const { RefCell::new(None) });
+
+/// Limits the number of threads running the tasks of the contexts it's associated with
+#[derive(Debug)]
+pub struct ThreadLimit {
+    max: usize,
+    active: AtomicUsize,
+}
+
+impl ThreadLimit {
+    /// Get the maximum number of threads allowed to run the tasks at a time
+    pub fn max_threads(&self) -> usize {
+        self.max
+    }
+    /// Get the number of threads currently running the tasks, including the thread
+    /// which called `with_max_threads`
+    pub fn active_threads(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+}
+
+/// Restores the previous limit of the thread and releases the calling thread's
+/// slot when dropped, even if the code it was set for panicked.
+struct LimitScope {
+    previous: Option<Arc<ThreadLimit>>,
+    limit: Arc<ThreadLimit>,
+}
+
+impl Drop for LimitScope {
+    fn drop(&mut self) {
+        set_current(self.previous.take());
+        self.limit.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Run `f` with the tasks launched by the ISPC code it calls, including tasks launched from
+/// within those tasks, running on at most `max_threads` threads at a time. The calling thread
+/// counts as one of these since it runs tasks while waiting in `sync`, so with a limit of 1
+/// all tasks are run by the calling thread.
+///
+/// # Panics
+/// Panics if `max_threads` is 0.
+pub fn with_max_threads<R, F: FnOnce() -> R>(max_threads: usize, f: F) -> R {
+    assert!(
+        max_threads > 0,
+        "ispc_rt: the thread limit must be at least 1"
+    );
+    let limit = Arc::new(ThreadLimit {
+        max: max_threads,
+        active: AtomicUsize::new(1),
+    });
+    let _scope = LimitScope {
+        previous: set_current(Some(limit.clone())),
+        limit,
+    };
+    f()
+}
+
+/// Get the limit associated with the code running on this thread
+pub fn current() -> Option<Arc<ThreadLimit>> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+/// Set the limit associated with the code running on this thread, returns the
+/// previously set limit.
+fn set_current(limit: Option<Arc<ThreadLimit>>) -> Option<Arc<ThreadLimit>> {
+    CURRENT.with(|c| c.replace(limit))
+}
+
+/// Restores the previous limit of the thread when dropped
+struct RestoreLimit(Option<Arc<ThreadLimit>>);
+
+impl Drop for RestoreLimit {
+    fn drop(&mut self) {
+        set_current(self.0.take());
+    }
+}
+
+/// Run `f` with `limit` as the current limit of this thread, used by the task
+/// system when running tasks of a context.
+pub(crate) fn run_with<R, F: FnOnce() -> R>(limit: Option<&Arc<ThreadLimit>>, f: F) -> R {
+    let _restore = RestoreLimit(set_current(limit.cloned()));
+    f()
+}
+
+/// Permission for a thread to run tasks under a limit, releases the thread's
+/// slot when dropped.
+pub(crate) struct Permit(Option<Arc<ThreadLimit>>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(ref limit) = self.0 {
+            limit.active.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Try to get a permit for this thread to run tasks under `limit`, returns None if the
+/// maximum number of threads are already running them. A thread which is already running
+/// tasks under the limit, or called `with_max_threads`, holds a slot and always succeeds.
+pub(crate) fn try_acquire(limit: Option<&Arc<ThreadLimit>>) -> Option<Permit> {
+    let limit = match limit {
+        Some(l) => l,
+        None => return Some(Permit(None)),
+    };
+    let holds_slot = CURRENT.with(|c| c.borrow().as_ref().is_some_and(|c| Arc::ptr_eq(c, limit)));
+    if holds_slot {
+        return Some(Permit(None));
+    }
+    limit
+        .active
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+            (active < limit.max).then_some(active + 1)
+        })
+        .ok()
+        .map(|_| Permit(Some(limit.clone())))
+}
@@ -0,0 +1,202 @@
+//! Publishes the health of a `Parallel` task system through the
+//! [`metrics`](https://docs.rs/metrics) facade, so services calling ISPC kernels can
+//! monitor the scheduler with whichever exporter they already use for the rest of their
+//! telemetry, e.g. Prometheus.
+//!
+//! The `MetricsObserver` is registered with the task system as a `TaskObserver` and
+//! updates the following metrics as tasks are launched and run:
+//!
+//! - `ispc_rt_contexts_active` (gauge): contexts which have been created and not synced yet
+//! - `ispc_rt_task_groups_queued` (gauge): task groups launched in contexts which haven't
+//!   been synced yet
+//! - `ispc_rt_tasks_queued` (gauge): tasks launched which haven't started running yet, tasks
+//!   which never run, e.g. as their context was cancelled, are removed when it's synced
+//! - `ispc_rt_workers_busy` (gauge): worker threads currently running a chunk of tasks,
+//!   threads running tasks while syncing aren't counted
+//! - `ispc_rt_tasks_completed` (counter): tasks which have finished running
+//! - `ispc_rt_tasks_per_second` (gauge): tasks completed per second, averaged over the
+//!   last second or so in which tasks were run
+//!
+//! # Example
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! use ispc_rt::metrics::MetricsObserver;
+//!
+//! // Install a metrics recorder, e.g. from metrics-exporter-prometheus, then
+//! let task_sys = ispc_rt::Parallel::builder()
+//!     .name("render")
+//!     .observer(Arc::new(MetricsObserver::with_name("render")))
+//!     .build();
+//! ispc_rt::set_task_system(|| task_sys);
+//! ```
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use metrics::{Counter, Gauge, Label, Unit};
+
+use crate::observer::{ChunkInfo, TaskObserver};
+
+/// How often the tasks per second gauge is updated
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// The work queued in a context, removed from the gauges when it's synced
+#[derive(Default)]
+struct ContextQueue {
+    /// Task groups launched in the context
+    groups: usize,
+    /// Tasks launched in the context which haven't started running yet
+    tasks: usize,
+}
+
+/// A `TaskObserver` which publishes metrics about the task system it's registered with,
+/// see the module documentation for the metrics reported.
+///
+/// The metrics are registered with the recorder installed when the observer is created,
+/// so the recorder should be installed first.
+pub struct MetricsObserver {
+    contexts_active: Gauge,
+    groups_queued: Gauge,
+    tasks_queued: Gauge,
+    workers_busy: Gauge,
+    tasks_completed: Counter,
+    tasks_per_second: Gauge,
+    /// The groups and tasks queued in each context which hasn't been synced yet
+    context_queues: Mutex<HashMap<usize, ContextQueue>>,
+    epoch: Instant,
+    /// Start of the current rate window in nanoseconds since `epoch`
+    window_start: AtomicU64,
+    /// Tasks completed in the current rate window
+    window_tasks: AtomicU64,
+}
+
+impl MetricsObserver {
+    /// Create an observer publishing the metrics without any labels
+    pub fn new() -> MetricsObserver {
+        MetricsObserver::with_labels(Vec::new())
+    }
+    /// Create an observer publishing the metrics with a `task_system` label set to `name`,
+    /// to tell apart the metrics of multiple task systems in the same process.
+    pub fn with_name(name: &str) -> MetricsObserver {
+        MetricsObserver::with_labels(vec![Label::new("task_system", name.to_owned())])
+    }
+    /// Create an observer publishing the metrics with the `labels` passed
+    pub fn with_labels(labels: Vec<Label>) -> MetricsObserver {
+        describe();
+        MetricsObserver {
+            contexts_active: metrics::gauge!("ispc_rt_contexts_active", labels.clone()),
+            groups_queued: metrics::gauge!("ispc_rt_task_groups_queued", labels.clone()),
+            tasks_queued: metrics::gauge!("ispc_rt_tasks_queued", labels.clone()),
+            workers_busy: metrics::gauge!("ispc_rt_workers_busy", labels.clone()),
+            tasks_completed: metrics::counter!("ispc_rt_tasks_completed", labels.clone()),
+            tasks_per_second: metrics::gauge!("ispc_rt_tasks_per_second", labels),
+            context_queues: Mutex::new(HashMap::new()),
+            epoch: Instant::now(),
+            window_start: AtomicU64::new(0),
+            window_tasks: AtomicU64::new(0),
+        }
+    }
+    /// Count the tasks completed towards the rate, and update the rate once the current
+    /// window has passed. Only the thread which ends the window updates the gauge.
+    fn count_completed(&self, tasks: u64) {
+        self.window_tasks.fetch_add(tasks, Ordering::Relaxed);
+        let now = self.epoch.elapsed().as_nanos() as u64;
+        let start = self.window_start.load(Ordering::Relaxed);
+        let elapsed = now.saturating_sub(start);
+        if elapsed >= RATE_WINDOW.as_nanos() as u64
+            && self
+                .window_start
+                .compare_exchange(start, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            let tasks = self.window_tasks.swap(0, Ordering::Relaxed);
+            self.tasks_per_second
+                .set(tasks as f64 / Duration::from_nanos(elapsed).as_secs_f64());
+        }
+    }
+}
+
+impl Default for MetricsObserver {
+    fn default() -> MetricsObserver {
+        MetricsObserver::new()
+    }
+}
+
+impl TaskObserver for MetricsObserver {
+    fn on_context_created(&self, context: usize) {
+        self.context_queues
+            .lock()
+            .unwrap()
+            .insert(context, ContextQueue::default());
+        self.contexts_active.increment(1.0);
+    }
+    fn on_launch(&self, context: usize, tasks: usize) {
+        let mut queues = self.context_queues.lock().unwrap();
+        let queue = queues.entry(context).or_default();
+        queue.groups += 1;
+        queue.tasks += tasks;
+        self.groups_queued.increment(1.0);
+        self.tasks_queued.increment(tasks as f64);
+    }
+    fn on_chunk_start(&self, chunk: &ChunkInfo) {
+        if let Some(queue) = self.context_queues.lock().unwrap().get_mut(&chunk.context) {
+            queue.tasks = queue.tasks.saturating_sub(chunk.tasks.len());
+        }
+        self.tasks_queued.decrement(chunk.tasks.len() as f64);
+        if chunk.thread != 0 {
+            self.workers_busy.increment(1.0);
+        }
+    }
+    fn on_chunk_end(&self, chunk: &ChunkInfo) {
+        if chunk.thread != 0 {
+            self.workers_busy.decrement(1.0);
+        }
+        self.tasks_completed.increment(chunk.tasks.len() as u64);
+        self.count_completed(chunk.tasks.len() as u64);
+    }
+    fn on_sync(&self, context: usize) {
+        let queue = self.context_queues.lock().unwrap().remove(&context);
+        let queue = queue.unwrap_or_default();
+        self.groups_queued.decrement(queue.groups as f64);
+        // The tasks which will never run as they were cancelled or skipped after a panic
+        self.tasks_queued.decrement(queue.tasks as f64);
+        self.contexts_active.decrement(1.0);
+    }
+}
+
+/// Describe the metrics to the installed recorder
+fn describe() {
+    metrics::describe_gauge!(
+        "ispc_rt_contexts_active",
+        Unit::Count,
+        "ISPC task contexts which have been created and not synced yet"
+    );
+    metrics::describe_gauge!(
+        "ispc_rt_task_groups_queued",
+        Unit::Count,
+        "ISPC task groups launched in contexts which haven't been synced yet"
+    );
+    metrics::describe_gauge!(
+        "ispc_rt_tasks_queued",
+        Unit::Count,
+        "ISPC tasks launched which haven't started running yet"
+    );
+    metrics::describe_gauge!(
+        "ispc_rt_workers_busy",
+        Unit::Count,
+        "Task system worker threads running ISPC tasks"
+    );
+    metrics::describe_counter!(
+        "ispc_rt_tasks_completed",
+        Unit::Count,
+        "ISPC tasks which have finished running"
+    );
+    metrics::describe_gauge!(
+        "ispc_rt_tasks_per_second",
+        Unit::CountPerSecond,
+        "ISPC tasks completed per second"
+    );
+}
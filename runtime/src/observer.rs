@@ -0,0 +1,59 @@
+//! Defines the `TaskObserver` trait used to hook into the execution of tasks by the
+//! `Parallel` task system, e.g. to gather custom metrics, implement watchdogs or forward
+//! events to an application's own profiler.
+
+use std::ops::Range;
+
+/// Describes a chunk of tasks being executed on some thread
+#[derive(Clone, Debug)]
+pub struct ChunkInfo {
+    /// Id of the context the chunk's task group was launched in
+    pub context: usize,
+    /// The tasks executed by the chunk, see `Chunk::tasks`
+    pub tasks: Range<i32>,
+    /// Id of the thread running the chunk. Worker threads start at 1, threads
+    /// outside of the task system which run chunks while syncing are 0.
+    pub thread: usize,
+}
+
+/// Trait to be implemented to observe the tasks run by a `Parallel` task system, see
+/// `ParallelBuilder::observer`.
+///
+/// The methods are called on the threads creating, running and syncing the tasks, so they
+/// should return quickly to not hold up the task system. All methods do nothing by default.
+///
+/// # Example
+/// ```no_run
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+///
+/// use ispc_rt::observer::{ChunkInfo, TaskObserver};
+///
+/// #[derive(Default)]
+/// struct TaskCounter(AtomicUsize);
+///
+/// impl TaskObserver for TaskCounter {
+///     fn on_chunk_end(&self, chunk: &ChunkInfo) {
+///         self.0.fetch_add(chunk.tasks.len(), Ordering::Relaxed);
+///     }
+/// }
+///
+/// let counter = Arc::new(TaskCounter::default());
+/// let task_sys = ispc_rt::Parallel::builder().observer(counter.clone()).build();
+/// ispc_rt::set_task_system(|| task_sys);
+/// ```
+pub trait TaskObserver: Send + Sync {
+    /// Called when a new context is created by the first `alloc` of an ISPC function
+    /// launching tasks, on the thread calling into ISPC.
+    fn on_context_created(&self, _context: usize) {}
+    /// Called when a group of `tasks` tasks is launched in a context, on the thread
+    /// launching them.
+    fn on_launch(&self, _context: usize, _tasks: usize) {}
+    /// Called on the thread about to run a chunk of tasks
+    fn on_chunk_start(&self, _chunk: &ChunkInfo) {}
+    /// Called on the thread which ran a chunk of tasks once they've returned
+    fn on_chunk_end(&self, _chunk: &ChunkInfo) {}
+    /// Called once a context has been synchronized, after all its tasks completed and
+    /// before it's destroyed, on the thread which called `sync`.
+    fn on_sync(&self, _context: usize) {}
+}
@@ -0,0 +1,58 @@
+//! Helpers to pad buffer lengths and image rows to a multiple of the gang width, so
+//! kernels can process whole gangs without a scalar loop for the remaining elements.
+//!
+//! The gang width is the `programCount` of the ISPC target the kernel is compiled for,
+//! e.g. 8 for `avx2-i32x8`. When compiling for multiple targets pad to the largest gang
+//! width, or to `MAX_GANG_WIDTH` to be safe on any target.
+//!
+//! # Example
+//! ```
+//! use ispc_rt::padding::{pad_len, padded_buffer, padded_image};
+//!
+//! assert_eq!(pad_len(100, 8), 104);
+//!
+//! // The buffer holds 104 elements, the last 4 are padding set to 0
+//! let buf = padded_buffer(100, 8, 0.0f32);
+//! assert_eq!(buf.len(), 104);
+//!
+//! // Each row of the image is padded to 16 elements
+//! let (img, pitch) = padded_image(10, 4, 16, 0u8);
+//! assert_eq!(pitch, 16);
+//! assert_eq!(img.len(), 16 * 4);
+//! ```
+
+use crate::aligned::AlignedVec;
+
+/// The largest gang width of the ISPC targets, e.g. `avx512skx-x64`
+pub const MAX_GANG_WIDTH: usize = 64;
+
+/// Round `len` up to a multiple of `gang_width`
+pub const fn pad_len(len: usize, gang_width: usize) -> usize {
+    assert!(gang_width > 0, "gang width must not be zero");
+    len.div_ceil(gang_width) * gang_width
+}
+
+/// Get the pitch, i.e. the number of elements from the start of one row to the next, of
+/// an image `width` elements wide with each row padded to a multiple of `gang_width`
+pub const fn padded_pitch(width: usize, gang_width: usize) -> usize {
+    pad_len(width, gang_width)
+}
+
+/// Create an aligned buffer for `len` elements padded to a multiple of `gang_width`,
+/// with all elements, including the padding, set to `value`
+pub fn padded_buffer<T: Clone>(len: usize, gang_width: usize, value: T) -> AlignedVec<T> {
+    AlignedVec::from_elem(value, pad_len(len, gang_width))
+}
+
+/// Create an aligned buffer for an image of `width` by `height` elements with each row
+/// padded to a multiple of `gang_width`, with all elements set to `value`. Returns the
+/// buffer and the pitch of its rows in elements.
+pub fn padded_image<T: Clone>(
+    width: usize,
+    height: usize,
+    gang_width: usize,
+    value: T,
+) -> (AlignedVec<T>, usize) {
+    let pitch = padded_pitch(width, gang_width);
+    (AlignedVec::from_elem(value, pitch * height), pitch)
+}
@@ -0,0 +1,180 @@
+//! Routes the output of ISPC's `print()` through a handler in Rust instead of writing
+//! it directly to stdout, where the output of tasks running on different threads
+//! gets interleaved with each other and with the output of the application.
+//!
+//! ISPC code built with `Config::route_print` calls `ispc_rt_fputs` and `ispc_rt_fflush`
+//! instead of the C library to write its output. Output is buffered per thread and
+//! passed to the handler one line at a time, optionally prefixed with the task or
+//! thread which printed it. Without a handler set the lines are logged through the
+//! `log` crate with the `ispc` target when the `log` feature is enabled, or written to
+//! stdout otherwise.
+//!
+//! # Example
+//! ```
+//! use ispc_rt::print::{self, PrintPrefix};
+//!
+//! print::set_print_prefix(PrintPrefix::Task);
+//! print::set_print_handler(|line| eprintln!("ispc: {line}"));
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::io::Write;
+use std::sync::atomic::{self, AtomicU8};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+/// A handler receiving each line printed by ISPC code, without the trailing newline
+type PrintHandler = dyn Fn(&str) + Send + Sync;
+
+static PRINT_HANDLER: RwLock<Option<Arc<PrintHandler>>> = RwLock::new(None);
+static PRINT_PREFIX: AtomicU8 = AtomicU8::new(PrintPrefix::None as u8);
+
+thread_local! {
+    /// Output printed on this thread which hasn't ended in a newline or been flushed yet
+    static LINE_BUFFER: RefCell<String> = const { RefCell::new(String::new()) };
+    /// The task running on this thread and the index of the thread in the task system,
+    /// set while a task is executing: (thread, task, total tasks)
+    static CURRENT_TASK: Cell<Option<(i32, i32, i32)>> = const { Cell::new(None) };
+}
+
+/// The prefix to put in front of each line printed by ISPC code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PrintPrefix {
+    /// Pass the lines as printed
+    None,
+    /// Prefix lines printed by tasks with the task index and count, e.g. `[task 3/16] `.
+    /// Lines printed outside of tasks aren't prefixed.
+    Task,
+    /// Prefix lines with the index of the task system thread printing them, e.g.
+    /// `[thread 2] `, or the name of the thread when printed outside of tasks.
+    Thread,
+}
+
+/// Set the handler to call with each line printed by ISPC code, replacing the default
+/// one. The handler is called from the thread the ISPC code runs on, so it may be
+/// called from multiple threads at once.
+pub fn set_print_handler<F: Fn(&str) + Send + Sync + 'static>(handler: F) {
+    *PRINT_HANDLER.write().unwrap() = Some(Arc::new(handler));
+}
+
+/// Restore the default handler, which logs the lines with the `log` feature enabled
+/// or writes them to stdout otherwise
+pub fn reset_print_handler() {
+    *PRINT_HANDLER.write().unwrap() = None;
+}
+
+/// Set the prefix to put in front of each line printed by ISPC code
+pub fn set_print_prefix(prefix: PrintPrefix) {
+    PRINT_PREFIX.store(prefix as u8, atomic::Ordering::Relaxed);
+}
+
+/// Get the prefix put in front of each line printed by ISPC code
+pub fn print_prefix() -> PrintPrefix {
+    match PRINT_PREFIX.load(atomic::Ordering::Relaxed) {
+        1 => PrintPrefix::Task,
+        2 => PrintPrefix::Thread,
+        _ => PrintPrefix::None,
+    }
+}
+
+/// Mark the task running on the current thread for the prefixes of its output, or
+/// clear it with `None` when the task finishes. Called by the task system.
+pub(crate) fn set_current_task(task: Option<(i32, i32, i32)>) {
+    CURRENT_TASK.with(|t| t.set(task));
+}
+
+/// Get the task running on the current thread: (thread, task, total tasks)
+pub(crate) fn current_task() -> Option<(i32, i32, i32)> {
+    CURRENT_TASK.with(|t| t.get())
+}
+
+/// Get the prefix for lines printed on the current thread
+fn line_prefix() -> String {
+    match (print_prefix(), current_task()) {
+        (PrintPrefix::Task, Some((_, task, total))) => format!("[task {task}/{total}] "),
+        (PrintPrefix::Thread, Some((thread, _, _))) => format!("[thread {thread}] "),
+        (PrintPrefix::Thread, None) => match thread::current().name() {
+            Some(name) => format!("[{name}] "),
+            None => format!("[{:?}] ", thread::current().id()),
+        },
+        _ => String::new(),
+    }
+}
+
+/// Pass a complete line to the handler
+fn emit(line: &str) {
+    let prefix = line_prefix();
+    let line = if prefix.is_empty() {
+        line.to_owned()
+    } else {
+        prefix + line
+    };
+    let handler = PRINT_HANDLER.read().unwrap().clone();
+    match handler {
+        Some(h) => h(&line),
+        None => default_handler(&line),
+    }
+}
+
+#[cfg(feature = "log")]
+fn default_handler(line: &str) {
+    log::info!(target: "ispc", "{line}");
+}
+
+#[cfg(not(feature = "log"))]
+fn default_handler(line: &str) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{line}");
+}
+
+/// Buffer the output `s` of the current thread, passing each completed line on to
+/// the handler
+pub fn write(s: &str) {
+    let lines = LINE_BUFFER.with(|b| {
+        let mut buf = b.borrow_mut();
+        buf.push_str(s);
+        match buf.rfind('\n') {
+            Some(end) => {
+                let rest = buf.split_off(end + 1);
+                Some(std::mem::replace(&mut *buf, rest))
+            }
+            None => None,
+        }
+    });
+    if let Some(lines) = lines {
+        for l in lines.lines() {
+            emit(l);
+        }
+    }
+}
+
+/// Pass output of the current thread which doesn't end in a newline on to the handler
+pub fn flush() {
+    let line = LINE_BUFFER.with(|b| std::mem::take(&mut *b.borrow_mut()));
+    if !line.is_empty() {
+        emit(&line);
+    }
+}
+
+/// Replaces `fputs` in ISPC objects built with `Config::route_print`. The stream is
+/// ignored, ISPC only prints to stdout.
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn ispc_rt_fputs(s: *const c_char, _stream: *mut c_void) -> c_int {
+    if s.is_null() {
+        return -1;
+    }
+    write(&CStr::from_ptr(s).to_string_lossy());
+    0
+}
+
+/// Replaces `fflush` in ISPC objects built with `Config::route_print`
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn ispc_rt_fflush(_stream: *mut c_void) -> c_int {
+    flush();
+    let _ = std::io::stdout().flush();
+    0
+}
@@ -0,0 +1,356 @@
+//! Defines the recording and replaying of the schedule used by the `Parallel` task system,
+//! i.e. which thread ran each chunk of tasks and in what order.
+//!
+//! Replaying a recorded schedule hands out the chunks to the same threads in the same order
+//! as during the recording, which makes rare data races or numerical differences between runs
+//! of task kernels reproducible, e.g. under a debugger.
+//!
+//! Contexts are identified by a key derived from the chunk of tasks that created them, so the
+//! ISPC code must launch the same tasks with the same arguments in both runs, and the task
+//! system must be built with the same number of threads and chunk size. Calling ISPC code which
+//! launches tasks from multiple threads at the same time is not supported. If the replay finds
+//! a chunk that doesn't match the recording it reports the divergence and goes back to
+//! scheduling chunks normally.
+//!
+//! # Example
+//! ```no_run
+//! use ispc_rt::replay::{Schedule, ScheduleRecorder};
+//! use ispc_rt::Parallel;
+//!
+//! // Record the schedule of a run which produced bad results
+//! let recorder = ScheduleRecorder::new();
+//! let task_sys = Parallel::builder().record_schedule(recorder.clone()).build();
+//! // ... call ISPC code which launches tasks ...
+//! recorder.save("schedule.txt").unwrap();
+//!
+//! // Later, replay it to reproduce the problem
+//! let schedule = Schedule::load("schedule.txt").unwrap();
+//! let task_sys = Parallel::builder().replay_schedule(schedule).build();
+//! ```
+
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Header written at the start of a schedule file
+const SCHEDULE_HEADER: &str = "ispc_rt schedule v1";
+
+/// A chunk of tasks handed out to a thread
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScheduleEvent {
+    /// Key of the context the chunk's task group was launched in, see `Context::replay_key`
+    pub context: u64,
+    /// Index of the chunk's task group in the order they were launched in the context
+    pub group: usize,
+    /// The tasks in the chunk, see `Chunk::tasks`
+    pub tasks: Range<i32>,
+    /// Id of the thread which ran the chunk. Worker threads start at 1, threads
+    /// outside of the task system which ran chunks while syncing are 0.
+    pub thread: usize,
+}
+
+/// The chunks handed out by a task system, in the order they were handed out
+#[derive(Clone, Debug, Default)]
+pub struct Schedule {
+    events: Vec<ScheduleEvent>,
+}
+
+impl Schedule {
+    /// Get the events in the schedule
+    pub fn events(&self) -> &[ScheduleEvent] {
+        &self.events
+    }
+    /// Write the schedule as text, with one event per line
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "{SCHEDULE_HEADER}")?;
+        for e in self.events.iter() {
+            writeln!(
+                out,
+                "{} {} {} {} {}",
+                e.thread, e.context, e.group, e.tasks.start, e.tasks.end
+            )?;
+        }
+        Ok(())
+    }
+    /// Read a schedule previously written with `Schedule::write`
+    pub fn read<R: BufRead>(input: R) -> io::Result<Schedule> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+        let mut lines = input.lines();
+        match lines.next() {
+            Some(Ok(ref l)) if l == SCHEDULE_HEADER => {}
+            Some(Err(e)) => return Err(e),
+            _ => return Err(invalid("not an ispc_rt schedule")),
+        }
+        let mut events = Vec::new();
+        for line in lines {
+            let line = line?;
+            let fields = line
+                .split_whitespace()
+                .map(|f| f.parse::<u64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid("invalid schedule event"))?;
+            match fields[..] {
+                [thread, context, group, start, end] => events.push(ScheduleEvent {
+                    context,
+                    group: group as usize,
+                    tasks: start as i32..end as i32,
+                    thread: thread as usize,
+                }),
+                [] => {}
+                _ => return Err(invalid("invalid schedule event")),
+            }
+        }
+        Ok(Schedule { events })
+    }
+    /// Save the schedule to a file at `path`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out)?;
+        out.flush()
+    }
+    /// Load a schedule from a file at `path` previously written by `Schedule::save`
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Schedule> {
+        Schedule::read(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Records the schedule of the task system it's attached to, see
+/// `ParallelBuilder::record_schedule`.
+#[derive(Debug, Default)]
+pub struct ScheduleRecorder {
+    events: Mutex<Vec<ScheduleEvent>>,
+}
+
+impl ScheduleRecorder {
+    /// Create a new recorder
+    pub fn new() -> Arc<ScheduleRecorder> {
+        Arc::new(ScheduleRecorder::default())
+    }
+    /// Get a copy of the schedule recorded so far
+    pub fn schedule(&self) -> Schedule {
+        Schedule {
+            events: self.events.lock().unwrap().clone(),
+        }
+    }
+    /// Discard the events recorded so far
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+    /// Save the schedule recorded so far to a file at `path`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.schedule().save(path)
+    }
+    /// Take a chunk with `take` and record it, the lock is held while taking the chunk
+    /// so the events are recorded in the order the chunks are handed out.
+    pub(crate) fn record<C, F, G>(&self, take: F, describe: G) -> Option<C>
+    where
+        F: FnOnce() -> Option<C>,
+        G: FnOnce(&C) -> ScheduleEvent,
+    {
+        let mut events = self.events.lock().unwrap();
+        let chunk = take()?;
+        events.push(describe(&chunk));
+        Some(chunk)
+    }
+}
+
+/// Tracks the progress through a schedule being replayed
+#[derive(Debug)]
+pub(crate) struct Replayer {
+    schedule: Schedule,
+    next: AtomicUsize,
+    diverged: AtomicBool,
+}
+
+impl Replayer {
+    pub(crate) fn new(schedule: Schedule) -> Replayer {
+        Replayer {
+            schedule,
+            next: AtomicUsize::new(0),
+            diverged: AtomicBool::new(false),
+        }
+    }
+    /// Check if the schedule is still being replayed, returns false once the whole schedule
+    /// has been replayed or the replay diverged
+    pub(crate) fn is_active(&self) -> bool {
+        !self.diverged.load(Ordering::SeqCst)
+            && self.next.load(Ordering::SeqCst) < self.schedule.events.len()
+    }
+    /// Get the next event to replay and its index
+    pub(crate) fn peek(&self) -> Option<(usize, &ScheduleEvent)> {
+        let i = self.next.load(Ordering::SeqCst);
+        self.schedule.events.get(i).map(|e| (i, e))
+    }
+    /// Claim the event at index `i` for the calling thread, returns false if another
+    /// thread with the same id claimed it first
+    pub(crate) fn claim(&self, i: usize) -> bool {
+        self.next
+            .compare_exchange(i, i + 1, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+    /// Check if the replay diverged from the schedule
+    #[cfg(test)]
+    pub(crate) fn diverged(&self) -> bool {
+        self.diverged.load(Ordering::SeqCst)
+    }
+    /// Report that the chunk handed out doesn't match the event at index `i` and stop replaying
+    pub(crate) fn diverge(&self, i: usize, tasks: Option<Range<i32>>) {
+        if !self.diverged.swap(true, Ordering::SeqCst) {
+            eprintln!(
+                "ispc_rt: schedule replay diverged at event {i}, expected {:?} but got tasks {tasks:?}, \
+                 continuing without replaying",
+                self.schedule.events[i]
+            );
+        }
+    }
+}
+
+/// The chunk running on this thread, which the contexts created by its tasks are keyed by
+#[derive(Clone, Copy)]
+struct ChunkScope {
+    /// Key of the context the chunk's group was launched in
+    context: u64,
+    /// Position of the chunk's group in its context
+    group: usize,
+    /// First task of the chunk
+    start: i32,
+    /// Number of contexts created by the chunk's tasks so far
+    created: u64,
+}
+
+thread_local!(static CHUNK_SCOPE: Cell<Option<ChunkScope>> = const { Cell::new(None) });
+
+/// Mix `value` into `hash` to derive context keys
+fn mix(hash: u64, value: u64) -> u64 {
+    // The finalizer of splitmix64
+    let mut z = (hash ^ value).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Derive the key of a new context created on this thread. Contexts created by a task are
+/// keyed by the chunk running the task and the number of contexts it created before,
+/// other contexts by the number of such contexts created before from `top_level`.
+pub(crate) fn next_context_key(top_level: &AtomicU64) -> u64 {
+    CHUNK_SCOPE.with(|s| match s.get() {
+        Some(scope) => {
+            s.set(Some(ChunkScope {
+                created: scope.created + 1,
+                ..scope
+            }));
+            let chunk = mix(mix(scope.context, scope.group as u64), scope.start as u64);
+            mix(mix(chunk, scope.created), 1)
+        }
+        None => mix(top_level.fetch_add(1, Ordering::SeqCst), 0),
+    })
+}
+
+/// Run `f` for the chunk starting at task `start` of the group at position `group` in
+/// the context with key `context`, so the contexts created by its tasks are keyed by it.
+pub(crate) fn run_in_chunk<R, F: FnOnce() -> R>(context: u64, group: usize, start: i32, f: F) -> R {
+    struct RestoreScope(Option<ChunkScope>);
+    impl Drop for RestoreScope {
+        fn drop(&mut self) {
+            CHUNK_SCOPE.with(|s| s.set(self.0));
+        }
+    }
+    let scope = ChunkScope {
+        context,
+        group,
+        start,
+        created: 0,
+    };
+    let _restore = RestoreScope(CHUNK_SCOPE.with(|s| s.replace(Some(scope))));
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule() -> Schedule {
+        let event = |thread, context, group, tasks| ScheduleEvent {
+            context,
+            group,
+            tasks,
+            thread,
+        };
+        Schedule {
+            events: vec![
+                event(0, 17, 0, 0..8),
+                event(2, 17, 0, 8..16),
+                event(1, u64::MAX, 3, 0..5),
+            ],
+        }
+    }
+
+    #[test]
+    fn schedule_round_trips() {
+        let mut text = Vec::new();
+        schedule().write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(
+            text,
+            format!(
+                "{SCHEDULE_HEADER}\n0 17 0 0 8\n2 17 0 8 16\n1 {} 3 0 5\n",
+                u64::MAX
+            )
+        );
+        let read = Schedule::read(text.as_bytes()).unwrap();
+        assert_eq!(read.events(), schedule().events());
+    }
+
+    #[test]
+    fn read_rejects_invalid_schedules() {
+        let invalid = |text: &str| {
+            let e = Schedule::read(text.as_bytes()).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{text:?}");
+        };
+        invalid("");
+        invalid("0 17 0 0 8\n");
+        invalid(&format!("{SCHEDULE_HEADER}\n0 17 0 0\n"));
+        invalid(&format!("{SCHEDULE_HEADER}\n0 17 0 0 8 9\n"));
+        invalid(&format!("{SCHEDULE_HEADER}\n0 17 x 0 8\n"));
+        // Blank lines are skipped
+        let read = Schedule::read(format!("{SCHEDULE_HEADER}\n\n0 17 0 0 8\n").as_bytes());
+        assert_eq!(read.unwrap().events().len(), 1);
+    }
+
+    #[test]
+    fn replayer_stops_once_diverged() {
+        let replayer = Replayer::new(schedule());
+        assert!(replayer.is_active());
+        let (i, event) = replayer.peek().unwrap();
+        assert_eq!((i, event.thread), (0, 0));
+        assert!(replayer.claim(i));
+        // Another thread can't claim the same event
+        assert!(!replayer.claim(i));
+        assert!(replayer.is_active() && !replayer.diverged());
+        replayer.diverge(1, Some(8..12));
+        assert!(!replayer.is_active() && replayer.diverged());
+    }
+
+    #[test]
+    fn context_keys_depend_on_the_chunk() {
+        let top_level = AtomicU64::new(0);
+        let first = next_context_key(&top_level);
+        let second = next_context_key(&top_level);
+        assert_ne!(first, second);
+        let key = |group, start| run_in_chunk(first, group, start, || next_context_key(&top_level));
+        assert_eq!(key(0, 0), key(0, 0));
+        assert_ne!(key(0, 0), key(1, 0));
+        assert_ne!(key(0, 0), key(0, 8));
+        // Each context created by the same chunk gets its own key
+        let (a, b) = run_in_chunk(first, 0, 0, || {
+            (next_context_key(&top_level), next_context_key(&top_level))
+        });
+        assert_ne!(a, b);
+        // The keys of nested contexts don't use up top level keys
+        assert_eq!(top_level.load(Ordering::SeqCst), 2);
+    }
+}
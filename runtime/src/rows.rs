@@ -0,0 +1,144 @@
+//! Runs kernels which don't launch tasks of their own in parallel, by splitting the
+//! image or buffer they write into chunks of rows and calling the kernel once per chunk
+//! on the threads of the task system.
+//!
+//! The chunks are launched as a task group on the task system set with `set_task_system`,
+//! or the default `Parallel` one, so they share its threads and respect the thread limits
+//! and cancellation tokens of the calling code like tasks launched from ISPC.
+//!
+//! # Example
+//! ```
+//! let (width, height) = (64, 48);
+//! let mut framebuffer = vec![0.0f32; width * height * 3];
+//! ispc_rt::for_each_row_chunk(&mut framebuffer, height, |chunk, y0, y1| {
+//!     // e.g. unsafe { kernel::shade_rows(chunk.as_mut_ptr(), width as i32, y0 as i32, y1 as i32) }
+//!     assert_eq!(chunk.len(), (y1 - y0) * width * 3);
+//!     for (y, row) in (y0..y1).zip(chunk.chunks_mut(width * 3)) {
+//!         row.fill(y as f32);
+//!     }
+//! });
+//! assert_eq!(framebuffer[width * 3 * 47], 47.0);
+//! ```
+
+use std::any::Any;
+use std::ffi::c_int;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Mutex;
+use std::thread;
+
+use crate::task::ISPCTaskFn;
+
+/// The chunks of a buffer to run a function on, passed to the tasks
+struct RowChunks<'a, T, F> {
+    data: *mut T,
+    row_len: usize,
+    height: usize,
+    rows_per_chunk: usize,
+    f: &'a F,
+    /// The panic of the first chunk which panicked, resumed once all chunks are done
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+/// Call `f` on chunks of the rows of the image `buf`, which holds `height` rows, in
+/// parallel on the threads of the task system. `f` is passed the elements of the rows
+/// in the chunk and the range of rows `[y0, y1)` it holds.
+///
+/// The rows are split into a few chunks for each thread the system can run, to balance
+/// the load when some rows take longer than others. Use `for_each_row_chunk_sized` to
+/// set the size of the chunks.
+///
+/// # Panics
+/// Panics if the length of `buf` isn't a multiple of `height`. If `f` panics the panic
+/// is resumed on the calling thread once all chunks are done.
+pub fn for_each_row_chunk<T, F>(buf: &mut [T], height: usize, f: F)
+where
+    T: Send,
+    F: Fn(&mut [T], usize, usize) + Sync,
+{
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let rows_per_chunk = height.div_ceil(threads * 4).max(1);
+    for_each_row_chunk_sized(buf, height, rows_per_chunk, f);
+}
+
+/// Call `f` on chunks of `rows_per_chunk` rows of the image `buf`, which holds `height`
+/// rows, in parallel on the threads of the task system. See `for_each_row_chunk`.
+///
+/// # Panics
+/// Panics if the length of `buf` isn't a multiple of `height` or `rows_per_chunk` is 0.
+pub fn for_each_row_chunk_sized<T, F>(buf: &mut [T], height: usize, rows_per_chunk: usize, f: F)
+where
+    T: Send,
+    F: Fn(&mut [T], usize, usize) + Sync,
+{
+    assert!(rows_per_chunk > 0, "ispc_rt: row chunks must not be empty");
+    if height == 0 {
+        assert!(
+            buf.is_empty(),
+            "ispc_rt: buffer of an image with no rows must be empty"
+        );
+        return;
+    }
+    assert!(
+        buf.len().is_multiple_of(height),
+        "ispc_rt: buffer of {} elements doesn't hold {height} rows of the same length",
+        buf.len()
+    );
+    let chunks = height.div_ceil(rows_per_chunk);
+    let count = c_int::try_from(chunks).expect("ispc_rt: too many row chunks to launch");
+    let job = RowChunks {
+        data: buf.as_mut_ptr(),
+        row_len: buf.len() / height,
+        height,
+        rows_per_chunk,
+        f: &f,
+        panic: Mutex::new(None),
+    };
+    let task_fn: ISPCTaskFn = row_chunk_task::<T, F>;
+    unsafe {
+        let task_sys = crate::get_task_system();
+        let mut handle = ptr::null_mut();
+        // Pass the job to the tasks through the parameter block, as ISPC does
+        let params = task_sys.alloc(
+            &mut handle,
+            mem::size_of::<*const RowChunks<T, F>>() as i64,
+            mem::align_of::<*const RowChunks<T, F>>() as i32,
+        );
+        *(params as *mut *const RowChunks<T, F>) = &job;
+        task_sys.launch(&mut handle, task_fn, params, count, 1, 1);
+        task_sys.sync(handle);
+    }
+    if let Some(p) = job.panic.into_inner().unwrap() {
+        panic::resume_unwind(p);
+    }
+}
+
+/// Run the function on the chunk of rows with the task index
+extern "C" fn row_chunk_task<T, F>(
+    data: *mut libc::c_void,
+    _thread_idx: c_int,
+    _thread_cnt: c_int,
+    task_idx: c_int,
+    _task_cnt: c_int,
+    _task_idx0: c_int,
+    _task_idx1: c_int,
+    _task_idx2: c_int,
+    _task_cnt0: c_int,
+    _task_cnt1: c_int,
+    _task_cnt2: c_int,
+) where
+    T: Send,
+    F: Fn(&mut [T], usize, usize) + Sync,
+{
+    let job = unsafe { &**(data as *const *const RowChunks<T, F>) };
+    let y0 = task_idx as usize * job.rows_per_chunk;
+    let y1 = (y0 + job.rows_per_chunk).min(job.height);
+    // Each task gets its own rows of the buffer
+    let chunk = unsafe {
+        std::slice::from_raw_parts_mut(job.data.add(y0 * job.row_len), (y1 - y0) * job.row_len)
+    };
+    if let Err(p) = panic::catch_unwind(AssertUnwindSafe(|| (job.f)(chunk, y0, y1))) {
+        job.panic.lock().unwrap().get_or_insert(p);
+    }
+}
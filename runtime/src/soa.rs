@@ -0,0 +1,141 @@
+//! Defines Structure-of-Arrays containers for passing streams of small vectors to ISPC
+//! kernels, which usually want each component in its own array to load a full gang
+//! of values at once, along with the conversions from and to Array-of-Structures data.
+//!
+//! Each component is stored in an `AlignedVec`, so the arrays passed to the kernel are
+//! aligned as well.
+//!
+//! # Example
+//! ```
+//! use ispc_rt::Soa3;
+//!
+//! let points = [[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]];
+//! let mut soa: Soa3<f32> = Soa3::from_aos(&points);
+//! assert_eq!(soa.component(0), &[1.0, 4.0]);
+//! assert_eq!(soa.component(2), &[3.0, 6.0]);
+//!
+//! // Pass the streams to a kernel taking `uniform float xs[], uniform float ys[], ...`
+//! let (xs, ys, zs) = (soa.as_mut_ptr(0), soa.as_mut_ptr(1), soa.as_mut_ptr(2));
+//!
+//! // Structs are converted through arrays of their components
+//! struct Point { x: f32, y: f32, z: f32 }
+//! let soa: Soa3<f32> = [Point { x: 1.0, y: 2.0, z: 3.0 }]
+//!     .iter()
+//!     .map(|p| [p.x, p.y, p.z])
+//!     .collect();
+//! assert_eq!(soa.to_aos(), vec![[1.0, 2.0, 3.0]]);
+//! ```
+
+use alloc::vec::Vec;
+use core::array;
+
+use crate::aligned::AlignedVec;
+
+/// A Structure-of-Arrays container for vectors of `N` components of type `T`, storing
+/// each component in its own aligned array
+#[derive(Clone, Debug, PartialEq)]
+pub struct Soa<T, const N: usize> {
+    components: [AlignedVec<T>; N],
+}
+
+/// Streams of two component vectors, e.g. 2D points
+pub type Soa2<T> = Soa<T, 2>;
+/// Streams of three component vectors, e.g. 3D points or RGB colors
+pub type Soa3<T> = Soa<T, 3>;
+/// Streams of four component vectors, e.g. RGBA colors
+pub type Soa4<T> = Soa<T, 4>;
+
+impl<T: Copy, const N: usize> Soa<T, N> {
+    /// Create an empty container
+    pub fn new() -> Soa<T, N> {
+        Soa {
+            components: array::from_fn(|_| AlignedVec::new()),
+        }
+    }
+    /// Create an empty container with space for `capacity` vectors
+    pub fn with_capacity(capacity: usize) -> Soa<T, N> {
+        Soa {
+            components: array::from_fn(|_| AlignedVec::with_capacity(capacity)),
+        }
+    }
+    /// Convert Array-of-Structures data to Structure-of-Arrays
+    pub fn from_aos(aos: &[[T; N]]) -> Soa<T, N> {
+        let mut soa = Soa::with_capacity(aos.len());
+        for v in aos {
+            soa.push(*v);
+        }
+        soa
+    }
+    /// Convert the data back to Array-of-Structures
+    pub fn to_aos(&self) -> Vec<[T; N]> {
+        (0..self.len()).map(|i| self.get(i)).collect()
+    }
+    /// Get the number of vectors stored
+    pub fn len(&self) -> usize {
+        self.components.first().map_or(0, |c| c.len())
+    }
+    /// Check if no vectors are stored
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Append the vector `v`
+    pub fn push(&mut self, v: [T; N]) {
+        for (c, x) in self.components.iter_mut().zip(v) {
+            c.push(x);
+        }
+    }
+    /// Get the vector at index `i`, panics if it's out of bounds
+    pub fn get(&self, i: usize) -> [T; N] {
+        array::from_fn(|c| self.components[c][i])
+    }
+    /// Set the vector at index `i`, panics if it's out of bounds
+    pub fn set(&mut self, i: usize, v: [T; N]) {
+        for (c, x) in self.components.iter_mut().zip(v) {
+            c[i] = x;
+        }
+    }
+    /// Get the array of the component `c`, e.g. `0` for the x coordinates
+    pub fn component(&self, c: usize) -> &[T] {
+        &self.components[c]
+    }
+    /// Get the mutable array of the component `c`
+    pub fn component_mut(&mut self, c: usize) -> &mut [T] {
+        &mut self.components[c]
+    }
+    /// Get a pointer to the array of the component `c`, to pass to ISPC
+    pub fn as_ptr(&self, c: usize) -> *const T {
+        self.components[c].as_ptr()
+    }
+    /// Get a mutable pointer to the array of the component `c`, to pass to ISPC
+    pub fn as_mut_ptr(&mut self, c: usize) -> *mut T {
+        self.components[c].as_mut_ptr()
+    }
+    /// Remove all vectors, keeping the allocations
+    pub fn clear(&mut self) {
+        for c in self.components.iter_mut() {
+            c.clear();
+        }
+    }
+}
+
+impl<T: Copy, const N: usize> Default for Soa<T, N> {
+    fn default() -> Soa<T, N> {
+        Soa::new()
+    }
+}
+
+impl<T: Copy, const N: usize> Extend<[T; N]> for Soa<T, N> {
+    fn extend<I: IntoIterator<Item = [T; N]>>(&mut self, iter: I) {
+        for v in iter {
+            self.push(v);
+        }
+    }
+}
+
+impl<T: Copy, const N: usize> FromIterator<[T; N]> for Soa<T, N> {
+    fn from_iter<I: IntoIterator<Item = [T; N]>>(iter: I) -> Soa<T, N> {
+        let mut soa = Soa::new();
+        soa.extend(iter);
+        soa
+    }
+}
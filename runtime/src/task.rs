@@ -4,9 +4,14 @@
 use libc;
 
 use std::cmp;
+use std::ffi::c_int;
 use std::iter::Iterator;
-use std::sync::atomic::{self, AtomicPtr, AtomicUsize};
+use std::sync::atomic::{self, AtomicBool, AtomicPtr, AtomicUsize};
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+use crate::cancel::CancellationToken;
+use crate::limit::ThreadLimit;
 
 /// A pointer to an ISPC task function.
 ///
@@ -19,18 +24,35 @@ use std::sync::{Arc, Mutex, RwLock};
 /// ```
 pub type ISPCTaskFn = extern "C" fn(
     data: *mut libc::c_void,
-    thread_idx: libc::c_int,
-    thread_cnt: libc::c_int,
-    task_idx: libc::c_int,
-    task_cnt: libc::c_int,
-    task_idx0: libc::c_int,
-    task_idx1: libc::c_int,
-    task_idx2: libc::c_int,
-    task_cnt0: libc::c_int,
-    task_cnt1: libc::c_int,
-    task_cnt2: libc::c_int,
+    thread_idx: c_int,
+    thread_cnt: c_int,
+    task_idx: c_int,
+    task_cnt: c_int,
+    task_idx0: c_int,
+    task_idx1: c_int,
+    task_idx2: c_int,
+    task_cnt0: c_int,
+    task_cnt1: c_int,
+    task_cnt2: c_int,
 );
 
+/// The order in which the tasks of a group launched over a 2D or 3D grid,
+/// e.g. `launch[count0, count1]`, are handed out to threads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkOrder {
+    /// Hand out tasks in the order of their task index, `x + y * count0 + z * count0 * count1`
+    /// (the default).
+    Linear,
+    /// Hand out the tasks of each `width x height` tile of the grid together, moving across
+    /// the tiles in linear order. Neighboring tasks, e.g. tiles of an image, tend to run on
+    /// the same thread and can share its cache.
+    Tiled { width: i32, height: i32 },
+    /// Hand out tasks along a Morton (Z-order) curve through the grid, which keeps
+    /// neighboring tasks close together at all scales. Computing the order requires sorting
+    /// the group's tasks on launch, so this is best suited to grids of moderate size.
+    Morton,
+}
+
 /// A list of all task groups spawned by a function in some launch context which
 /// will be sync'd at an explicit `sync` call or function exit.
 ///
@@ -57,6 +79,18 @@ pub struct Context {
     mem: Mutex<Vec<(AtomicPtr<libc::c_void>, std::alloc::Layout)>>,
     /// A unique identifier for this context
     pub id: usize,
+    /// When the context was created by the first `alloc` call, if the platform has a clock
+    created: Option<Instant>,
+    /// Set once `ISPCSync` has been called with the context's handle
+    syncing: AtomicBool,
+    /// Token used to cancel the remaining tasks in this context
+    cancellation: Option<CancellationToken>,
+    /// Limit on the number of threads running tasks in this context
+    thread_limit: Option<Arc<ThreadLimit>>,
+    /// Key identifying the context when recording or replaying a schedule
+    replay_key: u64,
+    /// The kernel reported when the context's tasks abort, see `ispc_rt::abort`
+    kernel: Option<&'static str>,
 }
 
 impl Context {
@@ -66,14 +100,73 @@ impl Context {
             tasks: RwLock::new(Vec::new()),
             mem: Mutex::new(Vec::new()),
             id,
+            created: now(),
+            syncing: AtomicBool::new(false),
+            cancellation: None,
+            thread_limit: None,
+            replay_key: 0,
+            kernel: None,
         }
     }
+    /// Associate a cancellation token with the context, once cancelled the remaining
+    /// tasks in the context will no longer be handed out to threads. The token should be
+    /// set before any task groups are launched in the context.
+    pub fn set_cancellation(&mut self, token: Option<CancellationToken>) {
+        self.cancellation = token;
+    }
+    /// Get the cancellation token associated with the context
+    pub fn cancellation(&self) -> Option<&CancellationToken> {
+        self.cancellation.as_ref()
+    }
+    /// Limit the number of threads running tasks in this context at a time, see
+    /// `ispc_rt::with_max_threads`. The thread syncing the context is not limited when
+    /// running the context's own tasks.
+    pub fn set_thread_limit(&mut self, limit: Option<Arc<ThreadLimit>>) {
+        self.thread_limit = limit;
+    }
+    /// Get the thread limit associated with the context
+    pub fn thread_limit(&self) -> Option<&Arc<ThreadLimit>> {
+        self.thread_limit.as_ref()
+    }
+    /// Set the key identifying this context across runs when recording or replaying the
+    /// schedule of a task system, see the `replay` module.
+    pub fn set_replay_key(&mut self, key: u64) {
+        self.replay_key = key;
+    }
+    /// Get the key identifying this context across runs
+    pub fn replay_key(&self) -> u64 {
+        self.replay_key
+    }
+    /// Set the kernel reported when the tasks in this context abort
+    pub fn set_kernel(&mut self, kernel: Option<&'static str>) {
+        self.kernel = kernel;
+    }
+    /// Get the kernel reported when the tasks in this context abort
+    pub fn kernel(&self) -> Option<&'static str> {
+        self.kernel
+    }
+    /// Check if the tasks in this context have been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.as_ref().is_some_and(|t| t.is_cancelled())
+    }
     /// Add a task group for execution that was launched in this context
     pub fn launch(&self, total: (i32, i32, i32), data: *mut libc::c_void, fcn: ISPCTaskFn) {
-        self.tasks
-            .write()
-            .unwrap()
-            .push(Arc::new(Group::new(total, AtomicPtr::new(data), fcn)));
+        self.launch_ordered(total, data, fcn, ChunkOrder::Linear);
+    }
+    /// Add a task group for execution that was launched in this context, whose tasks
+    /// will be handed out to threads in `order`
+    pub fn launch_ordered(
+        &self,
+        total: (i32, i32, i32),
+        data: *mut libc::c_void,
+        fcn: ISPCTaskFn,
+        order: ChunkOrder,
+    ) {
+        let mut group = Group::with_order(total, AtomicPtr::new(data), fcn, order);
+        group.cancellation = self.cancellation.clone();
+        let mut tasks = self.tasks.write().unwrap();
+        group.index = tasks.len();
+        tasks.push(Arc::new(group));
     }
     /// Check if all tasks currently in the task list are completed
     ///
@@ -99,10 +192,47 @@ impl Context {
         mem.push((AtomicPtr::new(ptr), layout));
         ptr
     }
+    /// Check if `ptr` points to the start of an allocation made in this context
+    pub fn owns(&self, ptr: *mut libc::c_void) -> bool {
+        let mem = self.mem.lock().unwrap();
+        mem.iter()
+            .any(|(p, _)| p.load(atomic::Ordering::SeqCst) == ptr)
+    }
+    /// Take ownership of the memory allocated in this context, it will no longer be freed
+    /// when the context is dropped.
+    pub(crate) fn take_allocations(&self) -> Vec<(AtomicPtr<libc::c_void>, std::alloc::Layout)> {
+        std::mem::take(&mut *self.mem.lock().unwrap())
+    }
+    /// Get the number of allocations made in this context and their total size in bytes
+    pub fn allocations(&self) -> (usize, usize) {
+        let mem = self.mem.lock().unwrap();
+        (mem.len(), mem.iter().map(|(_, layout)| layout.size()).sum())
+    }
+    /// Get the number of task groups launched in this context
+    pub fn group_count(&self) -> usize {
+        self.tasks.read().unwrap().len()
+    }
+    /// Get the task group at position `index` in the order they were launched in
+    pub fn group(&self, index: usize) -> Option<Arc<Group>> {
+        self.tasks.read().unwrap().get(index).cloned()
+    }
+    /// Get the time at which this context was created, returns None on platforms
+    /// without a clock such as `wasm32-unknown-unknown`
+    pub fn created(&self) -> Option<Instant> {
+        self.created
+    }
+    /// Mark that `ISPCSync` has been called on this context's handle
+    pub fn mark_syncing(&self) {
+        self.syncing.store(true, atomic::Ordering::SeqCst);
+    }
+    /// Check if `ISPCSync` has been called on this context's handle
+    pub fn is_syncing(&self) -> bool {
+        self.syncing.load(atomic::Ordering::SeqCst)
+    }
     /// An iterator over the **current** groups in the context which have remaining tasks to
     /// run on a thread. If more task groups are added before this iterator has returned
     /// None those will appear as well.
-    pub fn iter(&self) -> ContextIter {
+    pub fn iter(&self) -> ContextIter<'_> {
         ContextIter { context: self }
     }
     /// Get a Group with tasks remaining to be executed, returns None if there
@@ -181,6 +311,14 @@ pub struct Group {
     pub fcn: ISPCTaskFn,
     /// Data pointer to user params to pass to the function
     pub data: AtomicPtr<libc::c_void>,
+    /// Order in which the tasks are handed out to threads
+    order: ChunkOrder,
+    /// The task indices in the order they're run, only computed for `ChunkOrder::Morton`
+    morton_order: Option<Box<[i32]>>,
+    /// Token used to cancel the remaining tasks, shared with the group's context
+    cancellation: Option<CancellationToken>,
+    /// Position of the group in the order they were launched in its context
+    index: usize,
     /// Tracks how many chunks we've given out so far to threads
     chunks_launched: AtomicUsize,
     /// Tracks how many of the chunks we gave out are completed. A group is finished
@@ -197,18 +335,76 @@ pub struct Group {
 impl Group {
     /// Create a new task group for execution of the function
     pub fn new(total: (i32, i32, i32), data: AtomicPtr<libc::c_void>, fcn: ISPCTaskFn) -> Group {
+        Group::with_order(total, data, fcn, ChunkOrder::Linear)
+    }
+    /// Create a new task group for execution of the function, whose tasks will be
+    /// handed out to threads in `order`
+    pub fn with_order(
+        total: (i32, i32, i32),
+        data: AtomicPtr<libc::c_void>,
+        fcn: ISPCTaskFn,
+        order: ChunkOrder,
+    ) -> Group {
+        if let ChunkOrder::Tiled { width, height } = order {
+            assert!(width > 0 && height > 0, "Task tiles must not be empty");
+        }
+        let end = (total.0 * total.1 * total.2) as usize;
+        let morton_order = match order {
+            ChunkOrder::Morton => {
+                let mut ids: Vec<i32> = (0..end as i32).collect();
+                ids.sort_by_key(|&id| {
+                    let (x, y, z) = task_indices(total, id);
+                    morton_code(x as u64)
+                        | (morton_code(y as u64) << 1)
+                        | (morton_code(z as u64) << 2)
+                });
+                Some(ids.into_boxed_slice())
+            }
+            _ => None,
+        };
         Group {
             start: AtomicUsize::new(0),
-            end: (total.0 * total.1 * total.2) as usize,
+            end,
             total,
             data,
             fcn,
+            order,
+            morton_order,
+            cancellation: None,
+            index: 0,
             chunks_launched: AtomicUsize::new(0),
             chunks_finished: AtomicUsize::new(0),
         }
     }
+    /// Get the position of the group in the order they were launched in its context
+    pub fn index(&self) -> usize {
+        self.index
+    }
+    /// Get the task index of the task at position `i` in the order tasks are handed out
+    fn task_at(&self, i: i32) -> i32 {
+        match self.order {
+            ChunkOrder::Linear => i,
+            ChunkOrder::Tiled { width, height } => {
+                let (count0, count1, _) = self.total;
+                let slice = count0 * count1;
+                let (z, i) = (i / slice, i % slice);
+                // Every band of tile rows is full except the last, as is every tile within
+                // a band, so we can find the band and tile by dividing by the full size
+                let band = i / (count0 * height);
+                let band_height = cmp::min(height, count1 - band * height);
+                let i = i - band * count0 * height;
+                let tile = i / (width * band_height);
+                let tile_width = cmp::min(width, count0 - tile * width);
+                let i = i - tile * width * band_height;
+                let x = tile * width + i % tile_width;
+                let y = band * height + i / tile_width;
+                x + y * count0 + z * slice
+            }
+            ChunkOrder::Morton => self.morton_order.as_ref().unwrap()[i as usize],
+        }
+    }
     /// Get an iterator over `chunk_size` chunks of tasks to be executed for this group
-    pub fn chunks(&self, chunk_size: usize) -> GroupChunks {
+    pub fn chunks(&self, chunk_size: usize) -> GroupChunks<'_> {
         GroupChunks {
             group: self,
             chunk_size,
@@ -223,6 +419,11 @@ impl Group {
         assert!(finished <= launched);
         finished == launched && start >= self.end
     }
+    /// Skip the tasks which haven't been handed out yet. The group is finished
+    /// once the chunks already given to threads complete.
+    fn cancel(&self) {
+        self.start.fetch_max(self.end, atomic::Ordering::SeqCst);
+    }
     /// Check if this group has tasks left to execute
     fn has_tasks(&self) -> bool {
         let start = self.start.load(atomic::Ordering::SeqCst);
@@ -234,7 +435,12 @@ impl Group {
     /// though you may get fewer if there aren't that many tasks left. If the chunk
     /// you get is the last chunk to be executed (`chunk.end == total.0 * total.1 * total.2`)
     /// you must mark this group as finished upon completing execution of the chunk
-    fn get_chunk(&self, desired_tasks: usize) -> Option<Chunk> {
+    fn get_chunk(&self, desired_tasks: usize) -> Option<Chunk<'_>> {
+        if self.cancellation.as_ref().is_some_and(|t| t.is_cancelled()) {
+            // Drop the tasks which haven't been handed out yet
+            self.cancel();
+            return None;
+        }
         let start = self
             .start
             .fetch_add(desired_tasks, atomic::Ordering::SeqCst);
@@ -304,33 +510,158 @@ impl Chunk<'_> {
     pub fn execute(&self, thread_id: i32, total_threads: i32) {
         let total_tasks = self.total.0 * self.total.1 * self.total.2;
         let data = self.data.load(atomic::Ordering::SeqCst);
-        for t in self.start..self.end {
-            let id = self.task_indices(t);
+        for i in self.start..self.end {
+            let t = self.group.task_at(i);
+            let id = task_indices(self.total, t);
+            crate::print::set_current_task(Some((thread_id, t, total_tasks)));
             (self.fcn)(
                 data,
-                thread_id as libc::c_int,
-                total_threads as libc::c_int,
-                t as libc::c_int,
-                total_tasks as libc::c_int,
-                id.0 as libc::c_int,
-                id.1 as libc::c_int,
-                id.2 as libc::c_int,
-                self.total.0 as libc::c_int,
-                self.total.1 as libc::c_int,
-                self.total.2 as libc::c_int,
+                thread_id as c_int,
+                total_threads as c_int,
+                t as c_int,
+                total_tasks as c_int,
+                id.0 as c_int,
+                id.1 as c_int,
+                id.2 as c_int,
+                self.total.0 as c_int,
+                self.total.1 as c_int,
+                self.total.2 as c_int,
             );
+            crate::print::set_current_task(None);
         }
         // Tell the group this chunk is done
         self.group
             .chunks_finished
             .fetch_add(1, atomic::Ordering::SeqCst);
     }
-    /// Get the global task id for the task index
-    fn task_indices(&self, id: i32) -> (i32, i32, i32) {
-        (
-            id % self.total.0,
-            (id / self.total.0) % self.total.1,
-            id / (self.total.0 * self.total.1),
-        )
+    /// The range of tasks executed by this chunk, as positions in the order the group's
+    /// tasks are handed out. These are the task indices when running in `ChunkOrder::Linear`.
+    pub fn tasks(&self) -> std::ops::Range<i32> {
+        self.start..self.end
+    }
+    /// The position of the chunk's group in the order they were launched in its context
+    pub fn group_index(&self) -> usize {
+        self.group.index()
+    }
+}
+
+/// Get the global task id for the task index
+fn task_indices(total: (i32, i32, i32), id: i32) -> (i32, i32, i32) {
+    (
+        id % total.0,
+        (id / total.0) % total.1,
+        id / (total.0 * total.1),
+    )
+}
+
+/// Get the current time, or None on `wasm32-unknown-unknown` where `Instant::now` panics
+fn now() -> Option<Instant> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        None
+    } else {
+        Some(Instant::now())
+    }
+}
+
+/// Spread the lower 21 bits of `x` out to every third bit, to be interleaved
+/// with the other coordinates into a 3D Morton code
+fn morton_code(x: u64) -> u64 {
+    let mut x = x & 0x1f_ffff;
+    x = (x | (x << 32)) & 0x001f_0000_0000_ffff;
+    x = (x | (x << 16)) & 0x001f_0000_ff00_00ff;
+    x = (x | (x << 8)) & 0x100f_00f0_0f00_f00f;
+    x = (x | (x << 4)) & 0x10c3_0c30_c30c_30c3;
+    x = (x | (x << 2)) & 0x1249_2492_4924_9249;
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn no_task(
+        _data: *mut libc::c_void,
+        _thread_idx: c_int,
+        _thread_cnt: c_int,
+        _task_idx: c_int,
+        _task_cnt: c_int,
+        _task_idx0: c_int,
+        _task_idx1: c_int,
+        _task_idx2: c_int,
+        _task_cnt0: c_int,
+        _task_cnt1: c_int,
+        _task_cnt2: c_int,
+    ) {
+    }
+
+    fn visit_order(total: (i32, i32, i32), order: ChunkOrder) -> Vec<i32> {
+        let group = Group::with_order(total, AtomicPtr::default(), no_task, order);
+        (0..total.0 * total.1 * total.2)
+            .map(|i| group.task_at(i))
+            .collect()
+    }
+
+    fn assert_visits_all(total: (i32, i32, i32), order: ChunkOrder) {
+        let mut tasks = visit_order(total, order);
+        tasks.sort_unstable();
+        let expected: Vec<i32> = (0..total.0 * total.1 * total.2).collect();
+        assert_eq!(tasks, expected, "{order:?} over {total:?}");
+    }
+
+    const GRIDS: [(i32, i32, i32); 6] = [
+        (5, 3, 2),
+        (7, 1, 1),
+        (1, 9, 4),
+        (6, 6, 1),
+        (13, 11, 3),
+        (16, 8, 2),
+    ];
+
+    #[test]
+    fn tiled_order_visits_every_task_once() {
+        for total in GRIDS {
+            for (width, height) in [(1, 1), (2, 2), (4, 3), (3, 5), (32, 32)] {
+                assert_visits_all(total, ChunkOrder::Tiled { width, height });
+            }
+        }
+    }
+
+    #[test]
+    fn morton_order_visits_every_task_once() {
+        for total in GRIDS {
+            assert_visits_all(total, ChunkOrder::Morton);
+        }
+    }
+
+    #[test]
+    fn tiled_order_runs_tiles_together() {
+        let tasks = visit_order(
+            (5, 3, 1),
+            ChunkOrder::Tiled {
+                width: 2,
+                height: 2,
+            },
+        );
+        // The 2x2 tiles of the first two rows, then the partial tiles of the last row
+        assert_eq!(tasks, [0, 1, 5, 6, 2, 3, 7, 8, 4, 9, 10, 11, 12, 13, 14]);
+    }
+
+    #[test]
+    fn morton_order_follows_the_z_curve() {
+        let tasks = visit_order((4, 4, 1), ChunkOrder::Morton);
+        assert_eq!(
+            tasks,
+            [0, 1, 4, 5, 2, 3, 6, 7, 8, 9, 12, 13, 10, 11, 14, 15]
+        );
+    }
+
+    #[test]
+    fn morton_code_spreads_bits() {
+        assert_eq!(morton_code(0), 0);
+        assert_eq!(morton_code(0b1), 0b1);
+        assert_eq!(morton_code(0b111), 0b1001001);
+        assert_eq!(morton_code(0x1f_ffff), 0x1249_2492_4924_9249);
+        // Bits past the 21 that fit are dropped
+        assert_eq!(morton_code(1 << 21), 0);
     }
 }
@@ -0,0 +1,117 @@
+//! Defines a recorder for the chunks of tasks executed by the `Parallel` task system,
+//! which can be exported as a timeline for viewing in [Perfetto](https://ui.perfetto.dev)
+//! or `chrome://tracing`.
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The execution of a single chunk of tasks on some thread
+#[derive(Clone, Debug)]
+pub struct ChunkEvent {
+    /// Id of the context the chunk's task group was launched in
+    pub context: usize,
+    /// The tasks executed by the chunk, see `Chunk::tasks`
+    pub tasks: Range<i32>,
+    /// Id of the thread which ran the chunk. Worker threads start at 1, threads
+    /// outside of the task system which ran chunks while syncing are 0.
+    pub thread: usize,
+    /// Time the chunk started executing, relative to the creation of the recorder
+    pub start: Duration,
+    /// Time the chunk finished executing, relative to the creation of the recorder
+    pub end: Duration,
+}
+
+/// Records a `ChunkEvent` for each chunk of tasks run by the task system it's attached
+/// to, see `ParallelBuilder::trace`.
+#[derive(Debug)]
+pub struct TraceRecorder {
+    epoch: Instant,
+    events: Mutex<Vec<ChunkEvent>>,
+}
+
+impl TraceRecorder {
+    /// Create a new recorder, event timestamps are relative to its creation.
+    pub fn new() -> Arc<TraceRecorder> {
+        Arc::new(TraceRecorder {
+            epoch: Instant::now(),
+            events: Mutex::new(Vec::new()),
+        })
+    }
+    /// Get the time elapsed since the recorder was created
+    pub fn now(&self) -> Duration {
+        self.epoch.elapsed()
+    }
+    /// Add an event to the trace
+    pub fn record(&self, event: ChunkEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+    /// Get a copy of the events recorded so far
+    pub fn events(&self) -> Vec<ChunkEvent> {
+        self.events.lock().unwrap().clone()
+    }
+    /// Discard the events recorded so far
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+    /// Write the events recorded so far in the Chrome trace event JSON format.
+    ///
+    /// Each chunk is written as a complete event on the track of the thread which ran it,
+    /// with the context id and task range attached as arguments.
+    pub fn write_chrome_trace<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let events = self.events.lock().unwrap();
+        writeln!(out, "{{\"traceEvents\":[")?;
+        // Name the thread tracks so the timeline is readable
+        let threads: BTreeSet<usize> = events.iter().map(|e| e.thread).collect();
+        let mut first = true;
+        for t in threads {
+            let name = if t == 0 {
+                String::from("syncing thread")
+            } else {
+                format!("ispc worker {t}")
+            };
+            if !first {
+                writeln!(out, ",")?;
+            }
+            first = false;
+            write!(
+                out,
+                "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{t},\
+                 \"args\":{{\"name\":\"{name}\"}}}}"
+            )?;
+        }
+        for e in events.iter() {
+            if !first {
+                writeln!(out, ",")?;
+            }
+            first = false;
+            let ts = e.start.as_secs_f64() * 1e6;
+            let dur = e.end.saturating_sub(e.start).as_secs_f64() * 1e6;
+            write!(
+                out,
+                "{{\"name\":\"context {} tasks [{}, {})\",\"cat\":\"ispc\",\"ph\":\"X\",\
+                 \"ts\":{ts:.3},\"dur\":{dur:.3},\"pid\":1,\"tid\":{},\
+                 \"args\":{{\"context\":{},\"task_start\":{},\"task_end\":{}}}}}",
+                e.context,
+                e.tasks.start,
+                e.tasks.end,
+                e.thread,
+                e.context,
+                e.tasks.start,
+                e.tasks.end
+            )?;
+        }
+        writeln!(out, "\n]}}")
+    }
+    /// Save the events recorded so far to a `trace.json` file at `path`, which can be
+    /// opened in Perfetto or `chrome://tracing`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_chrome_trace(&mut out)?;
+        out.flush()
+    }
+}
@@ -0,0 +1,87 @@
+//! Defines the traits used to generate the ISPC declarations of types shared with
+//! ISPC code from their Rust definitions, see `IspcStruct`.
+
+use alloc::format;
+use alloc::string::String;
+
+/// Trait implemented by Rust types which have an equivalent type in ISPC
+pub trait IspcType {
+    /// Get the name of the type in ISPC
+    fn ispc_type() -> String;
+    /// Get the declaration of a struct member `name` of this type in ISPC
+    fn ispc_field(name: &str) -> String {
+        format!("{} {name}", Self::ispc_type())
+    }
+}
+
+/// Trait implemented by structs shared between Rust and ISPC, usually through
+/// `#[derive(IspcStruct)]` with the `derive` feature enabled.
+///
+/// The declarations are written to a header to be included by the ISPC code from
+/// the build script, with `Config::generated_header` in `ispc_compile`. Structs used
+/// by other structs must be declared before them.
+///
+/// # Example
+/// ```ignore
+/// // In the build script, where the shared types are a build dependency
+/// use ispc_rt::IspcStruct;
+///
+/// ispc_compile::Config::new()
+///     .generated_header(
+///         "types.isph",
+///         &[shared::Vec3::ispc_declaration(), shared::Ray::ispc_declaration()],
+///     )
+///     .file("src/trace.ispc")
+///     .compile("trace");
+/// ```
+pub trait IspcStruct: IspcType {
+    /// Get the declaration of the struct in ISPC
+    fn ispc_declaration() -> String;
+}
+
+macro_rules! ispc_types {
+    ($($rust:ty => $ispc:expr),*) => {
+        $(
+            impl IspcType for $rust {
+                fn ispc_type() -> String {
+                    String::from($ispc)
+                }
+            }
+        )*
+    };
+}
+
+ispc_types!(
+    bool => "bool",
+    i8 => "int8",
+    u8 => "uint8",
+    i16 => "int16",
+    u16 => "uint16",
+    i32 => "int32",
+    u32 => "uint32",
+    i64 => "int64",
+    u64 => "uint64",
+    f32 => "float",
+    f64 => "double"
+);
+
+impl<T: IspcType, const N: usize> IspcType for [T; N] {
+    fn ispc_type() -> String {
+        T::ispc_type()
+    }
+    fn ispc_field(name: &str) -> String {
+        T::ispc_field(&format!("{name}[{N}]"))
+    }
+}
+
+impl<T: IspcType> IspcType for *const T {
+    fn ispc_type() -> String {
+        format!("const {} * uniform", T::ispc_type())
+    }
+}
+
+impl<T: IspcType> IspcType for *mut T {
+    fn ispc_type() -> String {
+        format!("{} * uniform", T::ispc_type())
+    }
+}
@@ -0,0 +1,85 @@
+//! Support for running the `Parallel` task system on `wasm32` with the threads proposal,
+//! i.e. when building with the `atomics` and `bulk-memory` target features.
+//!
+//! The worker threads share the module's memory (a `SharedArrayBuffer` in the browser) with
+//! the thread calling ISPC code. Idle workers park on an `memory.atomic.wait32` instruction,
+//! `Atomics.wait` in JavaScript, and are woken up with `memory.atomic.notify` when tasks are
+//! launched, since that's how `std` implements parking on these targets.
+//!
+//! On `wasm32-wasip1-threads` `std` spawns threads through wasi-threads, so the default task
+//! system works as is. However the number of CPUs can't be queried, so the number of workers
+//! should be set with `ParallelBuilder::num_threads`.
+//!
+//! On `wasm32-unknown-unknown` in the browser `std` can't spawn threads, so the workers must be
+//! run in Web Workers started by the application, see `web_workers`. Note that the browser's
+//! main thread is not allowed to block, so the task system returned spins while waiting in
+//! `sync`, and that there's no clock on this target so `TraceRecorder` is not supported.
+//!
+//! # Example
+//! Build the task system with a function that starts a Web Worker for each worker thread,
+//! e.g. a function imported from JavaScript with `wasm-bindgen`:
+//!
+//! ```ignore
+//! #[wasm_bindgen]
+//! extern "C" {
+//!     fn start_ispc_worker(worker: usize);
+//! }
+//!
+//! ispc_rt::set_task_system(|| {
+//!     ispc_rt::wasm::web_workers(4, |_id, worker| {
+//!         start_ispc_worker(worker);
+//!         Ok(())
+//!     })
+//!     .build()
+//! });
+//! ```
+//!
+//! The Web Worker instantiates the module with the same memory, which also sets up its stack
+//! and thread local storage, then runs the worker until the task system is dropped:
+//!
+//! ```js
+//! self.onmessage = async ({ data: { module, memory, worker } }) => {
+//!     const wasm = await init(module, memory);
+//!     wasm.ispc_rt_worker_entry(worker);
+//! };
+//! ```
+
+use std::io;
+
+use crate::exec::{IdleStrategy, Parallel, ParallelBuilder, WorkerThread};
+
+/// Get a builder for a task system which runs its `num_workers` worker threads in Web Workers.
+///
+/// For each worker `spawn_worker` is called with the worker's id and a pointer to it. The
+/// function should start a Web Worker which passes the pointer to `ispc_rt_worker_entry` to
+/// run the worker. Threads waiting in `sync` spin instead of blocking, so that ISPC code can
+/// be called from the browser's main thread.
+pub fn web_workers<F>(num_workers: usize, spawn_worker: F) -> ParallelBuilder
+where
+    F: Fn(usize, usize) -> io::Result<()> + Send + Sync + 'static,
+{
+    let mut builder = Parallel::builder();
+    builder
+        .num_threads(num_workers)
+        .sync_idle_strategy(IdleStrategy::Spin)
+        .spawn_handler(move |worker| {
+            let id = worker.id();
+            let worker = Box::into_raw(Box::new(worker));
+            spawn_worker(id, worker as usize).inspect_err(|_| {
+                // The worker was never started so we still own it
+                drop(unsafe { Box::from_raw(worker) });
+            })
+        });
+    builder
+}
+
+/// Run a worker thread handed out by `web_workers` on the calling Web Worker, returns once
+/// the task system is dropped.
+///
+/// # Safety
+/// `worker` must be a pointer passed to the `spawn_worker` function of `web_workers`, and
+/// each worker must only be run once.
+#[no_mangle]
+pub unsafe extern "C" fn ispc_rt_worker_entry(worker: usize) {
+    Box::from_raw(worker as *mut WorkerThread).run();
+}
@@ -46,10 +46,10 @@
 //! build = "build.rs"
 //!
 //! [dependencies]
-//! ispc = "2.0"
+//! ispc = "3.0"
 //!
 //! [build-dependencies]
-//! ispc = "2.0"
+//! ispc = "3.0"
 //! ```
 //!
 //! Now you can use `ispc` to compile your code into a static library:
@@ -78,6 +78,11 @@
 //! ispc_module!(simple);
 //! ```
 //!
+//! A crate can build multiple ISPC libraries by calling `compile_library` (or `Config::compile`)
+//! once for each, and import the bindings to each with `ispc_module!`, see the
+//! [multi_module example](examples/multi_module). The functions exported from the libraries
+//! must have different names, since they share the C symbol namespace.
+//!
 //! ## Requirements for Compiling ISPC Code
 //!
 //! Both the [ISPC compiler](https://ispc.github.io/) and [libclang](http://clang.llvm.org/)
@@ -113,10 +118,10 @@
 //! build = "build.rs"
 //!
 //! [dependencies]
-//! ispc_rt = "2.0"
+//! ispc_rt = "3.0"
 //!
 //! [build-dependencies]
-//! ispc_rt = "2.0"
+//! ispc_rt = "3.0"
 //! ispc_compile = { "2.0", optional = true }
 //!
 //! [features]
@@ -196,6 +201,14 @@ pub use ispc_rt::*;
 /// a rust module containing bindings to the functions exported from ISPC. These
 /// can be imported by passing the name of your library to the `ispc_module` macro.
 ///
+/// The visibility of the module, extra attributes to put on it and the path of the
+/// bindings file can also be specified. The path is resolved relative to the file
+/// invoking the macro, as with `include!`, and defaults to the bindings of the library
+/// in the directory passed by the build script in `ISPC_OUT_DIR_<lib>`. Each library
+/// has its own variable, so multiple libraries can be imported in the same crate. The
+/// variables are set by `ispc_compile` and `PackagedModule` 3.0 and newer, libraries
+/// built with older versions only set `ISPC_OUT_DIR`, see the changelog.
+///
 /// # Example
 ///
 /// ```ignore
@@ -204,10 +217,44 @@ pub use ispc_rt::*;
 ///
 /// // Functions exported from foo will be callable under foo::*
 /// ispc_module!(foo);
+///
+/// // Other libraries built by the build script are imported the same way
+/// ispc_module!(foo_tasks);
+///
+/// // Functions exported from bar will be callable by dependent crates under bar::*
+/// // when the simd feature is enabled
+/// ispc_module!(pub bar, attrs(#[cfg(feature = "simd")]));
+///
+/// // Use bindings to baz which were packaged with the crate
+/// ispc_module!(pub(crate) baz, path = "bindings/baz.rs");
 /// ```
 #[macro_export]
 macro_rules! ispc_module {
+    (@include $lib:ident) => {
+        include!(concat!(
+            env!(concat!("ISPC_OUT_DIR_", stringify!($lib))),
+            "/",
+            stringify!($lib),
+            ".rs"
+        ));
+    };
+    (@include $lib:ident, $path:literal) => {
+        include!($path);
+    };
     ($lib:ident) => {
-        include!(concat!(env!("ISPC_OUT_DIR"), "/", stringify!($lib), ".rs"));
+        include!(concat!(
+            env!(concat!("ISPC_OUT_DIR_", stringify!($lib))),
+            "/",
+            stringify!($lib),
+            ".rs"
+        ));
+    };
+    ($vis:vis $lib:ident $(, attrs($(#[$attr:meta])*))? $(, path = $path:literal)?) => {
+        $($(#[$attr])*)?
+        $vis mod $lib {
+            // The bindings file declares its own module, which we re-export the contents of
+            $crate::ispc_module!(@include $lib $(, $path)?);
+            pub use self::$lib::*;
+        }
     };
 }
@@ -0,0 +1,421 @@
+//! Stress and soak test of the `ispc_rt::Parallel` task system. Each round builds a task
+//! system with a random number of threads, chunk size, chunk order and idle strategy, and
+//! runs randomized trees of nested launches and syncs on it from several threads at once,
+//! the way ISPC code calls `ISPCAlloc`, `ISPCLaunch` and `ISPCSync`. It checks that every
+//! task runs exactly once and has finished when the sync waiting on it returns, and that
+//! all the memory allocated for the tasks is released once the task system is dropped.
+//!
+//! ```text
+//! cargo run --release -p ispc-stress -- --rounds 100
+//! cargo run --release -p ispc-stress -- --duration 3600 --seed 42
+//! ```
+//!
+//! The seed picks the configuration of each round and the shape of its task trees, so a
+//! failing seed reproduces the same workload. The interleaving of the threads running it
+//! still differs between runs, so a failure may take a few runs to reproduce.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::ptr;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ispc_rt::{ChunkOrder, IdleStrategy, Parallel, TaskSystem};
+
+/// Print the message and exit with a failure exit code
+macro_rules! exit_failure {
+    ($($arg:tt)*) => {{
+        eprintln!("error: {}", format!($($arg)*));
+        std::process::exit(1);
+    }};
+}
+
+const USAGE: &str = "\
+Stress and soak test of the ispc_rt task system
+
+Usage:
+    ispc-stress [options]
+
+Options:
+    --seed <seed>         Seed of the random configurations [default: current time]
+    --rounds <n>          Number of rounds to run [default: 20]
+    --duration <seconds>  Keep running rounds for this long instead, for soak testing
+";
+
+/// Task memory is allocated with at least this alignment, which nothing else in the
+/// process uses, so the allocator can track the task memory which is still live
+const TASK_ALIGN: usize = 256;
+
+/// The bytes of task memory currently allocated
+static TASK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Tracks the live task memory on top of the system allocator
+struct TrackingAlloc;
+
+unsafe impl GlobalAlloc for TrackingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() && layout.align() >= TASK_ALIGN {
+            TASK_BYTES.fetch_add(layout.size(), Ordering::SeqCst);
+        }
+        ptr
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.align() >= TASK_ALIGN {
+            TASK_BYTES.fetch_sub(layout.size(), Ordering::SeqCst);
+        }
+        System.dealloc(ptr, layout);
+    }
+}
+
+#[global_allocator]
+static ALLOC: TrackingAlloc = TrackingAlloc;
+
+/// A xorshift64* generator, plenty for picking configurations and task trees
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+    /// Get a number in `lo..=hi`
+    fn range(&mut self, lo: u32, hi: u32) -> u32 {
+        lo + (self.next() % u64::from(hi - lo + 1)) as u32
+    }
+    /// Returns true one in `n` times
+    fn one_in(&mut self, n: u32) -> bool {
+        self.range(1, n) == 1
+    }
+}
+
+/// The task system and workload of a round
+struct Round {
+    threads: usize,
+    chunk_size: usize,
+    order: ChunkOrder,
+    idle: IdleStrategy,
+    dynamic: bool,
+    validate: bool,
+    syncers: usize,
+    kernels: usize,
+    max_depth: u32,
+}
+
+impl Round {
+    fn random(rng: &mut Rng) -> Round {
+        let order = match rng.range(0, 2) {
+            0 => ChunkOrder::Linear,
+            1 => ChunkOrder::Tiled {
+                width: rng.range(1, 4) as i32,
+                height: rng.range(1, 4) as i32,
+            },
+            _ => ChunkOrder::Morton,
+        };
+        let idle = match rng.range(0, 2) {
+            0 => IdleStrategy::Park,
+            1 => IdleStrategy::Backoff {
+                spins: rng.range(0, 100),
+                yields: rng.range(0, 10),
+            },
+            _ => IdleStrategy::Spin,
+        };
+        Round {
+            threads: rng.range(1, 8) as usize,
+            chunk_size: rng.range(1, 16) as usize,
+            order,
+            idle,
+            dynamic: rng.one_in(3),
+            validate: rng.one_in(3),
+            syncers: rng.range(1, 4) as usize,
+            kernels: rng.range(1, 16) as usize,
+            max_depth: rng.range(0, 4),
+        }
+    }
+    fn build(&self) -> Arc<Parallel> {
+        let mut builder = Parallel::builder();
+        builder
+            .num_threads(self.threads)
+            .chunk_size(self.chunk_size)
+            .chunk_order(self.order)
+            .idle_strategy(self.idle)
+            .validate_allocations(self.validate)
+            .name("stress");
+        if self.dynamic {
+            builder.dynamic_threads(1, Duration::from_millis(1));
+        }
+        builder.build()
+    }
+}
+
+/// Counts of the tasks launched and run in a round
+#[derive(Default)]
+struct Stats {
+    launched: AtomicUsize,
+    executed: AtomicUsize,
+    syncs: AtomicUsize,
+}
+
+/// The data passed to each task of a group, allocated in the launching context
+struct TaskData {
+    task_sys: *const Parallel,
+    stats: *const Stats,
+    /// The state of each task in the group, 0 before it runs, 1 while running and 2
+    /// once done
+    state: *const AtomicU8,
+    counts: (i32, i32, i32),
+    depth: u32,
+    max_depth: u32,
+    seed: u64,
+}
+
+/// Run a function launching random groups of tasks, some of which run such a function
+/// in turn, until `max_depth` is reached
+unsafe fn run_node(task_sys: &Parallel, stats: &Stats, depth: u32, max_depth: u32, rng: &mut Rng) {
+    let mut handle = ptr::null_mut();
+    let mut groups = Vec::new();
+    let max_count = 16 >> depth.min(3);
+    for _ in 0..rng.range(1, 3) {
+        let counts = (
+            rng.range(1, max_count) as i32,
+            rng.range(1, max_count / 2) as i32,
+            rng.range(1, 2) as i32,
+        );
+        let total = (counts.0 * counts.1 * counts.2) as usize;
+        let state: Vec<AtomicU8> = (0..total).map(|_| AtomicU8::new(0)).collect();
+        // Functions can make several allocations in a context, e.g. for each launch
+        for _ in 0..rng.range(0, 2) {
+            let size = rng.range(1, 4096) as usize;
+            let align = TASK_ALIGN << rng.range(0, 4);
+            let mem = task_sys.alloc(&mut handle, size as i64, align as i32);
+            assert_eq!(
+                mem as usize % align,
+                0,
+                "ispc-stress: misaligned allocation"
+            );
+            ptr::write_bytes(mem as *mut u8, 0xab, size);
+        }
+        let align = TASK_ALIGN << rng.range(0, 4);
+        let size = std::mem::size_of::<TaskData>() as i64;
+        let data = task_sys.alloc(&mut handle, size, align as i32) as *mut TaskData;
+        assert_eq!(
+            data as usize % align,
+            0,
+            "ispc-stress: misaligned allocation"
+        );
+        data.write(TaskData {
+            task_sys,
+            stats,
+            state: state.as_ptr(),
+            counts,
+            depth,
+            max_depth,
+            seed: rng.next(),
+        });
+        stats.launched.fetch_add(total, Ordering::SeqCst);
+        task_sys.launch(
+            &mut handle,
+            run_task,
+            data as *mut libc::c_void,
+            counts.0,
+            counts.1,
+            counts.2,
+        );
+        groups.push(state);
+        // ISPC code can sync explicitly between launches, the next launch then creates
+        // a new context
+        if rng.one_in(4) {
+            sync(task_sys, stats, &mut handle, &mut groups);
+        }
+    }
+    sync(task_sys, stats, &mut handle, &mut groups);
+}
+
+/// Sync the context of `handle` if any tasks were launched in it and check that all the
+/// tasks in the `groups` launched in it are done
+unsafe fn sync(
+    task_sys: &Parallel,
+    stats: &Stats,
+    handle: &mut *mut libc::c_void,
+    groups: &mut Vec<Vec<AtomicU8>>,
+) {
+    if !handle.is_null() {
+        task_sys.sync(*handle);
+        stats.syncs.fetch_add(1, Ordering::SeqCst);
+        *handle = ptr::null_mut();
+    }
+    for state in groups.drain(..) {
+        for (i, s) in state.iter().enumerate() {
+            let s = s.load(Ordering::SeqCst);
+            assert!(
+                s == 2,
+                "ispc-stress: task {i} of a group was {} when its context was synced",
+                if s == 0 { "not run" } else { "still running" }
+            );
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+extern "C" fn run_task(
+    data: *mut libc::c_void,
+    thread_idx: libc::c_int,
+    thread_cnt: libc::c_int,
+    task_idx: libc::c_int,
+    task_cnt: libc::c_int,
+    task_idx0: libc::c_int,
+    task_idx1: libc::c_int,
+    task_idx2: libc::c_int,
+    task_cnt0: libc::c_int,
+    task_cnt1: libc::c_int,
+    task_cnt2: libc::c_int,
+) {
+    let data = unsafe { &*(data as *const TaskData) };
+    assert!(
+        (0..thread_cnt).contains(&thread_idx),
+        "ispc-stress: thread index {thread_idx} out of {thread_cnt} threads"
+    );
+    assert_eq!((task_cnt0, task_cnt1, task_cnt2), data.counts);
+    assert_eq!(task_cnt, task_cnt0 * task_cnt1 * task_cnt2);
+    assert_eq!(
+        task_idx,
+        task_idx0 + task_idx1 * task_cnt0 + task_idx2 * task_cnt0 * task_cnt1
+    );
+    let state = unsafe { &*data.state.add(task_idx as usize) };
+    assert_eq!(
+        state.swap(1, Ordering::SeqCst),
+        0,
+        "ispc-stress: task {task_idx} was run twice"
+    );
+
+    let (task_sys, stats) = unsafe { (&*data.task_sys, &*data.stats) };
+    let mut rng = Rng::new(data.seed ^ (task_idx as u64).wrapping_mul(0xff51_afd7_ed55_8ccd));
+    // Stragglers keep the threads syncing on them waiting while others run out of work
+    if rng.one_in(64) {
+        thread::sleep(Duration::from_micros(u64::from(rng.range(1, 500))));
+    }
+    if data.depth < data.max_depth && rng.one_in(16 >> data.depth) {
+        unsafe { run_node(task_sys, stats, data.depth + 1, data.max_depth, &mut rng) };
+    }
+    stats.executed.fetch_add(1, Ordering::SeqCst);
+    state.store(2, Ordering::SeqCst);
+}
+
+fn run_round(index: usize, round: &Round, rng: &mut Rng) {
+    let start = Instant::now();
+    let task_sys = round.build();
+    let stats = Stats::default();
+    thread::scope(|s| {
+        for _ in 0..round.syncers {
+            let mut rng = Rng::new(rng.next());
+            let (task_sys, stats) = (&*task_sys, &stats);
+            s.spawn(move || {
+                for _ in 0..round.kernels {
+                    unsafe { run_node(task_sys, stats, 0, round.max_depth, &mut rng) };
+                    // Give the workers time to go idle before launching more tasks
+                    if rng.one_in(4) {
+                        thread::sleep(Duration::from_micros(u64::from(rng.range(0, 2000))));
+                    }
+                }
+            });
+        }
+    });
+    let launched = stats.launched.load(Ordering::SeqCst);
+    let executed = stats.executed.load(Ordering::SeqCst);
+    assert_eq!(
+        launched, executed,
+        "ispc-stress: not every task launched was run"
+    );
+    let unsynced = task_sys.unsynced_contexts();
+    assert!(
+        unsynced.is_empty(),
+        "ispc-stress: {} contexts remained after being synced, the first is {}",
+        unsynced.len(),
+        unsynced[0]
+    );
+
+    // The workers may still hold on to the last contexts they ran tasks from for a moment
+    drop(task_sys);
+    let released = Instant::now();
+    while TASK_BYTES.load(Ordering::SeqCst) != 0 && released.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(1));
+    }
+    let leaked = TASK_BYTES.load(Ordering::SeqCst);
+    assert_eq!(
+        leaked, 0,
+        "ispc-stress: {leaked} bytes of task memory were not released"
+    );
+
+    println!(
+        "round {index}: {} threads, chunks of {}, {:?}, {:?}{}{}, {} syncing threads x {} \
+         kernels of depth {}: {executed} tasks in {} syncs, {:?}",
+        round.threads,
+        round.chunk_size,
+        round.order,
+        round.idle,
+        if round.dynamic { ", dynamic" } else { "" },
+        if round.validate { ", validated" } else { "" },
+        round.syncers,
+        round.kernels,
+        round.max_depth,
+        stats.syncs.load(Ordering::SeqCst),
+        start.elapsed()
+    );
+}
+
+fn main() {
+    let mut seed = None;
+    let mut rounds = 20;
+    let mut duration = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| -> u64 {
+            let v = args
+                .next()
+                .unwrap_or_else(|| exit_failure!("missing value for {name}"));
+            v.parse()
+                .unwrap_or_else(|e| exit_failure!("invalid value {v} for {name}: {e}"))
+        };
+        match arg.as_str() {
+            "--seed" => seed = Some(value("--seed")),
+            "--rounds" => rounds = value("--rounds") as usize,
+            "--duration" => duration = Some(Duration::from_secs(value("--duration"))),
+            "-h" | "--help" => {
+                print!("{USAGE}");
+                return;
+            }
+            _ => exit_failure!("unknown option {arg}\n\n{USAGE}"),
+        }
+    }
+    let seed = seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64
+    });
+    println!("seed {seed}, pass --seed {seed} to run the same rounds again");
+
+    let mut rng = Rng::new(seed);
+    let start = Instant::now();
+    let mut index = 0;
+    loop {
+        let done = match duration {
+            Some(d) => start.elapsed() >= d,
+            None => index >= rounds,
+        };
+        if done {
+            break;
+        }
+        let round = Round::random(&mut rng);
+        run_round(index, &round, &mut rng);
+        index += 1;
+    }
+    println!("ran {index} rounds in {:?}", start.elapsed());
+}